@@ -0,0 +1,417 @@
+//! Keyframe animation of the camera and of objects in the scene, read from a text file with one
+//! key per line:
+//!
+//! ```text
+//! # <track> <frame> <value...>
+//! camera.lookfrom 1 13 2 3
+//! camera.lookfrom 48 -13 2 3
+//! object.3.rotate 1 0 0 0
+//! object.3.rotate 48 0 360 0
+//! # <interpolation> <track>
+//! spline camera.lookfrom
+//! ```
+//!
+//! Camera tracks are `camera.lookfrom`, `camera.lookat`, `camera.vfov`, `camera.aperture` and
+//! `camera.focus-distance`. Objects are picked by their index in the scene, the same as their
+//! object ID, and have `translate`, `rotate` (degrees about x, y and z, applied in that order)
+//! and `scale` tracks. Tracks interpolate linearly unless declared `spline`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::{Add, Mul, Sub};
+
+use crate::util::transform::Transform;
+use crate::util::vector3d::Vector3D;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// Catmull-Rom spline through the keys, so motion eases through them without stopping
+    Spline,
+}
+
+/// A value given at some frames and interpolated in between. Before the first key and after the
+/// last the value holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    /// Frames and values, sorted by frame
+    keys: Vec<(f32, T)>,
+    pub interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            keys: vec![],
+            interpolation,
+        }
+    }
+
+    /// Sets the value at `frame`, replacing any key already there
+    pub fn insert(&mut self, frame: f32, value: T) {
+        let i = self.keys.partition_point(|(f, _)| *f < frame);
+        match self.keys.get(i) {
+            Some((f, _)) if *f == frame => self.keys[i] = (frame, value),
+            _ => self.keys.insert(i, (frame, value)),
+        }
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = f32> + '_ {
+        self.keys.iter().map(|(f, _)| *f)
+    }
+
+    pub fn at(&self, frame: f32) -> Option<T> {
+        let (first, last) = (self.keys.first()?, self.keys.last()?);
+        if frame <= first.0 {
+            return Some(first.1);
+        }
+        if frame >= last.0 {
+            return Some(last.1);
+        }
+        let i = self.keys.partition_point(|(f, _)| *f <= frame) - 1;
+        let ((f1, p1), (f2, p2)) = (self.keys[i], self.keys[i + 1]);
+        let h = f2 - f1;
+        let s = (frame - f1) / h;
+        match self.interpolation {
+            Interpolation::Linear => Some(p1 + (p2 - p1) * s),
+            Interpolation::Spline => {
+                // Cubic Hermite segment with tangents from the neighbouring keys
+                let (m1, m2) = (self.tangent(i), self.tangent(i + 1));
+                let (s2, s3) = (s * s, s * s * s);
+                Some(
+                    p1 * (2. * s3 - 3. * s2 + 1.)
+                        + m1 * ((s3 - 2. * s2 + s) * h)
+                        + p2 * (3. * s2 - 2. * s3)
+                        + m2 * ((s3 - s2) * h),
+                )
+            }
+        }
+    }
+
+    /// Rate of change per frame through key `i`
+    fn tangent(&self, i: usize) -> T {
+        let before = self.keys[i.saturating_sub(1)];
+        let after = self.keys[(i + 1).min(self.keys.len() - 1)];
+        (after.1 - before.1) * (1. / (after.0 - before.0))
+    }
+}
+
+/// Where the camera is and how its lens is set at one frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub lookfrom: Vector3D,
+    pub lookat: Vector3D,
+    /// Direction that appears upwards in the image
+    pub vup: Vector3D,
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_distance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraTracks {
+    pub lookfrom: Track<Vector3D>,
+    pub lookat: Track<Vector3D>,
+    pub vfov: Track<f32>,
+    pub aperture: Track<f32>,
+    pub focus_distance: Track<f32>,
+}
+
+impl CameraTracks {
+    /// `pose` with the animated parameters replaced by their values at `frame`
+    pub fn pose(&self, pose: CameraPose, frame: f32) -> CameraPose {
+        CameraPose {
+            lookfrom: self.lookfrom.at(frame).unwrap_or(pose.lookfrom),
+            lookat: self.lookat.at(frame).unwrap_or(pose.lookat),
+            vup: pose.vup,
+            vfov: self.vfov.at(frame).unwrap_or(pose.vfov),
+            aperture: self.aperture.at(frame).unwrap_or(pose.aperture),
+            focus_distance: self.focus_distance.at(frame).unwrap_or(pose.focus_distance),
+        }
+    }
+}
+
+/// Movement of an object, applied as scale, then rotation, then translation
+#[derive(Clone, Debug)]
+pub struct TransformTracks {
+    pub translate: Track<Vector3D>,
+    /// Degrees about x, y and z
+    pub rotate: Track<Vector3D>,
+    pub scale: Track<f32>,
+}
+
+impl TransformTracks {
+    /// The movement at `frame`, or `None` if the object is scaled down to nothing there
+    pub fn at(&self, frame: f32) -> Option<Transform> {
+        let zero = Vector3D::new(0., 0., 0.);
+        let angles = self.rotate.at(frame).unwrap_or(zero);
+        let s = self.scale.at(frame).unwrap_or(1.);
+        Some(
+            Transform::translate(self.translate.at(frame).unwrap_or(zero))
+                * Transform::rotate(Vector3D::new(0., 0., 1.), angles.z())
+                * Transform::rotate(Vector3D::new(0., 1., 0.), angles.y())
+                * Transform::rotate(Vector3D::new(1., 0., 0.), angles.x())
+                * Transform::scale(Vector3D::new(s, s, s))?,
+        )
+    }
+
+    fn frames(&self) -> impl Iterator<Item = f32> + '_ {
+        self.translate
+            .frames()
+            .chain(self.rotate.frames())
+            .chain(self.scale.frames())
+    }
+}
+
+/// Everything that moves over the course of a shot
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub camera: CameraTracks,
+    /// Tracks of the objects, by index in the scene
+    pub objects: BTreeMap<usize, TransformTracks>,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        let linear = Interpolation::Linear;
+        Self {
+            camera: CameraTracks {
+                lookfrom: Track::new(linear),
+                lookat: Track::new(linear),
+                vfov: Track::new(linear),
+                aperture: Track::new(linear),
+                focus_distance: Track::new(linear),
+            },
+            objects: BTreeMap::new(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut animation = Self::new();
+        for (n, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            animation
+                .parse_line(&words)
+                .map_err(|e| format!("line {}: {}", n + 1, e))?;
+        }
+        Ok(animation)
+    }
+
+    fn parse_line(&mut self, words: &[&str]) -> Result<(), String> {
+        if let [interpolation @ ("linear" | "spline"), track] = words {
+            let interpolation = match *interpolation {
+                "spline" => Interpolation::Spline,
+                _ => Interpolation::Linear,
+            };
+            return self.set_interpolation(track, interpolation);
+        }
+        let (track, frame, values) = match words {
+            [track, frame, values @ ..] => (*track, *frame, values),
+            _ => return Err(format!("Expected a frame and values for {}", words[0])),
+        };
+        let frame: f32 = frame
+            .parse()
+            .map_err(|_| format!("Invalid frame '{}'", frame))?;
+        let values = values
+            .iter()
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|_| format!("Invalid value '{}'", v))
+            })
+            .collect::<Result<Vec<f32>, String>>()?;
+        let scalar = || match values[..] {
+            [v] => Ok(v),
+            _ => Err(format!("{} takes one value", track)),
+        };
+        let vector = || match values[..] {
+            [x, y, z] => Ok(Vector3D::new(x, y, z)),
+            _ => Err(format!("{} takes three values", track)),
+        };
+        match track.split('.').collect::<Vec<_>>()[..] {
+            ["camera", "lookfrom"] => self.camera.lookfrom.insert(frame, vector()?),
+            ["camera", "lookat"] => self.camera.lookat.insert(frame, vector()?),
+            ["camera", "vfov"] => self.camera.vfov.insert(frame, scalar()?),
+            ["camera", "aperture"] => self.camera.aperture.insert(frame, scalar()?),
+            ["camera", "focus-distance"] => self.camera.focus_distance.insert(frame, scalar()?),
+            ["object", index, channel] => {
+                let tracks = self.object(index)?;
+                match channel {
+                    "translate" => tracks.translate.insert(frame, vector()?),
+                    "rotate" => tracks.rotate.insert(frame, vector()?),
+                    "scale" => match scalar()? {
+                        0. => return Err("An object can't be scaled to zero".to_string()),
+                        s => tracks.scale.insert(frame, s),
+                    },
+                    _ => return Err(format!("Unknown track {}", track)),
+                }
+            }
+            _ => return Err(format!("Unknown track {}", track)),
+        }
+        Ok(())
+    }
+
+    fn set_interpolation(
+        &mut self,
+        track: &str,
+        interpolation: Interpolation,
+    ) -> Result<(), String> {
+        match track.split('.').collect::<Vec<_>>()[..] {
+            ["camera", "lookfrom"] => self.camera.lookfrom.interpolation = interpolation,
+            ["camera", "lookat"] => self.camera.lookat.interpolation = interpolation,
+            ["camera", "vfov"] => self.camera.vfov.interpolation = interpolation,
+            ["camera", "aperture"] => self.camera.aperture.interpolation = interpolation,
+            ["camera", "focus-distance"] => {
+                self.camera.focus_distance.interpolation = interpolation
+            }
+            ["object", index, channel] => {
+                let tracks = self.object(index)?;
+                match channel {
+                    "translate" => tracks.translate.interpolation = interpolation,
+                    "rotate" => tracks.rotate.interpolation = interpolation,
+                    "scale" => tracks.scale.interpolation = interpolation,
+                    _ => return Err(format!("Unknown track {}", track)),
+                }
+            }
+            _ => return Err(format!("Unknown track {}", track)),
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, index: &str) -> Result<&mut TransformTracks, String> {
+        let index: usize = index
+            .parse()
+            .map_err(|_| format!("Invalid object index '{}'", index))?;
+        let linear = Interpolation::Linear;
+        Ok(self
+            .objects
+            .entry(index)
+            .or_insert_with(|| TransformTracks {
+                translate: Track::new(linear),
+                rotate: Track::new(linear),
+                scale: Track::new(linear),
+            }))
+    }
+
+    /// First and last frame with a key, if any
+    pub fn frame_range(&self) -> Option<(u32, u32)> {
+        let c = &self.camera;
+        let frames = c
+            .lookfrom
+            .frames()
+            .chain(c.lookat.frames())
+            .chain(c.vfov.frames())
+            .chain(c.aperture.frames())
+            .chain(c.focus_distance.frames())
+            .chain(self.objects.values().flat_map(|o| o.frames()));
+        frames.fold(None, |range, f| {
+            let f = f.max(0.) as u32;
+            match range {
+                None => Some((f, f)),
+                Some((first, last)) => Some((first.min(f), last.max(f))),
+            }
+        })
+    }
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear() {
+        let mut track = Track::new(Interpolation::Linear);
+        assert_eq!(track.at(1.), None);
+        track.insert(10., 4.);
+        track.insert(0., 2.);
+        assert_eq!(track.at(-5.), Some(2.));
+        assert_eq!(track.at(5.), Some(3.));
+        assert_eq!(track.at(20.), Some(4.));
+        track.insert(10., 6.);
+        assert_eq!(track.at(5.), Some(4.));
+    }
+
+    #[test]
+    fn test_spline() {
+        let mut track = Track::new(Interpolation::Spline);
+        for (frame, value) in [(0., 0.), (1., 0.), (2., 1.), (3., 1.)].iter() {
+            track.insert(*frame, *value);
+        }
+        // The spline passes through the keys, smoothly rather than in straight lines
+        assert_eq!(track.at(1.), Some(0.));
+        assert_eq!(track.at(2.), Some(1.));
+        assert!((track.at(1.5).unwrap() - 0.5).abs() < 1e-6);
+        assert!(track.at(1.25).unwrap() < 0.25);
+        assert!(track.at(0.5).unwrap() < 0.);
+        // Evenly spaced keys on a line stay on the line
+        let mut line = Track::new(Interpolation::Spline);
+        for frame in 0..4 {
+            line.insert(frame as f32, Vector3D::new(frame as f32, 0., 0.));
+        }
+        assert!((line.at(1.25).unwrap() - Vector3D::new(1.25, 0., 0.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse() {
+        let animation = Animation::parse(
+            "# turntable\n\
+             camera.lookfrom 1 10 2 0\n\
+             camera.lookfrom 9 0 2 10  # a quarter turn\n\
+             spline camera.lookfrom\n\
+             camera.vfov 5 40\n\
+             object.2.rotate 1 0 0 0\n\
+             object.2.rotate 25 0 90 0\n",
+        )
+        .unwrap();
+        assert_eq!(animation.frame_range(), Some((1, 25)));
+        assert_eq!(
+            animation.camera.lookfrom.interpolation,
+            Interpolation::Spline
+        );
+        let base = CameraPose {
+            lookfrom: Vector3D::new(0., 0., 0.),
+            lookat: Vector3D::new(0., 0., -1.),
+            vup: Vector3D::new(0., 1., 0.),
+            vfov: 30.,
+            aperture: 0.1,
+            focus_distance: 10.,
+        };
+        let pose = animation.camera.pose(base, 9.);
+        assert_eq!(pose.lookfrom, Vector3D::new(0., 2., 10.));
+        assert_eq!(pose.lookat, base.lookat);
+        assert_eq!(pose.vfov, 40.);
+        let turn = animation.objects[&2].at(25.).unwrap();
+        let x = turn.vector(Vector3D::new(1., 0., 0.));
+        assert!((x - Vector3D::new(0., 0., -1.)).length() < 1e-5);
+
+        assert!(Animation::parse("camera.lookfrom 1 2 3").is_err());
+        assert!(Animation::parse("camera.zoom 1 2").is_err());
+        assert!(Animation::parse("object.x.scale 1 2").is_err());
+        assert!(Animation::parse("object.1.scale 1 0").is_err());
+        // Shrinking through zero on the way to a mirror image can't be rendered at the middle
+        let flip = Animation::parse("object.1.scale 0 1\nobject.1.scale 2 -1").unwrap();
+        assert!(flip.objects[&1].at(1.).is_none());
+        assert!(Animation::parse("cubic camera.vfov").is_err());
+        assert_eq!(
+            Animation::parse("\n# nothing\n").unwrap().frame_range(),
+            None
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Blue noise mask used by the blue noise sampler, made ahead of time by void and cluster
+//! (Ulichney 1993) with `sampler::void_and_cluster(64, 1.5)` so renders don't pay for it.
+
+/// Rank of every pixel of a tileable 64x64 mask, in rows
+pub(crate) static MASK: [u16; 4096] = [
+    1669, 3806, 965, 2627, 3090, 3774, 1152, 1649, 377, 1349, 2786, 124, 4029, 688, 2349, 164,
+    3018, 1883, 3787, 1082, 748, 3917, 2017, 430, 3105, 2252, 1908, 289, 1005, 3830, 155, 2914,
+    4081, 2095, 907, 2311, 237, 3229, 637, 2211, 3593, 3244, 2379, 1225, 3139, 597, 1775, 130, 846,
+    2126, 3478, 2373, 746, 2702, 3012, 1094, 447, 892, 3797, 2258, 2937, 3938, 2020, 51, 2746,
+    3200, 542, 3522, 2052, 817, 2512, 2897, 3864, 2454, 1870, 1043, 2904, 3216, 1297, 3609, 1635,
+    616, 2814, 2117, 3260, 202, 2420, 3460, 1593, 1154, 2809, 3493, 2489, 3152, 1666, 2324, 425,
+    3109, 3525, 1518, 3688, 1993, 3900, 1588, 1109, 2651, 528, 3486, 2000, 3922, 2554, 3583, 3115,
+    4039, 1370, 477, 3796, 1619, 97, 3417, 2171, 2755, 1754, 178, 1608, 899, 2443, 1378, 329, 2155,
+    1248, 1550, 283, 1788, 3582, 33, 2076, 696, 3596, 298, 1706, 2207, 349, 2664, 989, 3500, 78,
+    1387, 2604, 1692, 1224, 677, 2675, 360, 3738, 1458, 2074, 524, 839, 3439, 1407, 1087, 11, 2825,
+    724, 2525, 387, 2811, 113, 1824, 4089, 1474, 204, 1093, 2205, 1496, 439, 1031, 2666, 1973,
+    3178, 910, 2399, 4088, 1267, 3087, 751, 3457, 2653, 516, 3742, 3401, 782, 4071, 2942, 2565,
+    3901, 3213, 614, 1431, 3061, 1117, 3282, 2540, 3913, 773, 1539, 3852, 2002, 3077, 2360, 4001,
+    526, 3539, 2883, 3966, 2112, 3191, 879, 43, 4006, 1246, 2782, 3778, 1901, 2573, 3813, 1751,
+    1163, 3143, 1397, 3371, 3772, 2267, 823, 2735, 3025, 3624, 556, 2903, 2382, 1790, 3028, 190,
+    1308, 3545, 1846, 665, 1566, 258, 3663, 2142, 1323, 2992, 1844, 1108, 2502, 1605, 84, 891,
+    2178, 1189, 2724, 2372, 4041, 1599, 445, 2032, 1182, 3006, 2383, 3323, 451, 792, 1679, 1147,
+    3117, 908, 1837, 127, 1068, 1652, 2401, 2891, 1758, 3268, 2149, 169, 642, 3287, 2195, 490,
+    3980, 2325, 896, 1968, 566, 1260, 3306, 388, 2108, 1663, 871, 3313, 46, 3729, 799, 3955, 2196,
+    2766, 339, 2909, 3310, 2547, 1812, 1076, 4015, 109, 2244, 3103, 3467, 1925, 3658, 3154, 446,
+    1712, 3482, 224, 803, 1909, 2858, 3520, 577, 3718, 1, 1097, 1466, 2541, 3712, 2085, 319, 2314,
+    3649, 2560, 3324, 3823, 610, 3550, 1033, 326, 2532, 1591, 2940, 938, 1351, 3435, 1915, 132,
+    3594, 2693, 3086, 1622, 2436, 3866, 1158, 2587, 3790, 1981, 1106, 3446, 1525, 2531, 583, 1113,
+    3789, 1984, 1001, 3893, 457, 2824, 712, 3372, 1409, 385, 648, 1019, 2317, 1420, 2783, 3751,
+    1029, 2201, 3221, 3678, 106, 1393, 2637, 1637, 2082, 2748, 3954, 2944, 144, 3385, 2788, 1531,
+    687, 1325, 421, 1935, 1428, 2182, 2673, 3398, 890, 3930, 3578, 2400, 321, 2712, 790, 2967,
+    1664, 342, 1048, 3646, 14, 1855, 639, 3088, 315, 1461, 2738, 2222, 403, 3002, 1771, 3501, 1471,
+    2459, 40, 2253, 1419, 3202, 2448, 1693, 3815, 2699, 3599, 2982, 286, 3941, 719, 1940, 335,
+    2960, 1293, 2550, 984, 2307, 3248, 813, 3473, 302, 1744, 675, 1277, 1885, 1011, 4087, 3171,
+    2146, 2711, 3065, 142, 3952, 544, 1346, 2029, 431, 1160, 1852, 3194, 1541, 3644, 2468, 1305,
+    4024, 2027, 2606, 863, 2850, 3508, 1289, 2388, 4057, 611, 3159, 1273, 2041, 3266, 215, 789,
+    2975, 3432, 644, 3607, 1969, 280, 886, 2115, 1196, 1848, 2469, 1237, 2109, 3267, 2610, 1542,
+    3986, 598, 1742, 3863, 1975, 376, 4080, 1259, 3067, 2192, 3288, 2421, 3586, 493, 2497, 21,
+    3763, 940, 3516, 1200, 2910, 1822, 3639, 3106, 2294, 2832, 55, 4070, 2094, 1086, 569, 3303,
+    2283, 667, 3407, 1375, 3971, 2058, 161, 3344, 1836, 958, 3659, 86, 3899, 1030, 2685, 4021,
+    1865, 1159, 1655, 2628, 1042, 3967, 2843, 3284, 25, 4042, 529, 3388, 1687, 107, 1124, 3549,
+    2084, 2736, 180, 3140, 744, 2880, 1707, 2354, 972, 444, 3828, 875, 1592, 3036, 2019, 1233,
+    1795, 552, 1625, 2310, 816, 2511, 177, 1535, 775, 3405, 1326, 701, 3008, 263, 3807, 1857, 96,
+    3054, 1676, 402, 2315, 1007, 1589, 2714, 441, 2295, 2851, 1725, 2562, 537, 1620, 2225, 288,
+    3242, 3831, 139, 3017, 1341, 558, 2403, 1534, 3038, 2686, 906, 3667, 2869, 2395, 465, 909,
+    3419, 1227, 2437, 1485, 3616, 154, 2776, 3683, 1441, 1970, 2805, 375, 3910, 809, 2863, 3355,
+    2544, 3988, 354, 3374, 3784, 1121, 2687, 3942, 1694, 2568, 3749, 2327, 1475, 2796, 1177, 2523,
+    919, 3740, 2723, 3231, 588, 3026, 3858, 1223, 3562, 1455, 745, 2144, 3347, 3717, 1278, 2816,
+    2419, 832, 1929, 2269, 3438, 1799, 3687, 811, 1406, 232, 2285, 1290, 601, 3897, 1493, 2971,
+    1838, 3764, 437, 3365, 1072, 2049, 603, 3352, 2538, 110, 3204, 1208, 2147, 3497, 1487, 266,
+    1965, 2929, 1294, 2083, 1672, 590, 3273, 272, 2150, 485, 988, 1749, 3527, 650, 3227, 3943,
+    1344, 2124, 200, 1841, 3682, 2478, 794, 2043, 236, 3185, 3846, 1118, 2931, 363, 902, 3598, 527,
+    1402, 3155, 419, 949, 2678, 174, 2176, 3542, 1952, 3783, 3098, 1786, 2151, 3234, 309, 2534,
+    787, 1660, 2248, 2696, 3888, 1309, 1794, 904, 4037, 1667, 2631, 198, 2402, 631, 3705, 1091,
+    766, 3203, 102, 2376, 2986, 1941, 1220, 3631, 2872, 3240, 108, 2663, 2183, 294, 1920, 2994,
+    735, 3380, 1120, 1410, 62, 1718, 3436, 2636, 618, 2447, 193, 1877, 1476, 3128, 2090, 1710,
+    4084, 2572, 3610, 1602, 3902, 1228, 3223, 942, 1594, 374, 2731, 828, 67, 3640, 1338, 2040,
+    4011, 2902, 50, 841, 3198, 296, 3039, 2308, 508, 3414, 771, 3800, 1815, 3226, 2730, 2289, 3805,
+    1572, 2704, 4017, 946, 3512, 2434, 758, 1414, 1959, 3710, 1272, 848, 3625, 1482, 482, 2305,
+    4079, 2574, 2976, 2209, 3969, 1055, 3071, 1801, 1353, 3443, 2751, 3927, 2501, 92, 3412, 978,
+    234, 2028, 722, 2980, 2352, 543, 3944, 2576, 3330, 1185, 4059, 2435, 1049, 2760, 622, 3263,
+    1157, 3603, 1894, 1446, 2118, 3713, 1139, 2877, 2064, 1321, 3015, 998, 1386, 59, 1731, 461,
+    3441, 703, 1382, 397, 1634, 15, 2626, 4043, 357, 2364, 3089, 1697, 2875, 2440, 3779, 1045,
+    1656, 323, 835, 3262, 540, 1506, 314, 2251, 4060, 939, 2121, 495, 819, 1322, 2952, 2342, 2828,
+    1284, 3384, 306, 1845, 2861, 77, 2059, 681, 2263, 1578, 3494, 1772, 3079, 269, 1477, 2408, 499,
+    2593, 3976, 656, 2517, 1567, 29, 3883, 2494, 299, 3536, 2212, 4083, 3029, 1166, 2414, 1910,
+    3121, 2216, 3716, 3307, 1819, 3014, 1119, 674, 3861, 214, 1103, 3420, 22, 3189, 2708, 2038,
+    3521, 1783, 2460, 2873, 3588, 786, 3228, 31, 1636, 3701, 3280, 1842, 3839, 645, 1651, 3934,
+    2168, 1066, 3630, 1469, 1204, 3092, 3819, 235, 2884, 418, 740, 3884, 2292, 3669, 1727, 3375,
+    966, 2871, 182, 3553, 882, 3351, 1717, 620, 1917, 2821, 812, 379, 2003, 3633, 2885, 172, 3845,
+    1063, 2815, 586, 915, 2240, 1612, 3455, 1889, 2709, 2215, 718, 1976, 1332, 550, 3875, 1266,
+    114, 3811, 1130, 2016, 2616, 1281, 2961, 2405, 1188, 2698, 2186, 399, 1115, 3112, 18, 2629,
+    3211, 602, 2461, 3477, 821, 1791, 1296, 3364, 2130, 2615, 1181, 1933, 880, 199, 3034, 1372,
+    2236, 1748, 3158, 2010, 2643, 1162, 3135, 3739, 1459, 2429, 3283, 1519, 589, 953, 1665, 2553,
+    427, 1983, 1437, 3911, 173, 3183, 2455, 460, 1396, 4033, 3111, 1606, 3661, 2867, 961, 2330,
+    3141, 664, 1670, 249, 3730, 548, 1864, 3483, 290, 716, 1552, 3538, 2291, 3706, 1873, 865, 1545,
+    4050, 1911, 470, 2913, 2340, 3601, 931, 1483, 3251, 6, 3462, 2453, 4073, 2055, 422, 3833, 778,
+    1303, 267, 4018, 404, 2153, 933, 122, 3874, 1143, 2727, 2322, 3979, 3254, 1339, 3591, 3153,
+    2353, 2726, 3556, 1276, 849, 3695, 2916, 971, 366, 2586, 2170, 210, 3325, 1561, 2674, 2081,
+    3448, 3005, 1417, 2326, 3829, 982, 2857, 4035, 3219, 159, 2753, 1354, 497, 3023, 2266, 218,
+    2659, 3754, 1587, 146, 2641, 572, 4009, 1809, 2844, 1421, 567, 2745, 1172, 3485, 2466, 2984,
+    3707, 2299, 2881, 1492, 3615, 2551, 3035, 1843, 673, 3444, 85, 2066, 523, 1878, 858, 73, 1128,
+    1740, 367, 2050, 2645, 1581, 136, 2009, 3487, 1191, 632, 3998, 1884, 793, 390, 3914, 1009,
+    2495, 737, 3101, 131, 1695, 2053, 2463, 1270, 1939, 920, 3350, 2561, 3840, 1240, 3404, 968,
+    2078, 1150, 3873, 1903, 3053, 2250, 358, 1017, 3731, 3127, 1628, 798, 1823, 52, 1510, 518,
+    1060, 1810, 686, 3348, 1245, 467, 2235, 3665, 1609, 3076, 1250, 3753, 2928, 2607, 4069, 3319,
+    705, 3832, 3066, 581, 3296, 2288, 3752, 1736, 3199, 2768, 1422, 2413, 3704, 2834, 1385, 163,
+    1826, 3995, 2137, 1193, 3275, 436, 855, 3001, 594, 3951, 1700, 247, 2031, 591, 1685, 3148,
+    2559, 659, 3253, 316, 1311, 845, 3406, 2476, 2021, 175, 2280, 3868, 2789, 3269, 2030, 2617,
+    3589, 3174, 2416, 168, 1721, 4048, 2771, 1025, 318, 2591, 761, 2357, 261, 1586, 2110, 1356,
+    2846, 2265, 1040, 1839, 3981, 1222, 725, 2475, 248, 927, 3557, 44, 1114, 3238, 2234, 3394,
+    2777, 455, 1532, 3519, 2703, 3881, 1574, 3642, 2133, 3125, 2386, 1110, 3470, 2831, 2363, 45,
+    3503, 2812, 1507, 2384, 3690, 2680, 1642, 3933, 693, 1252, 3297, 353, 1134, 604, 3963, 1249,
+    250, 911, 3870, 2034, 2987, 806, 1400, 3329, 2140, 3964, 1743, 3222, 1080, 3546, 481, 2528,
+    221, 3511, 1511, 4, 2740, 463, 3019, 1504, 3945, 2223, 1662, 3016, 1982, 480, 1611, 764, 1145,
+    3650, 2549, 627, 964, 2213, 396, 2655, 54, 1427, 517, 3798, 1529, 796, 4022, 1298, 1780, 944,
+    3946, 2035, 635, 3279, 89, 1405, 2879, 3618, 2536, 1937, 3563, 2385, 1671, 3048, 2217, 2800,
+    1444, 507, 3535, 2581, 23, 1900, 2943, 449, 1363, 3636, 1992, 3009, 897, 3744, 1921, 743, 3247,
+    2491, 3622, 2141, 3399, 1053, 2835, 519, 3315, 729, 2623, 4078, 2462, 3786, 1961, 3131, 3,
+    1863, 2973, 1337, 3555, 1747, 3277, 894, 2908, 2613, 176, 3237, 1942, 356, 2273, 471, 2970,
+    212, 1180, 1849, 3000, 2224, 372, 1777, 818, 1479, 3004, 934, 103, 3524, 694, 1774, 3317, 1105,
+    2262, 1623, 3762, 657, 3489, 987, 2779, 170, 651, 2662, 1472, 3146, 1138, 4004, 2057, 1261,
+    905, 1614, 187, 1946, 3737, 1288, 1881, 3672, 1449, 980, 143, 2837, 386, 2293, 1408, 4020,
+    3367, 230, 2510, 1052, 4093, 1996, 3694, 1680, 2166, 1013, 2932, 3768, 2638, 3573, 1452, 3395,
+    2442, 4068, 860, 3496, 1099, 3885, 2820, 179, 4053, 2135, 2670, 1389, 3818, 293, 2605, 3960,
+    246, 3190, 941, 2334, 1505, 2498, 3821, 1806, 2345, 3923, 57, 1761, 2456, 291, 2767, 511, 3108,
+    3907, 2397, 768, 2665, 93, 2439, 336, 2934, 2184, 3515, 1279, 1713, 3600, 1020, 2394, 726,
+    2011, 2864, 587, 2350, 240, 1241, 671, 3532, 2423, 1401, 708, 1657, 1059, 2143, 736, 2798,
+    1530, 503, 2507, 3192, 1995, 595, 3410, 1271, 450, 3312, 1912, 1074, 3072, 1987, 797, 1350,
+    1943, 2865, 4008, 192, 2006, 3114, 795, 3403, 1238, 2173, 3335, 626, 2993, 1533, 3755, 1834,
+    407, 2917, 3540, 1514, 3145, 3879, 1174, 3354, 638, 1867, 3195, 814, 2919, 501, 3264, 1678,
+    3773, 1299, 3626, 1565, 3163, 2516, 2964, 438, 3974, 90, 3293, 3725, 282, 3074, 3816, 26, 1971,
+    3721, 1355, 285, 1570, 2642, 2268, 1764, 3750, 755, 2906, 2328, 545, 3459, 2467, 3676, 2640,
+    384, 1265, 3239, 561, 1197, 1638, 332, 2933, 876, 3793, 1366, 3543, 2197, 801, 2603, 1334,
+    2092, 1023, 458, 2210, 852, 2033, 1627, 2713, 3692, 429, 2533, 3949, 2093, 2744, 87, 913, 3043,
+    297, 1887, 873, 3463, 2047, 1123, 1776, 2770, 2070, 584, 2490, 1796, 1206, 2298, 3336, 957,
+    2764, 2167, 3775, 836, 3050, 1022, 2543, 1491, 186, 3898, 1631, 1211, 61, 1729, 649, 3391,
+    1689, 2226, 2758, 3703, 2558, 3991, 1792, 2588, 271, 2005, 1050, 149, 3440, 3164, 226, 4023,
+    3258, 1681, 3612, 2894, 505, 4027, 64, 1041, 2247, 1320, 191, 1503, 1102, 3878, 2499, 2164,
+    3386, 2715, 3834, 63, 1528, 3641, 3142, 822, 1300, 2935, 4034, 857, 3534, 459, 1658, 3020, 151,
+    3289, 1218, 409, 3567, 0, 3179, 2125, 3491, 970, 2775, 3259, 4077, 2281, 2945, 1071, 3847, 763,
+    3337, 69, 983, 2264, 541, 3209, 1571, 2737, 4092, 2366, 1683, 1146, 1913, 615, 2335, 2742, 320,
+    1269, 2449, 3059, 1445, 2597, 3838, 1782, 3513, 3144, 1947, 3450, 361, 1494, 1067, 535, 1365,
+    2241, 700, 2654, 255, 2287, 3415, 115, 2177, 1440, 2845, 2537, 3983, 689, 2422, 1827, 3965,
+    2829, 1974, 1371, 4010, 473, 1800, 2482, 278, 2056, 842, 1411, 3544, 167, 2527, 1875, 1348,
+    2087, 3003, 1436, 3509, 1127, 3637, 727, 3094, 494, 2876, 3801, 2555, 3627, 1413, 918, 3935,
+    1735, 3424, 912, 1948, 3301, 580, 2954, 888, 420, 2365, 682, 2998, 1825, 3962, 2441, 3261,
+    2847, 4049, 1832, 1078, 3769, 1560, 2710, 697, 3327, 181, 2008, 1156, 1500, 3461, 977, 555,
+    1600, 2378, 2707, 734, 2957, 1144, 3757, 660, 3064, 2689, 462, 2026, 1544, 3104, 345, 4046,
+    695, 3629, 245, 1960, 2471, 80, 2227, 1807, 995, 1489, 7, 862, 2979, 253, 2024, 3175, 123,
+    2190, 619, 3617, 262, 1234, 2159, 2676, 4086, 1682, 1247, 2614, 3566, 785, 185, 1962, 1169,
+    365, 3392, 2479, 539, 1926, 1171, 3621, 1719, 1000, 3110, 3686, 322, 2729, 2101, 3055, 3679,
+    254, 1073, 3423, 1650, 2249, 3298, 1499, 1866, 3614, 1190, 3810, 2741, 930, 3427, 2331, 1633,
+    2619, 3181, 898, 2958, 3820, 1287, 3291, 3909, 2452, 3464, 2152, 1724, 3518, 2505, 1175, 2810,
+    3817, 1495, 2336, 2893, 1699, 3655, 9, 1046, 3060, 3758, 112, 2114, 1429, 2930, 3680, 1673,
+    917, 2947, 1415, 3172, 3887, 351, 2948, 2451, 3919, 521, 2243, 1768, 3574, 75, 1433, 777, 3241,
+    1918, 3728, 119, 2819, 400, 3956, 1032, 82, 2392, 3286, 636, 2134, 1239, 2889, 491, 1142, 3880,
+    1549, 634, 1715, 362, 2595, 413, 1924, 658, 3168, 1126, 483, 3771, 721, 1830, 424, 2624, 1064,
+    3985, 752, 3245, 1443, 2545, 2060, 551, 3322, 1027, 2518, 3376, 617, 2361, 3895, 2131, 34, 881,
+    2312, 1509, 2071, 244, 1335, 2778, 838, 2969, 1213, 2404, 3924, 2660, 2202, 1264, 605, 2566,
+    1404, 856, 2158, 2622, 3096, 1573, 355, 1805, 3886, 16, 3581, 1919, 2431, 337, 2188, 3358,
+    2842, 2077, 3709, 928, 2921, 1328, 4026, 2750, 1595, 2231, 3320, 1360, 3031, 3523, 147, 1980,
+    2481, 510, 1880, 3856, 815, 1517, 2830, 1753, 4012, 251, 1316, 2752, 301, 3465, 1886, 2690,
+    3359, 4014, 715, 3505, 1890, 3334, 1601, 3843, 653, 3314, 996, 1704, 373, 4091, 3052, 2045,
+    3827, 2923, 3499, 624, 1988, 4028, 827, 2950, 2589, 1453, 3156, 833, 3743, 2754, 1301, 3987,
+    126, 1173, 3129, 1621, 3572, 165, 2290, 338, 963, 2918, 56, 4076, 2102, 837, 1640, 3147, 1242,
+    3481, 2882, 347, 3107, 3613, 2333, 448, 887, 2198, 1854, 3274, 762, 1527, 3685, 563, 1044,
+    2238, 3099, 1192, 2632, 27, 2351, 382, 2075, 1520, 184, 2870, 3514, 2368, 935, 1746, 325, 1133,
+    1645, 222, 3360, 1313, 2275, 3654, 1026, 506, 2229, 1705, 150, 3411, 916, 1853, 2577, 732,
+    2348, 486, 2054, 2658, 1765, 3220, 3809, 1932, 2571, 1092, 502, 2371, 2817, 3904, 277, 2214,
+    926, 1342, 2175, 133, 1170, 3799, 3210, 2594, 3735, 1100, 2866, 2412, 1263, 3010, 1769, 2792,
+    456, 3723, 805, 3982, 1112, 3585, 2684, 3045, 3741, 1891, 691, 1456, 36, 3458, 3122, 2313,
+    3664, 2526, 1065, 2808, 128, 1607, 3316, 2486, 4036, 1235, 3033, 2073, 565, 3160, 1450, 3745,
+    3382, 1274, 3959, 1018, 3453, 531, 1399, 770, 3611, 1562, 3083, 3681, 1155, 678, 1522, 3643,
+    2728, 4019, 1709, 3418, 2691, 1907, 1364, 153, 1615, 416, 4067, 1978, 343, 3803, 94, 1283,
+    1644, 2022, 2504, 1547, 3169, 1829, 929, 536, 2245, 1088, 3338, 2769, 3871, 2513, 1327, 443,
+    1928, 707, 3849, 1869, 3149, 728, 1994, 242, 3495, 641, 2564, 1564, 3653, 2256, 259, 2803,
+    1906, 35, 2887, 723, 1543, 2418, 2991, 2191, 308, 3379, 1861, 120, 2044, 2630, 3299, 1804, 211,
+    633, 2508, 954, 487, 3041, 738, 2900, 3466, 2246, 668, 3339, 1002, 2535, 3498, 2337, 3309, 208,
+    2974, 582, 2169, 313, 3302, 1384, 3882, 2578, 440, 1643, 1039, 2099, 747, 4038, 2833, 3217,
+    1454, 428, 2569, 3891, 1304, 2855, 1759, 985, 3854, 292, 2747, 1141, 4054, 937, 1583, 2483,
+    3196, 2100, 3714, 152, 3928, 1251, 2762, 683, 2427, 1377, 3850, 426, 2347, 993, 2988, 2025,
+    3167, 1553, 3937, 2127, 3592, 1781, 990, 2739, 1319, 2963, 1604, 2051, 759, 509, 4045, 1061,
+    3673, 1424, 2827, 3939, 2487, 1716, 79, 3082, 1963, 3698, 216, 2983, 3378, 1580, 1021, 76,
+    2367, 3551, 903, 2194, 3437, 525, 2377, 2977, 2014, 1376, 3475, 515, 1985, 3308, 643, 3822,
+    1089, 378, 2677, 1897, 951, 3212, 1677, 4003, 1008, 2859, 3201, 854, 3568, 1438, 3782, 1199,
+    3480, 101, 2407, 1187, 275, 2464, 3958, 2, 1860, 3727, 228, 3912, 3058, 2720, 1793, 2199, 692,
+    3454, 121, 1221, 769, 3595, 2148, 1257, 831, 3230, 2375, 1820, 333, 2585, 2062, 3802, 1733,
+    1201, 2926, 156, 1648, 1085, 3795, 183, 3243, 869, 2428, 1703, 3063, 2609, 219, 2272, 1778,
+    3605, 1230, 3366, 522, 2529, 42, 2106, 3529, 264, 1639, 1956, 2743, 17, 2278, 468, 2644, 843,
+    3715, 1653, 3265, 647, 1464, 3182, 2346, 592, 2608, 955, 1448, 48, 1253, 3161, 2612, 1732,
+    2406, 2007, 3390, 2773, 623, 3989, 2822, 1447, 578, 3940, 1285, 3579, 553, 2761, 3180, 628,
+    1955, 4085, 3218, 2694, 2128, 1467, 600, 3970, 2854, 65, 1229, 3890, 1439, 3479, 2996, 699,
+    1630, 2907, 2187, 3808, 1357, 3056, 608, 2583, 1244, 3973, 709, 3113, 1779, 4066, 1457, 1958,
+    2968, 405, 2667, 3777, 2080, 877, 3554, 1195, 3305, 1949, 3587, 2390, 3842, 328, 921, 3781,
+    514, 3097, 1079, 344, 1616, 2260, 158, 3472, 2682, 976, 2206, 3078, 885, 1430, 256, 3648, 2600,
+    1347, 432, 804, 3091, 3528, 1784, 2180, 1077, 3684, 2356, 750, 2122, 975, 300, 2519, 4062, 206,
+    895, 1785, 3396, 1095, 1858, 3736, 2344, 393, 2181, 3517, 950, 2818, 630, 3340, 2300, 962,
+    1876, 1232, 3049, 415, 2795, 1590, 2257, 433, 2911, 1016, 3353, 2063, 1575, 2939, 1343, 4090,
+    1798, 2500, 3193, 3746, 1116, 2046, 1691, 3759, 5, 1618, 2391, 3929, 1811, 2270, 925, 3389,
+    2445, 1930, 117, 1205, 2759, 273, 3345, 1513, 500, 3565, 1708, 2774, 3349, 1951, 1418, 2387,
+    3666, 585, 2681, 231, 2925, 889, 3402, 3037, 1111, 1579, 239, 2425, 3657, 160, 1359, 3999,
+    3416, 171, 2509, 1752, 4047, 104, 3660, 780, 1555, 1817, 475, 2688, 3651, 207, 2304, 844, 28,
+    3606, 1379, 802, 2580, 442, 3276, 714, 2849, 3490, 464, 3270, 1151, 2852, 53, 1563, 3619, 1012,
+    3993, 2506, 3726, 851, 2941, 1979, 2647, 3132, 99, 3950, 1212, 488, 3233, 1024, 3022, 1526,
+    2341, 4032, 2096, 1497, 140, 1757, 2672, 3872, 3281, 2065, 1167, 1763, 3120, 546, 2807, 847,
+    3699, 1306, 2119, 936, 2521, 3150, 3968, 2306, 3040, 741, 1153, 1923, 3506, 2668, 2039, 2999,
+    534, 1931, 2890, 4025, 1243, 2320, 1893, 1010, 2661, 2013, 346, 3756, 3102, 666, 2120, 2946,
+    1426, 554, 1675, 2323, 417, 4072, 997, 1352, 2415, 670, 2233, 3696, 2695, 81, 1991, 3533, 381,
+    1215, 3333, 593, 3926, 2132, 784, 484, 1340, 2899, 742, 3892, 2488, 1997, 1596, 2271, 3157,
+    607, 3426, 2951, 1871, 1280, 195, 3552, 1392, 3925, 2450, 3207, 406, 1490, 3876, 1168, 2374,
+    3422, 189, 1584, 3062, 359, 3691, 1484, 4056, 702, 1388, 2203, 1728, 3906, 2718, 268, 3257,
+    2079, 3468, 1129, 3187, 1789, 213, 3393, 1899, 2978, 1523, 864, 1767, 3908, 1307, 2784, 824,
+    1797, 2725, 2430, 1140, 2874, 3620, 2332, 1872, 3570, 380, 2756, 945, 39, 3791, 1136, 238,
+    2470, 1512, 364, 3794, 655, 2733, 952, 2139, 72, 1698, 612, 2848, 948, 3272, 317, 1741, 3804,
+    974, 2145, 2522, 893, 3342, 243, 2444, 3057, 3559, 2620, 1054, 452, 1275, 1833, 829, 3855, 12,
+    2802, 1395, 3662, 2584, 808, 3792, 324, 3561, 2546, 410, 3068, 676, 2193, 3711, 3100, 19, 3761,
+    1641, 311, 3197, 1038, 71, 3136, 1576, 2105, 3449, 1398, 2985, 3343, 1813, 3947, 2804, 1148,
+    2277, 3225, 1668, 334, 3311, 2892, 3569, 1330, 3724, 1816, 2515, 720, 2790, 1416, 3119, 570,
+    3894, 1345, 2781, 2107, 1122, 1686, 129, 826, 3361, 2438, 3184, 3671, 2649, 1556, 2359, 713,
+    2023, 472, 2219, 1582, 2716, 1184, 2067, 3170, 1058, 2358, 3488, 196, 1538, 986, 2069, 662,
+    3413, 2001, 1432, 2611, 4031, 2417, 1217, 3824, 613, 2575, 2162, 779, 466, 2089, 825, 3510, 13,
+    2012, 4058, 2567, 1254, 1914, 867, 2625, 2185, 105, 3975, 2123, 3602, 138, 2599, 1927, 3492,
+    95, 1750, 3124, 492, 3826, 2901, 1904, 3990, 1478, 88, 2174, 513, 1014, 2915, 3996, 3370, 1083,
+    3075, 3957, 576, 3292, 37, 1468, 4051, 1874, 1390, 2953, 3916, 2410, 3214, 1336, 2780, 859,
+    3780, 562, 1739, 872, 241, 3047, 1787, 352, 4065, 1219, 2868, 3693, 1674, 2590, 3081, 1451,
+    706, 3451, 2284, 512, 4000, 327, 3304, 1137, 1540, 3042, 883, 1194, 3300, 1610, 840, 2297,
+    3747, 730, 3442, 2355, 1292, 305, 2242, 704, 2962, 1186, 3381, 3719, 1868, 330, 1647, 2683,
+    118, 1333, 1882, 2370, 3766, 710, 2840, 260, 901, 2592, 575, 1773, 303, 4064, 2329, 162, 2924,
+    2116, 3387, 2793, 3634, 2279, 1075, 2785, 1568, 3409, 2321, 145, 1314, 549, 3733, 1015, 2959,
+    1726, 3670, 1081, 3093, 1661, 2474, 652, 3502, 395, 1986, 2432, 4061, 276, 2888, 1176, 2635,
+    1442, 1977, 874, 2722, 3564, 3162, 1745, 3788, 2004, 2465, 166, 1295, 3252, 2237, 3748, 772,
+    3429, 2905, 960, 1730, 2602, 2113, 3638, 3357, 2037, 1236, 3447, 2692, 1101, 1684, 3541, 1051,
+    1515, 371, 1286, 1957, 753, 3166, 3604, 252, 1944, 924, 3007, 3332, 2446, 1808, 2172, 408,
+    1329, 220, 2700, 2072, 1291, 3862, 2938, 1831, 2719, 3675, 1508, 560, 2179, 3397, 3915, 369,
+    3271, 20, 4044, 1629, 453, 1028, 2552, 392, 866, 1498, 3126, 2598, 932, 520, 1465, 2480, 2088,
+    225, 3577, 348, 3232, 1084, 496, 1654, 3116, 68, 3785, 739, 2156, 489, 3051, 2457, 3978, 3186,
+    2601, 3835, 116, 1470, 2426, 672, 2705, 3896, 1755, 749, 3844, 203, 2801, 3918, 2424, 3013,
+    731, 3765, 70, 870, 2232, 265, 1268, 754, 3134, 2763, 994, 1847, 663, 1585, 2138, 2582, 1131,
+    3044, 2018, 3877, 1361, 3434, 2838, 4075, 609, 1938, 3853, 2799, 3356, 1047, 4040, 1569, 2520,
+    1282, 3860, 1536, 2389, 3932, 791, 2286, 2896, 1546, 3321, 3734, 1895, 783, 32, 2218, 579,
+    1107, 3471, 1859, 3984, 1161, 3235, 1425, 398, 2221, 1179, 1524, 3246, 868, 3421, 1481, 1896,
+    3215, 2398, 3526, 1488, 3290, 3994, 2261, 38, 3841, 1331, 3173, 2458, 2856, 3722, 530, 3530,
+    2319, 733, 2794, 100, 2254, 1722, 274, 2309, 3507, 1626, 91, 1888, 423, 3030, 679, 1967, 3085,
+    776, 2886, 233, 2697, 1317, 1898, 368, 2493, 1209, 223, 2765, 1368, 3425, 1828, 1501, 2841,
+    2343, 401, 2997, 2161, 41, 2570, 3431, 2956, 3590, 2579, 532, 2091, 135, 3623, 504, 1036, 1738,
+    2797, 557, 1964, 1006, 2618, 1624, 2068, 435, 3580, 141, 1203, 853, 1862, 1383, 188, 3326,
+    1559, 3632, 943, 3285, 1226, 3011, 830, 1183, 3165, 2396, 3674, 1210, 2734, 3776, 454, 2259,
+    1814, 3341, 1035, 3547, 3069, 4016, 956, 2989, 1632, 3905, 2380, 1004, 3027, 3931, 861, 3250,
+    1358, 765, 1720, 3700, 629, 1856, 969, 98, 1954, 4082, 1149, 2806, 2204, 2656, 4007, 312, 1231,
+    3708, 3024, 201, 3575, 3255, 900, 2898, 1473, 2318, 4005, 3383, 2981, 2648, 3903, 1070, 1916,
+    2596, 559, 2048, 3865, 2669, 394, 2208, 3992, 767, 1502, 2157, 8, 1690, 3408, 1178, 4095, 640,
+    2163, 134, 1463, 574, 2220, 3668, 717, 2086, 284, 3597, 476, 2524, 304, 1966, 3732, 2679, 3373,
+    1057, 2409, 3936, 1369, 2836, 788, 3137, 1723, 3814, 923, 1403, 1953, 3118, 2477, 820, 2302,
+    1766, 1256, 596, 2492, 3770, 1803, 698, 1999, 270, 1646, 469, 2154, 3188, 281, 4002, 2920,
+    1462, 58, 1756, 3689, 1380, 2853, 217, 2657, 3558, 3123, 981, 2433, 197, 2966, 1381, 2548,
+    3848, 2772, 1711, 3452, 66, 2650, 3295, 1302, 2823, 1688, 2129, 3456, 1135, 148, 2316, 474,
+    1480, 3138, 279, 2111, 3760, 1548, 2472, 331, 3256, 621, 2990, 24, 3428, 1613, 3851, 383, 2787,
+    3953, 2189, 295, 3368, 1069, 2749, 3249, 2514, 1096, 3677, 684, 2503, 1310, 834, 2362, 3576,
+    1062, 2496, 3070, 538, 1972, 3363, 1056, 1821, 711, 3961, 2826, 1598, 1990, 3474, 434, 1879,
+    884, 3177, 1202, 1989, 991, 1760, 568, 4063, 807, 1391, 2757, 3837, 1603, 3073, 4052, 1998,
+    2706, 760, 3318, 2338, 573, 3531, 1318, 1762, 2530, 3652, 2230, 1098, 479, 2036, 3208, 1037,
+    1551, 2912, 1905, 1374, 60, 3920, 564, 1394, 3080, 1934, 2895, 1617, 3476, 1840, 391, 3236,
+    685, 2104, 3433, 947, 1597, 2411, 3857, 478, 2228, 1435, 350, 3697, 810, 3130, 1132, 3645,
+    2301, 257, 2485, 3977, 2862, 3537, 2200, 3206, 2473, 47, 3151, 547, 1902, 850, 1258, 341, 3584,
+    1696, 1214, 157, 3032, 1034, 2098, 4030, 414, 1554, 774, 2701, 3571, 1324, 2557, 83, 3469, 781,
+    3812, 3133, 2381, 1701, 3548, 2160, 205, 4074, 959, 10, 3825, 3084, 2165, 1577, 3767, 1255,
+    194, 3948, 2972, 310, 1262, 3021, 2542, 3328, 1090, 2136, 2639, 49, 1659, 2813, 599, 3369,
+    1423, 757, 411, 1516, 229, 1125, 1945, 3628, 1003, 2255, 2634, 3430, 2922, 2296, 999, 2878,
+    3997, 1892, 3702, 2732, 227, 2927, 1198, 3278, 3921, 1850, 2936, 661, 4094, 1835, 2339, 498,
+    1164, 2652, 412, 979, 2791, 756, 3294, 1486, 2276, 2671, 571, 1104, 2563, 125, 2860, 1734,
+    2717, 2282, 690, 3484, 2015, 3635, 111, 1737, 3889, 533, 3504, 2393, 4013, 1362, 2103, 3836,
+    2965, 1922, 3720, 2303, 2995, 3859, 646, 2839, 1557, 3972, 209, 625, 1460, 3869, 30, 2061, 654,
+    2556, 1558, 800, 2274, 680, 1950, 2369, 137, 1412, 340, 2239, 3331, 914, 3095, 3656, 2097,
+    1521, 3400, 1936, 3867, 1207, 2484, 389, 3445, 1770, 2949, 1434, 3608, 922, 4055, 606, 3346,
+    1373, 1851, 2633, 967, 1537, 669, 2721, 3046, 1312, 1818, 992, 2955, 307, 878, 1702, 74, 2621,
+    973, 3362, 1315, 2646, 1714, 287, 3224, 1216, 2042, 3647, 1802, 2539, 3205, 1367, 3377, 370,
+    1165, 3176, 3560,
+];
@@ -0,0 +1,202 @@
+use rayon::prelude::*;
+
+use crate::film::{Aov, Film};
+use crate::util::vector3d::Vector3D;
+
+/// Weights of the B3 spline the à-trous filter spreads over 5 taps
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// How strongly the filter respects edges in each guide. Smaller values keep edges sharper.
+pub struct DenoiseSettings {
+    /// Number of passes, each doubling the filter's reach
+    pub iterations: u32,
+    /// Relative colour difference, halved every pass
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    /// Relative depth difference across which pixels stop being averaged
+    pub sigma_depth: f32,
+    pub sigma_albedo: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.,
+            sigma_normal: 0.3,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+/// Edge avoiding à-trous wavelet filter (Dammertz et al. 2010). The colour is divided by the
+/// albedo so only lighting gets blurred and textures survive, then filtered with weights that
+/// drop off across changes in colour, normal, depth and albedo, and finally remodulated.
+pub fn denoise(film: &Film, settings: &DenoiseSettings) -> Vec<Vector3D> {
+    let (nx, ny) = (film.nx as i32, film.ny as i32);
+    let albedo: Vec<Vector3D> = film.aovs.iter().map(demodulation).collect();
+    let mut color: Vec<Vector3D> = film
+        .color
+        .iter()
+        .zip(albedo.iter())
+        .map(|(c, a)| *c / *a)
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        // Later passes compare smoother colours so can afford to be stricter
+        let sigma_color = settings.sigma_color / (1 << iteration) as f32;
+        color = (0..ny * nx)
+            .into_par_iter()
+            .map(|p| {
+                let (x, y) = (p % nx, p / nx);
+                let center = color[p as usize];
+                let aov = &film.aovs[p as usize];
+                let mut sum = Vector3D::new(0., 0., 0.);
+                let mut total = 0.;
+                for (ky, hy) in KERNEL.iter().enumerate() {
+                    let qy = y + (ky as i32 - 2) * step;
+                    if qy < 0 || qy >= ny {
+                        continue;
+                    }
+                    for (kx, hx) in KERNEL.iter().enumerate() {
+                        let qx = x + (kx as i32 - 2) * step;
+                        if qx < 0 || qx >= nx {
+                            continue;
+                        }
+                        let q = (qy * nx + qx) as usize;
+                        let w = hx
+                            * hy
+                            * color_weight(center, color[q], sigma_color)
+                            * guide_weight(aov, &film.aovs[q], settings);
+                        sum += color[q] * w;
+                        total += w;
+                    }
+                }
+                sum / total
+            })
+            .collect();
+    }
+
+    color
+        .iter()
+        .zip(albedo.iter())
+        .map(|(c, a)| *c * *a)
+        .collect()
+}
+
+/// Albedo the colour is divided by before filtering. Pixels that missed everything use white
+/// and black albedos are clamped so they can still be divided by.
+fn demodulation(aov: &Aov) -> Vector3D {
+    if aov.coverage == 0. {
+        return Vector3D::new(1., 1., 1.);
+    }
+    let a = aov.albedo;
+    Vector3D::new(a.r().max(0.01), a.g().max(0.01), a.b().max(0.01))
+}
+
+/// Like `edge_weight` but relative to the brightness of the colours, so bright and dark areas
+/// are smoothed alike
+fn color_weight(a: Vector3D, b: Vector3D, sigma: f32) -> f32 {
+    let mean = (a + b) / 2.;
+    let d = a - b;
+    (-d.squared_length() / (sigma * sigma * (mean.squared_length() + 1e-4))).exp()
+}
+
+fn edge_weight(a: Vector3D, b: Vector3D, sigma: f32) -> f32 {
+    let d = a - b;
+    (-d.squared_length() / (sigma * sigma)).exp()
+}
+
+/// How alike two pixels' AOVs are, from 1 for identical down towards 0. Normals are only
+/// compared where both pixels have one, since those seen only through a medium don't.
+fn guide_weight(a: &Aov, b: &Aov, settings: &DenoiseSettings) -> f32 {
+    if (a.coverage > 0.) != (b.coverage > 0.) {
+        return 0.;
+    }
+    let depth = (a.depth - b.depth).abs() / a.depth.max(1e-3);
+    let has_normal = |aov: &Aov| aov.normal.squared_length() > 0.;
+    let normal = match has_normal(a) && has_normal(b) {
+        true => edge_weight(a.normal, b.normal, settings.sigma_normal),
+        false => 1.,
+    };
+    normal
+        * edge_weight(a.albedo, b.albedo, settings.sigma_albedo)
+        * (-depth / settings.sigma_depth).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ray::Ray;
+
+    fn flat_film(color: impl Fn(u32, u32) -> Vector3D) -> Film {
+        let (nx, ny) = (16, 16);
+        let mut aov = Aov::empty();
+        aov.normal = Vector3D::new(0., 0., 1.);
+        aov.albedo = Vector3D::new(0.5, 0.5, 0.5);
+        aov.depth = 1.;
+        aov.coverage = 1.;
+        Film {
+            nx,
+            ny,
+            color: (0..nx * ny).map(|p| color(p % nx, p / nx)).collect(),
+            aovs: vec![aov; (nx * ny) as usize],
+        }
+    }
+
+    #[test]
+    fn test_constant_is_unchanged() {
+        let film = flat_film(|_, _| Vector3D::new(0.3, 0.2, 0.1));
+        for c in denoise(&film, &DenoiseSettings::default()).iter() {
+            assert!((*c - Vector3D::new(0.3, 0.2, 0.1)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_noise_is_reduced() {
+        // A checkerboard of small differences is noise, and should be smoothed out
+        let noisy = |x: u32, y: u32| match (x + y) % 2 {
+            0 => Vector3D::new(0.4, 0.4, 0.4),
+            _ => Vector3D::new(0.2, 0.2, 0.2),
+        };
+        let film = flat_film(noisy);
+        let out = denoise(&film, &DenoiseSettings::default());
+        let error = |c: &Vector3D| (*c - Vector3D::new(0.3, 0.3, 0.3)).length();
+        let before: f32 = film.color.iter().map(error).sum();
+        let after: f32 = out.iter().map(error).sum();
+        assert!(after < before * 0.3);
+    }
+
+    #[test]
+    fn test_normal_edges_are_kept() {
+        let mut film = flat_film(|x, _| match x < 8 {
+            true => Vector3D::new(0.1, 0.1, 0.1),
+            false => Vector3D::new(0.4, 0.4, 0.4),
+        });
+        for (p, aov) in film.aovs.iter_mut().enumerate() {
+            if p % 16 >= 8 {
+                aov.normal = Vector3D::new(1., 0., 0.);
+            }
+        }
+        let out = denoise(&film, &DenoiseSettings::default());
+        assert!((out[7] - film.color[7]).length() < 1e-3);
+        assert!((out[8] - film.color[8]).length() < 1e-3);
+    }
+
+    #[test]
+    fn test_medium_scatter_is_finite() {
+        // The left half of the image is only seen scattering in a medium
+        let mut film = flat_film(|x, y| Vector3D::new(0.1 * (x % 3) as f32, 0.2, 0.01 * y as f32));
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., -1.));
+        let scatter = Aov::from_scatter(&r, 1.5, Vector3D::new(0.9, 0.9, 0.9));
+        for (p, aov) in film.aovs.iter_mut().enumerate() {
+            if p % 16 < 8 {
+                *aov = scatter.merge(scatter).average(2);
+            }
+        }
+        let out = denoise(&film, &DenoiseSettings::default());
+        assert!(out.iter().all(|c| c.e.iter().all(|v| v.is_finite())));
+    }
+}
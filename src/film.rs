@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use crate::shapes::hitable::HitRecord;
+use crate::util::camera::Exposure;
+use crate::util::exr::write_exr;
+use crate::util::ray::Ray;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Arbitrary output variables: what the camera rays of a pixel first hit. While samples are
+/// being gathered the fields hold sums, which `average` turns into per pixel values.
+#[derive(Clone, Copy, Debug)]
+pub struct Aov {
+    pub normal: Vector3D,
+    pub position: Vector3D,
+    pub albedo: Vector3D,
+    /// Distance from the camera to the hit
+    pub depth: f32,
+    pub material_id: u32,
+    pub object_id: u32,
+    /// Fraction of samples that hit anything
+    pub coverage: f32,
+}
+
+impl Aov {
+    /// AOV of a ray that hit nothing
+    pub fn empty() -> Self {
+        Self {
+            normal: Vector3D::new(0., 0., 0.),
+            position: Vector3D::new(0., 0., 0.),
+            albedo: Vector3D::new(0., 0., 0.),
+            depth: 0.,
+            material_id: 0,
+            object_id: 0,
+            coverage: 0.,
+        }
+    }
+
+    pub fn from_hit(rec: &HitRecord, r: &Ray) -> Self {
+        Self {
+            normal: rec.normal,
+            position: rec.p,
+            albedo: rec.material.albedo_at(rec),
+            depth: rec.t * r.direction().length(),
+            material_id: rec.material.id(),
+            object_id: rec.object_id,
+            coverage: 1.,
+        }
+    }
+
+    /// AOV of a ray scattered by a medium at `t` along it. There's no surface, so no normal or
+    /// IDs, and the albedo is the medium's.
+    pub fn from_scatter(r: &Ray, t: f32, albedo: Vector3D) -> Self {
+        Self {
+            position: r.point_at_parameter(t),
+            albedo,
+            depth: t * r.direction().length(),
+            coverage: 1.,
+            ..Self::empty()
+        }
+    }
+
+    /// Adds up two samples. IDs can't be averaged, so the first one that hit anything wins.
+    pub fn merge(self, other: Self) -> Self {
+        let ids = match self.coverage > 0. {
+            true => self,
+            false => other,
+        };
+        Self {
+            normal: self.normal + other.normal,
+            position: self.position + other.position,
+            albedo: self.albedo + other.albedo,
+            depth: self.depth + other.depth,
+            material_id: ids.material_id,
+            object_id: ids.object_id,
+            coverage: self.coverage + other.coverage,
+        }
+    }
+
+    /// Turns the sums of `ns` merged samples into averages over the samples that hit. The
+    /// normal stays zero if none of them hit a surface.
+    pub fn average(self, ns: u32) -> Self {
+        if self.coverage == 0. {
+            return self;
+        }
+        Self {
+            normal: match self.normal.squared_length() > 0. {
+                true => unit_vector(self.normal),
+                false => self.normal,
+            },
+            position: self.position / self.coverage,
+            albedo: self.albedo / self.coverage,
+            depth: self.depth / self.coverage,
+            coverage: self.coverage / ns as f32,
+            ..self
+        }
+    }
+}
+
+/// A rendered image in linear colour, with rows stored from the top, along with its AOVs.
+pub struct Film {
+    pub nx: u32,
+    pub ny: u32,
+    pub color: Vec<Vector3D>,
+    pub aovs: Vec<Aov>,
+}
+
+impl Film {
+    /// Scales the colour as a photograph taken with `exposure` would be. Without one the film
+    /// is left exactly as rendered.
+    pub fn expose(&mut self, exposure: Option<Exposure>) {
+        if let Some(exposure) = exposure {
+            let scale = exposure.scale();
+            for c in self.color.iter_mut() {
+                *c *= scale;
+            }
+        }
+    }
+
+    /// Gamma corrected 8 bit RGB
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.color.len() * 3);
+        for c in self.color.iter() {
+            rgb.extend_from_slice(&to_srgb8(*c));
+        }
+        rgb
+    }
+
+    /// Writes the beauty as `R`, `G`, `B` and coverage as `A` to an EXR, with each AOV as an
+    /// extra layer.
+    pub fn write_exr(&self, path: &str) -> io::Result<()> {
+        let channel = |name: &str, f: &dyn Fn(&Aov) -> f32| {
+            (name.to_string(), self.aovs.iter().map(f).collect())
+        };
+        let mut channels = vec![];
+        for (i, name) in ["R", "G", "B"].iter().enumerate() {
+            channels.push((
+                name.to_string(),
+                self.color.iter().map(|c| c.e[i]).collect(),
+            ));
+        }
+        channels.push(channel("A", &|a| a.coverage));
+        for (i, axis) in ["X", "Y", "Z"].iter().enumerate() {
+            channels.push(channel(&format!("normal.{}", axis), &|a| a.normal.e[i]));
+            channels.push(channel(&format!("position.{}", axis), &|a| a.position.e[i]));
+        }
+        for (i, c) in ["R", "G", "B"].iter().enumerate() {
+            channels.push(channel(&format!("albedo.{}", c), &|a| a.albedo.e[i]));
+        }
+        channels.push(channel("depth.Z", &|a| a.depth));
+        channels.push(channel("materialId.V", &|a| a.material_id as f32));
+        channels.push(channel("objectId.V", &|a| a.object_id as f32));
+        let w = &mut BufWriter::new(File::create(path)?);
+        write_exr(w, self.nx, self.ny, &channels)
+    }
+
+    /// Writes each AOV to `<base>.<aov>.png`, remapped so it can be viewed
+    pub fn write_aov_pngs(&self, base: &str) -> io::Result<()> {
+        let covered = || self.aovs.iter().filter(|a| a.coverage > 0.);
+        let max_depth = covered().map(|a| a.depth).fold(0., f32::max);
+        let mut low = Vector3D::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut high = -low;
+        for a in covered() {
+            for i in 0..3 {
+                low.e[i] = low.e[i].min(a.position.e[i]);
+                high.e[i] = high.e[i].max(a.position.e[i]);
+            }
+        }
+        let write = |name: &str, f: &dyn Fn(&Aov) -> [u8; 3]| {
+            let pixels: Vec<u8> = self.aovs.iter().flat_map(|a| f(a).to_vec()).collect();
+            write_png(&format!("{}.{}.png", base, name), self.nx, self.ny, &pixels)
+        };
+        write("normal", &|a| to_8bit((a.normal + splat(1.)) * 0.5))?;
+        write("depth", &|a| to_8bit(splat(a.depth / max_depth)))?;
+        write("position", &|a| to_8bit((a.position - low) / (high - low)))?;
+        write("albedo", &|a| to_srgb8(a.albedo))?;
+        write("material_id", &|a| id_color(a.material_id, a))?;
+        write("object_id", &|a| id_color(a.object_id, a))?;
+        write("coverage", &|a| to_8bit(splat(a.coverage)))?;
+        Ok(())
+    }
+}
+
+/// Writes 8 bit RGB pixels to a PNG
+pub fn write_png(path: &str, nx: u32, ny: u32, rgb: &[u8]) -> io::Result<()> {
+    let w = &mut BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(w, nx, ny);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    Ok(())
+}
+
+/// Reads a PNG as its width, height and the colour of every pixel with channels from 0 to 1,
+/// with rows stored from the top
+pub fn read_png(path: &str) -> io::Result<(u32, u32, Vec<Vector3D>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+    let gray = matches!(
+        info.color_type,
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha
+    );
+    let stride = info.color_type.samples() * info.bit_depth as usize / 8;
+    let max = ((1u32 << info.bit_depth as u32) - 1) as f32;
+    let pixels = buf
+        .chunks(stride)
+        .take((info.width * info.height) as usize)
+        .map(|pixel| {
+            let value = |c: usize| match info.bit_depth {
+                png::BitDepth::Sixteen => u16::from_be_bytes([pixel[2 * c], pixel[2 * c + 1]]),
+                _ => pixel[c] as u16,
+            } as f32
+                / max;
+            match gray {
+                true => splat(value(0)),
+                false => Vector3D::new(value(0), value(1), value(2)),
+            }
+        })
+        .collect();
+    Ok((info.width, info.height, pixels))
+}
+
+/// Reads a PNG as its width, height and the brightness of every pixel from 0 to 1, with rows
+/// stored from the top
+pub fn read_png_luminance(path: &str) -> io::Result<(u32, u32, Vec<f32>)> {
+    let (nx, ny, pixels) = read_png(path)?;
+    let luminance = pixels
+        .iter()
+        .map(|c| (c.r() + c.g() + c.b()) / 3.)
+        .collect();
+    Ok((nx, ny, luminance))
+}
+
+fn splat(v: f32) -> Vector3D {
+    Vector3D::new(v, v, v)
+}
+
+fn to_8bit(c: Vector3D) -> [u8; 3] {
+    [
+        (255.99 * c.r()) as u8,
+        (255.99 * c.g()) as u8,
+        (255.99 * c.b()) as u8,
+    ]
+}
+
+/// Gamma 2 encoding used for the beauty image
+fn to_srgb8(c: Vector3D) -> [u8; 3] {
+    to_8bit(Vector3D::new(c.r().sqrt(), c.g().sqrt(), c.b().sqrt()))
+}
+
+/// A distinct colour for each ID so neighbouring objects stand apart
+fn id_color(id: u32, aov: &Aov) -> [u8; 3] {
+    if aov.coverage == 0. {
+        return [0, 0, 0];
+    }
+    let h = (id.wrapping_add(1)).wrapping_mul(0x9e37_79b9);
+    [(h >> 24) as u8, (h >> 16) as u8, (h >> 8) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::material::Material;
+
+    #[test]
+    fn test_merge_and_average() {
+        let mut rec = HitRecord::new(Material::Lambertian {
+            albedo: Vector3D::new(0.5, 0.5, 0.5),
+        });
+        rec.t = 2.;
+        rec.normal = Vector3D::new(0., 1., 0.);
+        rec.object_id = 7;
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., 2.));
+        let hit = Aov::from_hit(&rec, &r);
+        assert_eq!(hit.depth, 4.);
+        let aov = Aov::empty().merge(hit).merge(hit).merge(Aov::empty());
+        let aov = aov.average(4);
+        assert_eq!(aov.coverage, 0.5);
+        assert_eq!(aov.depth, 4.);
+        assert_eq!(aov.normal, Vector3D::new(0., 1., 0.));
+        assert_eq!(aov.albedo, Vector3D::new(0.5, 0.5, 0.5));
+        assert_eq!(aov.object_id, 7);
+        // Scattered in a medium halfway along
+        let scatter = Aov::from_scatter(&r, 1., Vector3D::new(0.9, 0.9, 0.9));
+        assert_eq!(scatter.position, Vector3D::new(0., 0., 2.));
+        assert_eq!(scatter.depth, 2.);
+        assert_eq!(scatter.coverage, 1.);
+        assert_eq!(scatter.normal, Vector3D::new(0., 0., 0.));
+        let aov = scatter.merge(scatter).average(2);
+        assert_eq!(aov.normal, Vector3D::new(0., 0., 0.));
+        assert_eq!(aov.depth, 2.);
+    }
+
+    #[test]
+    fn test_expose() {
+        let color = vec![Vector3D::new(0.3, 2., 1e-3), Vector3D::new(0.7, 0.1, 5.)];
+        let mut film = Film {
+            nx: 2,
+            ny: 1,
+            color: color.clone(),
+            aovs: vec![Aov::empty(); 2],
+        };
+        // A default render isn't exposed, even with a lens or f-stop chosen
+        for args in ["64 48", "64 48 --f-stop 2.8 --blades 6", "64 48 --focal-length 35"].iter() {
+            let args: Vec<String> = args.split(' ').map(String::from).collect();
+            let settings = crate::settings::RenderSettings::from_args(&args).unwrap();
+            film.expose(settings.exposure());
+            assert_eq!(film.color, color);
+        }
+        // Sunny 16 brings a sunlit scene of 10000 cd/m² to about a third of saturation
+        film.expose(Some(Exposure {
+            shutter: 0.01,
+            iso: 100.,
+            f_number: 16.,
+        }));
+        assert!((film.color[0].x() / 0.3 * 10000. - 0.33).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let path = std::env::temp_dir().join("raytrace_test_png_round_trip.png");
+        let path = path.to_str().unwrap();
+        write_png(path, 2, 1, &[255, 255, 255, 0, 0, 255]).unwrap();
+        let (nx, ny, luminance) = read_png_luminance(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((nx, ny), (2, 1));
+        assert_eq!(luminance[0], 1.);
+        assert!((luminance[1] - 1. / 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_empty_average() {
+        let aov = Aov::empty().merge(Aov::empty()).average(2);
+        assert_eq!(aov.coverage, 0.);
+        assert_eq!(aov.depth, 0.);
+    }
+}
@@ -0,0 +1,261 @@
+use std::f32::consts::PI;
+
+use crate::util::vector3d::Vector3D;
+
+/// Pixel reconstruction filters. Each is separable, so its weight at an offset `(x, y)` in
+/// pixels is `eval(x) * eval(y)`, and zero once either offset passes `radius`. The support is
+/// half-open, so a sample right on the edge of a box filter counts in only one pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    /// Gaussian with falloff `alpha`, shifted down so it reaches zero at the radius
+    Gaussian { radius: f32, alpha: f32 },
+    /// Mitchell-Netravali cubic. `b` and `c` trade blurring against ringing.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    BlackmanHarris { radius: f32 },
+}
+
+impl Filter {
+    /// Builds a filter by name, with its usual parameters and default radius unless one is given
+    pub fn from_name(name: &str, radius: Option<f32>) -> Result<Self, String> {
+        let filter = match name {
+            "box" => Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            },
+            "tent" => Filter::Tent {
+                radius: radius.unwrap_or(1.),
+            },
+            "gaussian" => Filter::Gaussian {
+                radius: radius.unwrap_or(1.5),
+                alpha: 2.,
+            },
+            "mitchell" => Filter::Mitchell {
+                radius: radius.unwrap_or(2.),
+                b: 1. / 3.,
+                c: 1. / 3.,
+            },
+            "blackman-harris" => Filter::BlackmanHarris {
+                radius: radius.unwrap_or(2.),
+            },
+            _ => return Err(format!("Unknown filter {}", name)),
+        };
+        match filter.radius() > 0. {
+            true => Ok(filter),
+            false => Err("Filter radius must be positive".to_string()),
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::BlackmanHarris { radius } => *radius,
+        }
+    }
+
+    /// Weight of a sample `x` pixels away along one axis, measured from the sample to the pixel
+    /// centre. The support is `(-radius, radius]`, so a sample on the border between two
+    /// pixels only counts in the one whose `[i, i + 1)` holds it.
+    pub fn eval(&self, x: f32) -> f32 {
+        let r = self.radius();
+        if x <= -r || x > r {
+            return 0.;
+        }
+        let x = x.abs();
+        match self {
+            Filter::Box { .. } => 1.,
+            Filter::Tent { .. } => r - x,
+            Filter::Gaussian { alpha, .. } => (-alpha * x * x).exp() - (-alpha * r * r).exp(),
+            Filter::Mitchell { b, c, .. } => {
+                let t = 2. * x / r;
+                match t > 1. {
+                    true => {
+                        ((-b - 6. * c) * t * t * t
+                            + (6. * b + 30. * c) * t * t
+                            + (-12. * b - 48. * c) * t
+                            + (8. * b + 24. * c))
+                            / 6.
+                    }
+                    false => {
+                        ((12. - 9. * b - 6. * c) * t * t * t
+                            + (-18. + 12. * b + 6. * c) * t * t
+                            + (6. - 2. * b))
+                            / 6.
+                    }
+                }
+            }
+            Filter::BlackmanHarris { .. } => {
+                let t = 2. * PI * (x + r) / (2. * r);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2. * t).cos() - 0.01168 * (3. * t).cos()
+            }
+        }
+    }
+
+    /// How many pixels beyond its own a sample can reach
+    pub fn reach(&self) -> i32 {
+        (self.radius() - 0.5).ceil().max(0.) as i32
+    }
+}
+
+/// A colour sample taken at position `(x, y)` on the film
+#[derive(Clone, Copy, Debug)]
+pub struct FilmSample {
+    pub x: f32,
+    pub y: f32,
+    pub color: Vector3D,
+}
+
+/// Weighted sums of the samples splatted onto a band of rows of the image. Pixel `(i, j)`
+/// covers `[i, i + 1) x [j, j + 1)` in film space, with rows counted from the bottom.
+pub struct SplatBuffer {
+    pub nx: u32,
+    /// First row of the band, which may lie outside the image
+    pub y0: i32,
+    pub rows: u32,
+    pub sums: Vec<Vector3D>,
+    pub weights: Vec<f32>,
+}
+
+impl SplatBuffer {
+    pub fn new(nx: u32, y0: i32, rows: u32) -> Self {
+        let size = (nx * rows) as usize;
+        Self {
+            nx,
+            y0,
+            rows,
+            sums: vec![Vector3D::new(0., 0., 0.); size],
+            weights: vec![0.; size],
+        }
+    }
+
+    /// Adds a sample at film position `(x, y)` to every pixel whose centre is within reach of
+    /// the filter.
+    pub fn add(&mut self, filter: &Filter, x: f32, y: f32, color: Vector3D) {
+        let r = filter.radius();
+        let x_start = ((x - r - 0.5).ceil() as i32).max(0);
+        let x_end = ((x + r - 0.5).floor() as i32).min(self.nx as i32 - 1);
+        let y_start = ((y - r - 0.5).ceil() as i32).max(self.y0);
+        let y_end = ((y + r - 0.5).floor() as i32).min(self.y0 + self.rows as i32 - 1);
+        for py in y_start..=y_end {
+            let wy = filter.eval(py as f32 + 0.5 - y);
+            for px in x_start..=x_end {
+                let w = wy * filter.eval(px as f32 + 0.5 - x);
+                let i = ((py - self.y0) as u32 * self.nx + px as u32) as usize;
+                self.sums[i] += color * w;
+                self.weights[i] += w;
+            }
+        }
+    }
+
+    /// Adds the band `other` into this buffer, ignoring rows this one doesn't cover
+    pub fn merge(&mut self, other: &SplatBuffer) {
+        for row in 0..other.rows as i32 {
+            let y = other.y0 + row;
+            if y < self.y0 || y >= self.y0 + self.rows as i32 {
+                continue;
+            }
+            let dst = ((y - self.y0) as u32 * self.nx) as usize;
+            let src = (row as u32 * other.nx) as usize;
+            for x in 0..self.nx as usize {
+                self.sums[dst + x] += other.sums[src + x];
+                self.weights[dst + x] += other.weights[src + x];
+            }
+        }
+    }
+
+    /// Normalised colour of every pixel, with rows ordered from the top
+    pub fn resolve(&self) -> Vec<Vector3D> {
+        let mut out = Vec::with_capacity(self.sums.len());
+        for row in (0..self.rows as usize).rev() {
+            for x in 0..self.nx as usize {
+                let i = row * self.nx as usize + x;
+                out.push(match self.weights[i].abs() > 1e-8 {
+                    true => self.sums[i] / self.weights[i],
+                    false => Vector3D::new(0., 0., 0.),
+                });
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_and_tent() {
+        let f = Filter::from_name("box", None).unwrap();
+        assert_eq!(f.eval(0.4), 1.);
+        assert_eq!(f.eval(-0.6), 0.);
+        assert_eq!(f.reach(), 0);
+        let f = Filter::from_name("tent", Some(2.)).unwrap();
+        assert_eq!(f.eval(0.), 2.);
+        assert_eq!(f.eval(1.5), 0.5);
+        assert_eq!(f.reach(), 2);
+    }
+
+    #[test]
+    fn test_smooth_filters_fall_to_zero() {
+        for name in ["gaussian", "mitchell", "blackman-harris"].iter() {
+            let f = Filter::from_name(name, None).unwrap();
+            assert!(f.eval(0.) > f.eval(0.5 * f.radius()));
+            assert!(f.eval(f.radius() * 0.999).abs() < 1e-3);
+            assert_eq!(f.eval(f.radius() + 0.1), 0.);
+        }
+    }
+
+    #[test]
+    fn test_mitchell_negative_lobe() {
+        let f = Filter::from_name("mitchell", None).unwrap();
+        assert!((f.eval(0.) - 8. / 9.).abs() < 1e-5);
+        assert!(f.eval(1.5) < 0.);
+    }
+
+    #[test]
+    fn test_bad_filters() {
+        assert!(Filter::from_name("lanczos", None).is_err());
+        assert!(Filter::from_name("tent", Some(0.)).is_err());
+    }
+
+    #[test]
+    fn test_splat() {
+        let mut buffer = SplatBuffer::new(3, 0, 2);
+        let box_filter = Filter::from_name("box", None).unwrap();
+        buffer.add(&box_filter, 1.5, 0.5, Vector3D::new(1., 1., 1.));
+        assert_eq!(buffer.weights, vec![0., 1., 0., 0., 0., 0.]);
+        let tent = Filter::from_name("tent", None).unwrap();
+        buffer.add(&tent, 1.5, 0.5, Vector3D::new(1., 1., 1.));
+        assert_eq!(buffer.weights, vec![0., 2., 0., 0., 0., 0.]);
+        // Halfway between pixel centres the tent reaches both neighbours
+        buffer.add(&tent, 1., 1., Vector3D::new(2., 2., 2.));
+        assert_eq!(buffer.weights, vec![0.25, 2.25, 0., 0.25, 0.25, 0.]);
+        let resolved = buffer.resolve();
+        // Top row first
+        assert_eq!(resolved[0], Vector3D::new(2., 2., 2.));
+        assert_eq!(resolved[3], Vector3D::new(2., 2., 2.));
+        assert_eq!(resolved[5], Vector3D::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_box_boundary() {
+        // On the border between the first two pixels the sample belongs to the second only
+        let mut buffer = SplatBuffer::new(3, 0, 1);
+        let box_filter = Filter::from_name("box", None).unwrap();
+        buffer.add(&box_filter, 1., 0.5, Vector3D::new(1., 1., 1.));
+        assert_eq!(buffer.weights, vec![0., 1., 0.]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut image = SplatBuffer::new(2, 0, 2);
+        let mut band = SplatBuffer::new(2, -1, 3);
+        let tent = Filter::from_name("tent", None).unwrap();
+        band.add(&tent, 0.75, 0.75, Vector3D::new(1., 1., 1.));
+        image.merge(&band);
+        assert_eq!(image.weights, vec![0.5625, 0.1875, 0.1875, 0.0625]);
+    }
+}
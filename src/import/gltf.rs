@@ -0,0 +1,547 @@
+//! glTF 2.0 scenes, as `.gltf` with separate or embedded buffers or as binary `.glb`.
+//!
+//! Meshes are flattened into world space through the node hierarchy. Metallic-roughness
+//! materials become `Material::Pbr`, and transmissive ones glass. The first camera found gives
+//! the view, and `KHR_lights_punctual` lights become punctual lights with their intensity used
+//! as it is.
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::{ImportOptions, ImportedScene};
+use crate::animation::CameraPose;
+use crate::lights::light::Light;
+use crate::shapes::bvh::Aabb;
+use crate::shapes::hitable::Hitable;
+use crate::shapes::mesh::Mesh;
+use crate::shapes::subdivision::Cage;
+use crate::util::material::{Absorption, Material};
+use crate::util::spectrum::Ior;
+use crate::util::texture::{Texture, TextureMapping};
+use crate::util::transform::Transform;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Extensions that change how the scene is imported
+const SUPPORTED_EXTENSIONS: [&str; 4] = [
+    "KHR_lights_punctual",
+    "KHR_materials_transmission",
+    "KHR_materials_ior",
+    "KHR_materials_volume",
+];
+
+pub fn load(path: &str, options: &ImportOptions) -> Result<ImportedScene, String> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut importer = Importer {
+        buffers: &buffers,
+        images: &images,
+        options,
+        textures: HashMap::new(),
+        objects: vec![],
+        lights: vec![],
+        camera: None,
+        bounds: Aabb::empty(),
+        warnings: BTreeSet::new(),
+    };
+    for extension in document.extensions_used() {
+        if !SUPPORTED_EXTENSIONS.contains(&extension) {
+            importer.warnings.insert(format!(
+                "extension {} isn't supported and is ignored",
+                extension
+            ));
+        }
+    }
+    if document.animations().next().is_some() {
+        importer
+            .warnings
+            .insert("animations are ignored".to_string());
+    }
+    if document.skins().next().is_some() {
+        importer
+            .warnings
+            .insert("skins are ignored, meshes keep their bind pose".to_string());
+    }
+    let materials: Vec<Material> = document
+        .materials()
+        .map(|m| importer.material(&m))
+        .collect();
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{} has no scene", path))?;
+    for node in scene.nodes() {
+        importer.node(&node, Transform::identity(), &materials);
+    }
+
+    let center = importer.bounds.centroid();
+    let camera = importer.camera.map(|pose| CameraPose {
+        focus_distance: (center - pose.lookfrom).length(),
+        ..pose
+    });
+    Ok(ImportedScene {
+        objects: importer.objects,
+        lights: importer.lights,
+        camera,
+        warnings: importer.warnings.into_iter().collect(),
+    })
+}
+
+/// State gathered while walking the scene
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    options: &'a ImportOptions,
+    /// Textures already made, by image, whether it holds sRGB colours and the factor it was
+    /// multiplied by
+    textures: HashMap<(usize, bool, [u32; 3]), Texture>,
+    objects: Vec<Box<dyn Hitable>>,
+    lights: Vec<Light>,
+    camera: Option<CameraPose>,
+    bounds: Aabb,
+    warnings: BTreeSet<String>,
+}
+
+impl<'a> Importer<'a> {
+    /// Adds `node` and its descendants, placed by `parent` and their own transforms
+    fn node(&mut self, node: &gltf::Node, parent: Transform, materials: &[Material]) {
+        let transform = match Transform::from_matrix(rows(node.transform().matrix())) {
+            Some(local) => parent * local,
+            None => {
+                // Scaled to nothing, so neither it nor its children can be seen
+                return;
+            }
+        };
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let material = match primitive.material().index() {
+                    Some(i) => materials[i].clone(),
+                    None => default_material(),
+                };
+                if let Some(mesh) = self.primitive(&primitive, &transform, material) {
+                    self.bounds = self.bounds.union(&mesh.bounds());
+                    self.objects.push(Box::new(mesh));
+                }
+            }
+        }
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, &transform);
+        }
+        if let Some(light) = node.light() {
+            self.light(&light, &transform);
+        }
+        for child in node.children() {
+            self.node(&child, transform, materials);
+        }
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &gltf::Primitive,
+        transform: &Transform,
+        material: Material,
+    ) -> Option<Mesh> {
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let positions: Vec<Vector3D> = reader
+            .read_positions()?
+            .map(|p| transform.point(Vector3D::new(p[0], p[1], p[2])))
+            .collect();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let mut triangles: Vec<[u32; 3]> = match primitive.mode() {
+            gltf::mesh::Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            gltf::mesh::Mode::TriangleStrip => (2..indices.len())
+                .map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            gltf::mesh::Mode::TriangleFan => (2..indices.len())
+                .map(|i| [indices[i - 1], indices[i], indices[0]])
+                .collect(),
+            mode => {
+                self.warnings
+                    .insert(format!("{:?} primitives can't be rendered", mode));
+                return None;
+            }
+        };
+        // A mirroring transform turns the triangles inside out
+        let (x, y, z) = (
+            transform.vector(Vector3D::new(1., 0., 0.)),
+            transform.vector(Vector3D::new(0., 1., 0.)),
+            transform.vector(Vector3D::new(0., 0., 1.)),
+        );
+        if x.cross(y).dot(z) < 0. {
+            for t in triangles.iter_mut() {
+                t.swap(1, 2);
+            }
+        }
+
+        let faces = triangles.iter().map(|t| t.to_vec()).collect();
+        let mut cage = Cage::new(positions, faces);
+        if let Some(normals) = reader.read_normals() {
+            cage.normals = normals
+                .map(|n| transform.normal(Vector3D::new(n[0], n[1], n[2])))
+                .collect();
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            // glTF counts v down from the top of the image, textures count up from the bottom
+            cage.uvs = uvs.into_f32().map(|[u, v]| (u, 1. - v)).collect();
+        }
+        Some(self.options.mesh(cage, material, &mut self.warnings))
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Material {
+        let name = material.name().unwrap_or("unnamed");
+        if material.emissive_factor().iter().any(|e| *e > 0.) {
+            self.warnings
+                .insert(format!("emission of material {} isn't supported", name));
+        }
+        if material.normal_texture().is_some() {
+            self.warnings
+                .insert(format!("normal map of material {} is ignored", name));
+        }
+        if let Some(transmission) = material.transmission() {
+            if transmission.transmission_factor() > 0.5 {
+                let absorption = material.volume().and_then(|v| {
+                    let [r, g, b] = v.attenuation_color();
+                    let distance = v.attenuation_distance();
+                    if !distance.is_finite() {
+                        return None;
+                    }
+                    match Absorption::new(Vector3D::new(r, g, b), distance) {
+                        Ok(absorption) => Some(absorption),
+                        Err(e) => {
+                            self.warnings
+                                .insert(format!("{} in material {}, ignoring it", e, name));
+                            None
+                        }
+                    }
+                });
+                return Material::Dielectric {
+                    ref_ind: Ior::Constant(material.ior().unwrap_or(1.5)),
+                    absorption,
+                };
+            }
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let factor = Vector3D::new(r, g, b);
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => self.texture(&info, true, factor, name),
+            None => Texture::Constant(factor),
+        };
+        let metallic_roughness = pbr
+            .metallic_roughness_texture()
+            .map(|info| self.texture(&info, false, Vector3D::new(1., 1., 1.), name));
+        Material::Pbr {
+            base_color,
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            metallic_roughness,
+        }
+    }
+
+    /// Texture of the image referred to by `info`, with its colours multiplied by `factor`.
+    /// Colour images are stored in sRGB and decoded with gamma 2 like other textures.
+    fn texture(
+        &mut self,
+        info: &gltf::texture::Info,
+        srgb: bool,
+        factor: Vector3D,
+        material: &str,
+    ) -> Texture {
+        if info.tex_coord() != 0 {
+            self.warnings.insert(format!(
+                "material {} uses a second set of texture coordinates, the first is used instead",
+                material
+            ));
+        }
+        let index = info.texture().source().index();
+        let key = (
+            index,
+            srgb,
+            [factor.x(), factor.y(), factor.z()].map(f32::to_bits),
+        );
+        let filter = self.options.texture_filter;
+        let images = self.images;
+        self.textures
+            .entry(key)
+            .or_insert_with(|| {
+                let image = &images[index];
+                let pixels = image_colors(image)
+                    .into_iter()
+                    .map(|c| match srgb {
+                        true => c * c * factor,
+                        false => c * factor,
+                    })
+                    .collect();
+                Texture::from_image(
+                    image.width as usize,
+                    image.height as usize,
+                    pixels,
+                    TextureMapping::Uv { scale: (1., 1.) },
+                    filter,
+                )
+            })
+            .clone()
+    }
+
+    fn camera(&mut self, camera: &gltf::Camera, transform: &Transform) {
+        let vfov = match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => p.yfov().to_degrees(),
+            gltf::camera::Projection::Orthographic(_) => {
+                self.warnings
+                    .insert("orthographic cameras aren't supported".to_string());
+                return;
+            }
+        };
+        if self.camera.is_some() {
+            self.warnings
+                .insert("only the first camera is used".to_string());
+            return;
+        }
+        // Cameras look down their -z axis with y up
+        let lookfrom = transform.point(Vector3D::new(0., 0., 0.));
+        let forward = unit_vector(transform.vector(Vector3D::new(0., 0., -1.)));
+        self.camera = Some(CameraPose {
+            lookfrom,
+            lookat: lookfrom + forward,
+            vup: transform.vector(Vector3D::new(0., 1., 0.)),
+            vfov,
+            aperture: 0.,
+            focus_distance: 1.,
+        });
+    }
+
+    fn light(&mut self, light: &gltf::khr_lights_punctual::Light, transform: &Transform) {
+        use gltf::khr_lights_punctual::Kind;
+
+        let [r, g, b] = light.color();
+        let intensity = Vector3D::new(r, g, b) * light.intensity();
+        // Lights shine down their -z axis
+        let position = transform.point(Vector3D::new(0., 0., 0.));
+        let direction = unit_vector(transform.vector(Vector3D::new(0., 0., -1.)));
+        self.lights.push(match light.kind() {
+            Kind::Point => Light::Point {
+                position,
+                intensity,
+            },
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Light::Spot {
+                position,
+                direction,
+                intensity,
+                total_width: outer_cone_angle.to_degrees(),
+                falloff_start: inner_cone_angle.to_degrees(),
+            },
+            Kind::Directional => Light::Directional {
+                direction,
+                radiance: intensity,
+                angular_radius: 0.,
+            },
+        });
+    }
+}
+
+/// The material glTF gives primitives that don't name one
+fn default_material() -> Material {
+    Material::Pbr {
+        base_color: Texture::Constant(Vector3D::new(1., 1., 1.)),
+        metallic: 1.,
+        roughness: 1.,
+        metallic_roughness: None,
+    }
+}
+
+/// Rows of the affine part of a glTF matrix, which is stored column by column
+fn rows(m: [[f32; 4]; 4]) -> [[f32; 4]; 3] {
+    std::array::from_fn(|i| [m[0][i], m[1][i], m[2][i], m[3][i]])
+}
+
+/// Colours of an image from 0 to 1, row by row from the top. Grey images are spread over all
+/// three channels and alpha is dropped.
+fn image_colors(image: &gltf::image::Data) -> Vec<Vector3D> {
+    use gltf::image::Format;
+
+    let (channels, bytes) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let value = |b: &[u8]| match bytes {
+        1 => b[0] as f32 / 255.,
+        2 => u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.,
+        _ => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+    };
+    image
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|p| match channels {
+            1 | 2 => {
+                let v = value(p);
+                Vector3D::new(v, v, v)
+            }
+            _ => Vector3D::new(
+                value(&p[..bytes]),
+                value(&p[bytes..]),
+                value(&p[2 * bytes..]),
+            ),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::hitable::HitRecord;
+    use crate::util::ray::Ray;
+    use crate::util::texture::TextureFilter;
+    use std::env;
+    use std::fs;
+
+    /// Base64 of a buffer holding a triangle's positions, three floats per vertex, followed by
+    /// its indices as unsigned shorts
+    fn triangle_buffer() -> String {
+        let mut bytes = vec![];
+        for v in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.].iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        for i in [0u16, 1, 2].iter() {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        base64(&bytes)
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        let table = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+            for i in 0..4 {
+                match i <= chunk.len() {
+                    true => out.push(table[(n >> (18 - 6 * i) & 63) as usize] as char),
+                    false => out.push('='),
+                }
+            }
+        }
+        out
+    }
+
+    fn write_scene(name: &str) -> String {
+        let json = format!(
+            r#"{{
+  "asset": {{"version": "2.0"}},
+  "extensionsUsed": ["KHR_lights_punctual", "KHR_materials_clearcoat"],
+  "extensions": {{"KHR_lights_punctual": {{"lights": [
+    {{"type": "spot", "color": [1, 0.5, 0], "intensity": 10,
+      "spot": {{"innerConeAngle": 0.1, "outerConeAngle": 0.5}}}}
+  ]}}}},
+  "scene": 0,
+  "scenes": [{{"nodes": [0, 2, 3]}}],
+  "nodes": [
+    {{"translation": [0, 0, -5], "children": [1]}},
+    {{"mesh": 0, "scale": [2, 2, 2]}},
+    {{"camera": 0, "translation": [0, 0, 1]}},
+    {{"translation": [0, 3, 0], "rotation": [-0.7071068, 0, 0, 0.7071068],
+      "extensions": {{"KHR_lights_punctual": {{"light": 0}}}}}}
+  ],
+  "cameras": [{{"type": "perspective", "perspective": {{"yfov": 0.5, "znear": 0.1}}}}],
+  "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}}],
+  "materials": [{{"name": "gold", "emissiveFactor": [1, 0, 0],
+    "pbrMetallicRoughness": {{"baseColorFactor": [1, 0.8, 0.3, 1], "metallicFactor": 1, "roughnessFactor": 0.2}}}}],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+      "min": [0, 0, 0], "max": [1, 1, 0]}},
+    {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+  ],
+  "bufferViews": [
+    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+  ],
+  "buffers": [{{"byteLength": 42, "uri": "data:application/octet-stream;base64,{}"}}]
+}}"#,
+            triangle_buffer()
+        );
+        let path = env::temp_dir().join(name);
+        fs::write(&path, json).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_import() {
+        let path = write_scene("raytrace_test_import.gltf");
+        let scene = super::super::load(&path, &ImportOptions::new(TextureFilter::Point)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // The triangle is scaled by its node and moved by the parent
+        assert_eq!(scene.objects.len(), 1);
+        let mut rec = HitRecord::new(default_material());
+        let r = Ray::new(Vector3D::new(0.5, 0.5, 0.), Vector3D::new(0., 0., -1.));
+        assert!(scene.objects[0].hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 5.).abs() < 1e-5);
+        let r = Ray::new(Vector3D::new(1.5, 0.3, 0.), Vector3D::new(0., 0., -1.));
+        assert!(scene.objects[0].hit(&r, 0.001, f32::MAX, &mut rec));
+        match &rec.material {
+            Material::Pbr {
+                metallic,
+                roughness,
+                ..
+            } => assert_eq!((*metallic, *roughness), (1., 0.2)),
+            _ => panic!("expected a metallic-roughness material"),
+        }
+
+        let camera = scene.camera.unwrap();
+        assert_eq!(camera.lookfrom, Vector3D::new(0., 0., 1.));
+        assert_eq!(camera.lookat, Vector3D::new(0., 0., 0.));
+        assert!((camera.vfov - 0.5f32.to_degrees()).abs() < 1e-4);
+
+        // Turned to shine straight down
+        match &scene.lights[..] {
+            [Light::Spot {
+                position,
+                direction,
+                intensity,
+                total_width,
+                ..
+            }] => {
+                assert_eq!(*position, Vector3D::new(0., 3., 0.));
+                assert!((*direction - Vector3D::new(0., -1., 0.)).length() < 1e-5);
+                assert_eq!(*intensity, Vector3D::new(10., 5., 0.));
+                assert!((total_width - 0.5f32.to_degrees()).abs() < 1e-4);
+            }
+            _ => panic!("expected one spot light"),
+        }
+
+        assert!(scene
+            .warnings
+            .iter()
+            .any(|w| w.contains("KHR_materials_clearcoat")));
+        assert!(scene.warnings.iter().any(|w| w.contains("emission")));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        assert!(
+            super::super::load("missing.glb", &ImportOptions::new(TextureFilter::Point)).is_err()
+        );
+    }
+}
@@ -0,0 +1,123 @@
+pub mod gltf;
+pub mod ply;
+pub mod preset;
+pub mod stl;
+pub mod terrain;
+
+use std::path::Path;
+
+use crate::animation::CameraPose;
+use crate::lights::light::Light;
+use crate::shapes::bvh::Aabb;
+use crate::shapes::hitable::{HitRecord, Hitable};
+use crate::shapes::mesh::Mesh;
+use crate::shapes::subdivision::{Cage, Scheme};
+use crate::util::material::Material;
+use crate::util::texture::{Texture, TextureFilter};
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// What was brought in from a scene file. Anything the renderer can't represent is left out and
+/// described in `warnings`.
+pub struct ImportedScene {
+    pub objects: Vec<Box<dyn Hitable>>,
+    pub lights: Vec<Light>,
+    /// Pose of the first camera in the file, if it has one
+    pub camera: Option<CameraPose>,
+    pub warnings: Vec<String>,
+}
+
+/// How imported meshes are prepared for rendering
+#[derive(Clone)]
+pub struct ImportOptions {
+    /// Filter for the image textures of materials
+    pub texture_filter: TextureFilter,
+    /// Scheme and number of levels to subdivide meshes by
+    pub subdivision: Option<(Scheme, u32)>,
+    /// Texture whose brightness at each vertex moves the surface out along its normal, and
+    /// the distance full brightness moves it
+    pub displacement: Option<(Texture, f32)>,
+}
+
+impl ImportOptions {
+    pub fn new(texture_filter: TextureFilter) -> Self {
+        Self {
+            texture_filter,
+            subdivision: None,
+            displacement: None,
+        }
+    }
+
+    /// Mesh of `cage` after any subdivision and displacement, noting in `warnings` anything
+    /// that couldn't be done
+    fn mesh(&self, cage: Cage, material: Material, warnings: &mut impl Extend<String>) -> Mesh {
+        let mut cage = match self.subdivision {
+            // Vertices split along seams are joined so the surface stays closed
+            Some((scheme, levels)) => cage.weld().subdivide(scheme, levels),
+            None => cage,
+        };
+        if let Some((texture, scale)) = &self.displacement {
+            if cage.uvs.is_empty() {
+                warnings.extend(Some(
+                    "meshes without texture coordinates can't be displaced".to_string(),
+                ));
+            }
+            cage.displace(|uv| {
+                let mut rec = HitRecord::new(Material::DummyMat {
+                    albedo: Vector3D::new(0., 0., 0.),
+                });
+                rec.uv = uv;
+                let v = texture.value(&rec);
+                (v.x() + v.y() + v.z()) / 3. * scale
+            });
+        }
+        cage.into_mesh(material)
+    }
+}
+
+/// Reads a scene, picking the format from the file's extension, or else the built-in scene of
+/// that name
+pub fn load(path: &str, options: &ImportOptions) -> Result<ImportedScene, String> {
+    if let Some(scene) = preset::load(path) {
+        return Ok(scene);
+    }
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => self::gltf::load(path, options),
+        Some("ply") => ply::load(path, options),
+        Some("stl") => stl::load(path, options),
+        Some("png") => terrain::load(path, options),
+        _ => Err(format!("Don't know how to read scene {}", path)),
+    }
+}
+
+/// A lone model as a scene, with the camera looking at it from the front, a little above and
+/// to the right, far enough back to fit it in view
+fn model(mesh: Mesh, warnings: Vec<String>) -> ImportedScene {
+    let bounds = mesh.bounds();
+    framed(Box::new(mesh), bounds, warnings)
+}
+
+/// A lone object within `bounds` as a scene, with the camera placed as for `model`
+fn framed(object: Box<dyn Hitable>, bounds: Aabb, warnings: Vec<String>) -> ImportedScene {
+    let center = bounds.centroid();
+    let radius = (bounds.max - bounds.min).length() / 2.;
+    let vfov: f32 = 30.;
+    let distance = radius / (vfov / 2.).to_radians().sin();
+    let lookfrom = center + unit_vector(Vector3D::new(0.5, 0.4, 1.)) * distance;
+    ImportedScene {
+        objects: vec![object],
+        lights: vec![],
+        camera: Some(CameraPose {
+            lookfrom,
+            lookat: center,
+            vup: Vector3D::new(0., 1., 0.),
+            vfov,
+            aperture: 0.,
+            focus_distance: distance,
+        }),
+        warnings,
+    }
+}
@@ -0,0 +1,427 @@
+//! Stanford PLY meshes in ASCII or binary of either byte order.
+//!
+//! Vertices may carry normals (`nx ny nz`), colours (`red green blue`) and texture coordinates
+//! (`u v`, `s t` or `texture_u texture_v`). Faces may be any polygons, which are kept as they are
+//! for subdivision. A
+//! `comment TextureFile <png>` in the header, as MeshLab writes, textures the mesh by its
+//! coordinates.
+
+use std::fs;
+use std::path::Path;
+
+use super::{model, ImportOptions, ImportedScene};
+use crate::shapes::subdivision::Cage;
+use crate::util::material::Material;
+use crate::util::texture::{Texture, TextureMapping};
+use crate::util::vector3d::Vector3D;
+
+pub fn load(path: &str, options: &ImportOptions) -> Result<ImportedScene, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let ply = parse(&bytes).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut warnings = vec![];
+    let mut material = Material::Lambertian {
+        albedo: Vector3D::new(0.5, 0.5, 0.5),
+    };
+    if let (Some(file), false) = (&ply.texture_file, ply.uvs.is_empty()) {
+        let texture_path = Path::new(path).with_file_name(file);
+        let texture_path = texture_path.to_string_lossy();
+        let mapping = TextureMapping::Uv { scale: (1., 1.) };
+        match Texture::from_png(&texture_path, mapping, options.texture_filter) {
+            Ok(texture) => material = Material::Textured { texture },
+            Err(e) => warnings.push(format!("can't read texture {}: {}", texture_path, e)),
+        }
+    }
+    let cage = Cage {
+        normals: ply.normals,
+        uvs: ply.uvs,
+        colors: ply.colors,
+        ..Cage::new(ply.positions, ply.faces)
+    };
+    let mesh = options.mesh(cage, material, &mut warnings);
+    Ok(model(mesh, warnings))
+}
+
+/// The parts of a PLY file the renderer uses. The optional vertex attributes are empty when
+/// the file doesn't have them.
+struct Ply {
+    positions: Vec<Vector3D>,
+    normals: Vec<Vector3D>,
+    /// Linear colours, decoded from the file's with gamma 2
+    colors: Vec<Vector3D>,
+    uvs: Vec<(f32, f32)>,
+    faces: Vec<Vec<u32>>,
+    texture_file: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(format!("unknown property type {}", name)),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Largest value of an integer type, which colours stored in it are scaled by
+    fn max(&self) -> f64 {
+        match self {
+            Scalar::I8 => 127.,
+            Scalar::U8 => 255.,
+            Scalar::I16 => 32767.,
+            Scalar::U16 => 65535.,
+            Scalar::I32 => 2147483647.,
+            Scalar::U32 => 4294967295.,
+            Scalar::F32 | Scalar::F64 => 1.,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(Scalar, String),
+    /// A count of the given type followed by that many items
+    List(Scalar, Scalar, String),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(_, name) | Property::List(_, _, name) => name,
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads values one at a time from the body of the file
+struct Body<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    format: Format,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let rest = &self.bytes[self.position..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or("file ends early")?;
+            let length = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.position += start + length;
+            let token = String::from_utf8_lossy(&rest[start..start + length]);
+            return token
+                .parse::<f64>()
+                .map_err(|_| format!("{} isn't a number", token));
+        }
+        let size = scalar.size();
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + size)
+            .ok_or("file ends early")?;
+        self.position += size;
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            b[..size].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<Ply, String> {
+    let (format, elements, texture_file, body_start) = parse_header(bytes)?;
+    let mut body = Body {
+        bytes,
+        position: body_start,
+        format,
+    };
+    let mut ply = Ply {
+        positions: vec![],
+        normals: vec![],
+        colors: vec![],
+        uvs: vec![],
+        faces: vec![],
+        texture_file,
+    };
+    for element in elements.iter() {
+        // Where each vertex attribute is found among the element's properties
+        let find = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| element.properties.iter().position(|p| p.name() == *n))
+                .collect::<Option<Vec<usize>>>()
+        };
+        let position = find(&["x", "y", "z"]);
+        let normal = find(&["nx", "ny", "nz"]);
+        let color = find(&["red", "green", "blue"]);
+        let uv = find(&["u", "v"])
+            .or_else(|| find(&["s", "t"]))
+            .or_else(|| find(&["texture_u", "texture_v"]))
+            .or_else(|| find(&["texture_s", "texture_t"]));
+        let indices = find(&["vertex_indices"])
+            .or_else(|| find(&["vertex_index"]))
+            .map(|p| p[0]);
+
+        let mut values = vec![0.; element.properties.len()];
+        let mut list = vec![];
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar(scalar, _) => {
+                        values[i] = body.read(*scalar)?;
+                        if color.as_ref().is_some_and(|c| c.contains(&i)) {
+                            values[i] /= scalar.max();
+                        }
+                    }
+                    Property::List(count, item, _) => {
+                        let n = body.read(*count)? as usize;
+                        let items = (0..n)
+                            .map(|_| body.read(*item))
+                            .collect::<Result<Vec<f64>, String>>()?;
+                        if Some(i) == indices {
+                            list = items;
+                        }
+                    }
+                }
+            }
+            let vector = |p: &[usize]| {
+                Vector3D::new(
+                    values[p[0]] as f32,
+                    values[p[1]] as f32,
+                    values[p[2]] as f32,
+                )
+            };
+            match element.name.as_str() {
+                "vertex" => {
+                    let p = position.as_ref().ok_or("vertices have no position")?;
+                    ply.positions.push(vector(p));
+                    if let Some(n) = &normal {
+                        ply.normals.push(vector(n));
+                    }
+                    if let Some(c) = &color {
+                        let c = vector(c);
+                        ply.colors.push(c * c);
+                    }
+                    if let Some(t) = &uv {
+                        ply.uvs.push((values[t[0]] as f32, values[t[1]] as f32));
+                    }
+                }
+                "face" => ply.faces.push(list.iter().map(|i| *i as u32).collect()),
+                _ => (),
+            }
+        }
+    }
+    if ply.faces.is_empty() {
+        return Err("there are no faces".to_string());
+    }
+    Ok(ply)
+}
+
+/// The format, elements and texture named in the header, and where the body starts
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, Option<String>, usize), String> {
+    const END: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or("not a PLY file")?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(bytes.len(), |p| end + p + 1);
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut lines = header.lines().map(|l| l.trim());
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut texture_file = None;
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("unknown format {}", name)),
+                })
+            }
+            ["comment", "TextureFile", _, ..] => {
+                texture_file = Some(line["comment TextureFile".len()..].trim().to_string())
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("bad element count {}", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::List(
+                    Scalar::from_name(count)?,
+                    Scalar::from_name(item)?,
+                    name.to_string(),
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::Scalar(
+                    Scalar::from_name(scalar)?,
+                    name.to_string(),
+                )),
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            _ => return Err(format!("can't understand header line {}", line)),
+        }
+    }
+    let format = format.ok_or("the header has no format")?;
+    Ok((format, elements, texture_file, body_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ply\n\
+        format ascii 1.0\n\
+        comment made by hand\n\
+        element vertex 4\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property uchar red\n\
+        property uchar green\n\
+        property uchar blue\n\
+        property float s\n\
+        property float t\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n";
+
+    #[test]
+    fn test_ascii() {
+        let text = format!(
+            "{}0 0 0 255 0 0 0 0\n1 0 0 255 0 0 1 0\n1 1 0 0 0 0 1 1\n0 1 0 0 0 0 0 1\n4 0 1 2 3\n",
+            HEADER
+        );
+        let ply = parse(text.as_bytes()).unwrap();
+        assert_eq!(ply.positions.len(), 4);
+        assert_eq!(ply.positions[2], Vector3D::new(1., 1., 0.));
+        assert_eq!(ply.faces, vec![vec![0, 1, 2, 3]]);
+        assert_eq!(ply.colors[0], Vector3D::new(1., 0., 0.));
+        assert_eq!(ply.uvs[2], (1., 1.));
+        assert!(ply.normals.is_empty());
+        assert!(parse(&text.as_bytes()[..text.len() - 4]).is_err());
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = match big_endian {
+            true => "binary_big_endian",
+            false => "binary_little_endian",
+        };
+        let mut bytes = format!(
+            "ply\nformat {} 1.0\n\
+             element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             element material 1\nproperty double shininess\n\
+             element face 1\nproperty list uchar uint vertex_index\nend_header\n",
+            format
+        )
+        .into_bytes();
+        let f = |v: f32| match big_endian {
+            true => v.to_be_bytes(),
+            false => v.to_le_bytes(),
+        };
+        for v in [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]].iter() {
+            for x in v.iter().chain([0., 0., 1.].iter()) {
+                bytes.extend_from_slice(&f(*x));
+            }
+        }
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.push(3);
+        for i in 0..3u32 {
+            match big_endian {
+                true => bytes.extend_from_slice(&i.to_be_bytes()),
+                false => bytes.extend_from_slice(&i.to_le_bytes()),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_binary() {
+        for big_endian in [false, true].iter() {
+            let ply = parse(&binary(*big_endian)).unwrap();
+            assert_eq!(ply.positions[1], Vector3D::new(1., 0., 0.));
+            assert_eq!(ply.normals[2], Vector3D::new(0., 0., 1.));
+            assert_eq!(ply.faces, vec![vec![0, 1, 2]]);
+            assert!(ply.colors.is_empty() && ply.uvs.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert!(parse(b"solid cube\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n").is_err());
+        assert!(
+            parse(b"ply\nformat ascii 1.0\nelement vertex 0\nproperty half x\nend_header\n")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Built-in scenes for the shapes no scene file describes, given to `--scene` by name instead of
+//! a path. Each stands its objects on a grey floor in the sun.
+
+use crate::animation::CameraPose;
+use crate::lights::light::Light;
+use crate::shapes::bvh::Aabb;
+use crate::shapes::csg::Csg;
+use crate::shapes::hitable::Hitable;
+use crate::shapes::quadric::Quadric;
+use crate::shapes::sdf::{Distance, Sdf};
+use crate::shapes::sphere::Sphere;
+use crate::shapes::transformed::Transformed;
+use crate::shapes::voxel::{VoxelGrid, EMPTY};
+use crate::util::material::Material;
+use crate::util::spectrum::Ior;
+use crate::util::transform::Transform;
+use crate::util::vector3d::Vector3D;
+
+use super::ImportedScene;
+
+/// Names of the built-in scenes
+pub const PRESETS: [&str; 4] = ["sdf", "csg", "quadrics", "blocks"];
+
+/// The built-in scene called `name`, if there is one
+pub fn load(name: &str) -> Option<ImportedScene> {
+    let objects = match name {
+        "sdf" => sdf(),
+        "csg" => csg(),
+        "quadrics" => quadrics(),
+        "blocks" => blocks(),
+        _ => return None,
+    };
+    Some(staged(objects))
+}
+
+/// The sun, low enough to cast long shadows
+pub fn sun() -> Light {
+    Light::Directional {
+        direction: Vector3D::new(-1., -2., -0.5),
+        radiance: Vector3D::new(1., 0.95, 0.85),
+        angular_radius: 0.5,
+    }
+}
+
+/// `objects` on the floor, seen from the front. They should fit between -3 and 3 along x and
+/// below 2.5.
+fn staged(mut objects: Vec<Box<dyn Hitable>>) -> ImportedScene {
+    objects.insert(
+        0,
+        Box::new(Sphere::new(
+            Vector3D::new(0., -1000., 0.),
+            1000.,
+            lambertian(0.5, 0.5, 0.5),
+        )),
+    );
+    let lookfrom = Vector3D::new(0., 2.5, 9.);
+    let lookat = Vector3D::new(0., 1., 0.);
+    ImportedScene {
+        objects,
+        lights: vec![sun()],
+        camera: Some(CameraPose {
+            lookfrom,
+            lookat,
+            vup: Vector3D::new(0., 1., 0.),
+            vfov: 30.,
+            aperture: 0.,
+            focus_distance: (lookfrom - lookat).length(),
+        }),
+        warnings: vec![],
+    }
+}
+
+fn lambertian(r: f32, g: f32, b: f32) -> Material {
+    Material::Lambertian {
+        albedo: Vector3D::new(r, g, b),
+    }
+}
+
+/// A cube hollowed by a sphere, a ball melting into a ring and a Mandelbulb
+fn sdf() -> Vec<Box<dyn Hitable>> {
+    let hollow = Distance::Cuboid {
+        half_size: Vector3D::new(0.8, 0.8, 0.8),
+    }
+    .subtraction(Distance::Sphere { radius: 1. })
+    .translate(Vector3D::new(-2.3, 0.8, 0.));
+    let blob = Distance::Sphere { radius: 0.6 }
+        .translate(Vector3D::new(0., 0.6, 0.))
+        .smooth_union(
+            Distance::Torus {
+                major_radius: 0.8,
+                minor_radius: 0.2,
+            }
+            .translate(Vector3D::new(0., 1.1, 0.)),
+            0.4,
+        );
+    let bulb = Distance::Mandelbulb {
+        power: 8.,
+        iterations: 8,
+    }
+    .translate(Vector3D::new(2.3, 1.2, 0.));
+    vec![
+        Box::new(Sdf::new(hollow, lambertian(0.8, 0.3, 0.2))),
+        Box::new(Sdf::new(
+            blob,
+            Material::Metal {
+                albedo: Vector3D::new(0.8, 0.8, 0.85),
+                fuzziness: 0.1,
+            },
+        )),
+        Box::new(Sdf::new(bulb, lambertian(0.3, 0.5, 0.8))),
+    ]
+}
+
+/// A bitten ball, a glass lens where two balls overlap and the rounded cube drilled through
+/// twice that CSG is known for
+fn csg() -> Vec<Box<dyn Hitable>> {
+    let ball = |center: Vector3D, radius: f32, material: Material| -> Box<dyn Hitable> {
+        Box::new(Sphere::new(center, radius, material))
+    };
+    let red = lambertian(0.8, 0.2, 0.2);
+    let bitten = Csg::difference(
+        ball(Vector3D::new(-2.3, 1., 0.), 1., red.clone()),
+        ball(Vector3D::new(-1.7, 1.5, 0.7), 0.6, red),
+    );
+    let glass = Material::Dielectric {
+        ref_ind: Ior::Constant(1.5),
+        absorption: None,
+    };
+    let lens = Csg::intersection(
+        ball(Vector3D::new(0., 1., -1.), 1.4, glass.clone()),
+        ball(Vector3D::new(0., 1., 1.), 1.4, glass),
+    );
+
+    let center = Vector3D::new(2.3, 0.8, 0.);
+    let green = lambertian(0.3, 0.7, 0.3);
+    let cube = Sdf::new(
+        Distance::Cuboid {
+            half_size: Vector3D::new(0.75, 0.75, 0.75),
+        }
+        .translate(center),
+        green.clone(),
+    );
+    let rounded = Csg::intersection(Box::new(cube), ball(center, 1., green));
+    let yellow = lambertian(0.9, 0.7, 0.2);
+    let upright = Distance::Cylinder {
+        radius: 0.4,
+        half_height: 1.,
+    }
+    .translate(center);
+    // The same cylinder lying along x
+    let across = Distance::function(move |p| {
+        let q = p - center;
+        let side = (q.y() * q.y() + q.z() * q.z()).sqrt() - 0.4;
+        side.max(q.x().abs() - 1.)
+    });
+    let reach = Vector3D::new(1., 0.4, 0.4);
+    let across = Sdf::new(across, yellow.clone()).with_bounds(Aabb {
+        min: center - reach,
+        max: center + reach,
+    });
+    let drilled = Csg::difference(
+        Box::new(Csg::difference(
+            Box::new(rounded),
+            Box::new(Sdf::new(upright, yellow)),
+        )),
+        Box::new(across),
+    );
+    vec![Box::new(bitten), Box::new(lens), Box::new(drilled)]
+}
+
+/// A row of quadrics: an ellipsoid, a bowl, a hyperboloid, an hourglass cone and an open tube
+fn quadrics() -> Vec<Box<dyn Hitable>> {
+    let metal = Material::Metal {
+        albedo: Vector3D::new(0.9, 0.8, 0.6),
+        fuzziness: 0.2,
+    };
+    let shapes = vec![
+        (
+            Quadric::ellipsoid(Vector3D::new(0.5, 0.8, 0.4), lambertian(0.8, 0.3, 0.2)),
+            Vector3D::new(-2.8, 0.8, 0.),
+        ),
+        (
+            Quadric::paraboloid(Vector3D::new(0.5, 0.5, 0.5), 1.2, metal),
+            Vector3D::new(-1.4, 0., 0.),
+        ),
+        (
+            Quadric::hyperboloid(Vector3D::new(0.3, 0.6, 0.3), 0.8, lambertian(0.3, 0.6, 0.8)),
+            Vector3D::new(0., 0.8, 0.),
+        ),
+        (
+            Quadric::cone(Vector3D::new(0.4, 1., 0.4), 1., lambertian(0.9, 0.7, 0.2)),
+            Vector3D::new(1.4, 1., 0.),
+        ),
+        (
+            Quadric::cylinder(Vector3D::new(0.4, 1., 0.4), 0.8, lambertian(0.3, 0.7, 0.3)),
+            Vector3D::new(2.8, 0.8, 0.),
+        ),
+    ];
+    shapes
+        .into_iter()
+        .map(|(shape, position)| -> Box<dyn Hitable> {
+            Box::new(Transformed::new(
+                Box::new(shape),
+                Transform::translate(position),
+            ))
+        })
+        .collect()
+}
+
+/// Rolling block hills of stone under dirt under grass, with a tree and a glass block on top
+fn blocks() -> Vec<Box<dyn Hitable>> {
+    const GRASS: u16 = 1;
+    const DIRT: u16 = 2;
+    const STONE: u16 = 3;
+    const WOOD: u16 = 4;
+    const LEAVES: u16 = 5;
+    const GLASS: u16 = 6;
+    let materials = vec![
+        lambertian(0.3, 0.6, 0.2),
+        lambertian(0.45, 0.3, 0.2),
+        lambertian(0.5, 0.5, 0.5),
+        lambertian(0.4, 0.25, 0.1),
+        lambertian(0.15, 0.45, 0.1),
+        Material::Dielectric {
+            ref_ind: Ior::Constant(1.5),
+            absorption: None,
+        },
+    ];
+    let dims = [24, 10, 16];
+    let mut voxels = vec![EMPTY; dims[0] * dims[1] * dims[2]];
+    let mut fill = |x: usize, y: usize, z: usize, material: u16| {
+        voxels[(z * dims[1] + y) * dims[0] + x] = material;
+    };
+    let ground = |x: usize, z: usize| {
+        let (x, z) = (x as f32, z as f32);
+        (2.5 + (x * 0.4).sin() * 1.2 + (z * 0.5 + x * 0.2).cos()).round() as usize
+    };
+    for z in 0..dims[2] {
+        for x in 0..dims[0] {
+            let top = ground(x, z);
+            for y in 0..top {
+                let material = match top - y {
+                    1 => GRASS,
+                    2 => DIRT,
+                    _ => STONE,
+                };
+                fill(x, y, z, material);
+            }
+        }
+    }
+    let (x, z) = (12, 8);
+    let base = ground(x, z);
+    for y in base..base + 4 {
+        fill(x, y, z, WOOD);
+    }
+    for (dx, dy, dz) in
+        (0..5).flat_map(|i| (0..3).flat_map(move |j| (0..5).map(move |k| (i, j, k))))
+    {
+        // The canopy narrows to a cross at the top
+        let corner = (dx == 0 || dx == 4) && (dz == 0 || dz == 4);
+        let edge = dx == 0 || dx == 4 || dz == 0 || dz == 4;
+        if corner || (dy == 2 && edge) || (dy < 2 && dx == 2 && dz == 2) {
+            continue;
+        }
+        fill(x + dx - 2, base + 3 + dy, z + dz - 2, LEAVES);
+    }
+    let (x, z) = (5, 9);
+    fill(x, ground(x, z), z, GLASS);
+    let grid = VoxelGrid::new(dims, voxels, materials)
+        .expect("the blocks fill their grid")
+        .with_origin(Vector3D::new(-3., 0., -2.))
+        .with_voxel_size(0.25);
+    vec![Box::new(grid)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::hitable::HitRecord;
+    use crate::util::ray::Ray;
+
+    #[test]
+    fn test_presets() {
+        for name in PRESETS.iter() {
+            let scene = load(name).unwrap();
+            assert!(scene.objects.len() > 1, "{}", name);
+            // Something besides the floor is in the middle of the view
+            let pose = scene.camera.unwrap();
+            let r = Ray::new(pose.lookfrom, pose.lookat - pose.lookfrom);
+            let mut rec = HitRecord::new(lambertian(0., 0., 0.));
+            let hit = scene.objects[1..]
+                .iter()
+                .any(|o| o.hit(&r, 1e-3, f32::MAX, &mut rec));
+            assert!(hit, "{}", name);
+        }
+        assert!(load("teapot").is_none());
+    }
+}
@@ -0,0 +1,161 @@
+//! STL meshes, binary or ASCII. Every facet keeps its own normal, so the mesh is shaded flat
+//! as CAD programs show it.
+
+use std::fs;
+
+use super::{model, ImportOptions, ImportedScene};
+use crate::shapes::subdivision::Cage;
+use crate::util::material::Material;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+pub fn load(path: &str, options: &ImportOptions) -> Result<ImportedScene, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let cage = parse(&bytes).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let material = Material::Lambertian {
+        albedo: Vector3D::new(0.5, 0.5, 0.5),
+    };
+    let mut warnings = vec![];
+    let mesh = options.mesh(cage, material, &mut warnings);
+    Ok(model(mesh, warnings))
+}
+
+/// A triangle and the normal the file gives it
+type Facet = (Vector3D, [Vector3D; 3]);
+
+/// The facets, each with its own three vertices
+fn parse(bytes: &[u8]) -> Result<Cage, String> {
+    // ASCII files start with "solid", but so do some binary ones, whose size then gives them away
+    let binary_size = bytes
+        .get(80..84)
+        .map(|c| 84 + 50 * u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize);
+    let facets = match (bytes.starts_with(b"solid"), binary_size) {
+        (_, Some(size)) if size == bytes.len() => parse_binary(bytes),
+        (true, _) => parse_ascii(&String::from_utf8_lossy(bytes))?,
+        _ => return Err("not an STL file".to_string()),
+    };
+    if facets.is_empty() {
+        return Err("there are no facets".to_string());
+    }
+
+    let mut positions = Vec::with_capacity(3 * facets.len());
+    let mut normals = Vec::with_capacity(3 * facets.len());
+    for (normal, [a, b, c]) in facets {
+        // Files often leave the normal out, to be taken from the winding
+        let normal = match normal.squared_length() > 0. {
+            true => unit_vector(normal),
+            false => unit_vector((b - a).cross(c - a)),
+        };
+        positions.extend_from_slice(&[a, b, c]);
+        normals.extend_from_slice(&[normal; 3]);
+    }
+    let faces = (0..positions.len() as u32 / 3)
+        .map(|i| vec![3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    Ok(Cage {
+        normals,
+        ..Cage::new(positions, faces)
+    })
+}
+
+/// Facets of a binary file: an 80 byte header and a count, then a normal, three vertices and
+/// two unused bytes for each facet
+fn parse_binary(bytes: &[u8]) -> Vec<Facet> {
+    let float = |b: &[u8], i: usize| f32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+    let vector = |b: &[u8], i: usize| Vector3D::new(float(b, i), float(b, i + 4), float(b, i + 8));
+    bytes[84..]
+        .chunks_exact(50)
+        .map(|f| (vector(f, 0), [vector(f, 12), vector(f, 24), vector(f, 36)]))
+        .collect()
+}
+
+fn parse_ascii(text: &str) -> Result<Vec<Facet>, String> {
+    let mut facets = vec![];
+    let mut normal = Vector3D::new(0., 0., 0.);
+    let mut vertices = vec![];
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let vector = |w: &[&str]| -> Result<Vector3D, String> {
+            let v = w
+                .iter()
+                .map(|x| x.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_| format!("bad numbers in {}", line.trim()))?;
+            match v.as_slice() {
+                [x, y, z] => Ok(Vector3D::new(*x, *y, *z)),
+                _ => Err(format!("expected three numbers in {}", line.trim())),
+            }
+        };
+        match words.as_slice() {
+            ["facet", "normal", rest @ ..] => {
+                normal = vector(rest)?;
+                vertices.clear();
+            }
+            ["vertex", rest @ ..] => vertices.push(vector(rest)?),
+            ["endfacet"] => match vertices.as_slice() {
+                [a, b, c] => facets.push((normal, [*a, *b, *c])),
+                _ => return Err(format!("a facet has {} vertices", vertices.len())),
+            },
+            _ => (),
+        }
+    }
+    Ok(facets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::hitable::{HitRecord, Hitable};
+    use crate::util::ray::Ray;
+
+    fn hit_normal(cage: Cage) -> Vector3D {
+        let dummy = Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        };
+        let mut rec = HitRecord::new(dummy.clone());
+        let mesh = cage.into_mesh(dummy);
+        let r = Ray::new(Vector3D::new(0.2, 0.2, 1.), Vector3D::new(0., 0., -1.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        rec.normal
+    }
+
+    #[test]
+    fn test_ascii() {
+        let text = "solid part\n\
+            facet normal 0 0 1\n\
+              outer loop\n\
+                vertex 0 0 0\n\
+                vertex 1 0 0\n\
+                vertex 0 1 0\n\
+              endloop\n\
+            endfacet\n\
+            facet normal 0 0 0\n\
+              outer loop\n\
+                vertex 0 0 -1\n\
+                vertex 1 0 -1\n\
+                vertex 0 1 -1\n\
+              endloop\n\
+            endfacet\n\
+            endsolid part\n";
+        let cage = parse(text.as_bytes()).unwrap();
+        assert_eq!((cage.positions.len(), cage.faces.len()), (6, 2));
+        assert_eq!(hit_normal(cage), Vector3D::new(0., 0., 1.));
+        assert!(parse(b"solid empty\nendsolid empty\n").is_err());
+        assert!(parse(b"solid bad\nfacet normal 0 0 1\nvertex 0 0\n").is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        // Starts with "solid" like some exporters write, but is binary
+        let mut bytes = b"solid".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for v in [0f32, 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0.].iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0, 0]);
+        let cage = parse(&bytes).unwrap();
+        assert_eq!(cage.faces.len(), 1);
+        assert_eq!(hit_normal(cage), Vector3D::new(0., 0., 1.));
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Grayscale images as terrain, white the highest. The image's longer side spans ten units
+//! and full brightness rises a fifth of that, with the top of the image furthest away.
+
+use super::{framed, ImportOptions, ImportedScene};
+use crate::film::read_png_luminance;
+use crate::shapes::heightfield::Heightfield;
+use crate::util::material::Material;
+use crate::util::vector3d::Vector3D;
+
+/// Length of the image's longer side in the scene
+const EXTENT: f32 = 10.;
+
+pub fn load(path: &str, options: &ImportOptions) -> Result<ImportedScene, String> {
+    let (nx, ny, heights) =
+        read_png_luminance(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut warnings = vec![];
+    if options.subdivision.is_some() || options.displacement.is_some() {
+        warnings.push("terrain isn't subdivided or displaced".to_string());
+    }
+    let field = terrain(nx as usize, ny as usize, heights)
+        .map_err(|e| format!("{} can't be used as terrain: {}", path, e))?;
+    let bounds = field.bounds();
+    Ok(framed(Box::new(field), bounds, warnings))
+}
+
+fn terrain(nx: usize, ny: usize, heights: Vec<f32>) -> Result<Heightfield, String> {
+    let scale = EXTENT / nx.max(ny) as f32;
+    let size = Vector3D::new(nx as f32 * scale, EXTENT / 5., ny as f32 * scale);
+    let material = Material::Lambertian {
+        albedo: Vector3D::new(0.5, 0.5, 0.5),
+    };
+    Heightfield::new(nx, ny, heights, size, material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::write_png;
+    use crate::shapes::hitable::HitRecord;
+    use crate::util::ray::Ray;
+    use crate::util::texture::TextureFilter;
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("raytrace_test_terrain.png");
+        let path = path.to_str().unwrap();
+        // A white peak in the middle of four by two black pixels
+        let mut rgb = vec![0; 4 * 2 * 3];
+        rgb[3..6].copy_from_slice(&[255, 255, 255]);
+        write_png(path, 4, 2, &rgb).unwrap();
+        let scene = load(path, &ImportOptions::new(TextureFilter::Point));
+        std::fs::remove_file(path).unwrap();
+        let scene = scene.unwrap();
+        assert!(scene.warnings.is_empty());
+        assert!(scene.camera.is_some());
+
+        // Ten wide and five deep, the peak at the second sample of the far row
+        let r = Ray::new(
+            Vector3D::new(-5. + 10. / 3., 5., -2.5),
+            Vector3D::new(0., -1., 0.),
+        );
+        let mut rec = HitRecord::new(Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        });
+        assert!(scene.objects[0].hit(&r, 1e-3, f32::MAX, &mut rec));
+        assert!((rec.p.y() - 2.).abs() < 1e-4);
+        assert!(load("missing.png", &ImportOptions::new(TextureFilter::Point)).is_err());
+    }
+}
@@ -1,2 +1,14 @@
+pub mod animation;
+mod blue_noise;
+pub mod denoise;
+pub mod film;
+pub mod filter;
+pub mod import;
+pub mod lights;
+pub mod sampler;
+pub mod scene;
+pub mod settings;
 pub mod shapes;
+pub mod stats;
 pub mod util;
+pub mod volume;
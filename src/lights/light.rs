@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+use crate::sampler::Sampler;
+use crate::util::sampling::{to_world, uniform_cone};
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Punctual light sources. These have no geometry, so rays never hit them and they only
+/// contribute through explicit shadow rays cast from the integrator.
+#[derive(Clone)]
+pub enum Light {
+    /// Emits `intensity` uniformly in all directions from `position`.
+    Point {
+        position: Vector3D,
+        intensity: Vector3D,
+    },
+    /// A point light restricted to a cone around `direction`. Full intensity inside
+    /// `falloff_start` degrees, fading smoothly to zero at `total_width` degrees.
+    Spot {
+        position: Vector3D,
+        direction: Vector3D,
+        intensity: Vector3D,
+        total_width: f32,
+        falloff_start: f32,
+    },
+    /// A light infinitely far away shining along `direction`, like the sun. A non zero
+    /// `angular_radius` (in degrees) gives the source a size so shadows get soft edges.
+    Directional {
+        direction: Vector3D,
+        radiance: Vector3D,
+        angular_radius: f32,
+    },
+}
+
+/// The result of sampling a light from a shading point.
+pub struct LightSample {
+    /// Unit vector pointing from the shading point towards the light
+    pub direction: Vector3D,
+    /// Distance to the light, used to bound the shadow ray
+    pub distance: f32,
+    /// Incoming radiance arriving at the shading point if unoccluded
+    pub radiance: Vector3D,
+}
+
+impl Light {
+    /// Samples incoming light at point `p`, drawing any random numbers needed from `sampler`.
+    /// Returns `None` if the light can't reach `p`.
+    pub fn sample(&self, p: Vector3D, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: *intensity / (distance * distance),
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                total_width,
+                falloff_start,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                let wi = to_light / distance;
+                let falloff = spot_falloff(
+                    -wi.dot(unit_vector(*direction)),
+                    (total_width * PI / 180.).cos(),
+                    (falloff_start * PI / 180.).cos(),
+                );
+                match falloff > 0. {
+                    true => Some(LightSample {
+                        direction: wi,
+                        distance,
+                        radiance: *intensity * falloff / (distance * distance),
+                    }),
+                    false => None,
+                }
+            }
+            Light::Directional {
+                direction,
+                radiance,
+                angular_radius,
+            } => {
+                let wi = -unit_vector(*direction);
+                Some(LightSample {
+                    direction: match *angular_radius > 0. {
+                        true => {
+                            let cos_max = (angular_radius * PI / 180.).cos();
+                            random_in_cone(wi, cos_max, sampler.get_2d())
+                        }
+                        false => wi,
+                    },
+                    distance: f32::MAX,
+                    radiance: *radiance,
+                })
+            }
+        }
+    }
+}
+
+pub struct LightList {
+    pub list: Vec<Light>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Light>) -> Self {
+        Self { list: lights }
+    }
+}
+
+/// Smooth falloff between the inner and outer cone of a spot light, as in pbrt.
+pub fn spot_falloff(cos_theta: f32, cos_total_width: f32, cos_falloff_start: f32) -> f32 {
+    if cos_theta < cos_total_width {
+        return 0.;
+    }
+    if cos_theta >= cos_falloff_start {
+        return 1.;
+    }
+    let delta = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+    (delta * delta) * (delta * delta)
+}
+
+/// Uniformly maps a point of the unit square to a direction inside the cone around unit vector
+/// `axis` whose half angle has cosine `cos_max`.
+pub fn random_in_cone(axis: Vector3D, cos_max: f32, u: (f32, f32)) -> Vector3D {
+    to_world(uniform_cone(u, cos_max), axis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    #[test]
+    fn test_point_inverse_square() {
+        let light = Light::Point {
+            position: Vector3D::new(0., 2., 0.),
+            intensity: Vector3D::new(4., 4., 4.),
+        };
+        let s = light.sample(Vector3D::new(0., 0., 0.), &mut IndependentSampler).unwrap();
+        assert_eq!(s.direction, Vector3D::new(0., 1., 0.));
+        assert_eq!(s.distance, 2.);
+        assert_eq!(s.radiance, Vector3D::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn test_spot_cone() {
+        let light = Light::Spot {
+            position: Vector3D::new(0., 1., 0.),
+            direction: Vector3D::new(0., -1., 0.),
+            intensity: Vector3D::new(1., 1., 1.),
+            total_width: 30.,
+            falloff_start: 20.,
+        };
+        let inside = light.sample(Vector3D::new(0., 0., 0.), &mut IndependentSampler).unwrap();
+        assert_eq!(inside.radiance, Vector3D::new(1., 1., 1.));
+        assert!(light.sample(Vector3D::new(5., 0., 0.), &mut IndependentSampler).is_none());
+    }
+
+    #[test]
+    fn test_spot_falloff() {
+        assert_eq!(spot_falloff(0.5, 0.8, 0.9), 0.);
+        assert_eq!(spot_falloff(0.95, 0.8, 0.9), 1.);
+        let f = spot_falloff(0.85, 0.8, 0.9);
+        assert!(f > 0. && f < 1.);
+    }
+
+    #[test]
+    fn test_directional() {
+        let light = Light::Directional {
+            direction: Vector3D::new(0., -2., 0.),
+            radiance: Vector3D::new(1., 1., 1.),
+            angular_radius: 0.,
+        };
+        let s = light.sample(Vector3D::new(3., 0., 3.), &mut IndependentSampler).unwrap();
+        assert_eq!(s.direction, Vector3D::new(0., 1., 0.));
+        assert_eq!(s.distance, f32::MAX);
+    }
+
+    #[test]
+    fn test_random_in_cone() {
+        let axis = unit_vector(Vector3D::new(1., 1., 0.));
+        let cos_max = (5. * PI / 180.).cos();
+        let mut sampler = IndependentSampler;
+        for _ in 0..100 {
+            let d = random_in_cone(axis, cos_max, sampler.get_2d());
+            assert!((d.length() - 1.).abs() < 1e-4);
+            assert!(d.dot(axis) >= cos_max - 1e-4);
+        }
+    }
+}
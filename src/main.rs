@@ -2,23 +2,44 @@ extern crate png;
 extern crate rand;
 extern crate rayon;
 
-use std::fs::File;
-use std::io::BufWriter;
+use std::fs;
 
-use self::rand::{thread_rng, Rng};
+use self::rand::rngs::StdRng;
+use self::rand::{thread_rng, Rng, SeedableRng};
 use rayon::prelude::*;
 
 use std::env;
+use std::path::Path;
+use std::process;
 use std::f32;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 
+use raytrace::animation::{Animation, CameraPose};
+use raytrace::denoise::{denoise, DenoiseSettings};
+use raytrace::film::{read_png, write_png, Aov, Film};
+use raytrace::filter::{FilmSample, SplatBuffer};
+use raytrace::import::{self, preset, ImportOptions, ImportedScene};
+use raytrace::lights::light::LightList;
+use raytrace::sampler::Sampler;
+use raytrace::scene::Scene;
+use raytrace::settings::{AovOutput, RenderSettings};
 use raytrace::shapes::hitable::{HitRecord, Hitable, HitableList};
 use raytrace::shapes::sphere::Sphere;
-use raytrace::util::camera::Camera;
+use raytrace::shapes::transformed::Transformed;
+use raytrace::stats::{self, Counter, RenderStats};
+use raytrace::util::aperture::Aperture;
+use raytrace::util::camera::{
+    Camera, CameraModel, CylindricalCamera, EquirectangularCamera, FisheyeCamera,
+    OrthographicCamera, Projection,
+};
 use raytrace::util::material::Material;
 
 use raytrace::util::ray::Ray;
+use raytrace::util::spectrum::{at_wavelength, sample_wavelength, spectral_to_rgb, Ior};
+use raytrace::util::texture::{Texture, TextureFilter, TextureMapping};
 use raytrace::util::vector3d::{unit_vector, Vector3D};
+use raytrace::volume::{henyey_greenstein, sample_henyey_greenstein, Medium};
 
 #[macro_export]
 macro_rules! make_sphere {
@@ -30,20 +51,22 @@ macro_rules! make_sphere {
     };
 }
 
-pub fn random_scene() -> HitableList {
-    let mut rng = thread_rng();  // Make a single rng to pass around
+/// The scene of random little spheres. The same `seed` always places them the same way.
+pub fn random_scene(ground: Material, seed: u64) -> HitableList {
+    let mut rng = StdRng::seed_from_u64(seed);  // Make a single rng to pass around
     let mut list = HitableList::new(vec![]);
     list.list.push(make_sphere!(
         Vector3D::new(0., -1000., 0.),
         1000.,
-        Material::Lambertian {
-            albedo: Vector3D::new(0.5, 0.5, 0.5),
-        },
+        ground,
     ));
     list.list.push(make_sphere!(
         Vector3D::new(0., 1., 0.),
         1.,
-        Material::Dielectric { ref_ind: 1.5 },
+        Material::Dielectric {
+            ref_ind: Ior::Cauchy { a: 1.5046, b: 0.0042 },  // BK7 glass
+            absorption: None,
+        },
     ));
     list.list.push(make_sphere!(
         Vector3D::new(-4., 1., 0.),
@@ -101,7 +124,10 @@ pub fn random_scene() -> HitableList {
                         list.list.push(make_sphere!(
                             center,
                             0.2,
-                            Material::Dielectric { ref_ind: 1.5 },
+                            Material::Dielectric {
+                                ref_ind: Ior::Constant(1.5),
+                                absorption: None,
+                            },
                         ));
                     }
                 }
@@ -111,123 +137,505 @@ pub fn random_scene() -> HitableList {
     list
 }
 
-pub fn color(r: &Ray, world: &HitableList, depth: i32) -> Vector3D {
-    let mut rec = HitRecord::new(Material::DummyMat {  // Start with an empty material record
-        albedo: Vector3D::new(0., 0., 0.),
-    });
-    match world.hit(r, 0.001, f32::MAX, &mut rec) {
-        true => {
-            // Start with empty result
-            let v1 = Vector3D::new(0., 0., 0.);
-            let v2 = Vector3D::new(0., 0., 0.);
-            let mut scattered = Ray::new(v1, v2);
-            let mut attenuation = Vector3D::new(0., 0., 0.);
-            match depth < 50
-                && rec
-                    .material
-                    .scatter(r, &rec, &mut attenuation, &mut scattered)  // Still scattering
-            {
-                true => attenuation * color(&scattered, world, depth + 1),
-                false => Vector3D::new(0., 0., 0.),  // Black spot
+pub fn scene_lights() -> LightList {
+    LightList::new(vec![preset::sun()])
+}
+
+/// How the settings ask for meshes in scene files to be subdivided, displaced and textured
+pub fn import_options(settings: &RenderSettings) -> Result<ImportOptions, String> {
+    let mut options = ImportOptions::new(settings.texture_filter);
+    options.subdivision = settings
+        .subdivision
+        .map(|scheme| (scheme, settings.subdivision_levels));
+    if let Some(path) = &settings.displacement {
+        let (nx, ny, pixels) = read_png(path)
+            .map_err(|e| format!("Can't read displacement image {}: {}", path, e))?;
+        // Heights are taken as stored, not as colours to be linearised
+        let texture = Texture::from_image(
+            nx as usize,
+            ny as usize,
+            pixels,
+            TextureMapping::Uv { scale: (1., 1.) },
+            TextureFilter::Point,
+        );
+        options.displacement = Some((texture, settings.displacement_scale));
+    }
+    Ok(options)
+}
+
+/// The scene file given in the settings, or else the random spheres placed by `seed` on
+/// `ground` under the sun
+pub fn load_scene(
+    settings: &RenderSettings,
+    options: &ImportOptions,
+    ground: &Material,
+    seed: u64,
+) -> Result<ImportedScene, String> {
+    match &settings.scene {
+        Some(path) => import::load(path, options),
+        None => Ok(ImportedScene {
+            objects: random_scene(ground.clone(), seed).list,
+            lights: scene_lights().list,
+            camera: None,
+            warnings: vec![],
+        }),
+    }
+}
+
+/// Where the camera stands in the scene when nothing animates it
+pub fn scene_pose(settings: &RenderSettings) -> CameraPose {
+    let lookfrom = Vector3D::new(13., 2., 3.);
+    CameraPose {
+        lookfrom,
+        lookat: Vector3D::new(0., 0., -1.),
+        vup: Vector3D::new(0., 1., 0.),
+        vfov: 30.,
+        aperture: 0.1,
+        focus_distance: settings
+            .focus_distance
+            .unwrap_or((lookfrom - Vector3D::new(4., 1., 0.)).length()),
+    }
+}
+
+/// Media added to the scene by the settings, centred where the camera looks and sized by how
+/// far away that is
+pub fn scene_media(settings: &RenderSettings, pose: &CameraPose) -> Vec<Medium> {
+    let radius = 0.3 * (pose.lookfrom - pose.lookat).length();
+    match settings.volume {
+        Some(kind) => vec![kind
+            .medium(pose.lookat, radius, settings.volume_density)
+            .with_anisotropy(settings.volume_anisotropy)],
+        None => vec![],
+    }
+}
+
+/// The camera at `pose`, using the projection and lens chosen in the settings
+pub fn scene_camera(
+    settings: &RenderSettings,
+    aperture: Aperture,
+    pose: &CameraPose,
+) -> Box<dyn CameraModel> {
+    let (lookfrom, lookat, vup) = (pose.lookfrom, pose.lookat, pose.vup);
+    let aspect = settings.nx as f32 / settings.ny as f32;
+    let perspective = match settings.lens(pose.focus_distance) {
+        Some(lens) => Camera::physical(lookfrom, lookat, vup, &lens, aspect),
+        None => Camera::new(
+            lookfrom,
+            lookat,
+            vup,
+            pose.vfov,
+            aspect,
+            pose.aperture,
+            pose.focus_distance,
+        ),
+    };
+    match settings.projection {
+        Projection::Perspective => Box::new(
+            perspective
+                .with_aperture(aperture)
+                .with_cat_eye(settings.cat_eye)
+                .with_tilt(settings.tilt),
+        ),
+        Projection::Orthographic => {
+            Box::new(OrthographicCamera::new(lookfrom, lookat, vup, 7., aspect))
+        }
+        Projection::Fisheye => Box::new(FisheyeCamera::new(lookfrom, lookat, vup, 180., aspect)),
+        Projection::Equirectangular => Box::new(EquirectangularCamera::new(lookfrom, lookat, vup)),
+        Projection::Cylindrical => Box::new(CylindricalCamera::new(lookfrom, lookat, vup, 90.)),
+    }
+}
+
+/// Light arriving directly from the punctual lights at a hit of `r`, found with shadow rays
+pub fn direct_light(
+    r: &Ray,
+    rec: &HitRecord,
+    scene: &Scene,
+    wavelength: Option<f32>,
+    sampler: &mut dyn Sampler,
+) -> Vector3D {
+    let mut total = Vector3D::new(0., 0., 0.);
+    for light in scene.lights.list.iter() {
+        if let Some(sample) = light.sample(rec.p, sampler) {
+            let response = match rec.material.light_response(r, rec, sample.direction) {
+                Some(response) => response,
+                None => continue,
+            };
+            if response.max_component() <= 0. {
+                continue;
+            }
+            let shadow_ray = Ray::new(rec.p, sample.direction);
+            stats::count(Counter::ShadowRays);
+            let mut shadow_rec = HitRecord::new(rec.material.clone());
+            if !scene.world.hit(&shadow_ray, 0.001, sample.distance, &mut shadow_rec) {
+                let transmittance = scene.transmittance(&shadow_ray, 0.001, sample.distance, sampler);
+                total += at_wavelength(response, wavelength)
+                    * at_wavelength(sample.radiance, wavelength)
+                    * transmittance;
+            }
+        }
+    }
+    total
+}
+
+/// Light arriving directly from the punctual lights at `p` inside a medium, turned by its
+/// phase function into the unit `direction` the path came along
+pub fn medium_light(
+    p: Vector3D,
+    direction: Vector3D,
+    medium: &Medium,
+    scene: &Scene,
+    wavelength: Option<f32>,
+    sampler: &mut dyn Sampler,
+) -> Vector3D {
+    let mut total = Vector3D::new(0., 0., 0.);
+    for light in scene.lights.list.iter() {
+        if let Some(sample) = light.sample(p, sampler) {
+            let shadow_ray = Ray::new(p, sample.direction);
+            stats::count(Counter::ShadowRays);
+            let mut shadow_rec = HitRecord::new(Material::DummyMat {
+                albedo: Vector3D::new(0., 0., 0.),
+            });
+            if !scene.world.hit(&shadow_ray, 0.001, sample.distance, &mut shadow_rec) {
+                // Light coming from the light turns back along the path, so the angle between
+                // them is the one between the path and the way to the light
+                let phase = henyey_greenstein(sample.direction.dot(direction), medium.g);
+                let transmittance = scene.transmittance(&shadow_ray, 0.001, sample.distance, sampler);
+                total += at_wavelength(sample.radiance, wavelength) * phase * transmittance;
             }
         }
-        false => {  // Return background color
-            let unit_direction = unit_vector(r.direction());
-            let t = 0.5 * (unit_direction.y() + 1.);
-            Vector3D::new(1., 1., 1.) * (1. - t) + Vector3D::new(0.5, 0.7, 1.) * t
+    }
+    total
+}
+
+/// Color of the sky seen along a ray that escapes the scene
+pub fn background(r: &Ray) -> Vector3D {
+    let unit_direction = unit_vector(r.direction());
+    let t = 0.5 * (unit_direction.y() + 1.);
+    Vector3D::new(1., 1., 1.) * (1. - t) + Vector3D::new(0.5, 0.7, 1.) * t
+}
+
+/// Traces a path through the scene. Bounces are followed in a loop while tracking the
+/// throughput of the path, and once the path is `rr_min_depth` bounces long it is randomly
+/// terminated with probability based on how little it can still contribute. If the ray carries
+/// a wavelength the channels of the result hold the radiance at its hero wavelengths. What the
+/// ray hits or scatters off first is recorded in `aov`.
+pub fn color(
+    r: &Ray,
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: &mut dyn Sampler,
+    aov: &mut Aov,
+) -> Vector3D {
+    let wavelength = r.wavelength();
+    let mut ray = *r;
+    let mut throughput = Vector3D::new(1., 1., 1.);
+    let mut radiance = Vector3D::new(0., 0., 0.);
+    let mut depth = 0;
+    loop {
+        let mut rec = HitRecord::new(Material::DummyMat {  // Start with an empty material record
+            albedo: Vector3D::new(0., 0., 0.),
+        });
+        let hit = scene.world.hit(&ray, 0.001, f32::MAX, &mut rec);
+        let t_surface = if hit { rec.t } else { f32::MAX };
+        let scattered = match scene.collision(&ray, 0.001, t_surface, sampler) {
+            // Met a medium before reaching any surface
+            Some((t, medium)) => {
+                let p = ray.point_at_parameter(t);
+                let direction = unit_vector(ray.direction());
+                if depth == 0 {
+                    *aov = Aov::from_scatter(&ray, t, medium.albedo);
+                }
+                throughput *= at_wavelength(medium.albedo, wavelength);
+                radiance += throughput * medium_light(p, direction, medium, scene, wavelength, sampler);
+                if depth >= settings.max_depth {
+                    stats::count(Counter::TerminatedDepthCap);
+                    break;
+                }
+                Ray::new(p, sample_henyey_greenstein(direction, medium.g, sampler.get_2d()))
+            }
+            None if !hit => {
+                radiance += throughput * at_wavelength(background(&ray), wavelength);
+                stats::count(Counter::TerminatedMiss);
+                break;
+            }
+            None => {
+                rec.differentials = ray.differential().and_then(|d| d.at_surface(&rec));
+                if depth == 0 {
+                    *aov = Aov::from_hit(&rec, &ray);
+                }
+                radiance += throughput * direct_light(&ray, &rec, scene, wavelength, sampler);
+                if depth >= settings.max_depth {
+                    stats::count(Counter::TerminatedDepthCap);
+                    break;
+                }
+                let mut scattered = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., 0.));
+                let mut attenuation = Vector3D::new(0., 0., 0.);
+                if !rec.material
+                    .scatter(&ray, &rec, sampler, &mut attenuation, &mut scattered) {
+                    stats::count(Counter::TerminatedAbsorbed);
+                    break;
+                }
+                throughput *= attenuation;
+                scattered
+            }
+        };
+        depth += 1;
+        if depth >= settings.rr_min_depth {
+            let survive = throughput.max_component().min(0.95);
+            if sampler.get_1d() >= survive {
+                stats::count(Counter::TerminatedRoulette);
+                break;
+            }
+            throughput /= survive;  // Keep the estimate unbiased
         }
+        ray = scattered.with_wavelength(wavelength);
+        stats::count(Counter::BounceRays);
     }
+    stats::count(Counter::Paths);
+    stats::add(Counter::PathBounces, depth as u64);
+    radiance
 }
 
+/// Smallest width of a camera sample's ray footprint, as a fraction of a pixel. Past 64 samples
+/// the footprints stop shrinking, so textures stay prefiltered instead of being looked up in
+/// their full resolution image and left for the many samples to average.
+const MIN_FOOTPRINT: f32 = 0.125;
+
+/// Samples of pixel `(i, j)`, counting rows from the bottom, in linear colour along with the
+/// pixel's AOVs
 pub fn calculate_pixel(
-    ns: u32,
-    cam: &Camera,
-    world: &HitableList,
+    cam: &dyn CameraModel,
+    scene: &Scene,
+    settings: &RenderSettings,
     i: u32,
     j: u32,
-    nx: u32,
-    ny: u32,
-) -> [u8; 3] {
-    let mut col: Vector3D = (0..ns)
+) -> (Vec<FilmSample>, Aov) {
+    let (nx, ny, ns) = (settings.nx, settings.ny, settings.ns);
+    // Each sample only needs to cover its share of the pixel
+    let footprint = (1. / (ns as f32).sqrt()).max(MIN_FOOTPRINT);
+    let (du, dv) = (footprint / nx as f32, footprint / ny as f32);
+    let (samples, aovs): (Vec<FilmSample>, Vec<Aov>) = (0..ns)
         .into_par_iter()
-        .map_init(rand::thread_rng, |rng, _| -> Vector3D {
-            let u = (i as f32 + rng.gen::<f32>()) / (nx as f32);
-            let v = (j as f32 + rng.gen::<f32>()) / (ny as f32);
-            let r = cam.get_ray(u, v);
-            color(&r, &world, 0)
-        })
-        .sum();
-    col /= ns as f32;  // Antialiasing average
-    col = Vector3D::new(col.r().sqrt(), col.g().sqrt(), col.b().sqrt());
-    [
-        (255.99 as f32 * col.r()) as u8,
-        (255.99 as f32 * col.g()) as u8,
-        (255.99 as f32 * col.b()) as u8,
-    ]
+        .map_init(
+            || settings.sampler.build(ns),
+            |sampler, s| {
+                let sampler = sampler.as_mut();
+                sampler.start_sample(i, j, s);
+                let start = Instant::now();
+                let (dx, dy) = sampler.get_2d();
+                let (x, y) = (i as f32 + dx, j as f32 + dy);
+                let mut aov = Aov::empty();
+                let (u, v) = (x / nx as f32, y / ny as f32);
+                let r = match settings.texture_filter {
+                    TextureFilter::Point => cam.get_ray(u, v, sampler),
+                    _ => cam.get_ray_differential(u, v, du, dv, sampler),
+                };
+                let r = match r {
+                    Some(r) => r,
+                    None => {
+                        let color = Vector3D::new(0., 0., 0.);
+                        return (FilmSample { x, y, color }, aov);
+                    }
+                };
+                stats::count(Counter::CameraRays);
+                let color = match settings.spectral {
+                    true => {
+                        let wavelength = sample_wavelength(sampler.get_1d());
+                        let r = r.with_wavelength(Some(wavelength));
+                        spectral_to_rgb(wavelength, color(&r, scene, settings, sampler, &mut aov))
+                    }
+                    false => color(&r, scene, settings, sampler, &mut aov),
+                };
+                stats::busy(start.elapsed());
+                (FilmSample { x, y, color }, aov)
+            },
+        )
+        .unzip();
+    stats::add(Counter::Samples, ns as u64);
+    let aov = aovs.into_iter().fold(Aov::empty(), Aov::merge);
+    (samples, aov.average(ns))
 }
 
-pub fn render(cam: Camera, world: HitableList, nx: u32, ny: u32, ns: u32) -> Vec<u8> {
-    let mut all_pixels: Vec<u8> = vec![];
-
-    let full_pixels: Vec<Vec<[u8; 3]>> = (0..ny)
+/// Renders the image. Each row is rendered in parallel into its own band of rows, wide enough
+/// for the reconstruction filter to spread samples into neighbouring pixels, and each band is
+/// added to the image as soon as it's done.
+pub fn render(cam: &dyn CameraModel, scene: Scene, settings: &RenderSettings) -> Film {
+    let (nx, ny) = (settings.nx, settings.ny);
+    let reach = settings.filter.reach();
+    let image = Mutex::new(SplatBuffer::new(nx, 0, ny));
+    let rows: Vec<Vec<Aov>> = (0..ny)
         .into_par_iter()
-        .rev()
         .map(|j| {
-            let pixels: Vec<[u8; 3]> = (0..nx)
+            let pixels: Vec<(Vec<FilmSample>, Aov)> = (0..nx)
                 .into_par_iter()
-                .map(|i| calculate_pixel(ns, &cam, &world, i, j, nx, ny))
+                .map(|i| calculate_pixel(cam, &scene, settings, i, j))
                 .collect();
-            pixels
+            let mut band = SplatBuffer::new(nx, j as i32 - reach, 2 * reach as u32 + 1);
+            let mut aovs = Vec::with_capacity(nx as usize);
+            for (samples, aov) in pixels {
+                for s in samples {
+                    band.add(&settings.filter, s.x, s.y, s.color);
+                }
+                aovs.push(aov);
+            }
+            image.lock().unwrap().merge(&band);
+            aovs
         })
         .collect();
 
-    for inner_pixels in full_pixels {
-        for pixel in inner_pixels {
-            all_pixels.extend_from_slice(&pixel);
-        }
+    let image = image.into_inner().unwrap();
+    let aovs = rows.into_iter().rev().flatten().collect();
+    Film {
+        nx,
+        ny,
+        color: image.resolve(),
+        aovs,
     }
+}
 
-    all_pixels
+/// Reports an error the render can't go on after and quits
+fn exit<T>(e: String) -> T {
+    eprintln!("{}", e);
+    process::exit(1);
 }
 
 fn main() {
     let start = SystemTime::now();
 
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let settings = RenderSettings::from_args(&args).unwrap_or_else(exit);
+    let (nx, ny) = (settings.nx, settings.ny);
 
-    let nx = args[1].parse::<u32>().unwrap(); // image width
-    let ny = args[2].parse::<u32>().unwrap(); // image height
-    let mut ns: u32 = 100; // Default value of 100 otherwise
-    match args.len() { // Check if there is a third argument for antialiasing sample count
-        4 => {ns = args[3].parse::<u32>().unwrap();},
-        _ => (),
+    let ground = match &settings.ground_texture {
+        Some(path) => {
+            // Repeated every two units
+            let mapping = TextureMapping::Planar {
+                s: Vector3D::new(0.5, 0., 0.),
+                t: Vector3D::new(0., 0., 0.5),
+            };
+            let texture = Texture::from_png(path, mapping, settings.texture_filter)
+                .map_err(|e| format!("Can't read ground texture {}: {}", path, e))
+                .unwrap_or_else(exit);
+            Material::Textured { texture }
+        }
+        None => Material::Lambertian {
+            albedo: Vector3D::new(0.5, 0.5, 0.5),
+        },
+    };
+    let aperture = match (&settings.aperture_image, settings.blades) {
+        (Some(path), _) => Aperture::from_png(path)
+            .map_err(|e| format!("Can't read aperture image {}: {}", path, e))
+            .unwrap_or_else(exit),
+        (None, Some(blades)) => Aperture::Polygon {
+            blades,
+            rotation: settings.blade_rotation,
+        },
+        (None, None) => Aperture::Circle,
+    };
+    let animation = match &settings.animation {
+        Some(path) => Animation::from_file(path).unwrap_or_else(exit),
+        None => Animation::new(),
+    };
+    let seed = thread_rng().gen::<u64>();
+    let options = import_options(&settings).unwrap_or_else(exit);
+    let imported = load_scene(&settings, &options, &ground, seed).unwrap_or_else(exit);
+    for warning in imported.warnings.iter() {
+        eprintln!("Warning: {}", warning);
+    }
+    let base_pose = match imported.camera {
+        Some(pose) => CameraPose {
+            focus_distance: settings.focus_distance.unwrap_or(pose.focus_distance),
+            ..pose
+        },
+        None => scene_pose(&settings),
+    };
+    // Built once and shared by every frame, which only moves the animated objects
+    let objects: Vec<Arc<dyn Hitable>> = imported.objects.into_iter().map(Arc::from).collect();
+    for index in animation.objects.keys().filter(|i| **i >= objects.len()) {
+        eprintln!("Warning: the scene has no object {} to animate", index);
     }
 
-    let filename = "out.png";
-    let file = File::create(filename).unwrap();
-    let ref mut w = BufWriter::new(file);
-
-    let mut encoder = png::Encoder::new(w, nx as u32, ny as u32);
-    encoder.set_color(png::ColorType::RGB);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-
-    let world = random_scene();
-
-    let lookfrom = Vector3D::new(13., 2., 3.);
-    let lookat = Vector3D::new(0., 0., -1.);
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        Vector3D::new(0., 1., 0.),
-        30.,
-        nx as f32 / ny as f32,
-        0.1,
-        (lookfrom - Vector3D::new(4., 1., 0.)).length(),
-    );
+    if settings.stats || settings.stats_json.is_some() {
+        stats::enable();
+    }
+    let frames = settings.frames.or_else(|| animation.frame_range());
+    let outputs: Vec<(f32, String)> = match frames {
+        Some((first, last)) => (first..=last)
+            .map(|frame| (frame as f32, format!("out.{:04}", frame)))
+            .collect(),
+        None => vec![(0., "out".to_string())],
+    };
+    for (frame, base) in outputs {
+        if frames.is_some() && Path::new(&format!("{}.png", base)).exists() {
+            println!("Skipping frame {}, {}.png already exists", frame, base);
+            continue;
+        }
+        let pose = animation.camera.pose(base_pose, frame);
+        let cam = scene_camera(&settings, aperture.clone(), &pose);
+        let mut world = HitableList::new(vec![]);
+        for (index, object) in objects.iter().enumerate() {
+            let object: Box<dyn Hitable> = Box::new(object.clone());
+            world.list.push(match animation.objects.get(&index) {
+                Some(tracks) => {
+                    let transform = tracks.at(frame).unwrap_or_else(|| {
+                        exit(format!("Object {} is scaled to nothing at frame {}", index, frame))
+                    });
+                    Box::new(Transformed::new(object, transform))
+                }
+                None => object,
+            });
+        }
+        let scene = Scene::new(world, LightList::new(imported.lights.clone()))
+            .with_media(scene_media(&settings, &base_pose));
 
-    let pixels = render(cam, world, nx, ny, ns);
+        stats::reset();
+        let render_start = Instant::now();
+        let mut film = render(cam.as_ref(), scene, &settings);
+        let render_stats = RenderStats::collect(render_start.elapsed());
+        film.expose(settings.exposure());
+        if settings.denoise {
+            film.color = denoise(&film, &DenoiseSettings::default());
+        }
 
-    writer.write_image_data(&pixels).unwrap();
+        let written = write_png(&format!("{}.png", base), nx, ny, &film.to_rgb8())
+            .and_then(|_| match settings.aovs {
+                Some(AovOutput::Exr) => film.write_exr(&format!("{}.exr", base)),
+                Some(AovOutput::Png) => film.write_aov_pngs(&base),
+                None => Ok(()),
+            });
+        written
+            .map_err(|e| format!("Can't write {}: {}", base, e))
+            .unwrap_or_else(exit);
+        if frames.is_some() {
+            println!("Wrote {}.png", base);
+        }
+        if settings.stats {
+            print!("{}", render_stats.report());
+        }
+        if let Some(path) = &settings.stats_json {
+            let path = match frames {
+                Some(_) => numbered(path, frame as u32),
+                None => path.clone(),
+            };
+            fs::write(&path, render_stats.to_json())
+                .map_err(|e| format!("Can't write {}: {}", path, e))
+                .unwrap_or_else(exit);
+        }
+    }
     println!("Finished in {} ms", start.elapsed().unwrap().as_millis());
 }
+
+/// `path` with the frame number put before its extension, as in `stats.0001.json`
+fn numbered(path: &str, frame: u32) -> String {
+    let path = Path::new(path);
+    let stem = path.with_extension("");
+    match path.extension() {
+        Some(extension) => format!(
+            "{}.{:04}.{}",
+            stem.display(),
+            frame,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}.{:04}", stem.display(), frame),
+    }
+}
@@ -0,0 +1,577 @@
+use rand::{thread_rng, Rng};
+
+use crate::blue_noise::MASK;
+
+/// Hands out the random numbers a sample of a pixel consumes, one dimension at a time. Every
+/// sample of a pixel asks for its dimensions in the same order, so samplers that know about the
+/// other samples can spread each dimension evenly over `[0, 1)`.
+pub trait Sampler {
+    /// Starts sample `index` of pixel `(x, y)`, going back to the first dimension
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// The available samplers, selected by name on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered strata, shuffled independently for every dimension
+    Stratified,
+    /// Halton sequence, rotated randomly for every pixel
+    Halton,
+    /// Sobol sequence with hash based Owen scrambling, best with a power of two samples
+    Sobol,
+    /// Sobol points rotated by a blue noise mask, so the error looks like blue noise
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!("Unknown sampler {}", name)),
+        }
+    }
+
+    /// Makes a sampler for pixels taking `ns` samples each
+    pub fn build(&self, ns: u32) -> Box<dyn Sampler> {
+        let state = State::default();
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { ns, state }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler { state }),
+        }
+    }
+}
+
+/// Which sample of which pixel is being taken, and how many dimensions it has used
+#[derive(Clone, Copy, Default)]
+struct State {
+    pixel: u32,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl State {
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        *self = Self {
+            pixel: hash(&[x, y]),
+            x,
+            y,
+            index,
+            dimension: 0,
+        };
+    }
+
+    /// Claims the next `n` dimensions, returning the first
+    fn next(&mut self, n: u32) -> u32 {
+        self.dimension += n;
+        self.dimension - n
+    }
+}
+
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _x: u32, _y: u32, _index: u32) {}
+
+    fn get_1d(&mut self) -> f32 {
+        thread_rng().gen::<f32>()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let mut rng = thread_rng();
+        (rng.gen::<f32>(), rng.gen::<f32>())
+    }
+}
+
+/// Splits each dimension into `ns` strata, or a grid of at least `ns` cells in 2D, and gives
+/// every sample its own stratum with a random offset inside it. Strata are assigned in a
+/// different order for every pixel and dimension so dimensions don't correlate.
+pub struct StratifiedSampler {
+    ns: u32,
+    state: State,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let s = self.state;
+        let seed = hash(&[s.pixel, self.state.next(1)]);
+        let stratum = permute(s.index, self.ns, seed);
+        (stratum as f32 + to_float(hash(&[seed, s.index]))) / self.ns as f32
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let s = self.state;
+        let seed = hash(&[s.pixel, self.state.next(2)]);
+        let nx = (self.ns as f32).sqrt().ceil() as u32;
+        let ny = self.ns.div_ceil(nx);
+        let cell = permute(s.index, nx * ny, seed);
+        let jitter = hash(&[seed, s.index]);
+        (
+            ((cell % nx) as f32 + to_float(jitter)) / nx as f32,
+            ((cell / nx) as f32 + to_float(hash(&[jitter]))) / ny as f32,
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Radical inverses of the sample index in successive prime bases, shifted by a random amount
+/// in every pixel. Past the last prime the dimensions are plain random numbers.
+pub struct HaltonSampler {
+    state: State,
+}
+
+impl HaltonSampler {
+    fn sample(&mut self) -> f32 {
+        let s = self.state;
+        let dimension = self.state.next(1);
+        let shift = to_float(hash(&[s.pixel, dimension]));
+        match PRIMES.get(dimension as usize) {
+            Some(base) => (radical_inverse(*base, s.index) + shift).fract(),
+            None => to_float(hash(&[s.pixel, dimension, s.index])),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.sample(), self.sample())
+    }
+}
+
+/// Owen scrambled Sobol points (Burley 2020). Each 1D or 2D request gets the first dimensions
+/// of the Sobol sequence, with the order of the samples shuffled and the points scrambled by
+/// seeds unique to the pixel and dimension.
+pub struct SobolSampler {
+    state: State,
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let s = self.state;
+        sobol_1d(s.index, hash(&[s.pixel, self.state.next(1)]))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let s = self.state;
+        sobol_2d(s.index, hash(&[s.pixel, self.state.next(2)]))
+    }
+}
+
+/// Sobol points which are the same in every pixel, each rotated by the value a blue noise mask
+/// has at the pixel (Georgiev and Fajardo 2016). Neighbouring pixels get very different
+/// rotations, so the error of the image is spread out as high frequency noise that is less
+/// visible and easier to filter.
+pub struct BlueNoiseSampler {
+    state: State,
+}
+
+impl BlueNoiseSampler {
+    /// Rotation for one dimension, read from the mask at an offset chosen by the dimension
+    fn shift(&self, dimension: u32) -> f32 {
+        let offset = hash(&[dimension, 0xb1_0e]);
+        let x = (self.state.x % MASK_SIZE + offset % MASK_SIZE) % MASK_SIZE;
+        let y = (self.state.y % MASK_SIZE + (offset >> 16) % MASK_SIZE) % MASK_SIZE;
+        let rank = MASK[(y * MASK_SIZE + x) as usize];
+        (rank as f32 + 0.5) / (MASK_SIZE * MASK_SIZE) as f32
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.next(1);
+        let u = sobol_1d(self.state.index, hash(&[dimension]));
+        (u + self.shift(dimension)).fract()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let dimension = self.state.next(2);
+        let (u, v) = sobol_2d(self.state.index, hash(&[dimension]));
+        (
+            (u + self.shift(dimension)).fract(),
+            (v + self.shift(dimension + 1)).fract(),
+        )
+    }
+}
+
+/// Mixes a list of values into a well distributed 32 bit hash
+fn hash(values: &[u32]) -> u32 {
+    let mut h = 0x9e37_79b9u32;
+    for v in values {
+        h ^= v.wrapping_mul(0x85eb_ca6b);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846c_a68b);
+        h ^= h >> 16;
+    }
+    h
+}
+
+/// Uses the top 24 bits, as many as an `f32` holds, as a number in `[0, 1)`
+fn to_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Element `i` of a random permutation of `0..n` chosen by `seed` (Kensler 2013)
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        // Values outside the range are walked until they land inside it
+        if i < n {
+            return (i.wrapping_add(seed)) % n;
+        }
+    }
+}
+
+/// Mirrors the digits of `index` in `base` about the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_n = 1.;
+    let mut reversed = 0u64;
+    while index > 0 {
+        reversed = reversed * base as u64 + (index % base) as u64;
+        index /= base;
+        inv_base_n *= inv_base;
+    }
+    ((reversed as f64 * inv_base_n) as f32).min(1. - f32::EPSILON / 2.)
+}
+
+/// First two dimensions of the Sobol sequence, as 32 bit fixed point fractions
+fn sobol(index: u32) -> (u32, u32) {
+    let (mut y, mut v, mut i) = (0u32, 1u32 << 31, index);
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+/// Shuffles the bits of `x` so every bit only depends on the ones below it (Laine and Karras)
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Owen scrambling of a fixed point fraction: every bit is flipped depending on the bits above
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn sobol_1d(index: u32, seed: u32) -> f32 {
+    let index = nested_uniform_scramble(index, seed);
+    to_float(nested_uniform_scramble(sobol(index).0, hash(&[seed, 0])))
+}
+
+fn sobol_2d(index: u32, seed: u32) -> (f32, f32) {
+    let (x, y) = sobol(nested_uniform_scramble(index, seed));
+    (
+        to_float(nested_uniform_scramble(x, hash(&[seed, 0]))),
+        to_float(nested_uniform_scramble(y, hash(&[seed, 1]))),
+    )
+}
+
+const MASK_SIZE: u32 = 64;
+
+/// Ranks the pixels of an `n` by `n` torus so that every prefix of the ranking is spread out
+/// evenly. Each pixel's energy sums a gaussian of width `sigma` around every chosen pixel.
+/// This is how the mask in `blue_noise` was made.
+#[cfg(test)]
+fn void_and_cluster(n: u32, sigma: f32) -> Vec<u32> {
+    let size = (n * n) as usize;
+    // Energy a chosen pixel adds to one `(dx, dy)` away, wrapping around the edges
+    let mut kernel = vec![0.; size];
+    for dy in 0..n {
+        for dx in 0..n {
+            let wx = dx.min(n - dx) as f32;
+            let wy = dy.min(n - dy) as f32;
+            kernel[(dy * n + dx) as usize] = (-(wx * wx + wy * wy) / (2. * sigma * sigma)).exp();
+        }
+    }
+    let toggle = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p as u32 % n, p as u32 / n);
+        for y in 0..n {
+            for x in 0..n {
+                let d = ((y + n - py) % n * n + (x + n - px) % n) as usize;
+                energy[(y * n + x) as usize] += sign * kernel[d];
+            }
+        }
+    };
+    // The most crowded chosen pixel, or the emptiest unchosen one
+    let extreme = |energy: &[f32], chosen: &[bool], want: bool| {
+        (0..size)
+            .filter(|p| chosen[*p] == want)
+            .max_by(|a, b| {
+                let (ea, eb) = match want {
+                    true => (energy[*a], energy[*b]),
+                    false => (-energy[*a], -energy[*b]),
+                };
+                ea.partial_cmp(&eb).unwrap()
+            })
+            .unwrap()
+    };
+
+    // Start from a tenth of the pixels picked at random, then move the most crowded of them
+    // into the biggest gap until that doesn't change anything
+    let mut chosen = vec![false; size];
+    let mut energy = vec![0.; size];
+    let mut count = 0;
+    let mut i = 0;
+    while count < size / 10 {
+        let p = (hash(&[i, 0x5eed]) % size as u32) as usize;
+        i += 1;
+        if !chosen[p] {
+            chosen[p] = true;
+            toggle(&mut energy, p, 1.);
+            count += 1;
+        }
+    }
+    loop {
+        let cluster = extreme(&energy, &chosen, true);
+        chosen[cluster] = false;
+        toggle(&mut energy, cluster, -1.);
+        let void = extreme(&energy, &chosen, false);
+        chosen[void] = true;
+        toggle(&mut energy, void, 1.);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; size];
+    // Rank the initial pixels by taking away the most crowded first
+    let (mut removing, mut removing_energy) = (chosen.clone(), energy.clone());
+    for r in (0..count).rev() {
+        let cluster = extreme(&removing_energy, &removing, true);
+        removing[cluster] = false;
+        toggle(&mut removing_energy, cluster, -1.);
+        rank[cluster] = r as u32;
+    }
+    // Then fill in the rest, biggest gap first
+    for r in count..size {
+        let void = extreme(&energy, &chosen, false);
+        chosen[void] = true;
+        toggle(&mut energy, void, 1.);
+        rank[void] = r as u32;
+    }
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    /// The 2D samples of one dimension of a pixel
+    fn points(kind: SamplerKind, ns: u32, skip: u32) -> Vec<(f32, f32)> {
+        let mut sampler = kind.build(ns);
+        (0..ns)
+            .map(|i| {
+                sampler.start_sample(3, 5, i);
+                for _ in 0..skip {
+                    sampler.get_1d();
+                }
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    /// Number of cells of an `n` by `n` grid holding no points
+    fn empty_cells(points: &[(f32, f32)], n: u32) -> usize {
+        let mut filled = vec![false; (n * n) as usize];
+        for (u, v) in points {
+            filled[((v * n as f32) as u32 * n + (u * n as f32) as u32) as usize] = true;
+        }
+        filled.iter().filter(|f| !**f).count()
+    }
+
+    #[test]
+    fn test_range() {
+        for kind in ALL.iter() {
+            let mut sampler = kind.build(16);
+            for i in 0..16 {
+                sampler.start_sample(i, 2 * i, i);
+                for _ in 0..40 {
+                    let u = sampler.get_1d();
+                    let (v, w) = sampler.get_2d();
+                    for x in [u, v, w].iter() {
+                        assert!(*x >= 0. && *x < 1., "{:?} gave {}", kind, x);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(SamplerKind::from_name("sobol"), Ok(SamplerKind::Sobol));
+        assert_eq!(
+            SamplerKind::from_name("blue-noise"),
+            Ok(SamplerKind::BlueNoise)
+        );
+        assert!(SamplerKind::from_name("poisson").is_err());
+    }
+
+    #[test]
+    fn test_stratified_covers_every_stratum() {
+        let mut sampler = SamplerKind::Stratified.build(10);
+        let mut strata = vec![0; 10];
+        for i in 0..10 {
+            sampler.start_sample(1, 1, i);
+            strata[(sampler.get_1d() * 10.) as usize] += 1;
+        }
+        assert_eq!(strata, vec![1; 10]);
+        assert_eq!(empty_cells(&points(SamplerKind::Stratified, 16, 1), 4), 0);
+    }
+
+    #[test]
+    fn test_low_discrepancy_grids() {
+        // Scrambled Sobol is a (0, 2) sequence, so 16 points fill every elementary interval
+        for skip in 0..3 {
+            let p = points(SamplerKind::Sobol, 16, skip);
+            assert_eq!(empty_cells(&p, 4), 0);
+            let wide: Vec<(f32, f32)> = p.iter().map(|(u, v)| (*u, v / 4.)).collect();
+            assert_eq!(empty_cells(&wide, 4), 12);
+        }
+        // Halton in bases 2 and 3 fills a 2 by 3 grid with every 6 points
+        let mut cells = vec![0; 6];
+        for i in 0..6 {
+            let (u, v) = (radical_inverse(2, i), radical_inverse(3, i));
+            cells[(v * 3.) as usize * 2 + (u * 2.) as usize] += 1;
+        }
+        assert_eq!(cells, vec![1; 6]);
+        // and rotating it in a pixel keeps the first dimension evenly spread
+        let mut strata = vec![0; 8];
+        for (u, _) in points(SamplerKind::Halton, 8, 0) {
+            strata[(u * 8.) as usize] += 1;
+        }
+        assert_eq!(strata, vec![1; 8]);
+    }
+
+    #[test]
+    fn test_better_than_independent() {
+        // Average squared error integrating a smooth function over the square, across pixels
+        let f = |(u, v): (f32, f32)| (u * 3.).sin() * v * v;
+        let exact = (1. - 3f32.cos()) / 3. / 3.;
+        let error = |kind: SamplerKind| {
+            let mut sampler = kind.build(64);
+            let mut total = 0.;
+            for pixel in 0..64 {
+                let mut sum = 0.;
+                for i in 0..64 {
+                    sampler.start_sample(pixel, 7, i);
+                    sum += f(sampler.get_2d());
+                }
+                total += (sum / 64. - exact).powi(2);
+            }
+            total / 64.
+        };
+        let independent = error(SamplerKind::Independent);
+        for kind in ALL[1..].iter() {
+            assert!(error(*kind) < independent / 4., "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_permute() {
+        for n in [1, 5, 16, 100].iter() {
+            let mut seen: Vec<u32> = (0..*n).map(|i| permute(i, *n, 1234)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..*n).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn test_blue_noise_mask() {
+        let mask: Vec<u32> = MASK.iter().map(|r| *r as u32).collect();
+        let mut ranks = mask.clone();
+        ranks.sort();
+        assert_eq!(ranks, (0..MASK_SIZE * MASK_SIZE).collect::<Vec<u32>>());
+        assert_eq!(mask, void_and_cluster(MASK_SIZE, 1.5));
+        // Blue noise has little low frequency content, so neighbours differ far more than the
+        // third of the range white noise would give
+        let n = MASK_SIZE as usize;
+        let mut difference = 0.;
+        for y in 0..n {
+            for x in 0..n {
+                let d = mask[y * n + x] as f32 - mask[y * n + (x + 1) % n] as f32;
+                difference += d.abs();
+            }
+        }
+        let mean = difference / (n * n) as f32 / (n * n) as f32;
+        assert!(mean > 0.4, "{}", mean);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::lights::light::LightList;
+use crate::sampler::Sampler;
+use crate::shapes::hitable::HitableList;
+use crate::util::ray::Ray;
+use crate::volume::Medium;
+
+/// Everything the integrator needs to know about the world: the geometry rays can hit, the
+/// punctual lights that are sampled explicitly and the media light travels through.
+pub struct Scene {
+    pub world: HitableList,
+    pub lights: LightList,
+    pub media: Vec<Medium>,
+}
+
+impl Scene {
+    pub fn new(world: HitableList, lights: LightList) -> Self {
+        Self {
+            world,
+            lights,
+            media: vec![],
+        }
+    }
+
+    pub fn with_media(mut self, media: Vec<Medium>) -> Self {
+        self.media = media;
+        self
+    }
+
+    /// Where between `t_min` and `t_max` the ray first meets any medium, and which. Each
+    /// medium is tracked on its own and the nearest meeting kept, which is the same as
+    /// tracking them all together.
+    pub fn collision(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(f32, &Medium)> {
+        let mut nearest: Option<(f32, &Medium)> = None;
+        for medium in self.media.iter() {
+            let t_max = nearest.map_or(t_max, |n| n.0);
+            if let Some(t) = medium.sample_collision(r, t_min, t_max, sampler) {
+                nearest = Some((t, medium));
+            }
+        }
+        nearest
+    }
+
+    /// Estimate of the fraction of light getting through the media between `t_min` and `t_max`
+    pub fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> f32 {
+        self.media
+            .iter()
+            .map(|m| m.transmittance(r, t_min, t_max, sampler))
+            .product()
+    }
+}
@@ -0,0 +1,494 @@
+use crate::filter::Filter;
+use crate::sampler::SamplerKind;
+use crate::shapes::subdivision::Scheme;
+use crate::util::camera::{Exposure, PhysicalLens, Projection};
+use crate::util::texture::TextureFilter;
+use crate::volume::VolumeKind;
+
+/// Most times meshes can be subdivided. Each level quadruples the faces, so six already turns a
+/// single triangle into 4096.
+pub const MAX_SUBDIVISION_LEVELS: u32 = 6;
+
+/// Where arbitrary output variables are written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AovOutput {
+    /// As extra layers of `out.exr` alongside the beauty
+    Exr,
+    /// As separate `out.<aov>.png` images
+    Png,
+}
+
+/// Options controlling a render, read from the command line as
+/// `width height [samples] [--flag value ...]`.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    /// Image width in pixels
+    pub nx: u32,
+    /// Image height in pixels
+    pub ny: u32,
+    /// Samples per pixel
+    pub ns: u32,
+    /// Bounce after which paths start being terminated by russian roulette
+    pub rr_min_depth: u32,
+    /// Hard cap on the number of bounces of a path
+    pub max_depth: u32,
+    /// Trace three hero wavelengths per path instead of RGB, so dispersion is visible
+    pub spectral: bool,
+    /// Print render statistics after each frame
+    pub stats: bool,
+    /// File to write render statistics to as JSON, numbered per frame for image sequences
+    pub stats_json: Option<String>,
+    /// Also write normals, depth, position, albedo, IDs and coverage
+    pub aovs: Option<AovOutput>,
+    /// Filter the noise out of the image using its albedo, normal and depth
+    pub denoise: bool,
+    /// How samples are weighted into the pixels around them
+    pub filter: Filter,
+    /// Where in the pixel, on the lens and so on each sample is taken
+    pub sampler: SamplerKind,
+    /// How the camera projects the scene onto the image
+    pub projection: Projection,
+    /// Focal length in millimetres. When set the perspective camera is built from the lens and
+    /// sensor rather than a field of view and aperture.
+    pub focal_length: Option<f32>,
+    /// Sensor width and height in millimetres
+    pub sensor: (f32, f32),
+    /// Aperture as an f-number, used for depth of field and exposure
+    pub f_number: f32,
+    /// Distance in metres to the plane in focus, overriding the scene's
+    pub focus_distance: Option<f32>,
+    /// Shutter time in seconds
+    pub shutter: Option<f32>,
+    pub iso: Option<f32>,
+    /// Number of diaphragm blades, giving polygonal bokeh
+    pub blades: Option<u32>,
+    /// Rotation of the blades in degrees
+    pub blade_rotation: f32,
+    /// Grayscale image giving the shape of the aperture
+    pub aperture_image: Option<String>,
+    /// Strength of the cat's eye vignetting of the bokeh towards the corners
+    pub cat_eye: f32,
+    /// Tilt of the lens in degrees, tilting the plane in focus
+    pub tilt: f32,
+    /// How image textures are filtered. Anything but point sampling traces ray differentials.
+    pub texture_filter: TextureFilter,
+    /// Image tiled over the ground
+    pub ground_texture: Option<String>,
+    /// File of keyframes moving the camera and objects
+    pub animation: Option<String>,
+    /// First and last frame to render as a numbered image sequence
+    pub frames: Option<(u32, u32)>,
+    /// Scene file to render instead of the random spheres
+    pub scene: Option<String>,
+    /// Scheme to subdivide the scene's meshes by
+    pub subdivision: Option<Scheme>,
+    pub subdivision_levels: u32,
+    /// Grayscale image displacing the scene's meshes along their normals
+    pub displacement: Option<String>,
+    /// Distance the brightest part of the displacement image moves the surface
+    pub displacement_scale: f32,
+    /// Medium added around where the camera looks
+    pub volume: Option<VolumeKind>,
+    /// Light the medium meets per unit distance where it's densest
+    pub volume_density: f32,
+    /// Henyey-Greenstein asymmetry of the medium's scattering, from -1 to 1
+    pub volume_anisotropy: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            nx: 200,
+            ny: 100,
+            ns: 100,
+            rr_min_depth: 5,
+            max_depth: 50,
+            spectral: false,
+            stats: false,
+            stats_json: None,
+            aovs: None,
+            denoise: false,
+            filter: Filter::Box { radius: 0.5 },
+            sampler: SamplerKind::Independent,
+            projection: Projection::Perspective,
+            focal_length: None,
+            sensor: (36., 24.),
+            f_number: 16.,
+            focus_distance: None,
+            shutter: None,
+            iso: None,
+            blades: None,
+            blade_rotation: 0.,
+            aperture_image: None,
+            cat_eye: 0.,
+            tilt: 0.,
+            texture_filter: TextureFilter::Point,
+            ground_texture: None,
+            animation: None,
+            scene: None,
+            subdivision: None,
+            subdivision_levels: 2,
+            displacement: None,
+            displacement_scale: 0.1,
+            volume: None,
+            volume_density: 1.,
+            volume_anisotropy: 0.,
+            frames: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Parses the program arguments, not including the program name.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut settings = Self::default();
+        let mut positional = vec![];
+        let mut filter = "box".to_string();
+        let mut filter_radius = None;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+            let mut value = || {
+                iter.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--min-depth" => settings.rr_min_depth = parse(arg, value()?)?,
+                "--max-depth" => settings.max_depth = parse(arg, value()?)?,
+                "--spectral" => settings.spectral = true,
+                "--stats" => settings.stats = true,
+                "--denoise" => settings.denoise = true,
+                "--stats-json" => settings.stats_json = Some(value()?.clone()),
+                "--filter" => filter = value()?.clone(),
+                "--filter-radius" => filter_radius = Some(parse(arg, value()?)?),
+                "--camera" => settings.projection = Projection::from_name(value()?)?,
+                "--focal-length" => settings.focal_length = Some(parse(arg, value()?)?),
+                "--sensor" => settings.sensor = parse_size(arg, value()?)?,
+                "--f-stop" => settings.f_number = parse(arg, value()?)?,
+                "--focus-distance" => settings.focus_distance = Some(parse(arg, value()?)?),
+                "--shutter" => settings.shutter = Some(parse_fraction(arg, value()?)?),
+                "--iso" => settings.iso = Some(parse(arg, value()?)?),
+                "--blades" => settings.blades = Some(parse(arg, value()?)?),
+                "--blade-rotation" => settings.blade_rotation = parse(arg, value()?)?,
+                "--aperture-image" => settings.aperture_image = Some(value()?.clone()),
+                "--cat-eye" => settings.cat_eye = parse(arg, value()?)?,
+                "--tilt" => settings.tilt = parse(arg, value()?)?,
+                "--texture-filter" => {
+                    settings.texture_filter = TextureFilter::from_name(value()?)?
+                }
+                "--ground-texture" => settings.ground_texture = Some(value()?.clone()),
+                "--animation" => settings.animation = Some(value()?.clone()),
+                "--scene" => settings.scene = Some(value()?.clone()),
+                "--subdivide" => settings.subdivision = Some(Scheme::from_name(value()?)?),
+                "--subdivision-levels" => {
+                    let levels = parse(arg, value()?)?;
+                    if levels > MAX_SUBDIVISION_LEVELS {
+                        return Err(format!(
+                            "{} can be at most {}",
+                            arg, MAX_SUBDIVISION_LEVELS
+                        ));
+                    }
+                    settings.subdivision_levels = levels;
+                }
+                "--displacement" => settings.displacement = Some(value()?.clone()),
+                "--displacement-scale" => settings.displacement_scale = parse(arg, value()?)?,
+                "--volume" => settings.volume = Some(VolumeKind::from_name(value()?)?),
+                "--volume-density" => settings.volume_density = parse(arg, value()?)?,
+                "--volume-anisotropy" => {
+                    let g: f32 = parse(arg, value()?)?;
+                    if g.abs() >= 1. {
+                        return Err(format!("{} must be between -1 and 1", arg));
+                    }
+                    settings.volume_anisotropy = g;
+                }
+                "--frames" => settings.frames = Some(parse_range(arg, value()?)?),
+                "--sampler" => settings.sampler = SamplerKind::from_name(value()?)?,
+                "--aovs" => {
+                    settings.aovs = match value()?.as_str() {
+                        "exr" => Some(AovOutput::Exr),
+                        "png" => Some(AovOutput::Png),
+                        v => return Err(format!("Invalid value '{}' for {}", v, arg)),
+                    }
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        match positional.len() {
+            2 | 3 => {
+                settings.nx = parse("width", positional[0])?;
+                settings.ny = parse("height", positional[1])?;
+                if positional.len() == 3 {
+                    settings.ns = parse("samples", positional[2])?;
+                }
+            }
+            _ => return Err("Expected: width height [samples] [options]".to_string()),
+        }
+        settings.filter = Filter::from_name(&filter, filter_radius)?;
+        let lengths = [
+            settings.focal_length.unwrap_or(1.),
+            settings.sensor.0,
+            settings.sensor.1,
+            settings.f_number,
+            settings.focus_distance.unwrap_or(1.),
+            settings.shutter.unwrap_or(1.),
+            settings.iso.unwrap_or(1.),
+        ];
+        if lengths.iter().any(|l| *l <= 0.) {
+            return Err("Camera settings must be positive".to_string());
+        }
+        if settings.blades.is_some_and(|b| b < 3) {
+            return Err("An aperture needs at least 3 blades".to_string());
+        }
+        Ok(settings)
+    }
+
+    /// The lens and sensor of a physical camera focused at `focus_distance`, unless the
+    /// settings override it, if a focal length was given
+    pub fn lens(&self, focus_distance: f32) -> Option<PhysicalLens> {
+        let focal_length = self.focal_length?;
+        Some(PhysicalLens {
+            focal_length,
+            sensor_width: self.sensor.0,
+            sensor_height: self.sensor.1,
+            f_number: self.f_number,
+            focus_distance: self.focus_distance.unwrap_or(focus_distance),
+        })
+    }
+
+    /// How to expose the image, only if a shutter time or ISO was given, so other renders keep
+    /// their brightness whatever the lens. The other defaults to the sunny 16 rule's 1/100s or
+    /// ISO 100.
+    pub fn exposure(&self) -> Option<Exposure> {
+        if self.shutter.is_none() && self.iso.is_none() {
+            return None;
+        }
+        Some(Exposure {
+            shutter: self.shutter.unwrap_or(0.01),
+            iso: self.iso.unwrap_or(100.),
+            f_number: self.f_number,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, name))
+}
+
+/// Parses a number that may be written as a fraction, like a shutter time of `1/60`
+fn parse_fraction(name: &str, value: &str) -> Result<f32, String> {
+    match value.split_once('/') {
+        Some((n, d)) => Ok(parse::<f32>(name, n)? / parse::<f32>(name, d)?),
+        None => parse(name, value),
+    }
+}
+
+/// Parses a size written as `<width>x<height>`
+fn parse_size(name: &str, value: &str) -> Result<(f32, f32), String> {
+    match value.split_once('x') {
+        Some((w, h)) => Ok((parse(name, w)?, parse(name, h)?)),
+        None => Err(format!("Invalid value '{}' for {}", value, name)),
+    }
+}
+
+/// Parses an inclusive range written as `<first>-<last>`, or a single number
+fn parse_range(name: &str, value: &str) -> Result<(u32, u32), String> {
+    let (first, last) = match value.split_once('-') {
+        Some((first, last)) => (parse(name, first)?, parse(name, last)?),
+        None => {
+            let frame = parse(name, value)?;
+            (frame, frame)
+        }
+    };
+    match first <= last {
+        true => Ok((first, last)),
+        false => Err(format!("Invalid value '{}' for {}", value, name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_positional() {
+        let s = RenderSettings::from_args(&args("640 480")).unwrap();
+        assert_eq!((s.nx, s.ny, s.ns), (640, 480, 100));
+        let s = RenderSettings::from_args(&args("640 480 8")).unwrap();
+        assert_eq!(s.ns, 8);
+        assert!(RenderSettings::from_args(&args("640")).is_err());
+    }
+
+    #[test]
+    fn test_depth_options() {
+        let s = RenderSettings::from_args(&args("64 48 --min-depth 3 --max-depth 10")).unwrap();
+        assert_eq!(s.rr_min_depth, 3);
+        assert_eq!(s.max_depth, 10);
+        assert!(RenderSettings::from_args(&args("64 48 --max-depth")).is_err());
+        assert!(RenderSettings::from_args(&args("64 48 --bogus 1")).is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        assert!(!RenderSettings::from_args(&args("64 48")).unwrap().spectral);
+        let s = RenderSettings::from_args(&args("64 48 --spectral 4")).unwrap();
+        assert!(s.spectral);
+        assert_eq!(s.ns, 4);
+        let s = RenderSettings::from_args(&args("64 48 --stats --stats-json s.json")).unwrap();
+        assert!(s.stats);
+        assert_eq!(s.stats_json, Some("s.json".to_string()));
+        assert!(!s.denoise);
+        let s = RenderSettings::from_args(&args("64 48 --denoise")).unwrap();
+        assert!(s.denoise);
+    }
+
+    #[test]
+    fn test_filter() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.filter, Filter::Box { radius: 0.5 });
+        let s = RenderSettings::from_args(&args("64 48 --filter-radius 3 --filter tent")).unwrap();
+        assert_eq!(s.filter, Filter::Tent { radius: 3. });
+        assert!(RenderSettings::from_args(&args("64 48 --filter sinc")).is_err());
+    }
+
+    #[test]
+    fn test_sampler() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.sampler, SamplerKind::Independent);
+        let s = RenderSettings::from_args(&args("64 48 16 --sampler halton")).unwrap();
+        assert_eq!(s.sampler, SamplerKind::Halton);
+        assert!(RenderSettings::from_args(&args("64 48 --sampler random")).is_err());
+    }
+
+    #[test]
+    fn test_camera() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.projection, Projection::Perspective);
+        let s = RenderSettings::from_args(&args("128 64 --camera equirectangular")).unwrap();
+        assert_eq!(s.projection, Projection::Equirectangular);
+        assert!(RenderSettings::from_args(&args("64 48 --camera pinhole")).is_err());
+    }
+
+    #[test]
+    fn test_physical_camera() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.lens(5.), None);
+        assert_eq!(s.exposure(), None);
+        let s = RenderSettings::from_args(&args(
+            "64 48 --focal-length 35 --sensor 23.5x15.6 --f-stop 2.8 --shutter 1/60 --iso 400",
+        ))
+        .unwrap();
+        let lens = s.lens(5.).unwrap();
+        assert_eq!((lens.focal_length, lens.sensor_width), (35., 23.5));
+        assert_eq!((lens.f_number, lens.focus_distance), (2.8, 5.));
+        let exposure = s.exposure().unwrap();
+        assert!((exposure.shutter - 1. / 60.).abs() < 1e-7);
+        assert_eq!((exposure.iso, exposure.f_number), (400., 2.8));
+        let s = RenderSettings::from_args(&args("64 48 --iso 200 --focus-distance 2")).unwrap();
+        assert_eq!(s.exposure().unwrap().shutter, 0.01);
+        assert_eq!(s.focus_distance, Some(2.));
+        assert!(RenderSettings::from_args(&args("64 48 --sensor 36")).is_err());
+        assert!(RenderSettings::from_args(&args("64 48 --f-stop 0")).is_err());
+    }
+
+    #[test]
+    fn test_lens_effects() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!((s.blades, s.cat_eye, s.tilt), (None, 0., 0.));
+        let s = RenderSettings::from_args(&args(
+            "64 48 --blades 6 --blade-rotation 15 --cat-eye 0.5 --tilt -8 --aperture-image a.png",
+        ))
+        .unwrap();
+        assert_eq!((s.blades, s.blade_rotation), (Some(6), 15.));
+        assert_eq!((s.cat_eye, s.tilt), (0.5, -8.));
+        assert_eq!(s.aperture_image, Some("a.png".to_string()));
+        assert!(RenderSettings::from_args(&args("64 48 --blades 2")).is_err());
+    }
+
+    #[test]
+    fn test_textures() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.texture_filter, TextureFilter::Point);
+        assert_eq!(s.ground_texture, None);
+        let s = RenderSettings::from_args(&args(
+            "64 48 --texture-filter trilinear --ground-texture grid.png",
+        ))
+        .unwrap();
+        assert_eq!(s.texture_filter, TextureFilter::Trilinear);
+        assert_eq!(s.ground_texture, Some("grid.png".to_string()));
+        assert!(RenderSettings::from_args(&args("64 48 --texture-filter box")).is_err());
+    }
+
+    #[test]
+    fn test_animation() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!((s.animation, s.frames), (None, None));
+        let s = RenderSettings::from_args(&args("64 48 --animation keys.txt --frames 1-48"))
+            .unwrap();
+        assert_eq!(s.animation, Some("keys.txt".to_string()));
+        assert_eq!(s.frames, Some((1, 48)));
+        let s = RenderSettings::from_args(&args("64 48 --frames 7")).unwrap();
+        assert_eq!(s.frames, Some((7, 7)));
+        assert!(RenderSettings::from_args(&args("64 48 --frames 9-3")).is_err());
+        assert!(RenderSettings::from_args(&args("64 48 --frames 1-x")).is_err());
+    }
+
+    #[test]
+    fn test_scene() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.scene, None);
+        let s = RenderSettings::from_args(&args("64 48 --scene robot.glb")).unwrap();
+        assert_eq!(s.scene, Some("robot.glb".to_string()));
+        assert!(RenderSettings::from_args(&args("64 48 --scene")).is_err());
+    }
+
+    #[test]
+    fn test_subdivision() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.subdivision, None);
+        assert_eq!(s.displacement, None);
+        let s = RenderSettings::from_args(&args(
+            "64 48 --subdivide catmull-clark --subdivision-levels 3 --displacement bumps.png --displacement-scale 0.05",
+        ))
+        .unwrap();
+        assert_eq!(s.subdivision, Some(Scheme::CatmullClark));
+        assert_eq!(s.subdivision_levels, 3);
+        assert_eq!(s.displacement, Some("bumps.png".to_string()));
+        assert_eq!(s.displacement_scale, 0.05);
+        assert!(RenderSettings::from_args(&args("64 48 --subdivide butterfly")).is_err());
+        // Every level has four times the faces, so a dozen would never fit in memory
+        assert!(RenderSettings::from_args(&args("64 48 --subdivision-levels 12")).is_err());
+    }
+
+    #[test]
+    fn test_volume() {
+        let s = RenderSettings::from_args(&args("64 48")).unwrap();
+        assert_eq!(s.volume, None);
+        let s = RenderSettings::from_args(&args(
+            "64 48 --volume cloud --volume-density 2.5 --volume-anisotropy 0.6",
+        ))
+        .unwrap();
+        assert_eq!(s.volume, Some(VolumeKind::Cloud));
+        assert_eq!(s.volume_density, 2.5);
+        assert_eq!(s.volume_anisotropy, 0.6);
+        assert!(RenderSettings::from_args(&args("64 48 --volume steam")).is_err());
+        assert!(RenderSettings::from_args(&args("64 48 --volume-anisotropy 1")).is_err());
+    }
+
+    #[test]
+    fn test_aovs() {
+        let s = RenderSettings::from_args(&args("64 48 --aovs exr")).unwrap();
+        assert_eq!(s.aovs, Some(AovOutput::Exr));
+        let s = RenderSettings::from_args(&args("64 48 --aovs png")).unwrap();
+        assert_eq!(s.aovs, Some(AovOutput::Png));
+        assert!(RenderSettings::from_args(&args("64 48 --aovs tiff")).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::util::ray::Ray;
+use crate::util::vector3d::Vector3D;
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3D,
+    pub max: Vector3D,
+}
+
+impl Aabb {
+    /// Box containing nothing, which grows to fit whatever is added to it
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3D::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vector3D::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: &[Vector3D]) -> Self {
+        points.iter().fold(Self::empty(), |b, p| b.grow(*p))
+    }
+
+    pub fn grow(&self, p: Vector3D) -> Self {
+        Self {
+            min: Vector3D::new(
+                self.min.x().min(p.x()),
+                self.min.y().min(p.y()),
+                self.min.z().min(p.z()),
+            ),
+            max: Vector3D::new(
+                self.max.x().max(p.x()),
+                self.max.y().max(p.y()),
+                self.max.z().max(p.z()),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        self.grow(other.min).grow(other.max)
+    }
+
+    pub fn centroid(&self) -> Vector3D {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x() < 0. {
+            return 0.;
+        }
+        2. * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Whether the ray passes through the box between `t_min` and `t_max`, given the inverse of
+    /// its direction
+    pub fn hit(&self, r: &Ray, inv_direction: Vector3D, t_min: f32, t_max: f32) -> bool {
+        self.clip(r, inv_direction, t_min, t_max).is_some()
+    }
+
+    /// Part of the ray between `t_min` and `t_max` that lies inside the box, if any
+    pub fn clip(
+        &self,
+        r: &Ray,
+        inv_direction: Vector3D,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<(f32, f32)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let o = r.origin().e[axis];
+            let inv = inv_direction.e[axis];
+            let mut near = (self.min.e[axis] - o) * inv;
+            let mut far = (self.max.e[axis] - o) * inv;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaN from a zero direction on the slab's plane must not reject the box
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+/// Bounding volume hierarchy over a set of primitives, known only by their bounding boxes.
+/// Built with the surface area heuristic, so rays only test the few primitives near them.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, in the order the leaves refer to them
+    indices: Vec<u32>,
+}
+
+struct Node {
+    bounds: Aabb,
+    /// First primitive of a leaf, or the second child of an inner node. The first child
+    /// directly follows its parent.
+    offset: u32,
+    /// Number of primitives in a leaf, 0 for inner nodes
+    count: u32,
+}
+
+const BINS: usize = 12;
+const MAX_LEAF: usize = 4;
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len() as u32).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    /// Bounds of everything in the hierarchy
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |n| n.bounds)
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |b, i| b.union(&bounds[*i as usize]));
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            offset: start as u32,
+            count: (end - start) as u32,
+        });
+        if end - start <= MAX_LEAF {
+            return;
+        }
+        let centroids = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |b, i| b.grow(bounds[*i as usize].centroid()));
+        let extent = centroids.max - centroids.min;
+        let axis = match (extent.x() > extent.y(), extent.y() > extent.z()) {
+            (true, _) if extent.x() > extent.z() => 0,
+            (_, true) => 1,
+            _ => 2,
+        };
+        let (low, width) = (centroids.min.e[axis], extent.e[axis]);
+        if width <= 0. {
+            return;
+        }
+        let bin = |i: &u32| {
+            let c = bounds[*i as usize].centroid().e[axis];
+            (((c - low) / width * BINS as f32) as usize).min(BINS - 1)
+        };
+
+        // Pick the split between bins with the lowest surface area cost
+        let mut bins = [(Aabb::empty(), 0usize); BINS];
+        for i in &self.indices[start..end] {
+            let b = &mut bins[bin(i)];
+            *b = (b.0.union(&bounds[*i as usize]), b.1 + 1);
+        }
+        let cost = |range: &[(Aabb, usize)]| {
+            let (b, n) = range
+                .iter()
+                .fold((Aabb::empty(), 0), |(b, n), (bb, bn)| (b.union(bb), n + bn));
+            b.surface_area() * n as f32
+        };
+        let (split, best) = (1..BINS)
+            .map(|s| (s, cost(&bins[..s]) + cost(&bins[s..])))
+            .fold((0, f32::MAX), |a, b| if b.1 < a.1 { b } else { a });
+        if end - start <= 2 * MAX_LEAF && best >= node_bounds.surface_area() * (end - start) as f32
+        {
+            return;
+        }
+
+        // Partition the indices around the split
+        let mut mid = start;
+        for i in start..end {
+            if bin(&self.indices[i]) < split {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            mid = (start + end) / 2;
+        }
+        self.nodes[node].count = 0;
+        self.build(bounds, start, mid);
+        self.nodes[node].offset = self.nodes.len() as u32;
+        self.build(bounds, mid, end);
+    }
+
+    /// Walks the primitives whose boxes the ray passes through, nearest boxes first. `hit` tests
+    /// primitive `i` against the ray up to the given distance and returns the distance of any
+    /// hit, which then limits the rest of the search. Returns whether anything was hit.
+    pub fn traverse(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        mut hit: impl FnMut(usize, f32) -> Option<f32>,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let d = r.direction();
+        let inv_direction = Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+        let mut closest = t_max;
+        let mut hit_anything = false;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0usize);
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !node.bounds.hit(r, inv_direction, t_min, closest) {
+                continue;
+            }
+            match node.count {
+                0 => {
+                    // Visit the child on the side the ray comes from first
+                    let axis = self.split_axis(n);
+                    let (first, second) = (n + 1, node.offset as usize);
+                    match d.e[axis] < 0. {
+                        true => {
+                            stack.push(first);
+                            stack.push(second);
+                        }
+                        false => {
+                            stack.push(second);
+                            stack.push(first);
+                        }
+                    }
+                }
+                count => {
+                    let start = node.offset as usize;
+                    for i in &self.indices[start..start + count as usize] {
+                        if let Some(t) = hit(*i as usize, closest) {
+                            closest = t;
+                            hit_anything = true;
+                        }
+                    }
+                }
+            }
+        }
+        hit_anything
+    }
+
+    /// Axis along which the children of inner node `n` are furthest apart
+    fn split_axis(&self, n: usize) -> usize {
+        let a = self.nodes[n + 1].bounds.centroid();
+        let b = self.nodes[self.nodes[n].offset as usize].bounds.centroid();
+        let d = b - a;
+        let (x, y, z) = (d.x().abs(), d.y().abs(), d.z().abs());
+        match (x > y && x > z, y > z) {
+            (true, _) => 0,
+            (false, true) => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(center: Vector3D, half: f32) -> Aabb {
+        let h = Vector3D::new(half, half, half);
+        Aabb {
+            min: center - h,
+            max: center + h,
+        }
+    }
+
+    #[test]
+    fn test_aabb_hit() {
+        let b = cube(Vector3D::new(0., 0., -5.), 1.);
+        let inv = |d: Vector3D| Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+        let forward = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., -1.));
+        assert!(b.hit(&forward, inv(forward.direction()), 0., f32::MAX));
+        assert!(!b.hit(&forward, inv(forward.direction()), 0., 3.));
+        let beside = Ray::new(Vector3D::new(2., 0., 0.), Vector3D::new(0., 0., -1.));
+        assert!(!b.hit(&beside, inv(beside.direction()), 0., f32::MAX));
+        assert_eq!(b.surface_area(), 24.);
+        assert_eq!(Aabb::empty().surface_area(), 0.);
+    }
+
+    #[test]
+    fn test_finds_nearest() {
+        // A row of small cubes along z, found in the same order as testing them all
+        let boxes: Vec<Aabb> = (0..100)
+            .map(|i| cube(Vector3D::new((i % 7) as f32, 0., -(i as f32)), 0.25))
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        assert_eq!(bvh.bounds().max.z(), 0.25);
+        for x in 0..7 {
+            let r = Ray::new(Vector3D::new(x as f32, 0., 10.), Vector3D::new(0., 0., -1.));
+            let mut tests = 0;
+            let mut nearest = None;
+            let found = bvh.traverse(&r, 0.001, f32::MAX, |i, t_max| {
+                tests += 1;
+                let t = 10. - boxes[i].max.z();
+                match t < t_max && boxes[i].min.x() < x as f32 && boxes[i].max.x() > x as f32 {
+                    true => {
+                        nearest = Some(i);
+                        Some(t)
+                    }
+                    false => None,
+                }
+            });
+            assert!(found);
+            assert_eq!(nearest, Some(x));
+            assert!(tests < 50, "tested {} boxes", tests);
+        }
+    }
+}
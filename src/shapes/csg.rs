@@ -0,0 +1,208 @@
+//! Constructive solid geometry: closed objects combined by where rays are inside them.
+
+use super::hitable::{HitRecord, Hitable, Interval};
+use crate::util::ray::Ray;
+
+/// How the insides of two objects combine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    /// Inside either
+    Union,
+    /// Inside both
+    Intersection,
+    /// Inside the first but not the second
+    Difference,
+}
+
+impl Operation {
+    fn inside(&self, a: bool, b: bool) -> bool {
+        match self {
+            Operation::Union => a || b,
+            Operation::Intersection => a && b,
+            Operation::Difference => a && !b,
+        }
+    }
+}
+
+/// Two closed objects combined into one, which is closed too so it can be combined further.
+/// Surfaces keep the material of the object they came from, and the parts of the second
+/// object's surface left by a difference face into the hole they make. Objects that aren't
+/// closed have no inside and so add nothing.
+pub struct Csg {
+    pub operation: Operation,
+    pub a: Box<dyn Hitable>,
+    pub b: Box<dyn Hitable>,
+}
+
+impl Csg {
+    pub fn new(operation: Operation, a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self { operation, a, b }
+    }
+
+    pub fn union(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self::new(Operation::Union, a, b)
+    }
+
+    pub fn intersection(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self::new(Operation::Intersection, a, b)
+    }
+
+    pub fn difference(a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        Self::new(Operation::Difference, a, b)
+    }
+
+    /// Stretches of the ray inside the combination, found by walking through where it enters
+    /// and leaves each object in order
+    fn combine(&self, a: Vec<Interval>, b: Vec<Interval>) -> Vec<Interval> {
+        // Each crossing, from which object and whether it goes in
+        let mut crossings: Vec<(HitRecord, bool, bool)> = vec![];
+        for (intervals, from_a) in [(a, true), (b, false)] {
+            for interval in intervals {
+                crossings.push((interval.enter, from_a, true));
+                crossings.push((interval.exit, from_a, false));
+            }
+        }
+        crossings.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+        // How many of each object's intervals the ray is in, which may overlap for meshes
+        let (mut depth_a, mut depth_b) = (0i32, 0i32);
+        let mut intervals = vec![];
+        let mut enter = None;
+        for (mut rec, from_a, entering) in crossings {
+            let was_inside = self.operation.inside(depth_a > 0, depth_b > 0);
+            let depth = if from_a { &mut depth_a } else { &mut depth_b };
+            *depth += if entering { 1 } else { -1 };
+            let inside = self.operation.inside(depth_a > 0, depth_b > 0);
+            if inside == was_inside {
+                continue;
+            }
+            if !from_a && self.operation == Operation::Difference {
+                rec.normal = -rec.normal;
+            }
+            match enter.take() {
+                Some(start) => intervals.push(Interval {
+                    enter: start,
+                    exit: rec,
+                }),
+                None => enter = Some(rec),
+            }
+        }
+        intervals
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let intervals = match self.intervals(r) {
+            Some(intervals) => intervals,
+            None => return false,
+        };
+        let nearest = intervals
+            .into_iter()
+            .flat_map(|i| vec![i.enter, i.exit])
+            .find(|hit| hit.t > t_min && hit.t < t_max);
+        match nearest {
+            Some(hit) => {
+                *rec = hit;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        let a = self.a.intervals(r).unwrap_or_default();
+        let b = match self.operation {
+            // Nothing to take away from or meet with
+            Operation::Difference | Operation::Intersection if a.is_empty() => vec![],
+            _ => self.b.intervals(r).unwrap_or_default(),
+        };
+        Some(self.combine(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::mesh::Mesh;
+    use crate::shapes::sphere::Sphere;
+    use crate::shapes::test_util::grey;
+    use crate::util::vector3d::Vector3D;
+
+    fn sphere(x: f32) -> Box<dyn Hitable> {
+        Box::new(Sphere::new(Vector3D::new(x, 0., 0.), 1., grey()))
+    }
+
+    /// Cube reaching 1 from the origin, its triangles facing out
+    fn cube() -> Box<dyn Hitable> {
+        let positions = (0..8)
+            .map(|i| {
+                let side = |bit: i32| if i & bit == 0 { -1. } else { 1. };
+                Vector3D::new(side(1), side(2), side(4))
+            })
+            .collect();
+        let quads = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let triangles = quads
+            .iter()
+            .flat_map(|[a, b, c, d]| vec![[*a, *b, *c], [*a, *c, *d]])
+            .collect();
+        Box::new(Mesh::new(positions, triangles, grey()))
+    }
+
+    /// Where the ray along x from far to the left hits, and which way the surface faces there
+    fn hit_along_x(object: &dyn Hitable, t_min: f32) -> Option<(f32, f32)> {
+        let r = Ray::new(Vector3D::new(-10., 0., 0.), Vector3D::new(1., 0., 0.));
+        let mut rec = HitRecord::new(grey());
+        match object.hit(&r, t_min, f32::MAX, &mut rec) {
+            true => Some((rec.p.x(), rec.normal.x())),
+            false => None,
+        }
+    }
+
+    #[test]
+    fn test_operations() {
+        let union = Csg::union(sphere(-0.5), sphere(0.5));
+        assert_eq!(hit_along_x(&union, 0.), Some((-1.5, -1.)));
+        assert_eq!(hit_along_x(&union, 9.), Some((1.5, 1.)));
+        // Lens shaped, from -0.5 to 0.5
+        let lens = Csg::intersection(sphere(-0.5), sphere(0.5));
+        assert_eq!(hit_along_x(&lens, 0.), Some((-0.5, -1.)));
+        assert_eq!(hit_along_x(&lens, 9.6), Some((0.5, 1.)));
+        // The bite taken out of the left sphere faces into the bite
+        let bitten = Csg::difference(sphere(-0.5), sphere(0.5));
+        assert_eq!(hit_along_x(&bitten, 0.), Some((-1.5, -1.)));
+        assert_eq!(hit_along_x(&bitten, 9.), Some((-0.5, 1.)));
+        assert_eq!(hit_along_x(&bitten, 9.6), None);
+        let apart = Csg::intersection(sphere(-2.), sphere(2.));
+        assert_eq!(hit_along_x(&apart, 0.), None);
+    }
+
+    #[test]
+    fn test_box_with_hole() {
+        // A sphere bigger than the cube's faces but smaller than its corners
+        let hole = Box::new(Sphere::new(Vector3D::new(0., 0., 0.), 1.3, grey()));
+        let holed = Csg::difference(cube(), hole);
+        // Straight through the middle there's nothing left
+        assert_eq!(hit_along_x(&holed, 0.), None);
+        // Near a corner the ray meets the cube, then the hole
+        let r = Ray::new(Vector3D::new(-10., 0.8, 0.8), Vector3D::new(1., 0., 0.));
+        let mut rec = HitRecord::new(grey());
+        assert!(holed.hit(&r, 0., f32::MAX, &mut rec));
+        assert!((rec.p.x() + 1.).abs() < 1e-5);
+        assert!(holed.hit(&r, rec.t + 1e-3, f32::MAX, &mut rec));
+        let inside = (1.3f32 * 1.3 - 2. * 0.8 * 0.8).sqrt();
+        assert!((rec.p.x() + inside).abs() < 1e-4);
+        assert!(rec.normal.x() > 0.);
+        // Nested further, the result is still closed
+        let nested = Csg::union(Box::new(holed), sphere(0.));
+        assert_eq!(hit_along_x(&nested, 0.), Some((-1., -1.)));
+        assert!(nested.intervals(&r).is_some());
+    }
+}
@@ -0,0 +1,573 @@
+//! Thin curves for hair, fur and grass: cubic Bézier or B-spline paths swept into flat ribbons
+//! facing the ray, or round tubes. Each is intersected in a frame looking down the ray, where
+//! it's a flat curve to test the origin against, after Nakamaru and Ohno 2002 as in pbrt.
+
+use super::bvh::{Aabb, Bvh};
+use super::hitable::{HitRecord, Hitable};
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::sampling::to_world;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// How a curve's control points make up its cubic pieces
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Basis {
+    /// Pieces of four points, each sharing its last point with the next: `3n + 1` points for
+    /// `n` pieces. The curve passes through every third point.
+    Bezier,
+    /// Uniform B-spline, a piece for every run of four points, smooth where they join but
+    /// passing through none of them
+    BSpline,
+}
+
+/// One strand, before it's split into pieces
+#[derive(Clone, Debug)]
+pub struct Curve {
+    pub basis: Basis,
+    pub points: Vec<Vector3D>,
+    /// Width at each point, or a single width for the whole curve
+    pub widths: Vec<f32>,
+}
+
+impl Curve {
+    pub fn new(basis: Basis, points: Vec<Vector3D>, widths: Vec<f32>) -> Self {
+        Self {
+            basis,
+            points,
+            widths,
+        }
+    }
+
+    /// Bézier pieces of the curve, with the widths at their control points. Empty if the
+    /// curve has the wrong number of points or widths.
+    fn pieces(&self) -> Vec<([Vector3D; 4], [f32; 4])> {
+        let n = self.points.len();
+        let width = |i: usize| match self.widths.len() {
+            1 => self.widths[0],
+            _ => self.widths[i],
+        };
+        if n < 4 || (self.widths.len() != 1 && self.widths.len() != n) {
+            return vec![];
+        }
+        match self.basis {
+            Basis::Bezier if (n - 1).is_multiple_of(3) => (0..n - 1)
+                .step_by(3)
+                .map(|i| {
+                    (
+                        [0, 1, 2, 3].map(|j| self.points[i + j]),
+                        [0, 1, 2, 3].map(|j| width(i + j)),
+                    )
+                })
+                .collect(),
+            Basis::Bezier => vec![],
+            Basis::BSpline => (0..n - 3)
+                .map(|i| {
+                    let p = [0, 1, 2, 3].map(|j| self.points[i + j]);
+                    let w = [0, 1, 2, 3].map(|j| Vector3D::new(width(i + j), 0., 0.));
+                    (bspline_to_bezier(p), bspline_to_bezier(w).map(|w| w.x()))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Bézier control points tracing the same piece as four B-spline points
+fn bspline_to_bezier([p0, p1, p2, p3]: [Vector3D; 4]) -> [Vector3D; 4] {
+    [
+        (p0 + p1 * 4. + p2) / 6.,
+        (p1 * 2. + p2) / 3.,
+        (p1 + p2 * 2.) / 3.,
+        (p1 + p2 * 4. + p3) / 6.,
+    ]
+}
+
+/// Halves of a Bézier piece split at its middle
+fn split(p: [Vector3D; 4]) -> ([Vector3D; 4], [Vector3D; 4]) {
+    let a = (p[0] + p[1]) * 0.5;
+    let b = (p[1] + p[2]) * 0.5;
+    let c = (p[2] + p[3]) * 0.5;
+    let ab = (a + b) * 0.5;
+    let bc = (b + c) * 0.5;
+    let mid = (ab + bc) * 0.5;
+    ([p[0], a, ab, mid], [mid, bc, c, p[3]])
+}
+
+/// Point on a Bézier piece at `u`, and the derivative there
+fn evaluate(p: &[Vector3D; 4], u: f32) -> (Vector3D, Vector3D) {
+    let a = p[0] + (p[1] - p[0]) * u;
+    let b = p[1] + (p[2] - p[1]) * u;
+    let c = p[2] + (p[3] - p[2]) * u;
+    let ab = a + (b - a) * u;
+    let bc = b + (c - b) * u;
+    let derivative = (bc - ab) * 3.;
+    // Where the first or last two points coincide the derivative vanishes at the end
+    let derivative = match derivative.squared_length() > 0. {
+        true => derivative,
+        false => p[3] - p[0],
+    };
+    (ab + (bc - ab) * u, derivative)
+}
+
+fn bezier_width(w: &[f32; 4], u: f32) -> f32 {
+    let v = 1. - u;
+    w[0] * v * v * v + 3. * w[1] * u * v * v + 3. * w[2] * u * u * v + w[3] * u * u * u
+}
+
+/// How a curve's width is swept out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveShape {
+    /// Flat strip always turned to face the ray, cheap for fine hair seen from afar
+    Ribbon,
+    /// Round tube, shaded as a cylinder across its width
+    Tube,
+}
+
+struct Segment {
+    points: [Vector3D; 4],
+    widths: [f32; 4],
+    /// Where the piece starts along its whole curve, and the share of the curve it makes up
+    u_offset: f32,
+    u_scale: f32,
+}
+
+/// Where a ray meets a piece, before shading
+struct CurveHit {
+    segment: usize,
+    /// Along the piece
+    u: f32,
+    /// Depth along the unit ray direction
+    z: f32,
+    /// Offset of the curve from the ray, across the view
+    offset: (f32, f32),
+    width: f32,
+}
+
+/// Many curves of one material, found through a BVH over their pieces. `u` runs along each
+/// curve and `v` across it, and `dpdu` follows the curve as hair shading needs.
+pub struct Curves {
+    segments: Vec<Segment>,
+    pub shape: CurveShape,
+    pub material: Material,
+    bvh: Bvh,
+}
+
+/// Most times a piece is halved before it's treated as straight
+const MAX_DEPTH: u32 = 10;
+
+impl Curves {
+    /// Curves with the wrong number of points or widths for their basis are left out
+    pub fn new(curves: &[Curve], shape: CurveShape, material: Material) -> Self {
+        let mut segments = vec![];
+        for curve in curves {
+            let pieces = curve.pieces();
+            let n = pieces.len() as f32;
+            for (i, (points, widths)) in pieces.into_iter().enumerate() {
+                segments.push(Segment {
+                    points,
+                    widths: widths.map(|w| w.max(0.)),
+                    u_offset: i as f32 / n,
+                    u_scale: 1. / n,
+                });
+            }
+        }
+        let bounds: Vec<Aabb> = segments
+            .iter()
+            .map(|s| {
+                let half = s.widths.iter().fold(0f32, |a, w| a.max(*w)) / 2.;
+                let b = Aabb::from_points(&s.points);
+                Aabb {
+                    min: b.min - Vector3D::new(half, half, half),
+                    max: b.max + Vector3D::new(half, half, half),
+                }
+            })
+            .collect();
+        Self {
+            bvh: Bvh::new(&bounds),
+            segments,
+            shape,
+            material,
+        }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    /// Nearest hit on piece `index` with depth between `z_min` and `z_max`, given the frame
+    /// looking down the ray
+    fn intersect(
+        &self,
+        index: usize,
+        r: &Ray,
+        frame: &[Vector3D; 3],
+        z_min: f32,
+        z_max: f32,
+    ) -> Option<CurveHit> {
+        let segment = &self.segments[index];
+        let o = r.origin();
+        let points = segment.points.map(|p| {
+            let d = p - o;
+            Vector3D::new(d.dot(frame[0]), d.dot(frame[1]), d.dot(frame[2]))
+        });
+        let max_width = segment.widths.iter().fold(0f32, |a, w| a.max(*w));
+        if max_width <= 0. {
+            return None;
+        }
+        // Halve until each part is within a twentieth of the width of a straight line
+        let bend = (0..2).fold(0f32, |l, i| {
+            let d = points[i] - points[i + 1] * 2. + points[i + 2];
+            l.max(d.x().abs()).max(d.y().abs()).max(d.z().abs())
+        });
+        let epsilon = max_width * 0.05;
+        let depth = match bend > 0. {
+            true => ((2f32.sqrt() * 6. * bend / (8. * epsilon)).log2() / 2.).round(),
+            false => 0.,
+        };
+        let depth = depth.max(0.).min(MAX_DEPTH as f32) as u32;
+        let mut hit = None;
+        self.recurse(
+            segment,
+            index,
+            points,
+            (0., 1.),
+            depth,
+            max_width / 2.,
+            z_min,
+            z_max,
+            &mut hit,
+        );
+        hit
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn recurse(
+        &self,
+        segment: &Segment,
+        index: usize,
+        points: [Vector3D; 4],
+        (u0, u1): (f32, f32),
+        depth: u32,
+        half: f32,
+        z_min: f32,
+        mut z_max: f32,
+        hit: &mut Option<CurveHit>,
+    ) {
+        if let Some(h) = hit {
+            z_max = z_max.min(h.z);
+        }
+        // The ray runs through the origin of the frame, so a part missing it can be skipped
+        let b = Aabb::from_points(&points);
+        if b.max.x() + half < 0.
+            || b.min.x() - half > 0.
+            || b.max.y() + half < 0.
+            || b.min.y() - half > 0.
+            || b.max.z() + half < z_min
+            || b.min.z() - half > z_max
+        {
+            return;
+        }
+        if depth > 0 {
+            let (a, b) = split(points);
+            let mid = (u0 + u1) / 2.;
+            self.recurse(
+                segment,
+                index,
+                a,
+                (u0, mid),
+                depth - 1,
+                half,
+                z_min,
+                z_max,
+                hit,
+            );
+            self.recurse(
+                segment,
+                index,
+                b,
+                (mid, u1),
+                depth - 1,
+                half,
+                z_min,
+                z_max,
+                hit,
+            );
+            return;
+        }
+
+        // Close enough to a straight line: reject rays beyond either end
+        let start = (points[1].y() - points[0].y()) * -points[0].y()
+            + points[0].x() * (points[0].x() - points[1].x());
+        let end = (points[2].y() - points[3].y()) * -points[3].y()
+            + points[3].x() * (points[3].x() - points[2].x());
+        if start < 0. || end < 0. {
+            return;
+        }
+        // Nearest point on the line to the ray, in the view
+        let (dx, dy) = (points[3].x() - points[0].x(), points[3].y() - points[0].y());
+        let length = dx * dx + dy * dy;
+        if length == 0. {
+            return;
+        }
+        let w = ((-points[0].x() * dx - points[0].y() * dy) / length).clamp(0., 1.);
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+        let width = bezier_width(&segment.widths, u);
+        let (p, _) = evaluate(&points, w);
+        if p.x() * p.x() + p.y() * p.y() > width * width / 4. {
+            return;
+        }
+        if p.z() <= z_min || p.z() >= z_max {
+            return;
+        }
+        *hit = Some(CurveHit {
+            segment: index,
+            u,
+            z: p.z(),
+            offset: (p.x(), p.y()),
+            width,
+        });
+    }
+
+    /// Fills in the surface where `hit` is, moving round tubes' hits out to their surface.
+    /// False, leaving `rec` alone, if that moves a tube's hit before `t_min`.
+    fn set_hit(
+        &self,
+        r: &Ray,
+        frame: &[Vector3D; 3],
+        hit: &CurveHit,
+        t_min: f32,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let segment = &self.segments[hit.segment];
+        let (_, derivative) = evaluate(&segment.points, hit.u);
+        let tangent = unit_vector(derivative);
+        // Facing the viewer and across the curve
+        let view = -frame[2];
+        let facing = view - tangent * tangent.dot(view);
+        let ns = match facing.squared_length() > 1e-12 {
+            true => unit_vector(facing),
+            false => to_world(Vector3D::new(1., 0., 0.), tangent),
+        };
+        let ts = ns.cross(tangent);
+        let half = hit.width / 2.;
+        // The curve lies this far across from the ray, so the ray is as far the other way
+        let across = frame[0] * hit.offset.0 + frame[1] * hit.offset.1;
+        let s = match half > 0. {
+            true => (-across.dot(ts) / half).clamp(-1., 1.),
+            false => 0.,
+        };
+        let length = r.direction().length();
+        let t = hit.z / length;
+        let (t, normal, curvature) = match self.shape {
+            CurveShape::Ribbon => (t, ns, 0.),
+            CurveShape::Tube => {
+                let bulge = (1. - s * s).max(0.).sqrt();
+                // The surface of the tube stands out towards the viewer
+                let toward = half * bulge / (length * view.dot(ns).max(1e-3));
+                if t - toward <= t_min {
+                    return false;
+                }
+                let curvature = match hit.width > 0. {
+                    true => 1. / hit.width,
+                    false => 0.,
+                };
+                (t - toward, unit_vector(ns * bulge + ts * s), curvature)
+            }
+        };
+        rec.t = t;
+        rec.normal = normal;
+        rec.curvature = curvature;
+        rec.p = r.point_at_parameter(t);
+        rec.material = self.material.clone();
+        rec.uv = (segment.u_offset + segment.u_scale * hit.u, (s + 1.) / 2.);
+        rec.dpdu = derivative / segment.u_scale;
+        rec.dpdv = ts * hit.width;
+        true
+    }
+}
+
+impl Hitable for Curves {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let length = r.direction().length();
+        if length == 0. {
+            return false;
+        }
+        let z = r.direction() / length;
+        let frame = [
+            to_world(Vector3D::new(1., 0., 0.), z),
+            to_world(Vector3D::new(0., 1., 0.), z),
+            z,
+        ];
+        let z_min = t_min * length;
+        // A piece whose hit is rejected leaves the search open for the pieces behind it
+        self.bvh.traverse(r, t_min, t_max, |segment, t_max| {
+            let hit = self.intersect(segment, r, &frame, z_min, t_max * length);
+            stats::intersection(Primitive::Curve, hit.is_some());
+            let hit = hit?;
+            match self.set_hit(r, &frame, &hit, t_min, rec) {
+                true => Some(hit.z / length),
+                false => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::light::Light;
+    use crate::sampler::IndependentSampler;
+    use crate::shapes::test_util::{grey, shoot};
+    use crate::util::material::Hair;
+
+    /// Straight curve up the y axis from -1 to 1
+    fn upright(basis: Basis, widths: Vec<f32>) -> Curve {
+        let points = match basis {
+            Basis::Bezier => (0..4).map(|i| i as f32 * 2. / 3. - 1.).collect::<Vec<_>>(),
+            // The end points are only there to steer the curve
+            Basis::BSpline => vec![-2., -1., 1., 2.],
+        };
+        let points = points
+            .into_iter()
+            .map(|y| Vector3D::new(0., y, 0.))
+            .collect();
+        Curve::new(basis, points, widths)
+    }
+
+    /// Straight down the z axis through `x` and `y`
+    fn shoot_at(curves: &Curves, x: f32, y: f32) -> Option<HitRecord> {
+        shoot(curves, Vector3D::new(x, y, 5.), Vector3D::new(0., 0., -2.))
+    }
+
+    #[test]
+    fn test_ribbon() {
+        let curves = Curves::new(
+            &[upright(Basis::Bezier, vec![0.2])],
+            CurveShape::Ribbon,
+            grey(),
+        );
+        let rec = shoot_at(&curves, 0.05, 0.5).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-4);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-4);
+        assert!((rec.uv.0 - 0.75).abs() < 1e-3);
+        assert!((rec.uv.1 - 0.5).abs() > 0.2);
+        assert!(rec.dpdu.y() > 0.);
+        assert!(shoot_at(&curves, 0.15, 0.5).is_none());
+        assert!(shoot_at(&curves, 0., 1.2).is_none());
+    }
+
+    #[test]
+    fn test_hair_lit_by_point_light() {
+        let brown = Material::Hair(Hair::from_melanin(1.3, 0., 0.3, 0.3));
+        let curves = Curves::new(
+            &[upright(Basis::Bezier, vec![0.2])],
+            CurveShape::Tube,
+            brown,
+        );
+        let r = Ray::new(Vector3D::new(0.02, 0., 5.), Vector3D::new(0., 0., -1.));
+        let mut rec = HitRecord::new(grey());
+        assert!(curves.hit(&r, 1e-3, f32::MAX, &mut rec));
+        // A light off to the side, in front of the fibre
+        let light = Light::Point {
+            position: Vector3D::new(2., 1., 3.),
+            intensity: Vector3D::new(10., 10., 10.),
+        };
+        let sample = light.sample(rec.p, &mut IndependentSampler).unwrap();
+        let response = rec
+            .material
+            .light_response(&r, &rec, sample.direction)
+            .unwrap();
+        assert!((response * sample.radiance).max_component() > 0.);
+    }
+
+    #[test]
+    fn test_tube() {
+        let curves = Curves::new(
+            &[upright(Basis::Bezier, vec![0.2])],
+            CurveShape::Tube,
+            grey(),
+        );
+        // Straight on it's hit at the front of the tube
+        let rec = shoot_at(&curves, 0., 0.).unwrap();
+        assert!((rec.p.z() - 0.1).abs() < 1e-4);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-4);
+        assert!((rec.curvature - 5.).abs() < 1e-4);
+        // Towards its edge the normal turns to the side
+        let rec = shoot_at(&curves, 0.08, 0.).unwrap();
+        assert!((rec.normal.x() - 0.8).abs() < 1e-3);
+        assert!((rec.p.z() - 0.06).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_tube_from_inside() {
+        let behind = upright(Basis::Bezier, vec![0.2]);
+        let behind = Curve::new(
+            Basis::Bezier,
+            behind
+                .points
+                .iter()
+                .map(|p| *p - Vector3D::new(0., 0., 2.))
+                .collect(),
+            vec![0.2],
+        );
+        let curves = Curves::new(
+            &[upright(Basis::Bezier, vec![0.2]), behind],
+            CurveShape::Tube,
+            grey(),
+        );
+        // Starting inside the near tube, its surface is behind the ray so the far one is hit
+        let r = Ray::new(Vector3D::new(0., 0., 0.05), Vector3D::new(0., 0., -1.));
+        let mut rec = HitRecord::new(grey());
+        assert!(curves.hit(&r, 1e-3, f32::MAX, &mut rec));
+        assert!((rec.p.z() + 1.9).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_bspline_and_widths() {
+        let curves = Curves::new(
+            &[upright(Basis::BSpline, vec![0.4, 0.4, 0., 0.])],
+            CurveShape::Ribbon,
+            grey(),
+        );
+        assert_eq!(curves.segment_count(), 1);
+        // Spans from (-2 + 4 * -1 + 1) / 6 to the same above, and narrows going up
+        assert!(shoot_at(&curves, 0., -0.8).is_some());
+        assert!(shoot_at(&curves, 0., -0.9).is_none());
+        assert!(shoot_at(&curves, 0.1, -0.6).is_some());
+        assert!(shoot_at(&curves, 0.1, 0.6).is_none());
+        // Too few widths
+        let broken = Curves::new(
+            &[upright(Basis::BSpline, vec![0.4, 0.4])],
+            CurveShape::Ribbon,
+            grey(),
+        );
+        assert_eq!(broken.segment_count(), 0);
+    }
+
+    #[test]
+    fn test_bent_and_nearest() {
+        // Arcs bowing towards the viewer, one behind the other
+        let arc = |z: f32| {
+            Curve::new(
+                Basis::Bezier,
+                vec![
+                    Vector3D::new(-1., 0., z),
+                    Vector3D::new(-0.5, 0., z + 1.),
+                    Vector3D::new(0.5, 0., z + 1.),
+                    Vector3D::new(1., 0., z),
+                ],
+                vec![0.1],
+            )
+        };
+        let curves = Curves::new(&[arc(-2.), arc(0.)], CurveShape::Ribbon, grey());
+        let rec = shoot_at(&curves, 0., 0.).unwrap();
+        // The middle of the front arc lies at three quarters of its bow
+        assert!((rec.p.z() - 0.75).abs() < 0.01);
+        assert!((rec.uv.0 - 0.5).abs() < 0.01);
+        assert!(shoot_at(&curves, 0., 0.1).is_none());
+    }
+}
@@ -0,0 +1,407 @@
+//! Terrain from a grid of heights, triangulated on the fly. Rays walk the grid cell by cell
+//! under them, skipping cells whose heights they pass above or below, so a large terrain costs
+//! only the few triangles along each ray.
+
+use super::bvh::Aabb;
+use super::hitable::{HitRecord, Hitable};
+use super::mesh::intersect_triangle;
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Distance to a hit in a cell, and the samples at the corners of the triangle hit with their
+/// barycentric weights
+type CellHit = (f32, [(usize, usize); 3], [f32; 3]);
+
+/// Surface over a regular grid of heights, centred on the origin in `x` and `z` and rising
+/// from `y = 0`. Each cell is split into two triangles, shaded with normals interpolated from
+/// the slope around each sample. `u` runs along `x` and `v` along `z`.
+pub struct Heightfield {
+    /// Samples along `x` and `z`
+    nx: usize,
+    nz: usize,
+    /// Height of each sample from 0 to 1, in rows along `x` from `-z` to `+z`
+    heights: Vec<f32>,
+    /// Width, height at 1 and depth
+    pub size: Vector3D,
+    pub material: Material,
+    normals: Vec<Vector3D>,
+    /// Lowest and highest height of each cell
+    ranges: Vec<(f32, f32)>,
+    bounds: Aabb,
+}
+
+impl Heightfield {
+    /// Terrain from `nx` by `nz` heights. Fails unless there are at least two samples each way
+    /// and a height for every sample.
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f32>,
+        size: Vector3D,
+        material: Material,
+    ) -> Result<Self, String> {
+        if nx < 2 || nz < 2 {
+            return Err(format!(
+                "a heightfield needs at least 2 by 2 samples, not {} by {}",
+                nx, nz
+            ));
+        }
+        if heights.len() != nx * nz {
+            return Err(format!(
+                "{} heights given for {} by {} samples",
+                heights.len(),
+                nx,
+                nz
+            ));
+        }
+        let mut field = Self {
+            nx,
+            nz,
+            heights,
+            size,
+            material,
+            normals: vec![],
+            ranges: vec![],
+            bounds: Aabb::empty(),
+        };
+        field.normals = (0..nz)
+            .flat_map(|j| (0..nx).map(move |i| (i, j)))
+            .map(|(i, j)| field.sample_normal(i, j))
+            .collect();
+        field.ranges = (0..nz - 1)
+            .flat_map(|j| (0..nx - 1).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let corners = field.cell(i, j).map(|p| p.y());
+                let low = corners.iter().fold(f32::MAX, |a, y| a.min(*y));
+                let high = corners.iter().fold(f32::MIN, |a, y| a.max(*y));
+                (low, high)
+            })
+            .collect();
+        let low = field.ranges.iter().fold(f32::MAX, |a, r| a.min(r.0));
+        let high = field.ranges.iter().fold(f32::MIN, |a, r| a.max(r.1));
+        field.bounds = Aabb {
+            min: Vector3D::new(-size.x() / 2., low, -size.z() / 2.),
+            max: Vector3D::new(size.x() / 2., high, size.z() / 2.),
+        };
+        Ok(field)
+    }
+
+    /// Terrain sampling `height` at `nx` by `nz` points, given `u` and `v` from 0 to 1
+    pub fn from_function(
+        nx: usize,
+        nz: usize,
+        height: impl Fn(f32, f32) -> f32,
+        size: Vector3D,
+        material: Material,
+    ) -> Result<Self, String> {
+        let heights = (0..nz)
+            .flat_map(|j| (0..nx).map(move |i| (i, j)))
+            .map(|(i, j)| height(i as f32 / (nx - 1) as f32, j as f32 / (nz - 1) as f32))
+            .collect();
+        Self::new(nx, nz, heights, size, material)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Spacing of the samples along `x` and `z`
+    fn spacing(&self) -> (f32, f32) {
+        (
+            self.size.x() / (self.nx - 1) as f32,
+            self.size.z() / (self.nz - 1) as f32,
+        )
+    }
+
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.nx + i] * self.size.y()
+    }
+
+    fn point(&self, i: usize, j: usize) -> Vector3D {
+        let (dx, dz) = self.spacing();
+        Vector3D::new(
+            i as f32 * dx - self.size.x() / 2.,
+            self.height(i, j),
+            j as f32 * dz - self.size.z() / 2.,
+        )
+    }
+
+    /// Corners of cell `(i, j)` from its `-x -z` corner, in order around it
+    fn cell(&self, i: usize, j: usize) -> [Vector3D; 4] {
+        [
+            self.point(i, j),
+            self.point(i + 1, j),
+            self.point(i + 1, j + 1),
+            self.point(i, j + 1),
+        ]
+    }
+
+    /// Normal at a sample from the slope between its neighbours, one-sided at the borders
+    fn sample_normal(&self, i: usize, j: usize) -> Vector3D {
+        let (dx, dz) = self.spacing();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+        let slope_x = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f32 * dx);
+        let slope_z = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f32 * dz);
+        unit_vector(Vector3D::new(-slope_x, 1., -slope_z))
+    }
+
+    /// Nearest hit on the two triangles of cell `(i, j)`
+    fn intersect_cell(
+        &self,
+        i: usize,
+        j: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<CellHit> {
+        // Wound so both face up
+        let triangles = [
+            [(i, j), (i + 1, j + 1), (i + 1, j)],
+            [(i, j), (i, j + 1), (i + 1, j + 1)],
+        ];
+        let mut nearest: Option<CellHit> = None;
+        for corners in triangles.iter() {
+            let [p0, p1, p2] = corners.map(|(i, j)| self.point(i, j));
+            let t_max = nearest.map_or(t_max, |n| n.0);
+            let hit = intersect_triangle([p0, p1, p2], r, t_min, t_max);
+            stats::intersection(Primitive::Triangle, hit.is_some());
+            if let Some((t, b1, b2)) = hit {
+                nearest = Some((t, *corners, [1. - b1 - b2, b1, b2]));
+            }
+        }
+        nearest
+    }
+
+    fn set_hit(
+        &self,
+        r: &Ray,
+        t: f32,
+        corners: [(usize, usize); 3],
+        weights: [f32; 3],
+        rec: &mut HitRecord,
+    ) {
+        let [p0, p1, p2] = corners.map(|(i, j)| self.point(i, j));
+        let geometric = unit_vector((p1 - p0).cross(p2 - p0));
+        let n = (0..3)
+            .map(|k| self.normals[corners[k].1 * self.nx + corners[k].0] * weights[k])
+            .sum::<Vector3D>();
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = match n.squared_length() > 0. {
+            true => unit_vector(n),
+            false => geometric,
+        };
+        rec.uv = (
+            ((rec.p.x() / self.size.x()) + 0.5).clamp(0., 1.),
+            ((rec.p.z() / self.size.z()) + 0.5).clamp(0., 1.),
+        );
+        // Across the whole width or depth, following the triangle's slope
+        let (sx, sz) = (self.size.x(), self.size.z());
+        rec.dpdu = Vector3D::new(sx, -geometric.x() / geometric.y() * sx, 0.);
+        rec.dpdv = Vector3D::new(0., -geometric.z() / geometric.y() * sz, sz);
+        rec.curvature = 0.;
+        rec.material = self.material.clone();
+    }
+}
+
+/// Walk of a ray along one axis of the grid: the cell it's in, which way it steps, where it
+/// next crosses into another cell and how far apart the crossings are
+struct Axis {
+    cell: i64,
+    step: i64,
+    next: f32,
+    delta: f32,
+}
+
+impl Axis {
+    /// From where the ray enters the grid at `t`, with samples `spacing` apart from `start`
+    /// and `cells` cells
+    fn new(origin: f32, direction: f32, t: f32, start: f32, spacing: f32, cells: usize) -> Self {
+        let position = (origin + direction * t - start) / spacing;
+        let cell = (position.floor() as i64).clamp(0, cells as i64 - 1);
+        let boundary = |c: i64| start + c as f32 * spacing;
+        match direction {
+            d if d > 0. => Self {
+                cell,
+                step: 1,
+                next: (boundary(cell + 1) - origin) / d,
+                delta: spacing / d,
+            },
+            d if d < 0. => Self {
+                cell,
+                step: -1,
+                next: (boundary(cell) - origin) / d,
+                delta: -spacing / d,
+            },
+            _ => Self {
+                cell,
+                step: 0,
+                next: f32::INFINITY,
+                delta: f32::INFINITY,
+            },
+        }
+    }
+
+    fn advance(&mut self) {
+        self.cell += self.step;
+        self.next += self.delta;
+    }
+}
+
+impl Hitable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let d = r.direction();
+        let inv_direction = Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+        let (t_enter, t_exit) = match self.bounds.clip(r, inv_direction, t_min, t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        let (o, (dx, dz)) = (r.origin(), self.spacing());
+        let (cells_x, cells_z) = (self.nx - 1, self.nz - 1);
+        let mut x = Axis::new(o.x(), d.x(), t_enter, self.bounds.min.x(), dx, cells_x);
+        let mut z = Axis::new(o.z(), d.z(), t_enter, self.bounds.min.z(), dz, cells_z);
+        // Rounding can put a hit a hair outside the heights of its cell
+        let slack = 1e-4 * self.size.y().abs();
+        let mut t = t_enter;
+        loop {
+            let (i, j) = (x.cell as usize, z.cell as usize);
+            let t_next = x.next.min(z.next);
+            // Heights of the ray across the cell, which can only meet it if they overlap
+            let (y0, y1) = (o.y() + d.y() * t, o.y() + d.y() * t_next.min(t_exit));
+            let (low, high) = self.ranges[j * cells_x + i];
+            if y0.min(y1) <= high + slack && y0.max(y1) >= low - slack {
+                if let Some((t, corners, weights)) = self.intersect_cell(i, j, r, t_min, t_max) {
+                    self.set_hit(r, t, corners, weights, rec);
+                    return true;
+                }
+            }
+            if t_next > t_exit {
+                break;
+            }
+            if x.next < z.next {
+                x.advance();
+            } else {
+                z.advance();
+            }
+            if !(0..cells_x as i64).contains(&x.cell) || !(0..cells_z as i64).contains(&z.cell) {
+                break;
+            }
+            t = t_next;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::test_util::{grey, shoot};
+
+    #[test]
+    fn test_new() {
+        let size = Vector3D::new(1., 1., 1.);
+        assert!(Heightfield::new(2, 2, vec![0.; 4], size, grey()).is_ok());
+        assert!(Heightfield::new(1, 4, vec![0.; 4], size, grey()).is_err());
+        assert!(Heightfield::new(2, 2, vec![0.; 3], size, grey()).is_err());
+        assert!(Heightfield::from_function(0, 0, |_, _| 0., size, grey()).is_err());
+    }
+
+    #[test]
+    fn test_slope() {
+        // Rising along x from 0 to 1 over a width of 2
+        let field =
+            Heightfield::from_function(5, 3, |u, _| u, Vector3D::new(2., 1., 2.), grey()).unwrap();
+        let rec = shoot(
+            &field,
+            Vector3D::new(0.5, 5., 0.3),
+            Vector3D::new(0., -1., 0.),
+        )
+        .unwrap();
+        assert!((rec.p.y() - 0.75).abs() < 1e-5);
+        let expected = unit_vector(Vector3D::new(-0.5, 1., 0.));
+        assert!((rec.normal - expected).length() < 1e-5);
+        assert!((rec.uv.0 - 0.75).abs() < 1e-5 && (rec.uv.1 - 0.65).abs() < 1e-5);
+        assert!((rec.dpdu - Vector3D::new(2., 1., 0.)).length() < 1e-5);
+        // Past the edge there's nothing
+        assert!(shoot(
+            &field,
+            Vector3D::new(1.1, 5., 0.),
+            Vector3D::new(0., -1., 0.)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_grazing_ray() {
+        // A single bump in the middle of flat ground
+        let bump = |u: f32, v: f32| match (u - 0.5).abs() < 0.05 && (v - 0.5).abs() < 0.05 {
+            true => 1.,
+            false => 0.,
+        };
+        let field =
+            Heightfield::from_function(41, 41, bump, Vector3D::new(4., 1., 4.), grey()).unwrap();
+        // Skimming along x just above the ground, it hits only the bump
+        let rec = shoot(
+            &field,
+            Vector3D::new(-3., 0.5, 0.),
+            Vector3D::new(1., 0., 0.),
+        )
+        .unwrap();
+        assert!(rec.p.x() > -0.2 && rec.p.x() < 0.);
+        assert!(rec.normal.x() < 0.);
+        assert!(shoot(
+            &field,
+            Vector3D::new(-3., 0.5, 1.),
+            Vector3D::new(1., 0., 0.)
+        )
+        .is_none());
+        // Going down at an angle from far away it lands on the flat ground
+        let rec = shoot(
+            &field,
+            Vector3D::new(-3., 1., 1.05),
+            Vector3D::new(1., -0.45, 0.),
+        )
+        .unwrap();
+        assert!((rec.p - Vector3D::new(-3. + 1. / 0.45, 0., 1.05)).length() < 1e-4);
+        assert!((rec.normal - Vector3D::new(0., 1., 0.)).length() < 1e-5);
+        // And from below it hits the underside
+        let rec = shoot(
+            &field,
+            Vector3D::new(1., -1., 1.),
+            Vector3D::new(0., 1., 0.),
+        )
+        .unwrap();
+        assert!(rec.p.y().abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_against_triangles() {
+        // Rays in every direction agree with testing every triangle
+        let wave = |u: f32, v: f32| (u * 9.).sin() * (v * 7.).cos() * 0.5 + 0.5;
+        let field =
+            Heightfield::from_function(17, 13, wave, Vector3D::new(3., 0.8, 2.), grey()).unwrap();
+        for k in 0..200 {
+            let a = k as f32 * 0.7;
+            let origin = Vector3D::new(a.cos() * 2., 1.5 - (k % 7) as f32 * 0.3, a.sin() * 1.5);
+            let direction = Vector3D::new(-a.cos() + 0.3, -0.4 + (k % 5) as f32 * 0.15, -a.sin());
+            let r = Ray::new(origin, direction);
+            let mut brute = None;
+            for j in 0..field.nz - 1 {
+                for i in 0..field.nx - 1 {
+                    let t_max = brute.unwrap_or(f32::MAX);
+                    if let Some((t, _, _)) = field.intersect_cell(i, j, &r, 1e-4, t_max) {
+                        brute = Some(t);
+                    }
+                }
+            }
+            let walked = shoot(&field, origin, direction).map(|rec| rec.t);
+            match (brute, walked) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-4, "ray {}", k),
+                (a, b) => assert_eq!(a, b, "ray {}", k),
+            }
+        }
+    }
+}
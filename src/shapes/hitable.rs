@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use crate::util::differential::SurfaceDifferentials;
 use crate::util::material::Material;
 use crate::util::ray::Ray;
 use crate::util::vector3d::Vector3D;
@@ -8,6 +11,18 @@ pub struct HitRecord {
     pub p: Vector3D,
     pub normal: Vector3D,
     pub material: Material,
+    /// Index of the hit object in the top level `HitableList` of the scene
+    pub object_id: u32,
+    /// Surface coordinates of the hit, each from 0 to 1
+    pub uv: (f32, f32),
+    /// How the position changes with `u` and `v`
+    pub dpdu: Vector3D,
+    pub dpdv: Vector3D,
+    /// How fast the normal turns per unit moved along the surface: one over the radius for a
+    /// sphere and 0 where it's flat
+    pub curvature: f32,
+    /// Footprint of the ray on the surface, if it carries differentials
+    pub differentials: Option<SurfaceDifferentials>,
 }
 
 impl HitRecord {
@@ -17,12 +32,44 @@ impl HitRecord {
             p: Vector3D::new(0., 0., 0.),
             normal: Vector3D::new(0., 0., 0.),
             material: m,
+            object_id: 0,
+            uv: (0., 0.),
+            dpdu: Vector3D::new(0., 0., 0.),
+            dpdv: Vector3D::new(0., 0., 0.),
+            curvature: 0.,
+            differentials: None,
         }
     }
 }
 
+/// Stretch of a ray inside a closed object, from the hit where it goes in to the one where it
+/// comes out. Either end may lie behind the ray's origin, or at infinity if the ray never
+/// crosses the surface on that side.
+#[derive(Clone)]
+pub struct Interval {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
 pub trait Hitable {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+
+    /// Every stretch of the whole line through the ray where it's inside the object, in order of
+    /// where they start. `None` if the object isn't closed and so has no inside.
+    fn intervals(&self, _r: &Ray) -> Option<Vec<Interval>> {
+        None
+    }
+}
+
+/// An object shared between scenes, such as the frames of an animation, so it's only built once
+impl Hitable for Arc<dyn Hitable> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        (**self).hit(r, t_min, t_max, rec)
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        (**self).intervals(r)
+    }
 }
 
 pub struct HitableList {
@@ -43,11 +90,12 @@ impl Hitable for HitableList {
         let mut temp_rec = HitRecord::new(rec.material.clone());
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
-        for h in self.list.iter() {
+        for (i, h) in self.list.iter().enumerate() {
             if h.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
+                rec.object_id = i as u32;
             }
         }
         hit_anything
@@ -0,0 +1,319 @@
+use super::bvh::{Aabb, Bvh};
+use super::hitable::{HitRecord, Hitable, Interval};
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::sampling::to_world;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Triangles sharing one material, with optional per-vertex normals and texture coordinates.
+/// The triangles are kept in a bounding volume hierarchy, so large meshes stay fast to hit.
+pub struct Mesh {
+    positions: Vec<Vector3D>,
+    triangles: Vec<[u32; 3]>,
+    /// Empty, or one normal per vertex to shade with instead of the flat triangles
+    normals: Vec<Vector3D>,
+    /// Empty, or texture coordinates for each vertex
+    uvs: Vec<(f32, f32)>,
+    /// Empty, or a colour for each vertex to paint the surface with
+    colors: Vec<Vector3D>,
+    pub material: Material,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    /// Mesh of the `triangles` joining vertices at `positions`. Triangles wound
+    /// counterclockwise face towards the viewer, and any referring to missing vertices are
+    /// dropped.
+    pub fn new(positions: Vec<Vector3D>, triangles: Vec<[u32; 3]>, material: Material) -> Self {
+        let triangles: Vec<[u32; 3]> = triangles
+            .into_iter()
+            .filter(|t| t.iter().all(|i| (*i as usize) < positions.len()))
+            .collect();
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|t| Aabb::from_points(&t.map(|i| positions[i as usize])))
+            .collect();
+        Self {
+            bvh: Bvh::new(&bounds),
+            positions,
+            triangles,
+            normals: vec![],
+            uvs: vec![],
+            colors: vec![],
+            material,
+        }
+    }
+
+    /// Shade with the given vertex normals, ignored unless there is one for each vertex
+    pub fn with_normals(mut self, normals: Vec<Vector3D>) -> Self {
+        if normals.len() == self.positions.len() {
+            self.normals = normals.into_iter().map(unit_vector).collect();
+        }
+        self
+    }
+
+    /// Texture coordinates for each vertex, ignored unless there is one for each vertex
+    pub fn with_uvs(mut self, uvs: Vec<(f32, f32)>) -> Self {
+        if uvs.len() == self.positions.len() {
+            self.uvs = uvs;
+        }
+        self
+    }
+
+    /// Linear colours for each vertex, which paint the surface as a diffuse material in place of
+    /// `material`. Ignored unless there is one for each vertex.
+    pub fn with_colors(mut self, colors: Vec<Vector3D>) -> Self {
+        if colors.len() == self.positions.len() {
+            self.colors = colors;
+        }
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn vertices(&self, triangle: usize) -> [Vector3D; 3] {
+        self.triangles[triangle].map(|i| self.positions[i as usize])
+    }
+
+    fn set_surface(&self, triangle: usize, b1: f32, b2: f32, rec: &mut HitRecord) {
+        let [i0, i1, i2] = self.triangles[triangle].map(|i| i as usize);
+        let [p0, p1, p2] = self.vertices(triangle);
+        let b0 = 1. - b1 - b2;
+        rec.p = p0 * b0 + p1 * b1 + p2 * b2;
+        let geometric = unit_vector((p1 - p0).cross(p2 - p0));
+        rec.normal = match self.normals.is_empty() {
+            true => geometric,
+            false => {
+                let n = self.normals[i0] * b0 + self.normals[i1] * b1 + self.normals[i2] * b2;
+                match n.squared_length() > 0. {
+                    true => unit_vector(n),
+                    false => geometric,
+                }
+            }
+        };
+        let [uv0, uv1, uv2] = match self.uvs.is_empty() {
+            true => [(0., 0.), (1., 0.), (1., 1.)],
+            false => [self.uvs[i0], self.uvs[i1], self.uvs[i2]],
+        };
+        rec.uv = (
+            uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+            uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+        );
+        // Solve for the derivatives from how position and uv change along two edges
+        let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let det = du02 * dv12 - dv02 * du12;
+        match det.abs() > 1e-10 {
+            true => {
+                rec.dpdu = (dp02 * dv12 - dp12 * dv02) / det;
+                rec.dpdv = (dp12 * du02 - dp02 * du12) / det;
+            }
+            false => {
+                rec.dpdu = to_world(Vector3D::new(1., 0., 0.), geometric);
+                rec.dpdv = to_world(Vector3D::new(0., 1., 0.), geometric);
+            }
+        }
+        rec.curvature = 0.;
+        rec.material = match self.colors.is_empty() {
+            true => self.material.clone(),
+            false => Material::Lambertian {
+                albedo: self.colors[i0] * b0 + self.colors[i1] * b1 + self.colors[i2] * b2,
+            },
+        };
+    }
+}
+
+/// Distance along the ray to the triangle with corners `p` and the barycentric coordinates of
+/// the second and third corner there, by the Möller-Trumbore algorithm
+pub(crate) fn intersect_triangle(
+    p: [Vector3D; 3],
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let [p0, p1, p2] = p;
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let pvec = r.direction().cross(e2);
+    let det = e1.dot(pvec);
+    if det == 0. {
+        return None;
+    }
+    let inv_det = 1. / det;
+    let tvec = r.origin() - p0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(e1);
+    let b2 = r.direction().dot(qvec) * inv_det;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    let t = e2.dot(qvec) * inv_det;
+    match t > t_min && t < t_max {
+        true => Some((t, b1, b2)),
+        false => None,
+    }
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let mut closest = None;
+        self.bvh.traverse(r, t_min, t_max, |triangle, t_max| {
+            let hit = intersect_triangle(self.vertices(triangle), r, t_min, t_max);
+            stats::intersection(Primitive::Triangle, hit.is_some());
+            let (t, b1, b2) = hit?;
+            closest = Some((triangle, t, b1, b2));
+            Some(t)
+        });
+        match closest {
+            Some((triangle, t, b1, b2)) => {
+                rec.t = t;
+                self.set_surface(triangle, b1, b2, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Treats the mesh as closed, pairing each crossing into it with the next one out. Crossings
+    /// tell in from out by which way their triangle faces.
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        let mut crossings = vec![];
+        self.bvh.traverse(r, f32::MIN, f32::MAX, |triangle, _| {
+            let hit = intersect_triangle(self.vertices(triangle), r, f32::MIN, f32::MAX);
+            stats::intersection(Primitive::Triangle, hit.is_some());
+            crossings.push((triangle, hit?));
+            None
+        });
+        crossings.sort_by(|a, b| a.1 .0.total_cmp(&b.1 .0));
+
+        let record = |crossing: Option<&(usize, (f32, f32, f32))>, t: f32| {
+            let mut rec = HitRecord::new(self.material.clone());
+            rec.t = t;
+            if let Some((triangle, (t, b1, b2))) = crossing {
+                rec.t = *t;
+                self.set_surface(*triangle, *b1, *b2, &mut rec);
+            }
+            rec
+        };
+        let mut intervals = vec![];
+        // Crossings into the mesh not yet matched with one out, innermost last
+        let mut entered = vec![];
+        for crossing in crossings.iter() {
+            let [p0, p1, p2] = self.vertices(crossing.0);
+            match (p1 - p0).cross(p2 - p0).dot(r.direction()) < 0. {
+                true => entered.push(crossing),
+                false => intervals.push(Interval {
+                    // Coming out without going in means the line started inside
+                    enter: record(entered.pop(), f32::NEG_INFINITY),
+                    exit: record(Some(crossing), f32::INFINITY),
+                }),
+            }
+        }
+        for crossing in entered {
+            intervals.push(Interval {
+                enter: record(Some(crossing), f32::NEG_INFINITY),
+                exit: record(None, f32::INFINITY),
+            });
+        }
+        intervals.sort_by(|a, b| a.enter.t.total_cmp(&b.enter.t));
+        Some(intervals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::test_util::grey;
+
+    /// A unit square at z = `z` facing +z, split into two triangles
+    fn square(z: f32) -> Mesh {
+        let positions = vec![
+            Vector3D::new(0., 0., z),
+            Vector3D::new(1., 0., z),
+            Vector3D::new(1., 1., z),
+            Vector3D::new(0., 1., z),
+        ];
+        Mesh::new(positions, vec![[0, 1, 2], [0, 2, 3]], grey()).with_uvs(vec![
+            (0., 0.),
+            (1., 0.),
+            (1., 1.),
+            (0., 1.),
+        ])
+    }
+
+    #[test]
+    fn test_hit_triangle() {
+        let mesh = square(-2.);
+        let mut rec = HitRecord::new(grey());
+        let r = Ray::new(Vector3D::new(0.25, 0.75, 0.), Vector3D::new(0., 0., -1.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.).abs() < 1e-5);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-6);
+        assert!((rec.uv.0 - 0.25).abs() < 1e-5 && (rec.uv.1 - 0.75).abs() < 1e-5);
+        assert!((rec.dpdu - Vector3D::new(1., 0., 0.)).length() < 1e-5);
+        assert!((rec.dpdv - Vector3D::new(0., 1., 0.)).length() < 1e-5);
+        assert!(!mesh.hit(&r, 0.001, 1.5, &mut rec));
+        let beside = Ray::new(Vector3D::new(1.5, 0.5, 0.), Vector3D::new(0., 0., -1.));
+        assert!(!mesh.hit(&beside, 0.001, f32::MAX, &mut rec));
+    }
+
+    #[test]
+    fn test_nearest_of_many() {
+        // A stack of squares is hit at the nearest one, whichever order they were built in
+        let mut positions = vec![];
+        let mut triangles = vec![];
+        for layer in [3, 0, 7, 1, 5].iter() {
+            let base = positions.len() as u32;
+            let z = -(*layer as f32) - 1.;
+            positions.extend(square(z).positions);
+            triangles.push([base, base + 1, base + 2]);
+            triangles.push([base, base + 2, base + 3]);
+        }
+        triangles.push([0, 1, 99]);
+        let mesh = Mesh::new(positions, triangles, grey());
+        assert_eq!(mesh.triangle_count(), 10);
+        let mut rec = HitRecord::new(grey());
+        let r = Ray::new(Vector3D::new(0.6, 0.3, 0.), Vector3D::new(0., 0., -2.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 0.5).abs() < 1e-5);
+        assert!((rec.p - Vector3D::new(0.6, 0.3, -1.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_vertex_normals() {
+        let normals = vec![
+            Vector3D::new(-1., 0., 1.),
+            Vector3D::new(1., 0., 1.),
+            Vector3D::new(1., 0., 1.),
+            Vector3D::new(-1., 0., 1.),
+        ];
+        let mesh = square(0.).with_normals(normals);
+        let mut rec = HitRecord::new(grey());
+        let r = Ray::new(Vector3D::new(0.5, 0.5, 1.), Vector3D::new(0., 0., -1.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-5);
+        let r = Ray::new(Vector3D::new(0.9, 0.5, 1.), Vector3D::new(0., 0., -1.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!(rec.normal.x() > 0.5);
+    }
+
+    #[test]
+    fn test_vertex_colors() {
+        let red = Vector3D::new(1., 0., 0.);
+        let mesh = square(0.).with_colors(vec![red; 4]);
+        let mut rec = HitRecord::new(grey());
+        let r = Ray::new(Vector3D::new(0.5, 0.5, 1.), Vector3D::new(0., 0., -1.));
+        assert!(mesh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material.albedo(), red);
+    }
+}
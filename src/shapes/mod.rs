@@ -1,2 +1,14 @@
+pub mod bvh;
+pub mod csg;
+pub mod curve;
+pub mod heightfield;
 pub mod hitable;
+pub mod mesh;
+pub mod quadric;
+pub mod sdf;
 pub mod sphere;
+pub mod subdivision;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod transformed;
+pub mod voxel;
@@ -0,0 +1,369 @@
+//! General quadric surfaces, the sphere's intersection widened to every surface of degree two:
+//! ellipsoids, paraboloids, hyperboloids, cones and cylinders. Most of them go on forever, so
+//! each is clipped to a box. `Sphere` solves its quadratic the same way but stays a type of its
+//! own, see there.
+
+use std::f32::consts::PI;
+
+use super::bvh::Aabb;
+use super::hitable::{HitRecord, Hitable};
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::sampling::to_world;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Surface where `f(p) = p·Mp + 2k·p + c` is zero, with the symmetric matrix `M` and the vector
+/// `k` taken from the coefficients of
+/// `Ax² + By² + Cz² + 2Dxy + 2Exz + 2Fyz + 2Gx + 2Hy + 2Iz + J`. `f` is negative inside, so
+/// normals point the way it grows.
+pub struct Quadric {
+    /// `A` to `J`
+    pub coefficients: [f32; 10],
+    /// Only the part of the surface inside this box is there
+    pub bounds: Aabb,
+    pub material: Material,
+}
+
+impl Quadric {
+    pub fn new(coefficients: [f32; 10], bounds: Aabb, material: Material) -> Self {
+        Self {
+            coefficients,
+            bounds,
+            material,
+        }
+    }
+
+    /// Quadric symmetric about every axis, but for `y` which may appear on its own:
+    /// `x2 x² + y2 y² + z2 z² + 2 y1 y + constant`
+    fn axis_aligned(
+        [x2, y2, z2]: [f32; 3],
+        y1: f32,
+        constant: f32,
+        bounds: Aabb,
+        material: Material,
+    ) -> Self {
+        Self::new(
+            [x2, y2, z2, 0., 0., 0., 0., y1, 0., constant],
+            bounds,
+            material,
+        )
+    }
+
+    /// Ellipsoid around the origin reaching `radii` along each axis
+    pub fn ellipsoid(radii: Vector3D, material: Material) -> Self {
+        let [x, y, z] = radii.e.map(|r| 1. / (r * r));
+        Self::axis_aligned(
+            [x, y, z],
+            0.,
+            -1.,
+            Aabb {
+                min: -radii,
+                max: radii,
+            },
+            material,
+        )
+    }
+
+    /// Bowl opening up the y axis from the origin, `radii.x` and `radii.z` across at a height of
+    /// `radii.y`, cut off at `height`
+    pub fn paraboloid(radii: Vector3D, height: f32, material: Material) -> Self {
+        let [x, y, z] = radii.e;
+        let spread = (height / y).sqrt();
+        Self::axis_aligned(
+            [1. / (x * x), 0., 1. / (z * z)],
+            -0.5 / y,
+            0.,
+            Aabb {
+                min: Vector3D::new(-x * spread, 0., -z * spread),
+                max: Vector3D::new(x * spread, height, z * spread),
+            },
+            material,
+        )
+    }
+
+    /// Hyperboloid of one sheet around the y axis, `radii.x` and `radii.z` across at its waist
+    /// and widening by them every `radii.y`, cut off `height` above and below the waist
+    pub fn hyperboloid(radii: Vector3D, height: f32, material: Material) -> Self {
+        let [x, y, z] = radii.e;
+        let spread = (1. + (height / y).powi(2)).sqrt();
+        Self::axis_aligned(
+            [1. / (x * x), -1. / (y * y), 1. / (z * z)],
+            0.,
+            -1.,
+            Aabb {
+                min: Vector3D::new(-x * spread, -height, -z * spread),
+                max: Vector3D::new(x * spread, height, z * spread),
+            },
+            material,
+        )
+    }
+
+    /// Hyperboloid of two sheets, cups opening up and down the y axis from `±radii.y`, cut off
+    /// `height` from the origin
+    pub fn hyperboloid_of_two_sheets(radii: Vector3D, height: f32, material: Material) -> Self {
+        let [x, y, z] = radii.e;
+        let spread = ((height / y).powi(2) - 1.).max(0.).sqrt();
+        Self::axis_aligned(
+            [1. / (x * x), -1. / (y * y), 1. / (z * z)],
+            0.,
+            1.,
+            Aabb {
+                min: Vector3D::new(-x * spread, -height, -z * spread),
+                max: Vector3D::new(x * spread, height, z * spread),
+            },
+            material,
+        )
+    }
+
+    /// Double cone around the y axis with its tip at the origin, `radii.x` and `radii.z` across
+    /// at a height of `radii.y`, cut off `height` above and below the tip
+    pub fn cone(radii: Vector3D, height: f32, material: Material) -> Self {
+        let [x, y, z] = radii.e;
+        let spread = height / y;
+        Self::axis_aligned(
+            [1. / (x * x), -1. / (y * y), 1. / (z * z)],
+            0.,
+            0.,
+            Aabb {
+                min: Vector3D::new(-x * spread, -height, -z * spread),
+                max: Vector3D::new(x * spread, height, z * spread),
+            },
+            material,
+        )
+    }
+
+    /// Open cylinder around the y axis, `radii.x` and `radii.z` across and reaching `height`
+    /// above and below the origin
+    pub fn cylinder(radii: Vector3D, height: f32, material: Material) -> Self {
+        let [x, _, z] = radii.e;
+        Self::axis_aligned(
+            [1. / (x * x), 0., 1. / (z * z)],
+            0.,
+            -1.,
+            Aabb {
+                min: Vector3D::new(-x, -height, -z),
+                max: Vector3D::new(x, height, z),
+            },
+            material,
+        )
+    }
+
+    /// Clips the surface to `bounds` instead
+    pub fn with_bounds(mut self, bounds: Aabb) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// `Mv`
+    fn matrix(&self, v: Vector3D) -> Vector3D {
+        let [a, b, c, d, e, f, ..] = self.coefficients;
+        Vector3D::new(
+            a * v.x() + d * v.y() + e * v.z(),
+            d * v.x() + b * v.y() + f * v.z(),
+            e * v.x() + f * v.y() + c * v.z(),
+        )
+    }
+
+    fn linear(&self) -> Vector3D {
+        let [.., g, h, i, _] = self.coefficients;
+        Vector3D::new(g, h, i)
+    }
+
+    /// Half the gradient of `f`, `Mp + k`
+    fn half_gradient(&self, p: Vector3D) -> Vector3D {
+        self.matrix(p) + self.linear()
+    }
+
+    /// Distances along the ray where it crosses the whole unclipped surface, nearest first
+    fn roots(&self, r: &Ray) -> Option<(f32, f32)> {
+        let (o, d) = (r.origin(), r.direction());
+        // f(o + td) = at² + 2bt + c
+        let a = d.dot(self.matrix(d));
+        let b = d.dot(self.half_gradient(o));
+        let c = o.dot(self.matrix(o)) + 2. * self.linear().dot(o) + self.coefficients[9];
+        if a.abs() <= 1e-12 * d.squared_length() {
+            // Parallel to an axis of a paraboloid or cylinder, where only one crossing is left
+            return match b != 0. {
+                true => {
+                    let t = -c / (2. * b);
+                    Some((t, t))
+                }
+                false => None,
+            };
+        }
+        solve_quadratic(a, b, c)
+    }
+
+    /// Whether `p` lies in the clipping box, allowing for rounding
+    fn in_bounds(&self, p: Vector3D) -> bool {
+        let slack = (self.bounds.max - self.bounds.min) * 1e-5;
+        (0..3).all(|axis| {
+            p.e[axis] >= self.bounds.min.e[axis] - slack.e[axis]
+                && p.e[axis] <= self.bounds.max.e[axis] + slack.e[axis]
+        })
+    }
+
+    /// Fills in the surface at a hit. `u` runs around the y axis and `v` up the clipping box.
+    fn set_surface(&self, rec: &mut HitRecord) {
+        let p = rec.p;
+        let gradient = self.half_gradient(p);
+        let length = gradient.length();
+        rec.normal = match length > 0. {
+            true => gradient / length,
+            false => Vector3D::new(0., 1., 0.),
+        };
+        let n = rec.normal;
+
+        let mut phi = p.z().atan2(p.x());
+        if phi < 0. {
+            phi += 2. * PI;
+        }
+        let height = self.bounds.max.y() - self.bounds.min.y();
+        rec.uv = (
+            phi / (2. * PI),
+            match height > 0. {
+                true => ((p.y() - self.bounds.min.y()) / height).clamp(0., 1.),
+                false => 0.,
+            },
+        );
+        // Turning about the y axis, kept in the tangent plane
+        let around = Vector3D::new(-p.z(), 0., p.x()) * (2. * PI);
+        let around = around - n * n.dot(around);
+        match around.squared_length() > 1e-12 {
+            true => {
+                rec.dpdu = around;
+                rec.dpdv = unit_vector(around.cross(n)) * height;
+            }
+            false => {
+                rec.dpdu = to_world(Vector3D::new(1., 0., 0.), n);
+                rec.dpdv = to_world(Vector3D::new(0., 1., 0.), n);
+            }
+        }
+
+        // Mean curvature of the level set, (|∇f|² tr H - ∇f·H∇f) / 2|∇f|³ with H = 2M, in
+        // terms of the half gradient
+        let [a, b, c, ..] = self.coefficients;
+        rec.curvature = match length > 0. {
+            true => {
+                let trace = a + b + c;
+                (length * length * trace - gradient.dot(self.matrix(gradient)))
+                    / (2. * length.powi(3))
+            }
+            false => 0.,
+        };
+        rec.material = self.material.clone();
+    }
+
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (near, far) = match self.roots(r) {
+            Some(roots) => roots,
+            None => return false,
+        };
+        for t in [near, far].iter() {
+            if *t > t_min && *t < t_max {
+                let p = r.point_at_parameter(*t);
+                if self.in_bounds(p) {
+                    rec.t = *t;
+                    rec.p = p;
+                    self.set_surface(rec);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Both solutions of `at² + 2bt + c = 0`, smallest first, if there are any
+pub(crate) fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let discriminant = b * b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    // Without cancelling the two terms when they're close
+    let q = -(b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = match q != 0. {
+        true => (q / a, c / q),
+        false => (0., 0.),
+    };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+impl Hitable for Quadric {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let hit = self.intersect(r, t_min, t_max, rec);
+        stats::intersection(Primitive::Quadric, hit);
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::test_util::{grey, shoot};
+
+    #[test]
+    fn test_ellipsoid() {
+        let q = Quadric::ellipsoid(Vector3D::new(1., 2., 3.), grey());
+        let rec = shoot(&q, Vector3D::new(0., 0., 10.), Vector3D::new(0., 0., -2.)).unwrap();
+        assert!((rec.t - 3.5).abs() < 1e-5);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-5);
+        // From inside it hits the far side
+        let rec = shoot(&q, Vector3D::new(0., 0., 0.), Vector3D::new(0., 1., 0.)).unwrap();
+        assert!((rec.p.y() - 2.).abs() < 1e-5);
+        assert!(shoot(&q, Vector3D::new(2., 0., 10.), Vector3D::new(0., 0., -1.)).is_none());
+        // A sphere curves by one over its radius
+        let sphere = Quadric::ellipsoid(Vector3D::new(2., 2., 2.), grey());
+        let rec = shoot(
+            &sphere,
+            Vector3D::new(5., 1., 0.),
+            Vector3D::new(-1., 0., 0.),
+        )
+        .unwrap();
+        assert!((rec.curvature - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_clipped() {
+        let bowl = Quadric::paraboloid(Vector3D::new(1., 1., 1.), 4., grey());
+        // Straight down into the bowl, through its open top to the bottom
+        let rec = shoot(
+            &bowl,
+            Vector3D::new(0., 10., 0.),
+            Vector3D::new(0., -1., 0.),
+        )
+        .unwrap();
+        assert!(rec.p.length() < 1e-5);
+        assert!((rec.normal - Vector3D::new(0., -1., 0.)).length() < 1e-5);
+        // Sideways at y = 1 it meets the outside at x = -1, and above the rim nothing
+        let rec = shoot(&bowl, Vector3D::new(-5., 1., 0.), Vector3D::new(1., 0., 0.)).unwrap();
+        assert!((rec.p.x() + 1.).abs() < 1e-5);
+        assert!(shoot(&bowl, Vector3D::new(-5., 5., 0.), Vector3D::new(1., 0., 0.)).is_none());
+        // A cylinder's front is past the box, so the ray hits the inside of its back
+        let tube = Quadric::cylinder(Vector3D::new(1., 1., 1.), 1., grey());
+        let down = unit_vector(Vector3D::new(0., -1., 1.));
+        let rec = shoot(&tube, Vector3D::new(0., 1.6, -1.5), down).unwrap();
+        assert!((rec.p.z() - 1.).abs() < 1e-4);
+        assert!(rec.normal.dot(down) > 0.);
+    }
+
+    #[test]
+    fn test_gradient_normals() {
+        let shapes = [
+            Quadric::hyperboloid(Vector3D::new(1., 2., 1.5), 3., grey()),
+            Quadric::hyperboloid_of_two_sheets(Vector3D::new(1., 1., 1.), 3., grey()),
+            Quadric::cone(Vector3D::new(1., 1., 1.), 3., grey()),
+        ];
+        for q in shapes.iter() {
+            let rec = shoot(q, Vector3D::new(-6., 1.8, 0.3), Vector3D::new(1., 0., 0.)).unwrap();
+            // The normal is square to the surface: nearby points on it lie in the tangent plane
+            let f = |p: Vector3D| p.dot(q.matrix(p)) + 2. * q.linear().dot(p) + q.coefficients[9];
+            assert!(f(rec.p).abs() < 1e-4);
+            let step = 1e-2;
+            let tangent = unit_vector(rec.dpdu) * step;
+            assert!(f(rec.p + tangent).abs() < 1e-3);
+            assert!(rec.normal.x() < 0.);
+        }
+    }
+}
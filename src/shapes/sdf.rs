@@ -0,0 +1,549 @@
+//! Implicit surfaces given by signed distance functions, found by sphere tracing: the ray steps
+//! forward by the distance to the nearest surface until it gets close enough to count as a hit.
+
+use std::sync::Arc;
+
+use super::bvh::Aabb;
+use super::hitable::{HitRecord, Hitable, Interval};
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::sampling::to_world;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Closest a march gets before it counts as a hit, however near the ray's origin it is
+const MIN_DISTANCE: f32 = 1e-5;
+
+/// Signed distance from a point to a surface: positive outside, negative inside. Primitives are
+/// centred on the origin and can be moved, combined and repeated.
+#[derive(Clone)]
+pub enum Distance {
+    Sphere {
+        radius: f32,
+    },
+    /// Box reaching `half_size` from the origin along each axis
+    Cuboid {
+        half_size: Vector3D,
+    },
+    /// Ring lying in the xz plane
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// Capped cylinder along the y axis
+    Cylinder {
+        radius: f32,
+        half_height: f32,
+    },
+    /// Half space below the plane through `offset * normal`, facing along `normal`
+    Plane {
+        normal: Vector3D,
+        offset: f32,
+    },
+    /// The power `power` Mandelbulb fractal, reaching about 1.2 from the origin. Its distance is
+    /// only an estimate, better with more iterations.
+    Mandelbulb {
+        power: f32,
+        iterations: u32,
+    },
+    Translate {
+        shape: Box<Distance>,
+        offset: Vector3D,
+    },
+    Union(Box<Distance>, Box<Distance>),
+    Intersection(Box<Distance>, Box<Distance>),
+    /// The first shape with the second cut out of it
+    Subtraction(Box<Distance>, Box<Distance>),
+    /// Union whose shapes melt into each other where they come within `k` of touching
+    SmoothUnion {
+        a: Box<Distance>,
+        b: Box<Distance>,
+        k: f32,
+    },
+    /// The shape copied every `period` along each axis, or not along axes where it's 0. The
+    /// shape should fit within one period.
+    Repeat {
+        shape: Box<Distance>,
+        period: Vector3D,
+    },
+    /// Any other distance. It must not overestimate the distance to the surface.
+    Function(Arc<dyn Fn(Vector3D) -> f32 + Send + Sync>),
+}
+
+impl Distance {
+    pub fn function(f: impl Fn(Vector3D) -> f32 + Send + Sync + 'static) -> Self {
+        Distance::Function(Arc::new(f))
+    }
+
+    pub fn translate(self, offset: Vector3D) -> Self {
+        Distance::Translate {
+            shape: Box::new(self),
+            offset,
+        }
+    }
+
+    pub fn union(self, other: Distance) -> Self {
+        Distance::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Distance) -> Self {
+        Distance::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtraction(self, other: Distance) -> Self {
+        Distance::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Distance, k: f32) -> Self {
+        Distance::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            k,
+        }
+    }
+
+    pub fn repeat(self, period: Vector3D) -> Self {
+        Distance::Repeat {
+            shape: Box::new(self),
+            period,
+        }
+    }
+
+    /// Signed distance from `p` to the surface
+    pub fn evaluate(&self, p: Vector3D) -> f32 {
+        match self {
+            Distance::Sphere { radius } => p.length() - radius,
+            Distance::Cuboid { half_size } => {
+                let q = Vector3D::new(p.x().abs(), p.y().abs(), p.z().abs()) - *half_size;
+                let outside = Vector3D::new(q.x().max(0.), q.y().max(0.), q.z().max(0.));
+                outside.length() + q.max_component().min(0.)
+            }
+            Distance::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - major_radius;
+                (ring * ring + p.y() * p.y()).sqrt() - minor_radius
+            }
+            Distance::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let side = (p.x() * p.x() + p.z() * p.z()).sqrt() - radius;
+                let cap = p.y().abs() - half_height;
+                let outside = (side.max(0.).powi(2) + cap.max(0.).powi(2)).sqrt();
+                outside + side.max(cap).min(0.)
+            }
+            Distance::Plane { normal, offset } => p.dot(unit_vector(*normal)) - offset,
+            Distance::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            Distance::Translate { shape, offset } => shape.evaluate(p - *offset),
+            Distance::Union(a, b) => a.evaluate(p).min(b.evaluate(p)),
+            Distance::Intersection(a, b) => a.evaluate(p).max(b.evaluate(p)),
+            Distance::Subtraction(a, b) => a.evaluate(p).max(-b.evaluate(p)),
+            Distance::SmoothUnion { a, b, k } => {
+                let (da, db) = (a.evaluate(p), b.evaluate(p));
+                if *k <= 0. {
+                    return da.min(db);
+                }
+                // Polynomial smooth minimum, which lies at most k / 4 below the plain one
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0., 1.);
+                db + (da - db) * h - k * h * (1. - h)
+            }
+            Distance::Repeat { shape, period } => {
+                let mut q = p;
+                for axis in 0..3 {
+                    let c = period.e[axis];
+                    if c > 0. {
+                        q.e[axis] -= c * (q.e[axis] / c).round();
+                    }
+                }
+                shape.evaluate(q)
+            }
+            Distance::Function(f) => f(p),
+        }
+    }
+
+    /// Box the surface fits in, or `None` if it goes on forever
+    pub fn bounds(&self) -> Option<Aabb> {
+        let centred = |r: Vector3D| Some(Aabb { min: -r, max: r });
+        match self {
+            Distance::Sphere { radius } => centred(Vector3D::new(*radius, *radius, *radius)),
+            Distance::Cuboid { half_size } => centred(*half_size),
+            Distance::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let outer = major_radius + minor_radius;
+                centred(Vector3D::new(outer, *minor_radius, outer))
+            }
+            Distance::Cylinder {
+                radius,
+                half_height,
+            } => centred(Vector3D::new(*radius, *half_height, *radius)),
+            Distance::Mandelbulb { .. } => centred(Vector3D::new(1.2, 1.2, 1.2)),
+            Distance::Translate { shape, offset } => shape.bounds().map(|b| Aabb {
+                min: b.min + *offset,
+                max: b.max + *offset,
+            }),
+            Distance::Union(a, b) => Some(a.bounds()?.union(&b.bounds()?)),
+            Distance::Intersection(a, b) => match (a.bounds(), b.bounds()) {
+                (Some(a), Some(b)) => Some(Aabb {
+                    min: Vector3D::new(
+                        a.min.x().max(b.min.x()),
+                        a.min.y().max(b.min.y()),
+                        a.min.z().max(b.min.z()),
+                    ),
+                    max: Vector3D::new(
+                        a.max.x().min(b.max.x()),
+                        a.max.y().min(b.max.y()),
+                        a.max.z().min(b.max.z()),
+                    ),
+                }),
+                (a, b) => a.or(b),
+            },
+            Distance::Subtraction(a, _) => a.bounds(),
+            Distance::SmoothUnion { a, b, k } => {
+                let b = a.bounds()?.union(&b.bounds()?);
+                let grow = Vector3D::new(*k, *k, *k) * 0.25;
+                Some(Aabb {
+                    min: b.min - grow,
+                    max: b.max + grow,
+                })
+            }
+            Distance::Plane { .. } | Distance::Repeat { .. } | Distance::Function(_) => None,
+        }
+    }
+}
+
+/// Distance estimate of the Mandelbulb, from how fast the point escapes under iteration
+fn mandelbulb(p: Vector3D, power: f32, iterations: u32) -> f32 {
+    let mut z = p;
+    let mut dr = 1.;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2. || r == 0. {
+            break;
+        }
+        let theta = (z.z() / r).acos() * power;
+        let phi = z.y().atan2(z.x()) * power;
+        dr = r.powf(power - 1.) * power * dr + 1.;
+        z = Vector3D::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ) * r.powf(power)
+            + p;
+        r = z.length();
+    }
+    match r > 0. {
+        true => 0.5 * r.ln() * r / dr,
+        false => 0.,
+    }
+}
+
+/// Surface where a signed distance function is zero, hit by sphere tracing. Normals come from
+/// finite differences of the distance.
+pub struct Sdf {
+    pub distance: Distance,
+    pub material: Material,
+    /// Box the surface lies in, which rays are only marched through. Without one rays march
+    /// until they run out of steps.
+    pub bounds: Option<Aabb>,
+    /// Most steps taken along a ray before giving up on hitting anything
+    pub max_steps: u32,
+    /// Distance from the surface that counts as a hit, as a fraction of the distance travelled
+    pub precision: f32,
+}
+
+impl Sdf {
+    pub fn new(distance: Distance, material: Material) -> Self {
+        Self {
+            bounds: distance.bounds(),
+            distance,
+            material,
+            max_steps: 256,
+            precision: 1e-4,
+        }
+    }
+
+    pub fn with_bounds(mut self, bounds: Aabb) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn with_precision(mut self, precision: f32) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Gradient of the distance at `p` from four samples at the corners of a tetrahedron
+    /// `h` across
+    fn normal(&self, p: Vector3D, h: f32) -> Vector3D {
+        let corners = [
+            Vector3D::new(1., -1., -1.),
+            Vector3D::new(-1., -1., 1.),
+            Vector3D::new(-1., 1., -1.),
+            Vector3D::new(1., 1., 1.),
+        ];
+        let gradient: Vector3D = corners
+            .iter()
+            .map(|k| *k * self.distance.evaluate(p + *k * h))
+            .sum();
+        match gradient.squared_length() > 0. {
+            true => unit_vector(gradient),
+            false => Vector3D::new(0., 1., 0.),
+        }
+    }
+
+    /// Part of the ray between `t_min` and `t_max` that needs marching through
+    fn span(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        match &self.bounds {
+            Some(bounds) => {
+                // Padded so surfaces touching the box aren't stepped past
+                let pad = (bounds.max - bounds.min) * 1e-3;
+                let padded = Aabb {
+                    min: bounds.min - pad,
+                    max: bounds.max + pad,
+                };
+                let d = r.direction();
+                let inv_direction = Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+                padded.clip(r, inv_direction, t_min, t_max)
+            }
+            None => Some((t_min, t_max)),
+        }
+    }
+
+    /// Which side of the surface the ray is on at `t`: 1 outside and -1 inside
+    fn side(&self, r: &Ray, t: f32) -> f32 {
+        match self.distance.evaluate(r.point_at_parameter(t)) < 0. {
+            true => -1.,
+            false => 1.,
+        }
+    }
+
+    /// Distance from the surface that counts as touching it at `t` along the ray
+    fn threshold(&self, r: &Ray, t: f32) -> f32 {
+        (self.precision * t.abs() * r.direction().length()).max(MIN_DISTANCE)
+    }
+
+    /// Where the ray next reaches the surface, marching from `t` up to `t_end` on the given
+    /// side of it. Unless `clear` it first moves off the surface it starts on.
+    fn march(&self, r: &Ray, mut t: f32, t_end: f32, side: f32, mut clear: bool) -> Option<f32> {
+        let speed = r.direction().length();
+        if speed == 0. {
+            return None;
+        }
+        for _ in 0..self.max_steps {
+            let distance = side * self.distance.evaluate(r.point_at_parameter(t));
+            let threshold = self.threshold(r, t);
+            if distance >= threshold {
+                clear = true;
+            } else if clear {
+                return Some(t);
+            }
+            t += distance.max(threshold) / speed;
+            if t > t_end {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Fills in the hit `t` along the ray
+    fn set_hit(&self, r: &Ray, t: f32, rec: &mut HitRecord) {
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = self.normal(rec.p, self.threshold(r, t).max(1e-4));
+        rec.material = self.material.clone();
+        // Implicit surfaces have no natural parameterization
+        rec.uv = (0., 0.);
+        rec.dpdu = to_world(Vector3D::new(1., 0., 0.), rec.normal);
+        rec.dpdv = to_world(Vector3D::new(0., 1., 0.), rec.normal);
+        rec.curvature = 0.;
+    }
+
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (t, t_end) = match self.span(r, t_min, t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        // Rays starting inside march towards where they leave
+        match self.march(r, t, t_end, self.side(r, t), true) {
+            Some(t) => {
+                self.set_hit(r, t, rec);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Hitable for Sdf {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let hit = self.intersect(r, t_min, t_max, rec);
+        stats::intersection(Primitive::Implicit, hit);
+        hit
+    }
+
+    /// Marches through the whole bounding box, or forward from the origin when there isn't one,
+    /// crossing the surface again each time it's reached
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        let from = match self.bounds {
+            Some(_) => f32::MIN,
+            None => 0.,
+        };
+        let mut intervals = vec![];
+        let (mut t, t_end) = match self.span(r, from, f32::MAX) {
+            Some(span) => span,
+            None => return Some(intervals),
+        };
+        let record = |t: f32| {
+            let mut rec = HitRecord::new(self.material.clone());
+            rec.t = t;
+            if t.is_finite() {
+                self.set_hit(r, t, &mut rec);
+            }
+            rec
+        };
+        // Where the current interval started, while inside
+        let mut enter = match self.side(r, t) < 0. {
+            true => Some(f32::NEG_INFINITY),
+            false => None,
+        };
+        let mut clear = true;
+        loop {
+            let side = if enter.is_some() { -1. } else { 1. };
+            let next = self.march(r, t, t_end, side, clear);
+            stats::intersection(Primitive::Implicit, next.is_some());
+            let next = match next {
+                Some(next) => next,
+                None => {
+                    if let Some(start) = enter {
+                        intervals.push(Interval {
+                            enter: record(start),
+                            exit: record(f32::INFINITY),
+                        });
+                    }
+                    break;
+                }
+            };
+            match enter.take() {
+                Some(start) => intervals.push(Interval {
+                    enter: record(start),
+                    exit: record(next),
+                }),
+                None => enter = Some(next),
+            }
+            t = next;
+            clear = false;
+        }
+        Some(intervals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dummy() -> Material {
+        Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        }
+    }
+
+    #[test]
+    fn test_primitives() {
+        let p = Vector3D::new(3., 0., 0.);
+        assert!((Distance::Sphere { radius: 1. }.evaluate(p) - 2.).abs() < 1e-6);
+        let cube = Distance::Cuboid {
+            half_size: Vector3D::new(1., 1., 1.),
+        };
+        assert!((cube.evaluate(p) - 2.).abs() < 1e-6);
+        assert!((cube.evaluate(Vector3D::new(0., 0.5, 0.)) + 0.5).abs() < 1e-6);
+        let torus = Distance::Torus {
+            major_radius: 2.,
+            minor_radius: 0.5,
+        };
+        assert!((torus.evaluate(p) - 0.5).abs() < 1e-6);
+        let cylinder = Distance::Cylinder {
+            radius: 1.,
+            half_height: 1.,
+        };
+        assert!((cylinder.evaluate(Vector3D::new(0., 3., 0.)) - 2.).abs() < 1e-6);
+        // The bulb's distance is an estimate, but it knows inside from outside
+        let bulb = Distance::Mandelbulb {
+            power: 8.,
+            iterations: 8,
+        };
+        assert!(bulb.evaluate(Vector3D::new(2., 0., 0.)) > 0.);
+        assert!(bulb.evaluate(Vector3D::new(0.1, 0., 0.)) <= 0.);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let sphere = |x: f32| Distance::Sphere { radius: 1. }.translate(Vector3D::new(x, 0., 0.));
+        let p = Vector3D::new(0.9, 0., 0.);
+        assert!((sphere(-1.).union(sphere(1.)).evaluate(p) + 0.9).abs() < 1e-6);
+        assert!((sphere(-1.).intersection(sphere(1.)).evaluate(p) - 0.9).abs() < 1e-6);
+        assert!((sphere(1.).subtraction(sphere(-1.)).evaluate(p) + 0.9).abs() < 1e-6);
+        // Blending fills in between the shapes
+        let gap = Vector3D::new(0., 0.8, 0.);
+        let apart = sphere(-1.).union(sphere(1.));
+        let blended = sphere(-1.).smooth_union(sphere(1.), 0.5);
+        assert!(blended.evaluate(gap) < apart.evaluate(gap));
+        let bounds = blended.bounds().unwrap();
+        assert!(bounds.min.x() < -2. && bounds.max.x() > 2.);
+        let repeated = Distance::Sphere { radius: 1. }.repeat(Vector3D::new(4., 0., 0.));
+        assert!((repeated.evaluate(Vector3D::new(8.5, 0., 0.)) + 0.5).abs() < 1e-5);
+        assert!((repeated.evaluate(Vector3D::new(0., 3., 0.)) - 2.).abs() < 1e-5);
+        assert!(repeated.bounds().is_none());
+    }
+
+    #[test]
+    fn test_sphere_tracing() {
+        let sdf = Sdf::new(
+            Distance::Sphere { radius: 1. }.translate(Vector3D::new(0., 0., -5.)),
+            dummy(),
+        );
+        let mut rec = HitRecord::new(dummy());
+        // Directions needn't be normalized
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., -2.));
+        assert!(sdf.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.).abs() < 1e-3);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-2);
+        // From inside it finds the far side, still facing out
+        let r = Ray::new(Vector3D::new(0., 0., -5.), Vector3D::new(0., 0., -1.));
+        assert!(sdf.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.).abs() < 1e-3);
+        assert!((rec.normal - Vector3D::new(0., 0., -1.)).length() < 1e-2);
+        let r = Ray::new(Vector3D::new(0., 2., 0.), Vector3D::new(0., 0., -1.));
+        assert!(!sdf.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!(!sdf.hit(
+            &Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., -1.)),
+            0.001,
+            3.,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let floor = Distance::Plane {
+            normal: Vector3D::new(0., 1., 0.),
+            offset: -1.,
+        };
+        let sdf = Sdf::new(floor, dummy());
+        assert!(sdf.bounds.is_none());
+        let mut rec = HitRecord::new(dummy());
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., -1., -1.));
+        assert!(sdf.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.p.y() + 1.).abs() < 1e-3);
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 1., 0.));
+        assert!(!sdf.hit(&r, 0.001, 100., &mut rec));
+    }
+}
@@ -1,9 +1,15 @@
-use super::hitable::{HitRecord, Hitable};
+use std::f32::consts::PI;
+
+use super::hitable::{HitRecord, Hitable, Interval};
+use super::quadric::solve_quadratic;
+use crate::stats::{self, Primitive};
 use crate::util::material::Material;
-use crate::util::random::rand_num;
 use crate::util::ray::Ray;
 use crate::util::vector3d::Vector3D;
 
+/// A ball, which is the quadric `Quadric::ellipsoid` with equal radii. It stays a type of its
+/// own as by far the most common shape: it needs no matrix or clipping box to find its hits,
+/// maps `uv` by longitude and latitude, and has an inside for CSG.
 pub struct Sphere {
     pub center: Vector3D,
     radius: f32,
@@ -13,51 +19,118 @@ pub struct Sphere {
 impl Sphere {
     pub fn new(center: Vector3D, radius: f32, m: Material) -> Self {
         Self {
-            center: center,
-            radius: radius,
+            center,
+            radius,
             material: m,
         }
     }
-}
 
-impl Hitable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-        let oc = r.origin().clone() - self.center.clone();
+    /// Fills in the surface coordinates of a hit, with `u` running around the y axis and `v` from
+    /// the bottom pole to the top
+    fn set_surface(&self, rec: &mut HitRecord) {
+        let n = rec.normal;
+        let mut phi = n.z().atan2(n.x());
+        if phi < 0. {
+            phi += 2. * PI;
+        }
+        let theta = n.y().clamp(-1., 1.).acos();
+        rec.uv = (phi / (2. * PI), 1. - theta / PI);
+        let r = self.radius;
+        rec.dpdu = Vector3D::new(-n.z(), 0., n.x()) * (2. * PI * r);
+        rec.dpdv = Vector3D::new(
+            -theta.cos() * phi.cos(),
+            theta.sin(),
+            -theta.cos() * phi.sin(),
+        ) * (PI * r);
+        rec.curvature = 1. / r;
+    }
+
+    /// Fills in the hit `t` along the ray
+    fn set_hit(&self, r: &Ray, t: f32, rec: &mut HitRecord) {
+        rec.t = t;
+        rec.p = r.point_at_parameter(rec.t);
+        rec.normal = (rec.p - self.center) / self.radius;
+        rec.material = self.material.clone();
+        self.set_surface(rec);
+    }
+
+    /// Distances along the ray to where it enters and leaves the sphere, if it passes through
+    fn roots(&self, r: &Ray) -> Option<(f32, f32)> {
+        let oc = r.origin() - self.center;
         let a = r.direction().dot(r.direction());
         let b = oc.dot(r.direction());
         let c = oc.dot(oc) - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-        let res = discriminant > 0.;
-        match res {
-            true => {
-                let mut temp = (-b - (b * b - a * c).sqrt()) / a;
-                if temp < t_max && temp > t_min {
-                    rec.t = temp;
-                    rec.p = r.point_at_parameter(rec.t);
-                    rec.normal = (rec.p - self.center) / self.radius;
-                    rec.material = self.material.clone();
-                    return true;
-                }
-                temp = (-b + (b * b - a * c).sqrt()) / a;
-                if temp < t_max && temp > t_min {
-                    rec.t = temp;
-                    rec.p = r.point_at_parameter(rec.t);
-                    rec.normal = (rec.p - self.center) / self.radius;
-                    rec.material = self.material.clone();
-                    return true;
-                }
-                return false;
+        solve_quadratic(a, b, c)
+    }
+
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (near, far) = match self.roots(r) {
+            Some(roots) => roots,
+            None => return false,
+        };
+        for t in [near, far].iter() {
+            if *t < t_max && *t > t_min {
+                self.set_hit(r, *t, rec);
+                return true;
             }
-            false => false,
         }
+        false
     }
 }
 
-pub fn random_in_unit_sphere() -> Vector3D {
-    loop {
-        let p = Vector3D::new(rand_num(), rand_num(), rand_num()) * 2. - Vector3D::new(1., 1., 1.);
-        if p.squared_length() >= 1. {
-            return p;
-        }
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let hit = self.intersect(r, t_min, t_max, rec);
+        stats::intersection(Primitive::Sphere, hit);
+        hit
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        let roots = self.roots(r);
+        stats::intersection(Primitive::Sphere, roots.is_some());
+        let (near, far) = match roots {
+            Some(roots) => roots,
+            None => return Some(vec![]),
+        };
+        let mut enter = HitRecord::new(self.material.clone());
+        let mut exit = enter.clone();
+        self.set_hit(r, near, &mut enter);
+        self.set_hit(r, far, &mut exit);
+        Some(vec![Interval { enter, exit }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surface_coordinates() {
+        let sphere = Sphere::new(
+            Vector3D::new(0., 0., 0.),
+            2.,
+            Material::Lambertian {
+                albedo: Vector3D::new(0.5, 0.5, 0.5),
+            },
+        );
+        let mut rec = HitRecord::new(Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        });
+        let r = Ray::new(Vector3D::new(0., 0., 5.), Vector3D::new(0., 0., -1.));
+        assert!(sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.uv.0 - 0.25).abs() < 1e-6);
+        assert!((rec.uv.1 - 0.5).abs() < 1e-6);
+        assert_eq!(rec.curvature, 0.5);
+        // Moving a little in u or v moves the hit along the derivatives
+        let step = 1e-3;
+        let point = |u: f32, v: f32| {
+            let (phi, theta) = (2. * PI * u, PI * (1. - v));
+            Vector3D::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()) * 2.
+        };
+        let (u, v) = rec.uv;
+        let du = (point(u + step, v) - point(u - step, v)) / (2. * step);
+        let dv = (point(u, v + step) - point(u, v - step)) / (2. * step);
+        assert!((du - rec.dpdu).length() < 0.01);
+        assert!((dv - rec.dpdv).length() < 0.01);
     }
 }
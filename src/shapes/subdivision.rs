@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+
+use super::mesh::Mesh;
+use crate::util::material::Material;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Rule for smoothing a polygon mesh by repeatedly refining it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// Loop subdivision, which splits every triangle into four. Other polygons are split into
+    /// triangles first.
+    Loop,
+    /// Catmull-Clark subdivision, which splits every polygon into quads, one per corner
+    CatmullClark,
+}
+
+impl Scheme {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "loop" => Ok(Scheme::Loop),
+            "catmull-clark" => Ok(Scheme::CatmullClark),
+            _ => Err(format!("Unknown subdivision scheme {}", name)),
+        }
+    }
+}
+
+/// A polygon mesh before it's split into the triangles that get rendered. Each vertex
+/// attribute is either empty or holds one value per vertex.
+#[derive(Clone, Debug, Default)]
+pub struct Cage {
+    pub positions: Vec<Vector3D>,
+    /// Polygons as vertex indices, counterclockwise seen from the front
+    pub faces: Vec<Vec<u32>>,
+    pub normals: Vec<Vector3D>,
+    pub uvs: Vec<(f32, f32)>,
+    pub colors: Vec<Vector3D>,
+}
+
+/// New vertex as a weighted sum of old ones
+type Stencil = Vec<(u32, f32)>;
+
+/// An edge between two vertices and the faces on either side of it
+struct Edge {
+    vertices: [u32; 2],
+    faces: Vec<usize>,
+}
+
+impl Cage {
+    /// Cage of the `faces` joining vertices at `positions`. Faces with fewer than three corners
+    /// or referring to missing vertices are dropped.
+    pub fn new(positions: Vec<Vector3D>, mut faces: Vec<Vec<u32>>) -> Self {
+        faces.retain(|f| f.len() >= 3 && f.iter().all(|i| (*i as usize) < positions.len()));
+        Self {
+            positions,
+            faces,
+            ..Default::default()
+        }
+    }
+
+    /// Merges vertices at exactly the same position, keeping the attributes of the first, so
+    /// faces stored apart become connected. Faces left with fewer than three corners are
+    /// dropped.
+    pub fn weld(self) -> Self {
+        let mut first = HashMap::new();
+        let mut kept = vec![];
+        let remap: Vec<u32> = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                *first.entry(p.e.map(f32::to_bits)).or_insert_with(|| {
+                    kept.push(i);
+                    kept.len() as u32 - 1
+                })
+            })
+            .collect();
+        let keep = |values: &[Vector3D]| match values.is_empty() {
+            true => vec![],
+            false => kept.iter().map(|i| values[*i]).collect(),
+        };
+        let faces = self
+            .faces
+            .iter()
+            .map(|f| {
+                let mut face: Vec<u32> = f.iter().map(|i| remap[*i as usize]).collect();
+                face.dedup();
+                if face.len() > 1 && face.first() == face.last() {
+                    face.pop();
+                }
+                face
+            })
+            .filter(|f| f.len() >= 3)
+            .collect();
+        Self {
+            positions: keep(&self.positions),
+            faces,
+            normals: keep(&self.normals),
+            uvs: match self.uvs.is_empty() {
+                true => vec![],
+                false => kept.iter().map(|i| self.uvs[*i]).collect(),
+            },
+            colors: keep(&self.colors),
+        }
+    }
+
+    /// The cage refined `levels` times by `scheme`. Texture coordinates and colours are
+    /// interpolated linearly and the normals are recomputed from the smoothed surface.
+    pub fn subdivide(&self, scheme: Scheme, levels: u32) -> Self {
+        let mut cage = self.clone();
+        for _ in 0..levels {
+            if scheme == Scheme::Loop {
+                cage.faces = cage.triangles().iter().map(|t| t.to_vec()).collect();
+            }
+            let (faces, smooth, linear) = refine(&cage.faces, cage.positions.len(), scheme);
+            let uvs: Vec<Vector3D> = cage
+                .uvs
+                .iter()
+                .map(|(u, v)| Vector3D::new(*u, *v, 0.))
+                .collect();
+            cage = Self {
+                positions: apply(&smooth, &cage.positions),
+                faces,
+                normals: vec![],
+                uvs: apply(&linear, &uvs)
+                    .iter()
+                    .map(|uv| (uv.x(), uv.y()))
+                    .collect(),
+                colors: apply(&linear, &cage.colors),
+            };
+        }
+        if levels > 0 {
+            cage.normals = cage.smooth_normals();
+        }
+        cage
+    }
+
+    /// Normal at each vertex, averaged over the faces around it weighted by their area
+    pub fn smooth_normals(&self) -> Vec<Vector3D> {
+        let mut normals = vec![Vector3D::new(0., 0., 0.); self.positions.len()];
+        for face in self.faces.iter() {
+            // Newell's method, which also copes with polygons that aren't flat
+            let mut n = Vector3D::new(0., 0., 0.);
+            for (i, a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                n += self.positions[*a as usize].cross(self.positions[b as usize]);
+            }
+            for v in face.iter() {
+                normals[*v as usize] += n;
+            }
+        }
+        normals
+            .into_iter()
+            .map(|n| match n.squared_length() > 0. {
+                true => unit_vector(n),
+                false => n,
+            })
+            .collect()
+    }
+
+    /// Moves each vertex along its normal by `height` at its texture coordinates, then
+    /// recomputes the normals of the displaced surface. Does nothing without texture
+    /// coordinates.
+    pub fn displace(&mut self, height: impl Fn((f32, f32)) -> f32) {
+        if self.uvs.is_empty() {
+            return;
+        }
+        let normals = match self.normals.is_empty() {
+            true => self.smooth_normals(),
+            false => self.normals.clone(),
+        };
+        for ((p, n), uv) in self.positions.iter_mut().zip(normals).zip(self.uvs.iter()) {
+            *p += n * height(*uv);
+        }
+        self.normals = self.smooth_normals();
+    }
+
+    /// The faces split into triangles, fanning out from their first corner
+    pub fn triangles(&self) -> Vec<[u32; 3]> {
+        self.faces
+            .iter()
+            .flat_map(|f| (2..f.len()).map(move |k| [f[0], f[k - 1], f[k]]))
+            .collect()
+    }
+
+    pub fn into_mesh(self, material: Material) -> Mesh {
+        let triangles = self.triangles();
+        Mesh::new(self.positions, triangles, material)
+            .with_normals(self.normals)
+            .with_uvs(self.uvs)
+            .with_colors(self.colors)
+    }
+}
+
+fn apply(stencils: &[Stencil], values: &[Vector3D]) -> Vec<Vector3D> {
+    if values.is_empty() {
+        return vec![];
+    }
+    stencils
+        .iter()
+        .map(|s| {
+            s.iter().fold(Vector3D::new(0., 0., 0.), |sum, (i, w)| {
+                sum + values[*i as usize] * *w
+            })
+        })
+        .collect()
+}
+
+/// One level of refinement: the new faces, and the stencils giving each new vertex in the
+/// smooth surface and by linear interpolation. New vertices are numbered with the old ones
+/// first, then one per edge and, for Catmull-Clark, one per face.
+fn refine(
+    faces: &[Vec<u32>],
+    vertex_count: usize,
+    scheme: Scheme,
+) -> (Vec<Vec<u32>>, Vec<Stencil>, Vec<Stencil>) {
+    let mut edges: Vec<Edge> = vec![];
+    let mut edge_index = HashMap::new();
+    let mut vertex_edges: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    let mut vertex_faces: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    for (f, face) in faces.iter().enumerate() {
+        for (i, a) in face.iter().enumerate() {
+            let b = face[(i + 1) % face.len()];
+            let key = (*a.min(&b), *a.max(&b));
+            let e = *edge_index.entry(key).or_insert_with(|| {
+                edges.push(Edge {
+                    vertices: [*a, b],
+                    faces: vec![],
+                });
+                vertex_edges[*a as usize].push(edges.len() - 1);
+                vertex_edges[b as usize].push(edges.len() - 1);
+                edges.len() - 1
+            });
+            edges[e].faces.push(f);
+            vertex_faces[*a as usize].push(f);
+        }
+    }
+    let edge_of = |a: u32, b: u32| edge_index[&(a.min(b), a.max(b))] as u32;
+    let edge_vertex = |a: u32, b: u32| vertex_count as u32 + edge_of(a, b);
+    let face_vertex = |f: usize| (vertex_count + edges.len() + f) as u32;
+    let centroid = |f: usize| -> Stencil {
+        let w = 1. / faces[f].len() as f32;
+        faces[f].iter().map(|v| (*v, w)).collect()
+    };
+    let scaled = |s: Stencil, k: f32| s.into_iter().map(move |(i, w)| (i, w * k));
+
+    // Old vertices move towards their neighbours
+    let mut smooth: Vec<Stencil> = (0..vertex_count)
+        .map(|v| {
+            let v32 = v as u32;
+            let other = |e: &usize| {
+                let [a, b] = edges[*e].vertices;
+                if a == v32 {
+                    b
+                } else {
+                    a
+                }
+            };
+            let boundary: Vec<u32> = vertex_edges[v]
+                .iter()
+                .filter(|e| edges[**e].faces.len() != 2)
+                .map(other)
+                .collect();
+            let neighbours: Vec<u32> = vertex_edges[v].iter().map(other).collect();
+            match (boundary.len(), neighbours.len()) {
+                // Unconnected vertices, corners and non-manifold vertices stay put
+                (_, 0) | (1, _) | (3.., _) => vec![(v32, 1.)],
+                // Boundaries are smoothed as curves of their own
+                (2, _) => vec![(v32, 0.75), (boundary[0], 0.125), (boundary[1], 0.125)],
+                (_, n) => match scheme {
+                    Scheme::Loop => {
+                        let beta = match n {
+                            3 => 3. / 16.,
+                            _ => 3. / (8. * n as f32),
+                        };
+                        let mut s = vec![(v32, 1. - n as f32 * beta)];
+                        s.extend(neighbours.iter().map(|u| (*u, beta)));
+                        s
+                    }
+                    Scheme::CatmullClark => {
+                        let n = n as f32;
+                        let faces = &vertex_faces[v];
+                        let mut s = vec![(v32, (n - 2.) / n)];
+                        s.extend(neighbours.iter().map(|u| (*u, 1. / (n * n))));
+                        for f in faces.iter() {
+                            s.extend(scaled(centroid(*f), 1. / (n * faces.len() as f32)));
+                        }
+                        s
+                    }
+                },
+            }
+        })
+        .collect();
+    let mut linear: Vec<Stencil> = (0..vertex_count as u32).map(|v| vec![(v, 1.)]).collect();
+
+    // A new vertex on every edge
+    for edge in edges.iter() {
+        let [a, b] = edge.vertices;
+        let midpoint = vec![(a, 0.5), (b, 0.5)];
+        smooth.push(match (edge.faces.as_slice(), scheme) {
+            ([f, g], Scheme::Loop) => {
+                let opposite =
+                    |f: usize| *faces[f].iter().find(|v| **v != a && **v != b).unwrap_or(&a);
+                vec![
+                    (a, 0.375),
+                    (b, 0.375),
+                    (opposite(*f), 0.125),
+                    (opposite(*g), 0.125),
+                ]
+            }
+            ([f, g], Scheme::CatmullClark) => {
+                let mut s = vec![(a, 0.25), (b, 0.25)];
+                s.extend(scaled(centroid(*f), 0.25));
+                s.extend(scaled(centroid(*g), 0.25));
+                s
+            }
+            _ => midpoint.clone(),
+        });
+        linear.push(midpoint);
+    }
+
+    let mut refined = vec![];
+    match scheme {
+        Scheme::Loop => {
+            for face in faces.iter() {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let (ab, bc, ca) = (edge_vertex(a, b), edge_vertex(b, c), edge_vertex(c, a));
+                refined.push(vec![a, ab, ca]);
+                refined.push(vec![b, bc, ab]);
+                refined.push(vec![c, ca, bc]);
+                refined.push(vec![ab, bc, ca]);
+            }
+        }
+        Scheme::CatmullClark => {
+            // And one in the middle of every face
+            for (f, face) in faces.iter().enumerate() {
+                smooth.push(centroid(f));
+                linear.push(centroid(f));
+                let n = face.len();
+                for i in 0..n {
+                    let (prev, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                    refined.push(vec![
+                        v,
+                        edge_vertex(v, next),
+                        face_vertex(f),
+                        edge_vertex(prev, v),
+                    ]);
+                }
+            }
+        }
+    }
+    (refined, smooth, linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vector3D, b: Vector3D) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn cube() -> Cage {
+        let positions = (0..8)
+            .map(|i| {
+                let c = |bit: i32| if i & bit != 0 { 1. } else { -1. };
+                Vector3D::new(c(1), c(2), c(4))
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        Cage::new(positions, faces)
+    }
+
+    #[test]
+    fn test_catmull_clark() {
+        let once = cube().subdivide(Scheme::CatmullClark, 1);
+        assert_eq!(once.faces.len(), 24);
+        assert_eq!(once.positions.len(), 8 + 12 + 6);
+        // A corner of the cube is pulled in to 5/9, its faces' middles stay
+        assert_near(once.positions[7], Vector3D::new(5., 5., 5.) / 9.);
+        assert_near(once.positions[8 + 12 + 5], Vector3D::new(1., 0., 0.));
+        assert_near(once.normals[7], unit_vector(Vector3D::new(1., 1., 1.)));
+        // and more levels keep rounding it towards a sphere
+        let twice = cube().subdivide(Scheme::CatmullClark, 3);
+        let radii: Vec<f32> = twice.positions.iter().map(|p| p.length()).collect();
+        let (min, max) = radii
+            .iter()
+            .fold((f32::MAX, 0f32), |(lo, hi), r| (lo.min(*r), hi.max(*r)));
+        assert!(max / min < 1.2, "{} {}", min, max);
+    }
+
+    #[test]
+    fn test_loop() {
+        let tetrahedron = Cage::new(
+            vec![
+                Vector3D::new(1., 1., 1.),
+                Vector3D::new(1., -1., -1.),
+                Vector3D::new(-1., 1., -1.),
+                Vector3D::new(-1., -1., 1.),
+            ],
+            vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]],
+        );
+        let once = tetrahedron.subdivide(Scheme::Loop, 1);
+        assert_eq!((once.positions.len(), once.faces.len()), (10, 16));
+        let twice = tetrahedron.subdivide(Scheme::Loop, 2);
+        assert_eq!(twice.faces.len(), 64);
+        // Each corner is pulled in towards the centre by the rule for three neighbours
+        assert_near(once.positions[0], Vector3D::new(0.25, 0.25, 0.25));
+
+        // A lone triangle is all boundary, which is smoothed as a curve and its edges split
+        let triangle = Cage::new(
+            vec![
+                Vector3D::new(0., 0., 0.),
+                Vector3D::new(1., 0., 0.),
+                Vector3D::new(0., 1., 0.),
+            ],
+            vec![vec![0, 1, 2]],
+        );
+        let split = triangle.subdivide(Scheme::Loop, 1);
+        assert_near(split.positions[0], Vector3D::new(0.125, 0.125, 0.));
+        assert_near(split.positions[3], Vector3D::new(0.5, 0., 0.));
+    }
+
+    #[test]
+    fn test_boundary_and_uvs() {
+        // A flat strip of two quads stays flat, with its texture coordinates spread linearly
+        let mut strip = Cage::new(
+            vec![
+                Vector3D::new(0., 0., 0.),
+                Vector3D::new(1., 0., 0.),
+                Vector3D::new(2., 0., 0.),
+                Vector3D::new(0., 1., 0.),
+                Vector3D::new(1., 1., 0.),
+                Vector3D::new(2., 1., 0.),
+            ],
+            vec![vec![0, 1, 4, 3], vec![1, 2, 5, 4]],
+        );
+        strip.uvs = strip
+            .positions
+            .iter()
+            .map(|p| (p.x() / 2., p.y()))
+            .collect();
+        let smooth = strip.subdivide(Scheme::CatmullClark, 2);
+        for (p, uv) in smooth.positions.iter().zip(smooth.uvs.iter()) {
+            assert_eq!(p.z(), 0.);
+            assert!(p.x() >= 0. && p.x() <= 2. && p.y() >= 0. && p.y() <= 1.);
+            assert!(uv.0 >= 0. && uv.0 <= 1.);
+        }
+        assert!(smooth.normals.iter().all(|n| n.z() > 0.99));
+        assert_eq!(
+            smooth
+                .into_mesh(Material::DummyMat {
+                    albedo: Vector3D::new(0., 0., 0.),
+                })
+                .triangle_count(),
+            64
+        );
+    }
+
+    #[test]
+    fn test_weld_and_displace() {
+        // Two triangles stored apart share an edge once welded
+        let mut cage = Cage::new(
+            vec![
+                Vector3D::new(0., 0., 0.),
+                Vector3D::new(1., 0., 0.),
+                Vector3D::new(0., 1., 0.),
+                Vector3D::new(1., 0., 0.),
+                Vector3D::new(1., 1., 0.),
+                Vector3D::new(0., 1., 0.),
+            ],
+            vec![vec![0, 1, 2], vec![3, 4, 5]],
+        )
+        .weld();
+        assert_eq!(cage.positions.len(), 4);
+        assert_eq!(cage.faces[1], vec![1, 3, 2]);
+
+        cage.displace(|_| 1.);
+        assert_eq!(cage.positions[0], Vector3D::new(0., 0., 0.));
+        cage.uvs = vec![(0., 0.), (1., 0.), (0., 1.), (1., 1.)];
+        cage.displace(|(u, _)| u);
+        assert_near(cage.positions[1], Vector3D::new(1., 0., 1.));
+        assert_near(cage.positions[0], Vector3D::new(0., 0., 0.));
+    }
+}
@@ -0,0 +1,25 @@
+//! Helpers shared by the shapes' tests
+
+use super::hitable::{HitRecord, Hitable};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::vector3d::Vector3D;
+
+pub(crate) fn grey() -> Material {
+    Material::Lambertian {
+        albedo: Vector3D::new(0.5, 0.5, 0.5),
+    }
+}
+
+/// Where a ray from `origin` along `direction` first hits `object`, if anywhere
+pub(crate) fn shoot(
+    object: &dyn Hitable,
+    origin: Vector3D,
+    direction: Vector3D,
+) -> Option<HitRecord> {
+    let mut rec = HitRecord::new(grey());
+    match object.hit(&Ray::new(origin, direction), 1e-4, f32::MAX, &mut rec) {
+        true => Some(rec),
+        false => None,
+    }
+}
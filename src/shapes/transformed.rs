@@ -0,0 +1,87 @@
+use super::hitable::{HitRecord, Hitable, Interval};
+use crate::util::ray::Ray;
+use crate::util::transform::Transform;
+use crate::util::vector3d::unit_vector;
+
+/// A hitable moved, turned or scaled by `transform`. Rays are taken into the object's own space
+/// to be intersected and the hit is brought back out.
+pub struct Transformed {
+    pub object: Box<dyn Hitable>,
+    pub transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(object: Box<dyn Hitable>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    /// The ray in the object's own space. Leaving the direction unnormalized keeps t the same in
+    /// both spaces.
+    fn local(&self, r: &Ray) -> Ray {
+        let inv = self.transform.inverse();
+        Ray::new(inv.point(r.origin()), inv.vector(r.direction())).with_wavelength(r.wavelength())
+    }
+
+    /// Brings a hit of the local ray back out to the world ray `r`
+    fn to_world(&self, r: &Ray, rec: &mut HitRecord) {
+        rec.p = r.point_at_parameter(rec.t);
+        rec.normal = unit_vector(self.transform.normal(rec.normal));
+        rec.dpdu = self.transform.vector(rec.dpdu);
+        rec.dpdv = self.transform.vector(rec.dpdv);
+        rec.curvature /= self.transform.mean_scale();
+    }
+}
+
+impl Hitable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        if !self.object.hit(&self.local(r), t_min, t_max, rec) {
+            return false;
+        }
+        self.to_world(r, rec);
+        true
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval>> {
+        let mut intervals = self.object.intervals(&self.local(r))?;
+        for interval in intervals.iter_mut() {
+            self.to_world(r, &mut interval.enter);
+            self.to_world(r, &mut interval.exit);
+        }
+        Some(intervals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::util::material::Material;
+    use crate::util::vector3d::Vector3D;
+
+    #[test]
+    fn test_moved_sphere() {
+        let sphere = Sphere::new(
+            Vector3D::new(0., 0., 0.),
+            1.,
+            Material::Lambertian {
+                albedo: Vector3D::new(0.5, 0.5, 0.5),
+            },
+        );
+        let transform = Transform::translate(Vector3D::new(0., 0., -5.))
+            * Transform::scale(Vector3D::new(1., 1., 2.)).unwrap();
+        let moved = Transformed::new(Box::new(sphere), transform);
+        let mut rec = HitRecord::new(Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        });
+        // Stretched along z, the sphere's front is now at z = -3
+        let r = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., -2.));
+        assert!(moved.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.5).abs() < 1e-5);
+        assert!((rec.p - Vector3D::new(0., 0., -3.)).length() < 1e-5);
+        assert!((rec.normal - Vector3D::new(0., 0., 1.)).length() < 1e-5);
+        // and its side still faces sideways
+        let r = Ray::new(Vector3D::new(5., 0., -5.), Vector3D::new(-1., 0., 0.));
+        assert!(moved.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.normal - Vector3D::new(1., 0., 0.)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,377 @@
+//! Grids of voxels, each empty or filled with one of a list of materials, as simulations and
+//! block worlds produce them. Rays step from voxel to voxel in order (Amanatides and Woo 1987)
+//! until what fills them changes, so the cost grows with the voxels crossed, not the voxels
+//! filled.
+
+use super::bvh::Aabb;
+use super::hitable::{HitRecord, Hitable};
+use crate::stats::{self, Primitive};
+use crate::util::material::Material;
+use crate::util::ray::Ray;
+use crate::util::vector3d::Vector3D;
+
+/// Material index of an empty voxel
+pub const EMPTY: u16 = 0;
+
+/// Box of cubic voxels from `origin`, `dims` voxels along each axis. Voxel `n` other than
+/// `EMPTY` is filled with `materials[n - 1]`. Surfaces lie wherever neighbouring voxels are
+/// filled differently and face out of the voxel filled with the material they take, which is
+/// the one being entered or, going out into empty space, the one being left. `u` and `v` run
+/// across each voxel's face.
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    voxels: Vec<u16>,
+    materials: Vec<Material>,
+    pub origin: Vector3D,
+    pub voxel_size: f32,
+}
+
+impl VoxelGrid {
+    /// Grid of unit voxels from the origin, with `voxels` in rows along `x`, then layers along
+    /// `y`. Fails unless the grid is at least one voxel each way, every voxel has a material
+    /// index and every index is `EMPTY` or names one of `materials`.
+    pub fn new(
+        dims: [usize; 3],
+        voxels: Vec<u16>,
+        materials: Vec<Material>,
+    ) -> Result<Self, String> {
+        if dims.contains(&0) {
+            return Err(format!(
+                "a voxel grid can't be {} by {} by {}",
+                dims[0], dims[1], dims[2]
+            ));
+        }
+        let count = dims[0] * dims[1] * dims[2];
+        if voxels.len() != count {
+            return Err(format!(
+                "{} material indices given for {} voxels",
+                voxels.len(),
+                count
+            ));
+        }
+        if let Some(v) = voxels.iter().find(|v| (**v as usize) > materials.len()) {
+            return Err(format!(
+                "voxels refer to material {} of {}",
+                v,
+                materials.len()
+            ));
+        }
+        Ok(Self {
+            dims,
+            voxels,
+            materials,
+            origin: Vector3D::new(0., 0., 0.),
+            voxel_size: 1.,
+        })
+    }
+
+    /// Empty grid to be filled with `set`
+    pub fn empty(dims: [usize; 3], materials: Vec<Material>) -> Result<Self, String> {
+        Self::new(dims, vec![EMPTY; dims[0] * dims[1] * dims[2]], materials)
+    }
+
+    /// Moves the grid's lowest corner to `origin`
+    pub fn with_origin(mut self, origin: Vector3D) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_voxel_size(mut self, voxel_size: f32) -> Self {
+        self.voxel_size = voxel_size;
+        self
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+        (z * self.dims[1] + y) * self.dims[0] + x
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    fn contains(&self, voxel: [usize; 3]) -> bool {
+        (0..3).all(|a| voxel[a] < self.dims[a])
+    }
+
+    /// What fills `voxel`, or None outside the grid
+    pub fn get(&self, voxel: [usize; 3]) -> Option<u16> {
+        match self.contains(voxel) {
+            true => Some(self.voxels[self.index(voxel)]),
+            false => None,
+        }
+    }
+
+    /// Fills `voxel` with `material`, failing outside the grid or for a material that isn't
+    /// there
+    pub fn set(&mut self, voxel: [usize; 3], material: u16) -> Result<(), String> {
+        if !self.contains(voxel) {
+            return Err(format!("voxel {:?} is outside the grid", voxel));
+        }
+        if material as usize > self.materials.len() {
+            return Err(format!(
+                "material {} of {} isn't there",
+                material,
+                self.materials.len()
+            ));
+        }
+        let i = self.index(voxel);
+        self.voxels[i] = material;
+        Ok(())
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let size = self.dims.map(|n| n as f32 * self.voxel_size);
+        Aabb {
+            min: self.origin,
+            max: self.origin + Vector3D { e: size },
+        }
+    }
+
+    /// Voxel holding `p`, which must be in the grid or on its surface
+    fn voxel_at(&self, p: Vector3D) -> [i64; 3] {
+        [0, 1, 2].map(|axis| {
+            let position = (p.e[axis] - self.origin.e[axis]) / self.voxel_size;
+            (position.floor() as i64).clamp(0, self.dims[axis] as i64 - 1)
+        })
+    }
+
+    fn material_of(&self, cell: [i64; 3]) -> u16 {
+        match (0..3).all(|a| (0..self.dims[a] as i64).contains(&cell[a])) {
+            true => self.voxels[self.index(cell.map(|c| c as usize))],
+            false => EMPTY,
+        }
+    }
+
+    /// Fills in the face across `axis` crossed at `t`, going from voxels filled with `from`
+    /// into ones filled with `to`
+    #[allow(clippy::too_many_arguments)]
+    fn set_hit(
+        &self,
+        r: &Ray,
+        t: f32,
+        axis: usize,
+        step: i64,
+        from: u16,
+        to: u16,
+        rec: &mut HitRecord,
+    ) {
+        let mut normal = Vector3D::new(0., 0., 0.);
+        // Out of the voxel giving the material
+        normal.e[axis] = match to {
+            EMPTY => step as f32,
+            _ => -step as f32,
+        };
+        let material = match to {
+            EMPTY => from,
+            _ => to,
+        };
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = normal;
+        rec.material = self.materials[material as usize - 1].clone();
+        // The other two axes in order around the face
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let fraction = |k: usize| {
+            let position = (rec.p.e[k] - self.origin.e[k]) / self.voxel_size;
+            position - position.floor()
+        };
+        rec.uv = (fraction(a), fraction(b));
+        rec.dpdu = Vector3D::new(0., 0., 0.);
+        rec.dpdv = Vector3D::new(0., 0., 0.);
+        rec.dpdu.e[a] = self.voxel_size;
+        rec.dpdv.e[b] = self.voxel_size;
+        rec.curvature = 0.;
+    }
+
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (o, d) = (r.origin(), r.direction());
+        let inv_direction = Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+        let bounds = self.bounds();
+        let (t_enter, t_exit) = match bounds.clip(r, inv_direction, t_min, t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        let mut cell = self.voxel_at(r.point_at_parameter(t_enter));
+        let mut step = [0i64; 3];
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let boundary = |c: i64| self.origin.e[axis] + c as f32 * self.voxel_size;
+            let d = d.e[axis];
+            if d > 0. {
+                step[axis] = 1;
+                next[axis] = (boundary(cell[axis] + 1) - o.e[axis]) / d;
+                delta[axis] = self.voxel_size / d;
+            } else if d < 0. {
+                step[axis] = -1;
+                next[axis] = (boundary(cell[axis]) - o.e[axis]) / d;
+                delta[axis] = -self.voxel_size / d;
+            }
+        }
+
+        let mut current = self.material_of(cell);
+        if t_enter > t_min {
+            // Coming in from outside the grid, through the face it crossed last
+            let axis = (0..3).filter(|a| step[*a] != 0).max_by(|a, b| {
+                let near = |a: usize| {
+                    let side = if step[a] > 0 { bounds.min } else { bounds.max };
+                    (side.e[a] - o.e[a]) / d.e[a]
+                };
+                near(*a).total_cmp(&near(*b))
+            });
+            if let (Some(axis), true) = (axis, current != EMPTY) {
+                self.set_hit(r, t_enter, axis, step[axis], EMPTY, current, rec);
+                return true;
+            }
+            current = EMPTY;
+        }
+        loop {
+            let axis = (0..3).min_by(|a, b| next[*a].total_cmp(&next[*b])).unwrap();
+            let t = next[axis];
+            // Leaving a filled voxel through the grid's surface is a hit even if rounding puts
+            // it a little past where the ray leaves the grid
+            if !t.is_finite() || t >= t_max || (t > t_exit && current == EMPTY) {
+                return false;
+            }
+            cell[axis] += step[axis];
+            next[axis] += delta[axis];
+            let material = self.material_of(cell);
+            // A crossing right at the start was already left behind
+            if material != current && t > t_min {
+                self.set_hit(r, t, axis, step[axis], current, material, rec);
+                return true;
+            }
+            current = material;
+        }
+    }
+}
+
+impl Hitable for VoxelGrid {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let hit = self.intersect(r, t_min, t_max, rec);
+        stats::intersection(Primitive::Voxel, hit);
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::test_util::shoot;
+
+    fn colored(c: f32) -> Material {
+        Material::Lambertian {
+            albedo: Vector3D::new(c, c, c),
+        }
+    }
+
+    fn albedo(rec: &HitRecord) -> f32 {
+        rec.material.albedo().x()
+    }
+
+    #[test]
+    fn test_new_and_set() {
+        let one = || vec![colored(0.5)];
+        assert!(VoxelGrid::new([2, 1, 1], vec![EMPTY, 1], one()).is_ok());
+        assert!(VoxelGrid::new([2, 1, 1], vec![1], one()).is_err());
+        assert!(VoxelGrid::new([2, 1, 1], vec![1, 2], one()).is_err());
+        assert!(VoxelGrid::new([0, 1, 1], vec![], one()).is_err());
+        assert!(VoxelGrid::empty([4, 0, 4], one()).is_err());
+        let mut grid = VoxelGrid::empty([2, 2, 2], one()).unwrap();
+        assert!(grid.set([1, 1, 1], 1).is_ok());
+        assert_eq!(grid.get([1, 1, 1]), Some(1));
+        assert!(grid.set([1, 2, 1], 1).is_err());
+        assert!(grid.set([0, 0, 0], 2).is_err());
+        assert_eq!(grid.get([0, 0, 0]), Some(EMPTY));
+        assert_eq!(grid.get([0, 0, 2]), None);
+    }
+
+    #[test]
+    fn test_faces() {
+        let mut grid = VoxelGrid::empty([3, 3, 3], vec![colored(0.2), colored(0.8)]).unwrap();
+        grid.set([1, 1, 1], 1).unwrap();
+        // Straight on from outside the grid, onto the middle voxel's face
+        let rec = shoot(
+            &grid,
+            Vector3D::new(1.25, 1.5, 5.),
+            Vector3D::new(0., 0., -1.),
+        )
+        .unwrap();
+        assert!((rec.t - 3.).abs() < 1e-5);
+        assert_eq!(rec.normal, Vector3D::new(0., 0., 1.));
+        assert!((rec.uv.0 - 0.25).abs() < 1e-5 && (rec.uv.1 - 0.5).abs() < 1e-5);
+        assert_eq!(albedo(&rec), 0.2);
+        // Starting inside it, the face it leaves through faces on
+        let rec = shoot(
+            &grid,
+            Vector3D::new(1.5, 1.5, 1.5),
+            Vector3D::new(1., 0., 0.),
+        )
+        .unwrap();
+        assert!((rec.p.x() - 2.).abs() < 1e-5);
+        assert_eq!(rec.normal, Vector3D::new(1., 0., 0.));
+        // Between two materials it takes the one entered
+        grid.set([2, 1, 1], 2).unwrap();
+        let rec = shoot(
+            &grid,
+            Vector3D::new(1.5, 1.5, 1.5),
+            Vector3D::new(1., 0., 0.),
+        )
+        .unwrap();
+        assert_eq!(albedo(&rec), 0.8);
+        assert_eq!(rec.normal, Vector3D::new(-1., 0., 0.));
+        // Filled right to the grid's edge, leaving the grid leaves the voxel
+        let rec = shoot(
+            &grid,
+            Vector3D::new(2.5, 1.5, 1.5),
+            Vector3D::new(1., 0., 0.),
+        )
+        .unwrap();
+        assert!((rec.p.x() - 3.).abs() < 1e-5);
+        assert_eq!(rec.normal, Vector3D::new(1., 0., 0.));
+        assert!(shoot(
+            &grid,
+            Vector3D::new(0.5, 0.5, 5.),
+            Vector3D::new(0., 0., -1.)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_diagonal_walk() {
+        // A staircase, hit at the first step in the way of a ray climbing through it
+        let dims = [8, 8, 1];
+        let voxels = (0..64)
+            .map(|i| match i % 8 >= i / 8 + 2 {
+                true => 1,
+                false => EMPTY,
+            })
+            .collect();
+        let grid = VoxelGrid::new(dims, voxels, vec![colored(0.5)])
+            .unwrap()
+            .with_origin(Vector3D::new(-4., -4., 0.))
+            .with_voxel_size(0.5);
+        assert_eq!(grid.bounds().max, Vector3D::new(0., 0., 0.5));
+        // Along x at the height of the fifth row, which is filled from the seventh column on
+        let origin = Vector3D::new(-5., -1.75, 0.25);
+        let rec = shoot(&grid, origin, Vector3D::new(1., 0., 0.)).unwrap();
+        assert!((rec.p.x() + 1.).abs() < 1e-5);
+        assert_eq!(rec.normal, Vector3D::new(-1., 0., 0.));
+        // Coming down the last column, it lands on the top step
+        let rec = shoot(
+            &grid,
+            Vector3D::new(-0.4, 1., 0.3),
+            Vector3D::new(0.01, -1., 0.),
+        )
+        .unwrap();
+        assert_eq!(rec.normal, Vector3D::new(0., 1., 0.));
+        assert!((rec.p.y() + 1.).abs() < 1e-5);
+        // Running along the diagonal just beside the stairs it never meets them
+        let rec = shoot(
+            &grid,
+            Vector3D::new(-4.2, -4.3, 0.25),
+            Vector3D::new(1., 1., 0.),
+        );
+        assert!(rec.is_none());
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Events counted while rendering
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    CameraRays,
+    BounceRays,
+    ShadowRays,
+    Samples,
+    Paths,
+    PathBounces,
+    /// Paths that escaped to the background
+    TerminatedMiss,
+    /// Paths that reached the hard bounce cap
+    TerminatedDepthCap,
+    /// Paths killed by russian roulette
+    TerminatedRoulette,
+    /// Paths absorbed by a material
+    TerminatedAbsorbed,
+}
+
+const COUNTERS: usize = 10;
+
+/// Kinds of primitive that count their intersection tests
+#[derive(Clone, Copy, Debug)]
+pub enum Primitive {
+    Sphere,
+    Triangle,
+    /// Surface of a signed distance function, tested once per ray however many steps it takes
+    Implicit,
+    Quadric,
+    /// Piece of a curve, tested once per piece however often it's halved
+    Curve,
+    /// Grid of voxels, tested once per ray however many voxels it steps through
+    Voxel,
+}
+
+const PRIMITIVES: [Primitive; 6] = [
+    Primitive::Sphere,
+    Primitive::Triangle,
+    Primitive::Implicit,
+    Primitive::Quadric,
+    Primitive::Curve,
+    Primitive::Voxel,
+];
+
+impl Primitive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Sphere => "sphere",
+            Primitive::Triangle => "triangle",
+            Primitive::Implicit => "implicit",
+            Primitive::Quadric => "quadric",
+            Primitive::Curve => "curve",
+            Primitive::Voxel => "voxel",
+        }
+    }
+}
+
+/// Counters owned by a single thread. Only the owning thread writes to its slot, so counting
+/// is a plain load and store without any contention, and every slot is summed at the end.
+#[repr(align(128))]
+#[derive(Default)]
+struct ThreadSlot {
+    counters: [AtomicU64; COUNTERS],
+    tests: [AtomicU64; PRIMITIVES.len()],
+    hits: [AtomicU64; PRIMITIVES.len()],
+    busy_nanos: AtomicU64,
+}
+
+/// Counting slows rendering down noticeably, so it only happens once enabled
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Slots of every thread that has counted anything
+static SLOTS: Mutex<Vec<Arc<ThreadSlot>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static SLOT: Arc<ThreadSlot> = {
+        let slot = Arc::new(ThreadSlot::default());
+        SLOTS.lock().unwrap().push(slot.clone());
+        slot
+    };
+}
+
+/// Turns counting on for the rest of the program
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn bump(counter: &AtomicU64, n: u64) {
+    counter.store(counter.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+}
+
+pub fn add(counter: Counter, n: u64) {
+    if !enabled() {
+        return;
+    }
+    SLOT.with(|slot| bump(&slot.counters[counter as usize], n));
+}
+
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+/// Records an intersection test against a primitive and whether it hit
+pub fn intersection(primitive: Primitive, hit: bool) {
+    if !enabled() {
+        return;
+    }
+    SLOT.with(|slot| {
+        bump(&slot.tests[primitive as usize], 1);
+        if hit {
+            bump(&slot.hits[primitive as usize], 1);
+        }
+    });
+}
+
+/// Records time the current thread spent doing useful work
+pub fn busy(time: Duration) {
+    if !enabled() {
+        return;
+    }
+    SLOT.with(|slot| bump(&slot.busy_nanos, time.as_nanos() as u64));
+}
+
+/// Clears every counter, e.g. between frames
+pub fn reset() {
+    for slot in SLOTS.lock().unwrap().iter() {
+        let atomics = slot.counters.iter().chain(&slot.tests).chain(&slot.hits);
+        for a in atomics.chain(std::iter::once(&slot.busy_nanos)) {
+            a.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Totals of every counter at the end of a render
+pub struct RenderStats {
+    pub elapsed: Duration,
+    counters: [u64; COUNTERS],
+    tests: [u64; PRIMITIVES.len()],
+    hits: [u64; PRIMITIVES.len()],
+    /// Time each worker thread spent rendering
+    pub thread_busy: Vec<Duration>,
+}
+
+impl RenderStats {
+    /// Sums the counters of all threads. `elapsed` is the wall clock time of the render.
+    pub fn collect(elapsed: Duration) -> Self {
+        let mut stats = Self {
+            elapsed,
+            counters: [0; COUNTERS],
+            tests: [0; PRIMITIVES.len()],
+            hits: [0; PRIMITIVES.len()],
+            thread_busy: vec![],
+        };
+        for slot in SLOTS.lock().unwrap().iter() {
+            for (total, c) in stats.counters.iter_mut().zip(slot.counters.iter()) {
+                *total += c.load(Ordering::Relaxed);
+            }
+            for (total, c) in stats.tests.iter_mut().zip(slot.tests.iter()) {
+                *total += c.load(Ordering::Relaxed);
+            }
+            for (total, c) in stats.hits.iter_mut().zip(slot.hits.iter()) {
+                *total += c.load(Ordering::Relaxed);
+            }
+            // Only threads that rendered anything, which leaves out the main thread
+            let nanos = slot.busy_nanos.load(Ordering::Relaxed);
+            if nanos > 0 {
+                stats.thread_busy.push(Duration::from_nanos(nanos));
+            }
+        }
+        stats
+    }
+
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    pub fn tests(&self, primitive: Primitive) -> u64 {
+        self.tests[primitive as usize]
+    }
+
+    pub fn hits(&self, primitive: Primitive) -> u64 {
+        self.hits[primitive as usize]
+    }
+
+    /// Average number of bounces per path
+    pub fn average_path_length(&self) -> f64 {
+        ratio(self.get(Counter::PathBounces), self.get(Counter::Paths))
+    }
+
+    pub fn samples_per_second(&self) -> f64 {
+        self.get(Counter::Samples) as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// Fraction of the render each worker thread spent busy
+    pub fn utilisation(&self) -> Vec<f64> {
+        let elapsed = self.elapsed.as_secs_f64().max(1e-9);
+        self.thread_busy
+            .iter()
+            .map(|busy| busy.as_secs_f64() / elapsed)
+            .collect()
+    }
+
+    fn terminations(&self) -> [(&'static str, u64); 4] {
+        [
+            ("miss", self.get(Counter::TerminatedMiss)),
+            ("depth_cap", self.get(Counter::TerminatedDepthCap)),
+            ("roulette", self.get(Counter::TerminatedRoulette)),
+            ("absorbed", self.get(Counter::TerminatedAbsorbed)),
+        ]
+    }
+
+    /// Human readable summary
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        out += &format!("Render time:         {} ms\n", self.elapsed.as_millis());
+        out += &format!("Samples per second:  {:.0}\n", self.samples_per_second());
+        out += &format!("Camera rays:         {}\n", self.get(Counter::CameraRays));
+        out += &format!("Bounce rays:         {}\n", self.get(Counter::BounceRays));
+        out += &format!("Shadow rays:         {}\n", self.get(Counter::ShadowRays));
+        out += &format!("Average path length: {:.2}\n", self.average_path_length());
+        out += "Paths terminated by:\n";
+        for (name, n) in self.terminations().iter() {
+            out += &format!("  {:<18} {}\n", name, n);
+        }
+        out += "Intersections (tests / hits):\n";
+        for p in PRIMITIVES.iter() {
+            let (tests, hits) = (self.tests(*p), self.hits(*p));
+            out += &format!(
+                "  {:<18} {} / {} ({:.1}%)\n",
+                p.name(),
+                tests,
+                hits,
+                100. * ratio(hits, tests)
+            );
+        }
+        out += "Thread utilisation:\n";
+        for (i, u) in self.utilisation().iter().enumerate() {
+            out += &format!("  thread {:<11} {:.1}%\n", i, 100. * u);
+        }
+        out
+    }
+
+    /// The same numbers as `report` as a JSON object
+    pub fn to_json(&self) -> String {
+        let rays = format!(
+            "{{\"camera\": {}, \"bounce\": {}, \"shadow\": {}}}",
+            self.get(Counter::CameraRays),
+            self.get(Counter::BounceRays),
+            self.get(Counter::ShadowRays)
+        );
+        let terminated: Vec<String> = self
+            .terminations()
+            .iter()
+            .map(|(name, n)| format!("\"{}\": {}", name, n))
+            .collect();
+        let intersections: Vec<String> = PRIMITIVES
+            .iter()
+            .map(|p| {
+                format!(
+                    "\"{}\": {{\"tests\": {}, \"hits\": {}}}",
+                    p.name(),
+                    self.tests(*p),
+                    self.hits(*p)
+                )
+            })
+            .collect();
+        let utilisation: Vec<String> = self
+            .utilisation()
+            .iter()
+            .map(|u| format!("{:.4}", u))
+            .collect();
+        format!(
+            "{{\n  \"elapsed_ms\": {},\n  \"samples\": {},\n  \"samples_per_second\": {:.1},\n  \
+             \"rays\": {},\n  \"paths\": {},\n  \"average_path_length\": {:.4},\n  \
+             \"terminated\": {{{}}},\n  \"intersections\": {{{}}},\n  \
+             \"thread_utilisation\": [{}]\n}}\n",
+            self.elapsed.as_millis(),
+            self.get(Counter::Samples),
+            self.samples_per_second(),
+            rays,
+            self.get(Counter::Paths),
+            self.average_path_length(),
+            terminated.join(", "),
+            intersections.join(", "),
+            utilisation.join(", ")
+        )
+    }
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    match b {
+        0 => 0.,
+        _ => a as f64 / b as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting() {
+        // Counters are global, so only check they grow by at least what this test adds
+        enable();
+        let before = RenderStats::collect(Duration::from_secs(1));
+        add(Counter::ShadowRays, 3);
+        intersection(Primitive::Sphere, true);
+        intersection(Primitive::Sphere, false);
+        let after = RenderStats::collect(Duration::from_secs(1));
+        assert!(after.get(Counter::ShadowRays) >= before.get(Counter::ShadowRays) + 3);
+        assert!(after.tests(Primitive::Sphere) >= before.tests(Primitive::Sphere) + 2);
+        assert!(after.hits(Primitive::Sphere) > before.hits(Primitive::Sphere));
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let json = RenderStats::collect(Duration::from_millis(10)).to_json();
+        assert!(json.starts_with('{'));
+        assert!(json.trim_end().ends_with('}'));
+        for key in [
+            "\"rays\"",
+            "\"sphere\"",
+            "\"terminated\"",
+            "\"thread_utilisation\"",
+        ]
+        .iter()
+        {
+            assert!(json.contains(key));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::f32::consts::PI;
+use std::io;
+
+use crate::film::read_png_luminance;
+use crate::util::sampling::{concentric_disk, uniform_triangle, Distribution2D};
+use crate::util::vector3d::Vector3D;
+
+/// Shape of the lens opening, which out of focus highlights take on as bokeh
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    Circle,
+    /// Regular polygon formed by `blades` straight diaphragm blades, turned by `rotation`
+    /// degrees
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    /// Opening whose transmission is given by a grayscale image stretched over the lens
+    Image(Distribution2D),
+}
+
+impl Aperture {
+    /// Aperture with the shape of the brightness of a PNG image
+    pub fn from_png(path: &str) -> io::Result<Self> {
+        let (nx, ny, luminance) = read_png_luminance(path)?;
+        // Images are stored from the top, but the lens plane's y axis points up
+        let rows: Vec<f32> = luminance
+            .chunks(nx as usize)
+            .rev()
+            .flat_map(|row| row.to_vec())
+            .collect();
+        Ok(Aperture::Image(Distribution2D::new(
+            &rows,
+            nx as usize,
+            ny as usize,
+        )))
+    }
+
+    /// Maps a point of the unit square to a point uniformly spread over the opening, which
+    /// fits inside the square from -1 to 1 in the `z = 0` plane
+    pub fn sample(&self, u: (f32, f32)) -> Vector3D {
+        match self {
+            Aperture::Circle => concentric_disk(u),
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the centre and an edge, then a point in it
+                let n = (*blades).max(3);
+                let scaled = u.0 * n as f32;
+                let i = (scaled as u32).min(n - 1);
+                let corner = |k: u32| {
+                    let angle = rotation * PI / 180. + 2. * PI * k as f32 / n as f32;
+                    Vector3D::new(angle.cos(), angle.sin(), 0.)
+                };
+                let b = uniform_triangle((scaled - i as f32, u.1));
+                corner(i) * b[1] + corner(i + 1) * b[2]
+            }
+            Aperture::Image(distribution) => {
+                let ((x, y), _) = distribution.sample(u);
+                Vector3D::new(2. * x - 1., 2. * y - 1., 0.)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon() {
+        let hexagon = Aperture::Polygon {
+            blades: 6,
+            rotation: 0.,
+        };
+        // An edge of a hexagon with a corner on the x axis is sqrt(3)/2 from the centre
+        let apothem = 3f32.sqrt() / 2.;
+        let mut extent = 0f32;
+        for i in 0..64 {
+            for j in 0..64 {
+                let p = hexagon.sample((i as f32 / 64., j as f32 / 64.));
+                assert!(p.length() <= 1. + 1e-5);
+                assert!(p.y().abs() <= apothem + 1e-5);
+                extent = extent.max(p.x());
+            }
+        }
+        assert!(extent > 0.95);
+        // Turned by 30 degrees the flat edges face along x instead
+        let turned = Aperture::Polygon {
+            blades: 6,
+            rotation: 30.,
+        };
+        for i in 0..16 {
+            let p = turned.sample((i as f32 / 16., 0.99));
+            assert!(p.x().abs() <= apothem + 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_image() {
+        // Only the top right quarter of the lens lets light through
+        let distribution = Distribution2D::new(&[0., 0., 0., 1.], 2, 2);
+        let aperture = Aperture::Image(distribution);
+        for i in 0..16 {
+            let p = aperture.sample((i as f32 / 16., 0.3));
+            assert!(p.x() >= 0. && p.y() >= 0.);
+        }
+    }
+}
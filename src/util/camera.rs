@@ -1,9 +1,74 @@
 use std::f32::consts::PI;
 
-use crate::util::random::rand_num;
+use crate::sampler::Sampler;
+use crate::util::aperture::Aperture;
+use crate::util::differential::RayDifferential;
 use crate::util::ray::Ray;
 use crate::util::vector3d::{unit_vector, Vector3D};
 
+/// Turns a position on the film into a ray leaving the camera
+pub trait CameraModel: Sync {
+    /// Ray through film position `(u, v)`, both running from 0 to 1 with `v` upwards, leaving
+    /// the lens at the point picked by `lens` from the unit square. Returns `None` where the film
+    /// sees nothing, such as outside a fisheye's image circle.
+    fn generate_ray(&self, u: f32, v: f32, lens: (f32, f32)) -> Option<Ray>;
+
+    /// Ray through film position `(u, v)`, drawing the point on the lens from `sampler`
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.generate_ray(u, v, sampler.get_2d())
+    }
+
+    /// Like `get_ray`, but with differentials holding the rays through the same point on the
+    /// lens from `du` and `dv` further along the film. They're left out where those rays see
+    /// nothing.
+    fn get_ray_differential(
+        &self,
+        u: f32,
+        v: f32,
+        du: f32,
+        dv: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Ray> {
+        let lens = sampler.get_2d();
+        let ray = self.generate_ray(u, v, lens)?;
+        let differential = match (
+            self.generate_ray(u + du, v, lens),
+            self.generate_ray(u, v + dv, lens),
+        ) {
+            (Some(rx), Some(ry)) => Some(RayDifferential::new(&rx, &ry)),
+            _ => None,
+        };
+        Some(ray.with_differential(differential))
+    }
+}
+
+/// The projections a camera can use, selected by name on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    /// Equidistant fisheye, where distance from the centre of the image is proportional to angle
+    Fisheye,
+    /// Full 360 by 180 degree panorama in latitude and longitude, as used for environment maps
+    Equirectangular,
+    /// 360 degree panorama wrapped around a vertical cylinder
+    Cylindrical,
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "cylindrical" => Ok(Projection::Cylindrical),
+            _ => Err(format!("Unknown camera {}", name)),
+        }
+    }
+}
+
+/// Perspective thin lens camera
 pub struct Camera {
     origin: Vector3D,
     lower_left_corner: Vector3D,
@@ -12,6 +77,12 @@ pub struct Camera {
     u: Vector3D,
     v: Vector3D,
     lens_radius: f32,
+    aperture: Aperture,
+    /// How far the lens barrel clips the aperture towards the corners of the image
+    cat_eye: f32,
+    /// Normal of the plane in focus, which faces the camera unless the lens is tilted
+    focus_normal: Vector3D,
+    focus_point: Vector3D,
 }
 
 impl Camera {
@@ -27,9 +98,7 @@ impl Camera {
         let theta = vfov * PI / 180.;
         let half_height = (theta / 2.).tan();
         let half_width = aspect * half_height;
-        let w = unit_vector(lookfrom - lookat);
-        let u = unit_vector(vup.cross(w));
-        let v = w.cross(u);
+        let (u, v, w) = basis(lookfrom, lookat, vup);
         Self {
             origin: lookfrom,
             lower_left_corner: lookfrom
@@ -39,25 +108,471 @@ impl Camera {
             vertical: v * 2. * half_height * focus_dist,
             horizontal: u * 2. * half_width * focus_dist,
             lens_radius: aperture / 2.,
-            u: u,
-            v: v,
+            aperture: Aperture::Circle,
+            cat_eye: 0.,
+            focus_normal: w,
+            focus_point: lookfrom - w * focus_dist,
+            u,
+            v,
+        }
+    }
+
+    /// Perspective camera described by a lens and sensor, fitting an image of the given aspect
+    /// ratio inside the sensor
+    pub fn physical(
+        lookfrom: Vector3D,
+        lookat: Vector3D,
+        vup: Vector3D,
+        lens: &PhysicalLens,
+        aspect: f32,
+    ) -> Self {
+        Self::new(
+            lookfrom,
+            lookat,
+            vup,
+            lens.vfov(aspect),
+            aspect,
+            lens.aperture(),
+            lens.focus_distance,
+        )
+    }
+
+    /// Gives the aperture a different shape, and so the bokeh
+    pub fn with_aperture(self, aperture: Aperture) -> Self {
+        Self { aperture, ..self }
+    }
+
+    /// Clips the aperture with the lens barrel away from the centre of the image, so bokeh
+    /// there is cut into a cat's eye and the corners darken. At 1 the clipping circle's centre
+    /// has moved a lens radius by the corners.
+    pub fn with_cat_eye(self, strength: f32) -> Self {
+        Self {
+            cat_eye: strength,
+            ..self
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = random_in_unit_disk() * self.lens_radius;
+    /// Tilts the lens by `degrees` about the horizontal axis, swinging the plane in focus
+    /// around the focus point as with a tilt-shift lens. Positive angles bring the plane in
+    /// focus closer at the bottom of the image and further away at the top, so it can lie
+    /// along the ground.
+    pub fn with_tilt(self, degrees: f32) -> Self {
+        let (sin, cos) = (degrees * PI / 180.).sin_cos();
+        let w = self.u.cross(self.v);
+        Self {
+            focus_normal: w * cos + self.v * sin,
+            ..self
+        }
+    }
+}
+
+/// A lens and sensor as photographers describe them. Sizes on the sensor are in millimetres and
+/// the scene is taken to be in metres.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalLens {
+    pub focal_length: f32,
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    /// Focal length divided by the diameter of the aperture
+    pub f_number: f32,
+    /// Distance in metres to the plane in focus
+    pub focus_distance: f32,
+}
+
+impl PhysicalLens {
+    /// Vertical field of view in degrees of the largest part of the sensor with the aspect
+    /// ratio of the image
+    pub fn vfov(&self, aspect: f32) -> f32 {
+        let height = self.sensor_height.min(self.sensor_width / aspect);
+        2. * (height / (2. * self.focal_length)).atan() * 180. / PI
+    }
+
+    /// Diameter of the aperture in metres
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number / 1000.
+    }
+}
+
+/// Photographic exposure. Radiance in the scene is taken to be in cd/m², and the image is
+/// scaled so the brightest luminance the sensor records without saturating becomes 1
+/// (saturation based sensitivity, as in Lagarde and de Rousiers 2014).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    /// Shutter time in seconds
+    pub shutter: f32,
+    pub iso: f32,
+    pub f_number: f32,
+}
+
+impl Exposure {
+    /// Exposure value at ISO 100
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter * 100. / self.iso).log2()
+    }
+
+    /// Factor the rendered radiance is multiplied by
+    pub fn scale(&self) -> f32 {
+        1. / (1.2 * 2f32.powf(self.ev100()))
+    }
+}
+
+impl CameraModel for Camera {
+    fn generate_ray(&self, u: f32, v: f32, lens: (f32, f32)) -> Option<Ray> {
+        let lens = self.aperture.sample(lens);
+        if self.cat_eye > 0. {
+            let (width, height) = (self.horizontal.length(), self.vertical.length());
+            let half_diagonal = (width * width + height * height).sqrt() / 2.;
+            let barrel = Vector3D::new((u - 0.5) * width, (v - 0.5) * height, 0.)
+                * (-self.cat_eye / half_diagonal);
+            if (lens - barrel).squared_length() > 1. {
+                return None;
+            }
+        }
+        let rd = lens * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
-        let dest =
-            self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin - offset;
-        Ray::new(self.origin + offset, dest)
+        // Where the ray through the centre of the lens meets the plane in focus
+        let pinhole =
+            self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin;
+        let t = (self.focus_point - self.origin).dot(self.focus_normal)
+            / pinhole.dot(self.focus_normal);
+        let focus = match t > 0. {
+            true => pinhole * t,
+            false => pinhole,
+        };
+        Some(Ray::new(self.origin + offset, focus - offset))
     }
 }
 
-pub fn random_in_unit_disk() -> Vector3D {
-    loop {
-        let p = Vector3D::new(rand_num(), rand_num(), 0.) * 2. - Vector3D::new(1., 1., 0.);
-        if p.dot(p) < 1. {
-            return p;
+/// Parallel rays leaving a rectangle `height` tall, so objects keep their size at any distance
+pub struct OrthographicCamera {
+    lower_left_corner: Vector3D,
+    horizontal: Vector3D,
+    vertical: Vector3D,
+    direction: Vector3D,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: Vector3D,
+        lookat: Vector3D,
+        vup: Vector3D,
+        height: f32,
+        aspect: f32,
+    ) -> Self {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Self {
+            lower_left_corner: lookfrom - u * (height * aspect / 2.) - v * (height / 2.),
+            horizontal: u * height * aspect,
+            vertical: v * height,
+            direction: -w,
         }
     }
 }
+
+impl CameraModel for OrthographicCamera {
+    fn generate_ray(&self, u: f32, v: f32, _lens: (f32, f32)) -> Option<Ray> {
+        let origin = self.lower_left_corner + self.horizontal * u + self.vertical * v;
+        Some(Ray::new(origin, self.direction))
+    }
+}
+
+/// Equidistant fisheye whose image circle spans `fov` degrees and just fits the height of the
+/// image
+pub struct FisheyeCamera {
+    origin: Vector3D,
+    u: Vector3D,
+    v: Vector3D,
+    w: Vector3D,
+    fov: f32,
+    aspect: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(lookfrom: Vector3D, lookat: Vector3D, vup: Vector3D, fov: f32, aspect: f32) -> Self {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            fov: fov * PI / 180.,
+            aspect,
+        }
+    }
+}
+
+impl CameraModel for FisheyeCamera {
+    fn generate_ray(&self, u: f32, v: f32, _lens: (f32, f32)) -> Option<Ray> {
+        let x = (2. * u - 1.) * self.aspect;
+        let y = 2. * v - 1.;
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+        let theta = r * self.fov / 2.;
+        let phi = y.atan2(x);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let direction = self.u * (sin_theta * phi.cos()) + self.v * (sin_theta * phi.sin())
+            - self.w * cos_theta;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Sees in every direction, with longitude across the image and latitude up it. The centre of
+/// the image looks towards `lookat`.
+pub struct EquirectangularCamera {
+    origin: Vector3D,
+    u: Vector3D,
+    v: Vector3D,
+    w: Vector3D,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Vector3D, lookat: Vector3D, vup: Vector3D) -> Self {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn generate_ray(&self, u: f32, v: f32, _lens: (f32, f32)) -> Option<Ray> {
+        let phi = (u - 0.5) * 2. * PI;
+        let theta = (v - 0.5) * PI;
+        let direction = self.u * (theta.cos() * phi.sin()) + self.v * theta.sin()
+            - self.w * (theta.cos() * phi.cos());
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Sees all the way around horizontally, projected onto a cylinder so vertical lines stay
+/// straight. `vfov` is the vertical field of view in degrees at the centre of the image.
+pub struct CylindricalCamera {
+    origin: Vector3D,
+    u: Vector3D,
+    v: Vector3D,
+    w: Vector3D,
+    half_height: f32,
+}
+
+impl CylindricalCamera {
+    pub fn new(lookfrom: Vector3D, lookat: Vector3D, vup: Vector3D, vfov: f32) -> Self {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_height: (vfov * PI / 360.).tan(),
+        }
+    }
+}
+
+impl CameraModel for CylindricalCamera {
+    fn generate_ray(&self, u: f32, v: f32, _lens: (f32, f32)) -> Option<Ray> {
+        let phi = (u - 0.5) * 2. * PI;
+        let height = (2. * v - 1.) * self.half_height;
+        let direction = self.u * phi.sin() + self.v * height - self.w * phi.cos();
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Right, up and backwards unit vectors of a camera at `lookfrom` facing `lookat`
+fn basis(lookfrom: Vector3D, lookat: Vector3D, vup: Vector3D) -> (Vector3D, Vector3D, Vector3D) {
+    let w = unit_vector(lookfrom - lookat);
+    let u = unit_vector(vup.cross(w));
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    const FROM: Vector3D = Vector3D { e: [0., 0., 0.] };
+    const AT: Vector3D = Vector3D { e: [0., 0., -1.] };
+    const UP: Vector3D = Vector3D { e: [0., 1., 0.] };
+
+    fn direction(cam: &dyn CameraModel, u: f32, v: f32) -> Vector3D {
+        let r = cam.get_ray(u, v, &mut IndependentSampler).unwrap();
+        unit_vector(r.direction())
+    }
+
+    fn assert_near(a: Vector3D, b: Vector3D) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_perspective() {
+        let cam = Camera::new(FROM, AT, UP, 90., 2., 0., 1.);
+        assert_near(direction(&cam, 0.5, 0.5), AT);
+        assert_near(
+            direction(&cam, 0.5, 1.),
+            unit_vector(Vector3D::new(0., 1., -1.)),
+        );
+    }
+
+    #[test]
+    fn test_physical() {
+        // A 50mm lens on a full frame sensor sees about 27 degrees vertically
+        let lens = PhysicalLens {
+            focal_length: 50.,
+            sensor_width: 36.,
+            sensor_height: 24.,
+            f_number: 2.,
+            focus_distance: 3.,
+        };
+        assert!((lens.vfov(1.5) - 26.99).abs() < 0.01);
+        // A wider image uses less of the sensor's height
+        assert!((lens.vfov(3.) - 2. * (6f32 / 50.).atan() * 180. / PI).abs() < 1e-4);
+        assert!((lens.aperture() - 0.025).abs() < 1e-6);
+        // Rays through the middle of the image leave from all over the lens but meet in focus
+        let cam = Camera::physical(FROM, AT, UP, &lens, 1.5);
+        for _ in 0..10 {
+            let r = cam.get_ray(0.5, 0.5, &mut IndependentSampler).unwrap();
+            let t = (-3. - r.origin().z()) / r.direction().z();
+            assert_near(r.point_at_parameter(t), Vector3D::new(0., 0., -3.));
+        }
+    }
+
+    #[test]
+    fn test_cat_eye() {
+        let cam = Camera::new(FROM, AT, UP, 90., 1., 1., 1.).with_cat_eye(1.);
+        let mut sampler = IndependentSampler;
+        let blocked = |u: f32, v: f32, sampler: &mut IndependentSampler| {
+            (0..1000)
+                .filter(|_| cam.get_ray(u, v, sampler).is_none())
+                .count()
+        };
+        assert_eq!(blocked(0.5, 0.5, &mut sampler), 0);
+        // In the corners the barrel's circle is a lens radius off centre, leaving 39% of the
+        // lens open
+        let corner = blocked(1., 1., &mut sampler);
+        assert!(corner > 550 && corner < 670, "{}", corner);
+    }
+
+    #[test]
+    fn test_differentials() {
+        // The neighbouring rays leave from the same point on the lens
+        let cam = Camera::new(FROM, AT, UP, 90., 2., 0.5, 2.);
+        let r = cam
+            .get_ray_differential(0.5, 0.5, 0.01, 0.02, &mut IndependentSampler)
+            .unwrap();
+        let d = r.differential().unwrap();
+        assert_eq!(d.rx_origin, r.origin());
+        assert_eq!(d.ry_origin, r.origin());
+        // and meet the main ray's neighbours on the plane in focus
+        let focus = |o: Vector3D, dir: Vector3D| o + dir * ((-2. - o.z()) / dir.z());
+        let pinhole = Camera::new(FROM, AT, UP, 90., 2., 0., 2.);
+        let rx = pinhole.get_ray(0.51, 0.5, &mut IndependentSampler).unwrap();
+        assert_near(
+            focus(d.rx_origin, d.rx_direction),
+            focus(rx.origin(), rx.direction()),
+        );
+        // Next to the edge of a fisheye's image circle the neighbour sees nothing
+        let fisheye = FisheyeCamera::new(FROM, AT, UP, 180., 1.);
+        let r = fisheye
+            .get_ray_differential(0.995, 0.5, 0.01, 0.01, &mut IndependentSampler)
+            .unwrap();
+        assert!(r.differential().is_none());
+    }
+
+    #[test]
+    fn test_tilt() {
+        // Distance along the view direction at which a pixel's rays meet
+        let focus_depth = |cam: &Camera, v: f32| {
+            let a = cam.get_ray(0.5, v, &mut IndependentSampler).unwrap();
+            let b = cam.get_ray(0.5, v, &mut IndependentSampler).unwrap();
+            // The rays are coplanar, so meet where their heights and depths line up
+            let (da, db) = (a.direction(), b.direction());
+            let t = ((b.origin() - a.origin()).cross(db)).length() / da.cross(db).length();
+            -a.point_at_parameter(t).z()
+        };
+        let flat = Camera::new(FROM, AT, UP, 90., 1., 0.5, 2.);
+        assert!((focus_depth(&flat, 0.1) - 2.).abs() < 1e-3);
+        let tilted = Camera::new(FROM, AT, UP, 90., 1., 0.5, 2.).with_tilt(20.);
+        assert!((focus_depth(&tilted, 0.5) - 2.).abs() < 1e-3);
+        assert!(focus_depth(&tilted, 0.1) < 1.9);
+        assert!(focus_depth(&tilted, 0.9) > 2.1);
+    }
+
+    #[test]
+    fn test_exposure() {
+        // The sunny 16 rule: f/16 at 1/100s and ISO 100, close to EV 15
+        let sunny = Exposure {
+            shutter: 0.01,
+            iso: 100.,
+            f_number: 16.,
+        };
+        assert!((sunny.ev100() - 14.64).abs() < 0.01);
+        // Each stop of shutter, ISO or aperture doubles the exposure
+        let brighter = Exposure {
+            shutter: 0.02,
+            ..sunny
+        };
+        assert!((brighter.scale() / sunny.scale() - 2.).abs() < 1e-4);
+        let brighter = Exposure {
+            f_number: 16. / 2f32.sqrt(),
+            iso: 200.,
+            ..sunny
+        };
+        assert!((brighter.scale() / sunny.scale() - 4.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_orthographic() {
+        let cam = OrthographicCamera::new(FROM, AT, UP, 2., 2.);
+        let r = cam.get_ray(0., 1., &mut IndependentSampler).unwrap();
+        assert_near(r.origin(), Vector3D::new(-2., 1., 0.));
+        assert_near(r.direction(), AT);
+        assert_near(direction(&cam, 0.3, 0.8), AT);
+    }
+
+    #[test]
+    fn test_fisheye() {
+        let cam = FisheyeCamera::new(FROM, AT, UP, 180., 1.5);
+        assert_near(direction(&cam, 0.5, 0.5), AT);
+        // The top of the image circle is 90 degrees away from the centre
+        assert_near(direction(&cam, 0.5, 1.), UP);
+        assert_near(
+            direction(&cam, 0.5, 0.75),
+            unit_vector(Vector3D::new(0., 1., -1.)),
+        );
+        assert!(cam.get_ray(0., 0.5, &mut IndependentSampler).is_none());
+    }
+
+    #[test]
+    fn test_equirectangular() {
+        let cam = EquirectangularCamera::new(FROM, AT, UP);
+        assert_near(direction(&cam, 0.5, 0.5), AT);
+        assert_near(direction(&cam, 0.75, 0.5), Vector3D::new(1., 0., 0.));
+        assert_near(direction(&cam, 0., 0.5), Vector3D::new(0., 0., 1.));
+        assert_near(direction(&cam, 0.3, 1.), UP);
+    }
+
+    #[test]
+    fn test_cylindrical() {
+        let cam = CylindricalCamera::new(FROM, AT, UP, 90.);
+        assert_near(direction(&cam, 0.5, 0.5), AT);
+        assert_near(direction(&cam, 0.25, 0.5), Vector3D::new(-1., 0., 0.));
+        assert_near(
+            direction(&cam, 0.5, 1.),
+            unit_vector(Vector3D::new(0., 1., -1.)),
+        );
+        // Vertical lines stay vertical: the same column always has the same heading
+        let d = direction(&cam, 0.6, 0.9);
+        let e = direction(&cam, 0.6, 0.2);
+        assert!((d.x() / d.z() - e.x() / e.z()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_projection_names() {
+        assert_eq!(Projection::from_name("fisheye"), Ok(Projection::Fisheye));
+        assert!(Projection::from_name("pinhole").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Ray differentials: the rays through the neighbouring pixels, carried along with a ray so we
+//! know how large an area of a surface it stands for. Texture lookups use that footprint to
+//! filter away detail smaller than a pixel. Reflection and refraction follow Igehy, "Tracing Ray
+//! Differentials" (1999), as formulated in pbrt.
+
+use crate::shapes::hitable::HitRecord;
+use crate::util::ray::Ray;
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Origins and directions of the rays one pixel step away in x and in y
+#[derive(Clone, Copy, Debug)]
+pub struct RayDifferential {
+    pub rx_origin: Vector3D,
+    pub rx_direction: Vector3D,
+    pub ry_origin: Vector3D,
+    pub ry_direction: Vector3D,
+}
+
+/// How position and surface coordinates change from one pixel to the next where a ray hits
+#[derive(Clone, Copy, Debug)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vector3D,
+    pub dpdy: Vector3D,
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
+impl RayDifferential {
+    pub fn new(rx: &Ray, ry: &Ray) -> Self {
+        Self {
+            rx_origin: rx.origin(),
+            rx_direction: rx.direction(),
+            ry_origin: ry.origin(),
+            ry_direction: ry.direction(),
+        }
+    }
+
+    /// Footprint on the surface at `rec`, found by meeting the offset rays with the tangent plane
+    pub fn at_surface(&self, rec: &HitRecord) -> Option<SurfaceDifferentials> {
+        let n = rec.normal;
+        let plane = |origin: Vector3D, direction: Vector3D| {
+            let denom = n.dot(direction);
+            if denom.abs() < 1e-12 {
+                return None;
+            }
+            Some(origin + direction * (n.dot(rec.p - origin) / denom))
+        };
+        let dpdx = plane(self.rx_origin, self.rx_direction)? - rec.p;
+        let dpdy = plane(self.ry_origin, self.ry_direction)? - rec.p;
+
+        // Least squares fit of dp = dpdu * du + dpdv * dv
+        let (a00, a01, a11) = (
+            rec.dpdu.dot(rec.dpdu),
+            rec.dpdu.dot(rec.dpdv),
+            rec.dpdv.dot(rec.dpdv),
+        );
+        let det = a00 * a11 - a01 * a01;
+        let solve = |dp: Vector3D| {
+            if det.abs() < 1e-12 {
+                return (0., 0.);
+            }
+            let (b0, b1) = (rec.dpdu.dot(dp), rec.dpdv.dot(dp));
+            ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        Some(SurfaceDifferentials {
+            dpdx,
+            dpdy,
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        })
+    }
+
+    /// Differentials of the ray mirrored into `wi` at `rec` when arriving along `d`
+    pub fn reflect(&self, d: Vector3D, rec: &HitRecord, wi: Vector3D) -> Option<Self> {
+        let footprint = rec.differentials?;
+        let (n, wi, wo) = (rec.normal, unit_vector(wi), -unit_vector(d));
+        let offset = |dpdx: Vector3D, direction: Vector3D| {
+            let dndx = dpdx * rec.curvature;
+            let dwodx = -unit_vector(direction) - wo;
+            let ddndx = dwodx.dot(n) + wo.dot(dndx);
+            wi - dwodx + (dndx * wo.dot(n) + n * ddndx) * 2.
+        };
+        Some(Self {
+            rx_origin: rec.p + footprint.dpdx,
+            rx_direction: offset(footprint.dpdx, self.rx_direction),
+            ry_origin: rec.p + footprint.dpdy,
+            ry_direction: offset(footprint.dpdy, self.ry_direction),
+        })
+    }
+
+    /// Differentials of the ray refracted into `wi` at `rec` when arriving along `d`, where
+    /// `normal` faces the side the ray arrives from and `ni_over_nt` is the ratio of the
+    /// refractive indices
+    pub fn refract(
+        &self,
+        d: Vector3D,
+        rec: &HitRecord,
+        normal: Vector3D,
+        ni_over_nt: f32,
+        wi: Vector3D,
+    ) -> Option<Self> {
+        let footprint = rec.differentials?;
+        let (n, wi, wo, eta) = (normal, unit_vector(wi), -unit_vector(d), ni_over_nt);
+        // The normal turns the same way whichever side it faces, so flip its derivative with it
+        let curvature = rec.curvature * normal.dot(rec.normal).signum();
+        let cos_o = wo.dot(n);
+        let cos_i = wi.dot(n).abs();
+        let mu = eta * cos_o - cos_i;
+        let offset = |dpdx: Vector3D, direction: Vector3D| {
+            let dndx = dpdx * curvature;
+            let dwodx = -unit_vector(direction) - wo;
+            let ddndx = dwodx.dot(n) + wo.dot(dndx);
+            let dmudx = (eta - eta * eta * cos_o / cos_i) * ddndx;
+            wi - dwodx * eta + dndx * mu + n * dmudx
+        };
+        Some(Self {
+            rx_origin: rec.p + footprint.dpdx,
+            rx_direction: offset(footprint.dpdx, self.rx_direction),
+            ry_origin: rec.p + footprint.dpdy,
+            ry_direction: offset(footprint.dpdy, self.ry_direction),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::material::{reflect, refract, Material};
+
+    fn assert_near(a: Vector3D, b: Vector3D, tolerance: f32) {
+        assert!((a - b).length() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    /// Hit on the floor `y = 0` of a ray from `origin` along `direction`, with `u` along x and
+    /// `v` along z
+    fn floor_hit(origin: Vector3D, direction: Vector3D) -> HitRecord {
+        let mut rec = HitRecord::new(Material::Lambertian {
+            albedo: Vector3D::new(0.5, 0.5, 0.5),
+        });
+        rec.t = -origin.y() / direction.y();
+        rec.p = origin + direction * rec.t;
+        rec.normal = Vector3D::new(0., 1., 0.);
+        rec.uv = (rec.p.x(), rec.p.z());
+        rec.dpdu = Vector3D::new(1., 0., 0.);
+        rec.dpdv = Vector3D::new(0., 0., 1.);
+        rec
+    }
+
+    /// Rays turned by about `step` radians from `direction`, sideways and upwards
+    fn fan(origin: Vector3D, direction: Vector3D, step: f32) -> RayDifferential {
+        let side = Vector3D::new(1., 0., 0.);
+        let up = unit_vector(direction.cross(side));
+        let scale = step * direction.length();
+        RayDifferential::new(
+            &Ray::new(origin, direction + side * scale),
+            &Ray::new(origin, direction + up * scale),
+        )
+    }
+
+    #[test]
+    fn test_footprint() {
+        // Looking straight down from a height of 2, a step of 0.01 covers 0.02 of the floor
+        let origin = Vector3D::new(0., 2., 0.);
+        let direction = Vector3D::new(0., -1., 0.);
+        let rec = floor_hit(origin, direction);
+        let footprint = fan(origin, direction, 0.01).at_surface(&rec).unwrap();
+        assert_near(footprint.dpdx, Vector3D::new(0.02, 0., 0.), 1e-6);
+        assert!((footprint.dudx - 0.02).abs() < 1e-6);
+        assert!(footprint.dvdx.abs() < 1e-6);
+        assert!((footprint.dvdy - 0.02).abs() < 1e-6);
+        // Seen at a grazing angle the footprint stretches away from the camera
+        let direction = Vector3D::new(0., -1., 4.);
+        let rec = floor_hit(origin, direction);
+        let footprint = fan(origin, direction, 0.01).at_surface(&rec).unwrap();
+        assert!(footprint.dvdy.abs() > 3. * footprint.dudx);
+    }
+
+    #[test]
+    fn test_flat_mirror_is_exact() {
+        let origin = Vector3D::new(0., 2., 0.);
+        let direction = Vector3D::new(0.3, -1., 0.2);
+        let mut rec = floor_hit(origin, direction);
+        let differential = fan(origin, direction, 0.01);
+        rec.differentials = differential.at_surface(&rec);
+        let wi = reflect(&unit_vector(direction), rec.normal);
+        let reflected = differential.reflect(direction, &rec, wi).unwrap();
+        let expected = reflect(&unit_vector(differential.rx_direction), rec.normal);
+        assert_near(reflected.rx_direction, expected, 1e-5);
+        let hit_x = floor_hit(origin, differential.rx_direction);
+        assert_near(reflected.rx_origin, hit_x.p, 1e-5);
+    }
+
+    #[test]
+    fn test_curved_mirror_spreads() {
+        // A ray hitting the top of a unit sphere straight on, with neighbours 0.001 apart
+        let origin = Vector3D::new(0., 3., 0.);
+        let direction = Vector3D::new(0., -1., 0.);
+        let mut rec = floor_hit(Vector3D::new(0., 2., 0.), direction);
+        rec.p = Vector3D::new(0., 1., 0.);
+        rec.t = 2.;
+        rec.curvature = 1.;
+        let offset = Vector3D::new(0.001, 0., 0.);
+        let differential = RayDifferential::new(
+            &Ray::new(origin + offset, direction),
+            &Ray::new(origin, direction),
+        );
+        rec.differentials = differential.at_surface(&rec);
+        let wi = reflect(&direction, rec.normal);
+        let reflected = differential.reflect(direction, &rec, wi).unwrap();
+        // The neighbour really hits where the normal has turned by 0.001 radians, so it leaves
+        // at twice that angle
+        let normal = unit_vector(Vector3D::new(0.001, 1., 0.));
+        let expected = reflect(&direction, normal);
+        assert_near(reflected.rx_direction, expected, 1e-5);
+    }
+
+    #[test]
+    fn test_refraction() {
+        let origin = Vector3D::new(0., 2., 0.);
+        let direction = unit_vector(Vector3D::new(0.5, -1., 0.));
+        let mut rec = floor_hit(origin, direction);
+        let differential = fan(origin, direction, 0.001);
+        rec.differentials = differential.at_surface(&rec);
+        // Between equal media nothing bends
+        let same = differential
+            .refract(direction, &rec, rec.normal, 1., direction)
+            .unwrap();
+        assert_near(
+            same.rx_direction,
+            unit_vector(differential.rx_direction),
+            1e-5,
+        );
+        // Into glass the neighbours bend the same way a flat surface bends them
+        let mut wi = Vector3D::new(0., 0., 0.);
+        assert!(refract(direction, rec.normal, 1. / 1.5, &mut wi));
+        let into = differential
+            .refract(direction, &rec, rec.normal, 1. / 1.5, wi)
+            .unwrap();
+        let mut expected = Vector3D::new(0., 0., 0.);
+        refract(
+            differential.rx_direction,
+            rec.normal,
+            1. / 1.5,
+            &mut expected,
+        );
+        assert_near(into.rx_direction, expected, 1e-4);
+    }
+}
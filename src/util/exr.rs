@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+/// Writes an uncompressed scanline OpenEXR image with 32 bit float channels. Each channel is a
+/// name, such as `R` or `normal.X`, and `nx * ny` values stored row by row from the top.
+pub fn write_exr<W: Write>(
+    w: &mut W,
+    nx: u32,
+    ny: u32,
+    channels: &[(String, Vec<f32>)],
+) -> io::Result<()> {
+    // Readers expect the channels sorted by name
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut chlist = vec![];
+    for (name, _) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+    let mut window = vec![];
+    for v in [0, 0, nx as i32 - 1, ny as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    let one = 1f32.to_le_bytes();
+    // Magic number then version 2, single part scanline
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &one);
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &one);
+    header.push(0);
+    w.write_all(&header)?;
+
+    // One block per scanline, after the header and the offset table
+    let block_size = 8 + 4 * nx as u64 * channels.len() as u64;
+    let table_start = header.len() as u64 + 8 * ny as u64;
+    for y in 0..ny as u64 {
+        w.write_all(&(table_start + y * block_size).to_le_bytes())?;
+    }
+    for y in 0..ny as usize {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&((block_size - 8) as i32).to_le_bytes())?;
+        for (_, values) in channels.iter() {
+            for v in values[y * nx as usize..(y + 1) * nx as usize].iter() {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_layout() {
+        let channels = vec![
+            ("R".to_string(), vec![1., 2., 3., 4., 5., 6.]),
+            ("G".to_string(), vec![0.; 6]),
+        ];
+        let mut buf = vec![];
+        write_exr(&mut buf, 3, 2, &channels).unwrap();
+        assert_eq!(&buf[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        // G sorts before R in the channel list
+        let g = buf.windows(2).position(|w| w == b"G\0").unwrap();
+        let r = buf.windows(2).position(|w| w == b"R\0").unwrap();
+        assert!(g < r);
+        // The offset table sits between the header terminator and the scanline blocks
+        let read_u64 = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+        let block_size = 8 + 4 * 3 * 2;
+        let first = buf.len() - 2 * block_size;
+        assert_eq!(buf[first - 17], 0);
+        assert_eq!(read_u64(first - 16) as usize, first);
+        assert_eq!(read_u64(first - 8) as usize, first + block_size);
+        // Row 1 starts with its y coordinate and holds 4, 5, 6 in R after G's zeroes
+        let row1 = first + block_size;
+        assert_eq!(
+            i32::from_le_bytes(buf[row1..row1 + 4].try_into().unwrap()),
+            1
+        );
+        let r = row1 + 8 + 12;
+        assert_eq!(f32::from_le_bytes(buf[r..r + 4].try_into().unwrap()), 4.);
+    }
+}
@@ -1,54 +1,428 @@
+use std::collections::hash_map::DefaultHasher;
+use std::f32::consts::{LN_2, PI};
+use std::hash::{Hash, Hasher};
+
+use crate::sampler::Sampler;
 use crate::shapes::hitable::HitRecord;
-use crate::shapes::sphere::random_in_unit_sphere;
-use crate::util::random::rand_num;
 use crate::util::ray::Ray;
+use crate::util::sampling::{cosine_hemisphere, to_world, uniform_ball};
+use crate::util::spectrum::{at_wavelength, Ior, NOMINAL_WAVELENGTH};
+use crate::util::texture::Texture;
 use crate::util::vector3d::{unit_vector, Vector3D};
 
 #[derive(Clone)]
 pub enum Material {
     DummyMat { albedo: Vector3D },
     Lambertian { albedo: Vector3D },
+    /// Diffuse surface whose albedo is read from a texture
+    Textured { texture: Texture },
     Metal { albedo: Vector3D, fuzziness: f32 },
-    Dielectric { ref_ind: f32 },
+    Dielectric {
+        ref_ind: Ior,
+        absorption: Option<Absorption>,
+    },
+    /// Metallic-roughness material as used by glTF: a diffuse base under a clear specular coat,
+    /// turning into metal tinted by the base colour as `metallic` goes to 1
+    Pbr {
+        base_color: Texture,
+        metallic: f32,
+        roughness: f32,
+        /// Scales `metallic` by its blue channel and `roughness` by its green one
+        metallic_roughness: Option<Texture>,
+    },
+    /// Hair or fur fibres, meant for curves
+    Hair(Hair),
+}
+
+/// Beer-Lambert absorption inside a dielectric, describing tinted glass or coloured liquids.
+#[derive(Clone, Copy, Debug)]
+pub struct Absorption {
+    /// Fraction of light of each colour left after travelling `distance` through the medium
+    transmittance: Vector3D,
+    distance: f32,
+}
+
+impl Absorption {
+    /// Absorption leaving `transmittance` of the light after `distance`, which must be positive
+    pub fn new(transmittance: Vector3D, distance: f32) -> Result<Self, String> {
+        if !(distance > 0. && distance.is_finite()) {
+            return Err(format!(
+                "Absorption distance must be positive, got {}",
+                distance
+            ));
+        }
+        Ok(Self {
+            transmittance,
+            distance,
+        })
+    }
+
+    pub fn transmittance(&self) -> Vector3D {
+        self.transmittance
+    }
+
+    /// Fraction of light left after travelling `length` through the medium, at the hero
+    /// wavelengths if `wavelength` is given
+    pub fn transmittance_over(&self, length: f32, wavelength: Option<f32>) -> Vector3D {
+        let t = at_wavelength(self.transmittance, wavelength);
+        let k = length / self.distance;
+        Vector3D::new(t.x().powf(k), t.y().powf(k), t.z().powf(k))
+    }
+}
+
+/// Index of refraction of a hair fibre
+const HAIR_IOR: f32 = 1.55;
+
+/// Lobes of hair scattering followed on their own: reflection off the surface, transmission
+/// straight through, and transmission after one internal reflection. Longer paths are lumped
+/// into one more lobe.
+const HAIR_LOBES: usize = 3;
+
+/// Hair fibre scattering after Chiang et al. 2016, "A Practical and Controllable Hair and Fur
+/// Model for Production Path Tracing". Fibres are rough dielectric cylinders with absorbing
+/// insides and tilted cuticle scales. It needs the fibre's direction in `dpdu` and how far
+/// across it the hit is in `v`, as curves give them.
+#[derive(Clone, Copy, Debug)]
+pub struct Hair {
+    /// Absorption coefficient inside the fibre, per fibre diameter
+    pub sigma_a: Vector3D,
+    /// Longitudinal roughness from 0 to 1, spreading highlights along the fibre
+    pub beta_m: f32,
+    /// Azimuthal roughness from 0 to 1, spreading light around the fibre
+    pub beta_n: f32,
+    /// Tilt of the cuticle scales in degrees
+    pub alpha: f32,
+}
+
+impl Hair {
+    /// Hair coloured by its concentrations of the dark brown eumelanin and the red pheomelanin.
+    /// Eumelanin of about 0.3 is blonde, 1.3 brown and 8 black.
+    pub fn from_melanin(eumelanin: f32, pheomelanin: f32, beta_m: f32, beta_n: f32) -> Self {
+        let sigma_a = Vector3D::new(0.419, 0.697, 1.37) * eumelanin
+            + Vector3D::new(0.187, 0.4, 1.05) * pheomelanin;
+        Self {
+            sigma_a,
+            beta_m,
+            beta_n,
+            alpha: 2.,
+        }
+    }
+
+    /// Hair absorbing so that, after many bounces between fibres, it looks about `color`
+    pub fn from_color(color: Vector3D, beta_m: f32, beta_n: f32) -> Self {
+        let scale = Self::color_scale(beta_n);
+        let sigma_a = Vector3D {
+            e: color.e.map(|c| (c.clamp(1e-4, 1.).ln() / scale).powi(2)),
+        };
+        Self {
+            sigma_a,
+            beta_m,
+            beta_n,
+            alpha: 2.,
+        }
+    }
+
+    /// Colour the hair looks, the inverse of `from_color`
+    pub fn color(&self) -> Vector3D {
+        let scale = Self::color_scale(self.beta_n);
+        Vector3D {
+            e: self.sigma_a.e.map(|s| (-s.max(0.).sqrt() * scale).exp()),
+        }
+    }
+
+    /// Fit relating the colour of a mass of hair to its absorption, by its azimuthal roughness
+    fn color_scale(beta_n: f32) -> f32 {
+        5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5)
+    }
+
+    /// Variance of the longitudinal spread of each lobe
+    fn variances(&self) -> [f32; HAIR_LOBES + 1] {
+        let b = self.beta_m;
+        let v = (0.726 * b + 0.812 * b * b + 3.7 * b.powi(20)).powi(2);
+        [v, 0.25 * v, 4. * v, 4. * v]
+    }
+
+    /// Scale of the logistic distribution spreading each lobe around the fibre
+    fn azimuthal_scale(&self) -> f32 {
+        let b = self.beta_n;
+        (PI / 8.).sqrt() * (0.265 * b + 1.194 * b * b + 5.372 * b.powi(22))
+    }
+
+    /// Sine and cosine of the outgoing elevation, turned by the scales for `lobe`
+    fn tilt(&self, lobe: usize, sin_o: f32, cos_o: f32) -> (f32, f32) {
+        let alpha = self.alpha.to_radians();
+        let angle = match lobe {
+            0 => -2. * alpha,
+            1 => alpha,
+            2 => 4. * alpha,
+            _ => 0.,
+        };
+        (
+            sin_o * angle.cos() + cos_o * angle.sin(),
+            (cos_o * angle.cos() - sin_o * angle.sin()).abs(),
+        )
+    }
+
+    /// Share of the light each lobe carries leaving at elevation `θ_o` from offset `h` across
+    /// the fibre, and the angle the refracted ray crosses the fibre at
+    fn attenuation(&self, sin_o: f32, cos_o: f32, h: f32) -> ([Vector3D; HAIR_LOBES + 1], f32) {
+        let sin_t = sin_o / HAIR_IOR;
+        let cos_t = safe_sqrt(1. - sin_t * sin_t);
+        // Index of refraction for the projection of the ray onto the fibre's cross-section
+        let eta_p = (HAIR_IOR * HAIR_IOR - sin_o * sin_o).sqrt() / cos_o.max(1e-6);
+        let sin_gamma_t = (h / eta_p).clamp(-1., 1.);
+        let cos_gamma_t = safe_sqrt(1. - sin_gamma_t * sin_gamma_t);
+        let transmittance = Vector3D {
+            e: self
+                .sigma_a
+                .e
+                .map(|s| (-s * 2. * cos_gamma_t / cos_t).exp()),
+        };
+        let f = fresnel_dielectric(cos_o * safe_sqrt(1. - h * h), HAIR_IOR);
+        let mut ap = [Vector3D::new(f, f, f); HAIR_LOBES + 1];
+        ap[1] = transmittance * (1. - f) * (1. - f);
+        for p in 2..HAIR_LOBES {
+            ap[p] = ap[p - 1] * transmittance * f;
+        }
+        // Every longer path, summed as a geometric series
+        let one = Vector3D::new(1., 1., 1.);
+        ap[HAIR_LOBES] = ap[HAIR_LOBES - 1] * transmittance * f / (one - transmittance * f);
+        (ap, sin_gamma_t.asin())
+    }
+
+    /// Scattered light times the cosine to the fibre's normal plane, and the density with which
+    /// `sample` picks `wi`. Directions are in the fibre's frame, with `x` along it and the
+    /// azimuth measured from `y` towards `z`.
+    fn evaluate(&self, wo: Vector3D, wi: Vector3D, h: f32) -> (Vector3D, f32) {
+        let sin_o = wo.x().clamp(-1., 1.);
+        let cos_o = safe_sqrt(1. - sin_o * sin_o);
+        let sin_i = wi.x().clamp(-1., 1.);
+        let cos_i = safe_sqrt(1. - sin_i * sin_i);
+        let phi = wi.z().atan2(wi.y()) - wo.z().atan2(wo.y());
+        let gamma_o = h.clamp(-1., 1.).asin();
+        let (ap, gamma_t) = self.attenuation(sin_o, cos_o, h);
+        let pdfs = lobe_pdfs(&ap);
+        let v = self.variances();
+        let s = self.azimuthal_scale();
+
+        let mut f = Vector3D::new(0., 0., 0.);
+        let mut pdf = 0.;
+        for p in 0..HAIR_LOBES {
+            let (sin_op, cos_op) = self.tilt(p, sin_o, cos_o);
+            let m = longitudinal(cos_i, cos_op, sin_i, sin_op, v[p]);
+            let n = azimuthal(phi, p, s, gamma_o, gamma_t);
+            f += ap[p] * (m * n);
+            pdf += pdfs[p] * m * n;
+        }
+        let m = longitudinal(cos_i, cos_o, sin_i, sin_o, v[HAIR_LOBES]) / (2. * PI);
+        f += ap[HAIR_LOBES] * m;
+        pdf += pdfs[HAIR_LOBES] * m;
+        (f, pdf)
+    }
+
+    /// Picks a direction to scatter light arriving from `wo` into, from four uniform numbers,
+    /// and the weight the light carries: the scattered light times the cosine over the
+    /// density. Directions are in the fibre's frame, as for `evaluate`.
+    fn sample(&self, wo: Vector3D, h: f32, u: [f32; 4]) -> Option<(Vector3D, Vector3D)> {
+        let sin_o = wo.x().clamp(-1., 1.);
+        let cos_o = safe_sqrt(1. - sin_o * sin_o);
+        let gamma_o = h.clamp(-1., 1.).asin();
+        let (ap, gamma_t) = self.attenuation(sin_o, cos_o, h);
+        let pdfs = lobe_pdfs(&ap);
+        let mut p = 0;
+        let mut choice = u[0];
+        while p < HAIR_LOBES && choice >= pdfs[p] {
+            choice -= pdfs[p];
+            p += 1;
+        }
+
+        // Elevation about the tilted outgoing direction
+        let v = self.variances()[p];
+        let (sin_op, cos_op) = self.tilt(p, sin_o, cos_o);
+        let u1 = u[1].max(1e-5);
+        let cos_theta = 1. + v * (u1 + (1. - u1) * (-2. / v).exp()).ln();
+        let sin_theta = safe_sqrt(1. - cos_theta * cos_theta);
+        let cos_phi = (2. * PI * u[2]).cos();
+        let sin_i = (-cos_theta * sin_op + sin_theta * cos_phi * cos_op).clamp(-1., 1.);
+        let cos_i = safe_sqrt(1. - sin_i * sin_i);
+
+        let dphi = match p < HAIR_LOBES {
+            true => {
+                let s = self.azimuthal_scale();
+                lobe_phi(p, gamma_o, gamma_t) + sample_trimmed_logistic(u[3], s, -PI, PI)
+            }
+            false => 2. * PI * u[3],
+        };
+        let phi_i = wo.z().atan2(wo.y()) + dphi;
+        let wi = Vector3D::new(sin_i, cos_i * phi_i.cos(), cos_i * phi_i.sin());
+        let (f, pdf) = self.evaluate(wo, wi, h);
+        match pdf > 0. && pdf.is_finite() {
+            true => Some((wi, f / pdf)),
+            false => None,
+        }
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.).sqrt()
+}
+
+/// Fresnel reflectance of unpolarised light entering a dielectric of index `eta` from air
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin_t = safe_sqrt(1. - cos_i * cos_i) / eta;
+    if sin_t >= 1. {
+        return 1.;
+    }
+    let cos_t = safe_sqrt(1. - sin_t * sin_t);
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.
+}
+
+/// Chance of sampling each hair lobe, in proportion to the light it carries
+fn lobe_pdfs(ap: &[Vector3D; HAIR_LOBES + 1]) -> [f32; HAIR_LOBES + 1] {
+    let weights = ap.map(|a| (a.x() + a.y() + a.z()) / 3.);
+    let total: f32 = weights.iter().sum();
+    match total > 0. {
+        true => weights.map(|w| w / total),
+        false => [1. / (HAIR_LOBES + 1) as f32; HAIR_LOBES + 1],
+    }
+}
+
+/// Modified Bessel function of the first kind, order 0
+fn bessel_i0(x: f32) -> f32 {
+    let mut value = 0.;
+    let mut x2i = 1.;
+    let mut factorial = 1.;
+    let mut four_i = 1.;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f32;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.;
+    }
+    value
+}
+
+fn log_bessel_i0(x: f32) -> f32 {
+    match x > 12. {
+        true => x + 0.5 * (-(2. * PI).ln() + (1. / x).ln() + 1. / (8. * x)),
+        false => bessel_i0(x).ln(),
+    }
+}
+
+/// Longitudinal scattering function of d'Eon et al. 2011, with variance `v`
+fn longitudinal(cos_i: f32, cos_o: f32, sin_i: f32, sin_o: f32, v: f32) -> f32 {
+    let a = cos_i * cos_o / v;
+    let b = sin_i * sin_o / v;
+    match v <= 0.1 {
+        // In logarithms, which stay finite for narrow lobes
+        true => (log_bessel_i0(a) - b - 1. / v + LN_2 + (1. / (2. * v)).ln()).exp(),
+        false => (-b).exp() * bessel_i0(a) / ((1. / v).sinh() * 2. * v),
+    }
+}
+
+/// Azimuth a ray leaves at after `p` internal passes, with no roughness
+fn lobe_phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    let p = p as f32;
+    2. * p * gamma_t - 2. * gamma_o + p * PI
+}
+
+/// Azimuthal scattering of lobe `p` at relative azimuth `phi`
+fn azimuthal(phi: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let dphi = (phi - lobe_phi(p, gamma_o, gamma_t) + PI).rem_euclid(2. * PI) - PI;
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let e = (-x.abs() / s).exp();
+    e / (s * (1. + e) * (1. + e))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1. / (1. + (-x / s).exp())
+}
+
+/// Logistic distribution cut down to `[a, b]`
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1. / (u * k + logistic_cdf(a, s)) - 1.).ln();
+    x.clamp(a, b)
 }
 
 #[allow(unused)]
 impl Material {
+    /// Scatters `r_in` off the surface, drawing random numbers from `sampler`. When the ray
+    /// carries a wavelength the attenuation is given at its hero wavelengths rather than in RGB.
+    /// Ray differentials are carried through mirror reflection and refraction.
     pub fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
+        sampler: &mut dyn Sampler,
         attenuation: &mut Vector3D,
         scattered: &mut Ray,
     ) -> bool {
         match self {
             Material::DummyMat { albedo } => true,
             Material::Lambertian { albedo } => {
-                let dir = rec.normal.clone() + random_in_unit_sphere();
+                let dir = to_world(cosine_hemisphere(sampler.get_2d()), rec.normal);
                 *scattered = Ray::new(rec.p, dir);
-                *attenuation = albedo.clone();
+                *attenuation = at_wavelength(*albedo, r_in.wavelength());
+                true
+            }
+            Material::Textured { texture } => {
+                let dir = to_world(cosine_hemisphere(sampler.get_2d()), rec.normal);
+                *scattered = Ray::new(rec.p, dir);
+                *attenuation = at_wavelength(texture.value(rec), r_in.wavelength());
                 true
             }
             Material::Metal { albedo, fuzziness } => {
-                let u = unit_vector(r_in.direction()).clone();
-                let reflected =
-                    reflect(&u, rec.normal) + random_in_unit_sphere() * fuzziness.clone();
-                *scattered = Ray::new(rec.p, reflected);
-                *attenuation = albedo.clone();
+                let u = unit_vector(r_in.direction());
+                let fuzz = uniform_ball(sampler.get_2d(), sampler.get_1d()) * *fuzziness;
+                let reflected = reflect(&u, rec.normal) + fuzz;
+                let differential = r_in
+                    .differential()
+                    .and_then(|d| d.reflect(r_in.direction(), rec, reflected));
+                *scattered = Ray::new(rec.p, reflected).with_differential(differential);
+                *attenuation = at_wavelength(*albedo, r_in.wavelength());
                 scattered.direction().dot(rec.normal) > 0.
             }
-            Material::Dielectric { ref_ind } => {
+            Material::Dielectric {
+                ref_ind,
+                absorption,
+            } => {
+                *attenuation = match (r_in.wavelength(), ref_ind.is_dispersive()) {
+                    // Only the hero wavelength continues, carrying the others' share of energy
+                    (Some(_), true) => Vector3D::new(3., 0., 0.),
+                    _ => Vector3D::new(1., 1., 1.),
+                };
+                let ref_ind = ref_ind.at(r_in.wavelength().unwrap_or(NOMINAL_WAVELENGTH));
                 let outward_normal: Vector3D;
                 let reflected = reflect(&r_in.direction(), rec.normal);
                 let ni_over_nt: f32;
-                *attenuation = Vector3D::new(1., 1., 1.);
                 let mut refracted = Vector3D::new(0., 0., 0.);
                 let reflect_prob: f32;
                 let cosine: f32;
                 match r_in.direction().dot(rec.normal) > 0. {
                     true => {
+                        // Leaving the medium, so the ray has travelled through it since its origin
+                        if let Some(absorption) = absorption {
+                            let length = rec.t * r_in.direction().length();
+                            *attenuation *=
+                                absorption.transmittance_over(length, r_in.wavelength());
+                        }
                         outward_normal = -rec.normal;
-                        ni_over_nt = ref_ind.clone();
+                        ni_over_nt = ref_ind;
                         cosine =
                             ref_ind * r_in.direction().dot(rec.normal) / r_in.direction().length();
                     }
@@ -67,16 +441,218 @@ impl Material {
                         reflect_prob = 1.;
                     }
                 };
-                match rand_num() < reflect_prob {
-                    true => *scattered = Ray::new(rec.p, reflected),
-                    false => *scattered = Ray::new(rec.p, refracted),
+                let differential = r_in.differential();
+                match sampler.get_1d() < reflect_prob {
+                    true => {
+                        let differential = differential
+                            .and_then(|d| d.reflect(r_in.direction(), rec, reflected));
+                        *scattered = Ray::new(rec.p, reflected).with_differential(differential);
+                    }
+                    false => {
+                        let differential = differential.and_then(|d| {
+                            d.refract(r_in.direction(), rec, outward_normal, ni_over_nt, refracted)
+                        });
+                        *scattered = Ray::new(rec.p, refracted).with_differential(differential);
+                    }
                 };
                 true
             }
+            Material::Hair(hair) => {
+                let wo = -unit_vector(r_in.direction());
+                let [tangent, ts, ns] = fibre_frame(wo, rec);
+                let local = Vector3D::new(wo.dot(tangent), wo.dot(ts), wo.dot(ns));
+                let h = (2. * rec.uv.1 - 1.).clamp(-1., 1.);
+                let (u0, u1) = sampler.get_2d();
+                let (u2, u3) = sampler.get_2d();
+                match hair.sample(local, h, [u0, u1, u2, u3]) {
+                    Some((wi, weight)) => {
+                        let dir = tangent * wi.x() + ts * wi.y() + ns * wi.z();
+                        *scattered = Ray::new(rec.p, dir);
+                        *attenuation = at_wavelength(weight, r_in.wavelength());
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Material::Pbr { base_color, .. } => {
+                let base = base_color.value(rec);
+                let (metallic, roughness) = self.metallic_roughness(rec);
+                let u = unit_vector(r_in.direction());
+                let cosine = -u.dot(rec.normal);
+                // Pick one of the lobes in proportion to how much light it reflects
+                let choice = sampler.get_1d();
+                let coat = (1. - metallic) * schlick(cosine.max(0.), &1.5);
+                let specular = match choice < metallic {
+                    true => Some(base),
+                    false if choice < metallic + coat => Some(Vector3D::new(1., 1., 1.)),
+                    false => None,
+                };
+                match specular {
+                    Some(tint) => {
+                        let fuzz = uniform_ball(sampler.get_2d(), sampler.get_1d())
+                            * (roughness * roughness);
+                        let reflected = reflect(&u, rec.normal) + fuzz;
+                        let differential = r_in
+                            .differential()
+                            .and_then(|d| d.reflect(r_in.direction(), rec, reflected));
+                        *scattered = Ray::new(rec.p, reflected).with_differential(differential);
+                        *attenuation = at_wavelength(tint, r_in.wavelength());
+                        scattered.direction().dot(rec.normal) > 0.
+                    }
+                    None => {
+                        let dir = to_world(cosine_hemisphere(sampler.get_2d()), rec.normal);
+                        *scattered = Ray::new(rec.p, dir);
+                        *attenuation = at_wavelength(base, r_in.wavelength());
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    /// Metalness and roughness of a `Pbr` material at a hit, reading its texture there
+    fn metallic_roughness(&self, rec: &HitRecord) -> (f32, f32) {
+        match self {
+            Material::Pbr {
+                metallic,
+                roughness,
+                metallic_roughness,
+                ..
+            } => match metallic_roughness {
+                Some(texture) => {
+                    let v = texture.value(rec);
+                    (metallic * v.z(), roughness * v.y())
+                }
+                None => (*metallic, *roughness),
+            },
+            _ => (0., 1.),
+        }
+    }
+
+    /// Overall colour of the material, ignoring lighting. Used as a guide for compositing and
+    /// denoising.
+    pub fn albedo(&self) -> Vector3D {
+        match self {
+            Material::DummyMat { albedo }
+            | Material::Lambertian { albedo }
+            | Material::Metal { albedo, .. } => *albedo,
+            Material::Dielectric {
+                absorption: Some(absorption),
+                ..
+            } => absorption.transmittance(),
+            Material::Dielectric { .. } => Vector3D::new(1., 1., 1.),
+            Material::Textured { texture } => texture.average(),
+            Material::Pbr { base_color, .. } => base_color.average(),
+            Material::Hair(hair) => hair.color(),
+        }
+    }
+
+    /// Colour of the material at a hit, reading textures there
+    pub fn albedo_at(&self, rec: &HitRecord) -> Vector3D {
+        match self {
+            Material::Textured { texture } => texture.value(rec),
+            Material::Pbr { base_color, .. } => base_color.value(rec),
+            _ => self.albedo(),
+        }
+    }
+
+    /// Identifier derived from the material's parameters, so every object sharing a material
+    /// gets the same ID. It fits in 24 bits so it survives being stored as a float.
+    pub fn id(&self) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        let albedo = self.albedo();
+        let (kind, param) = match self {
+            Material::DummyMat { .. } => (0, 0.),
+            Material::Lambertian { .. } => (1, 0.),
+            Material::Metal { fuzziness, .. } => (2, *fuzziness),
+            Material::Dielectric { ref_ind, .. } => (3, ref_ind.nominal()),
+            Material::Textured { .. } => (4, 0.),
+            Material::Pbr {
+                metallic,
+                roughness,
+                ..
+            } => (5, metallic + 2. * roughness),
+            Material::Hair(hair) => (6, hair.beta_m + 2. * hair.beta_n + 4. * hair.alpha),
+        };
+        kind.hash(&mut hasher);
+        for v in [albedo.x(), albedo.y(), albedo.z(), param].iter() {
+            v.to_bits().hash(&mut hasher);
+        }
+        (hasher.finish() & 0xff_ffff) as u32
+    }
+
+    /// Albedo of the diffuse part of the material, used to shade explicit light samples.
+    /// Specular materials can't be lit by a punctual light so they return `None`.
+    pub fn diffuse_albedo(&self, rec: &HitRecord) -> Option<Vector3D> {
+        match self {
+            Material::Lambertian { albedo } => Some(*albedo),
+            Material::Textured { texture } => Some(texture.value(rec)),
+            Material::Pbr { base_color, .. } => {
+                let (metallic, _) = self.metallic_roughness(rec);
+                Some(base_color.value(rec) * (1. - metallic))
+            }
+            _ => None,
+        }
+    }
+
+    /// Light scattered back along `r_in` for each unit of light arriving at the hit from the
+    /// unit direction `wi`, times the cosine there, used to shade explicit light samples.
+    /// Mirrors, glass and the specular coat of `Pbr` only scatter into a few directions, so
+    /// can't be lit by a punctual light: the first two return `None` and `Pbr` only its diffuse
+    /// base.
+    pub fn light_response(&self, r_in: &Ray, rec: &HitRecord, wi: Vector3D) -> Option<Vector3D> {
+        match self {
+            Material::Metal { albedo, fuzziness } if *fuzziness > 0. => {
+                if wi.dot(rec.normal) <= 0. {
+                    return Some(Vector3D::new(0., 0., 0.));
+                }
+                let reflected = reflect(&unit_vector(r_in.direction()), rec.normal);
+                Some(*albedo * fuzz_pdf(reflected, *fuzziness, wi))
+            }
+            Material::Hair(hair) => {
+                let wo = -unit_vector(r_in.direction());
+                let [tangent, ts, ns] = fibre_frame(wo, rec);
+                let local = |w: Vector3D| Vector3D::new(w.dot(tangent), w.dot(ts), w.dot(ns));
+                let h = (2. * rec.uv.1 - 1.).clamp(-1., 1.);
+                Some(hair.evaluate(local(wo), local(wi), h).0)
+            }
+            _ => {
+                let albedo = self.diffuse_albedo(rec)?;
+                Some(albedo * wi.dot(rec.normal).max(0.) / PI)
+            }
         }
     }
 }
 
+/// Density over unit directions `w` of the mirror direction `reflected` nudged by a uniform
+/// point in a ball of radius `fuzziness`, which is how `Metal` scatters: the share of the ball
+/// along `w`, weighted by the square of the distance
+fn fuzz_pdf(reflected: Vector3D, fuzziness: f32, w: Vector3D) -> f32 {
+    let c = w.dot(reflected);
+    let discriminant = c * c - (1. - fuzziness * fuzziness);
+    if discriminant < 0. {
+        return 0.;
+    }
+    let root = discriminant.sqrt();
+    let (near, far) = ((c - root).max(0.), c + root);
+    if far <= 0. {
+        return 0.;
+    }
+    (far.powi(3) - near.powi(3)) / (4. * PI * fuzziness.powi(3))
+}
+
+/// The frame of a hair fibre at a hit seen from `wo`: along the fibre, across it, and facing
+/// back towards `wo`
+fn fibre_frame(wo: Vector3D, rec: &HitRecord) -> [Vector3D; 3] {
+    let tangent = unit_vector(rec.dpdu);
+    let facing = wo - tangent * tangent.dot(wo);
+    let ns = match facing.squared_length() > 1e-12 {
+        true => unit_vector(facing),
+        false => to_world(Vector3D::new(1., 0., 0.), tangent),
+    };
+    [tangent, ns.cross(tangent), ns]
+}
+
 pub fn reflect(v: &Vector3D, n: Vector3D) -> Vector3D {
     *v - n * v.dot(n) * 2.
 }
@@ -99,3 +675,172 @@ pub fn schlick(cosine: f32, ref_idx: &f32) -> f32 {
     r0 *= r0;
     r0 + (1. - r0) * (1. - cosine).powf(5.)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id() {
+        let red = Material::Lambertian {
+            albedo: Vector3D::new(1., 0., 0.),
+        };
+        let green = Material::Lambertian {
+            albedo: Vector3D::new(0., 1., 0.),
+        };
+        let red_metal = Material::Metal {
+            albedo: Vector3D::new(1., 0., 0.),
+            fuzziness: 0.,
+        };
+        assert_eq!(red.id(), red.clone().id());
+        assert_ne!(red.id(), green.id());
+        assert_ne!(red.id(), red_metal.id());
+    }
+
+    /// Random directions and offsets for testing hair
+    fn hair_samples(n: usize) -> Vec<(Vector3D, f32, [f32; 4])> {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        (0..n)
+            .map(|_| {
+                let wo = crate::util::sampling::uniform_sphere((rng.gen(), rng.gen()));
+                let h = rng.gen::<f32>() * 2. - 1.;
+                let u = [rng.gen(), rng.gen(), rng.gen(), rng.gen()];
+                (unit_vector(wo), h, u)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hair_white_furnace() {
+        // Without absorption every bit of light is scattered somewhere
+        for beta in [0.2, 0.5, 0.9].iter() {
+            let hair = Hair {
+                sigma_a: Vector3D::new(0., 0., 0.),
+                beta_m: *beta,
+                beta_n: *beta,
+                alpha: 2.,
+            };
+            let samples = hair_samples(20000);
+            let total: Vector3D = samples
+                .iter()
+                .filter_map(|(wo, h, u)| hair.sample(*wo, *h, *u))
+                .map(|(_, weight)| weight)
+                .sum();
+            let mean = total / samples.len() as f32;
+            for c in mean.e.iter() {
+                assert!((c - 1.).abs() < 0.05, "beta {} gives {}", beta, mean);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hair_sampling_matches_density() {
+        // Weights are the scattered light over the density the samples were drawn with, so
+        // they average to the light scattered, as integrated over uniform directions
+        let hair = Hair::from_melanin(1.3, 0., 0.6, 0.6);
+        let (wo, h) = (unit_vector(Vector3D::new(0.3, 0.2, 1.)), 0.4);
+        let samples = hair_samples(40000);
+        let sampled: Vector3D = samples
+            .iter()
+            .filter_map(|(_, _, u)| hair.sample(wo, h, *u))
+            .map(|(_, weight)| weight)
+            .sum::<Vector3D>()
+            / samples.len() as f32;
+        let uniform: Vector3D = samples
+            .iter()
+            .map(|(wi, _, _)| hair.evaluate(wo, *wi, h).0 * (4. * PI))
+            .sum::<Vector3D>()
+            / samples.len() as f32;
+        assert!((sampled - uniform).length() < 0.05 * uniform.length().max(0.1));
+        // Brown hair absorbs more blue than red
+        assert!(sampled.x() > sampled.z());
+    }
+
+    #[test]
+    fn test_fuzzy_metal_lit_by_point_light() {
+        use crate::lights::light::Light;
+        use crate::sampler::IndependentSampler;
+        let metal = Material::Metal {
+            albedo: Vector3D::new(0.8, 0.6, 0.4),
+            fuzziness: 0.3,
+        };
+        let mut rec = HitRecord::new(metal.clone());
+        rec.normal = Vector3D::new(0., 0., 1.);
+        let r_in = Ray::new(Vector3D::new(0., 0., 2.), Vector3D::new(0., 0., -1.));
+        // A light just off the mirror direction is seen, one off to the side isn't
+        let light = |x: f32| Light::Point {
+            position: Vector3D::new(x, 0., 3.),
+            intensity: Vector3D::new(1., 1., 1.),
+        };
+        let mut sampler = IndependentSampler;
+        let near = light(0.5).sample(rec.p, &mut sampler).unwrap();
+        let response = metal.light_response(&r_in, &rec, near.direction).unwrap();
+        assert!(response.max_component() > 0.);
+        let far = light(3.).sample(rec.p, &mut sampler).unwrap();
+        let response = metal.light_response(&r_in, &rec, far.direction).unwrap();
+        assert_eq!(response.max_component(), 0.);
+        // Straight on, every direction it scatters into is above the surface, so the response
+        // integrates to the albedo. It's the same all round, so integrate over rings.
+        let n = 2000;
+        let total = (0..n)
+            .map(|i| {
+                let theta = (i as f32 + 0.5) / n as f32 * PI / 2.;
+                let wi = Vector3D::new(theta.sin(), 0., theta.cos());
+                let ring = 2. * PI * theta.sin() * PI / 2. / n as f32;
+                metal.light_response(&r_in, &rec, wi).unwrap() * ring
+            })
+            .sum::<Vector3D>();
+        assert!((total - Vector3D::new(0.8, 0.6, 0.4)).length() < 0.01, "{}", total);
+        // A perfect mirror can't be lit by a point
+        let mirror = Material::Metal {
+            albedo: Vector3D::new(1., 1., 1.),
+            fuzziness: 0.,
+        };
+        assert!(mirror.light_response(&r_in, &rec, near.direction).is_none());
+    }
+
+    #[test]
+    fn test_hair_color() {
+        let hair = Hair::from_color(Vector3D::new(0.6, 0.4, 0.2), 0.3, 0.3);
+        assert!((hair.color() - Vector3D::new(0.6, 0.4, 0.2)).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_absorption() {
+        let absorption = Absorption::new(Vector3D::new(0.5, 1., 0.), 2.).unwrap();
+        assert_eq!(
+            absorption.transmittance_over(2., None),
+            Vector3D::new(0.5, 1., 0.)
+        );
+        assert_eq!(
+            absorption.transmittance_over(4., None),
+            Vector3D::new(0.25, 1., 0.)
+        );
+        assert_eq!(
+            absorption.transmittance_over(0., None),
+            Vector3D::new(1., 1., 1.)
+        );
+        assert!(Absorption::new(Vector3D::new(0.5, 0.5, 0.5), 0.).is_err());
+        assert!(Absorption::new(Vector3D::new(0.5, 0.5, 0.5), -1.).is_err());
+    }
+
+    #[test]
+    fn test_absorbing_dielectric() {
+        // Leaving glass that halves the light every unit after travelling two units through it
+        let glass = Material::Dielectric {
+            ref_ind: Ior::Constant(1.5),
+            absorption: Some(Absorption::new(Vector3D::new(0.5, 0.5, 1.), 1.).unwrap()),
+        };
+        let r_in = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., 1.));
+        let mut rec = HitRecord::new(glass.clone());
+        rec.t = 2.;
+        rec.p = r_in.point_at_parameter(2.);
+        rec.normal = Vector3D::new(0., 0., 1.);
+        let mut sampler = crate::sampler::IndependentSampler;
+        let mut attenuation = Vector3D::new(0., 0., 0.);
+        let mut scattered = Ray::new(Vector3D::new(0., 0., 0.), Vector3D::new(0., 0., 0.));
+        assert!(glass.scatter(&r_in, &rec, &mut sampler, &mut attenuation, &mut scattered));
+        assert!((attenuation - Vector3D::new(0.25, 0.25, 1.)).length() < 1e-5);
+    }
+}
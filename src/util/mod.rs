@@ -1,5 +1,11 @@
+pub mod aperture;
 pub mod camera;
+pub mod differential;
+pub mod exr;
 pub mod material;
-pub mod random;
 pub mod ray;
+pub mod sampling;
+pub mod spectrum;
+pub mod texture;
+pub mod transform;
 pub mod vector3d;
@@ -1,8 +0,0 @@
-extern crate rand;
-
-use self::rand::{thread_rng, Rng};
-
-pub fn rand_num() -> f32 {
-    let mut rng = thread_rng();
-    rng.gen::<f32>()
-}
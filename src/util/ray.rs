@@ -1,14 +1,34 @@
+use super::differential::RayDifferential;
 use super::vector3d::Vector3D;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
     a: Vector3D,
     b: Vector3D,
+    wavelength: Option<f32>,
+    differential: Option<RayDifferential>,
 }
 
 impl Ray {
     pub fn new(a: Vector3D, b: Vector3D) -> Self {
-        Self { a, b }
+        Self {
+            a,
+            b,
+            wavelength: None,
+            differential: None,
+        }
+    }
+
+    /// Tags the ray with the wavelength in nanometres it carries when rendering spectrally
+    pub fn with_wavelength(mut self, wavelength: Option<f32>) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
+
+    /// Attaches the rays through the neighbouring pixels, which track the footprint of the ray
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
     }
 
     pub fn origin(&self) -> Vector3D {
@@ -19,6 +39,14 @@ impl Ray {
         self.b
     }
 
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
+
+    pub fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
+
     pub fn point_at_parameter(&self, t: f32) -> Vector3D {
         self.a + self.b * t
     }
@@ -35,6 +63,8 @@ mod tests {
         let ray = Ray::new(v1, v2);
         assert_eq!(ray.origin(), v1);
         assert_eq!(ray.direction(), v2);
+        assert_eq!(ray.wavelength(), None);
+        assert_eq!(ray.with_wavelength(Some(550.)).wavelength(), Some(550.));
     }
 
     #[test]
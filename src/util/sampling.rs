@@ -0,0 +1,564 @@
+//! Warps from uniform samples of the unit square to the shapes and distributions the renderer
+//! samples, each with the probability density it produces. None of them use rejection, so every
+//! sample consumes a fixed number of random numbers.
+
+use std::f32::consts::PI;
+
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Maps the square onto the unit disk in the `z = 0` plane, keeping neighbouring points close
+/// together (Shirley and Chiu 1997)
+pub fn concentric_disk((u, v): (f32, f32)) -> Vector3D {
+    let (x, y) = (2. * u - 1., 2. * v - 1.);
+    if x == 0. && y == 0. {
+        return Vector3D::new(0., 0., 0.);
+    }
+    let (r, theta) = match x.abs() > y.abs() {
+        true => (x, PI / 4. * (y / x)),
+        false => (y, PI / 2. - PI / 4. * (x / y)),
+    };
+    Vector3D::new(r * theta.cos(), r * theta.sin(), 0.)
+}
+
+/// Density of `concentric_disk` over the area of the disk
+pub fn concentric_disk_pdf() -> f32 {
+    1. / PI
+}
+
+/// A direction chosen uniformly over the whole sphere
+pub fn uniform_sphere((u, v): (f32, f32)) -> Vector3D {
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vector3D::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Density of `uniform_sphere` per unit solid angle
+pub fn uniform_sphere_pdf() -> f32 {
+    1. / (4. * PI)
+}
+
+/// A point chosen uniformly inside the unit ball, its direction taken from `u` and its distance
+/// from the centre from `w`
+pub fn uniform_ball(u: (f32, f32), w: f32) -> Vector3D {
+    uniform_sphere(u) * w.cbrt()
+}
+
+/// Density of `uniform_ball` over the volume of the ball
+pub fn uniform_ball_pdf() -> f32 {
+    3. / (4. * PI)
+}
+
+/// A direction chosen uniformly over the hemisphere around `+z`
+pub fn uniform_hemisphere((u, v): (f32, f32)) -> Vector3D {
+    let z = u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vector3D::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Density of `uniform_hemisphere` per unit solid angle
+pub fn uniform_hemisphere_pdf() -> f32 {
+    1. / (2. * PI)
+}
+
+/// A direction around `+z` with density proportional to its cosine with `+z`, found by lifting
+/// a point of the disk onto the hemisphere (Malley's method)
+pub fn cosine_hemisphere(u: (f32, f32)) -> Vector3D {
+    let d = concentric_disk(u);
+    let z = (1. - d.x() * d.x() - d.y() * d.y()).max(0.).sqrt();
+    Vector3D::new(d.x(), d.y(), z)
+}
+
+/// Density of `cosine_hemisphere` per unit solid angle for a direction at `cos_theta` to `+z`
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.) / PI
+}
+
+/// A direction chosen uniformly inside the cone around `+z` whose half angle has cosine
+/// `cos_max`
+pub fn uniform_cone((u, v): (f32, f32), cos_max: f32) -> Vector3D {
+    let cos_theta = 1. - u * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Density of `uniform_cone` per unit solid angle
+pub fn uniform_cone_pdf(cos_max: f32) -> f32 {
+    1. / (2. * PI * (1. - cos_max))
+}
+
+/// Barycentric coordinates of a point chosen uniformly over a triangle, with low distortion
+/// (Heitz 2019)
+pub fn uniform_triangle((u, v): (f32, f32)) -> [f32; 3] {
+    let (b0, b1) = match u < v {
+        true => (u / 2., v - u / 2.),
+        false => (u - v / 2., v / 2.),
+    };
+    [b0, b1, 1. - b0 - b1]
+}
+
+/// Density of `uniform_triangle` over the area of a triangle of area `area`
+pub fn uniform_triangle_pdf(area: f32) -> f32 {
+    1. / area
+}
+
+/// Solid angle of the spherical triangle with unit vertices `a`, `b` and `c`, from its
+/// spherical excess
+pub fn spherical_triangle_area(a: Vector3D, b: Vector3D, c: Vector3D) -> f32 {
+    let (alpha, beta, gamma) = spherical_triangle_angles(a, b, c);
+    (alpha + beta + gamma - PI).max(0.)
+}
+
+/// A direction chosen uniformly over the spherical triangle with unit vertices `a`, `b` and `c`
+/// (Arvo 1995). Returns `None` for triangles too small to sample.
+pub fn spherical_triangle(
+    a: Vector3D,
+    b: Vector3D,
+    c: Vector3D,
+    (u, v): (f32, f32),
+) -> Option<Vector3D> {
+    let (alpha, beta, gamma) = spherical_triangle_angles(a, b, c);
+    let area = alpha + beta + gamma - PI;
+    if area.is_nan() || area <= 1e-6 {
+        return None;
+    }
+    // Pick the area of the sub-triangle with vertices a, b and the point c' on the edge from a
+    // to c, then find c' from it
+    let area_sub = u * area;
+    let (s, t) = (area_sub - alpha).sin_cos();
+    let (sin_alpha, cos_alpha) = alpha.sin_cos();
+    let k1 = t - cos_alpha;
+    let k2 = s + sin_alpha * a.dot(b);
+    let cos_b =
+        (((k2 * t - k1 * s) * cos_alpha - k2) / ((k2 * s + k1 * t) * sin_alpha)).clamp(-1., 1.);
+    let sin_b = (1. - cos_b * cos_b).max(0.).sqrt();
+    let c_sub = a * cos_b + unit_vector(orthogonal_part(c, a)) * sin_b;
+    // Then pick a point on the arc from b to c'
+    let cos_theta = 1. - v * (1. - c_sub.dot(b));
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    Some(b * cos_theta + unit_vector(orthogonal_part(c_sub, b)) * sin_theta)
+}
+
+/// Density of `spherical_triangle` per unit solid angle
+pub fn spherical_triangle_pdf(a: Vector3D, b: Vector3D, c: Vector3D) -> f32 {
+    1. / spherical_triangle_area(a, b, c)
+}
+
+/// Piecewise constant distribution over `[0, 1)`, proportional to a list of non negative
+/// values
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    /// Average of the values, which normalises them into a density
+    pub integral: f32,
+}
+
+impl Distribution1D {
+    /// A distribution following `func`. If every value is zero it falls back to uniform.
+    pub fn new(func: &[f32]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.) / n as f32;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = match integral > 0. {
+                true => *c / integral,
+                false => i as f32 / n as f32,
+            };
+        }
+        Self {
+            func: func.iter().map(|f| f.max(0.)).collect(),
+            cdf,
+            integral,
+        }
+    }
+
+    /// Maps `u` to a point of `[0, 1)`, returning it with its density and the bucket it is in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // Last bucket whose cdf starts at or before u
+        let i = self.cdf.partition_point(|c| *c <= u).clamp(1, self.func.len()) - 1;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = match width > 0. {
+            true => (u - self.cdf[i]) / width,
+            false => 0.,
+        };
+        let x = ((i as f32 + offset) / self.func.len() as f32).min(1. - f32::EPSILON / 2.);
+        (x, self.pdf(i), i)
+    }
+
+    /// Density of the bucket `i`
+    pub fn pdf(&self, i: usize) -> f32 {
+        match self.integral > 0. {
+            true => self.func[i] / self.integral,
+            false => 1.,
+        }
+    }
+}
+
+/// Piecewise constant distribution over the unit square, proportional to an `nx` by `ny` grid
+/// of non negative values stored row by row. Picks a row from the marginal distribution, then a
+/// column within it.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(values: &[f32], nx: usize, ny: usize) -> Self {
+        let rows: Vec<Distribution1D> = values
+            .chunks(nx)
+            .take(ny)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal = Distribution1D::new(&rows.iter().map(|r| r.integral).collect::<Vec<f32>>());
+        Self { rows, marginal }
+    }
+
+    /// Maps `(u, v)` to a point of the square, `v` choosing the row, along with its density
+    pub fn sample(&self, (u, v): (f32, f32)) -> ((f32, f32), f32) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    /// Density of the point `(x, y)` of the square
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let columns = self.rows[row].func.len();
+        let column = ((x * columns as f32) as usize).min(columns - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+/// Turns `v`, given in a frame whose `+z` axis is the unit vector `n`, into world space
+/// (Duff et al. 2017)
+pub fn to_world(v: Vector3D, n: Vector3D) -> Vector3D {
+    let sign = 1f32.copysign(n.z());
+    let a = -1. / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    let s = Vector3D::new(1. + sign * n.x() * n.x() * a, sign * b, -sign * n.x());
+    let t = Vector3D::new(b, sign + n.y() * n.y() * a, -n.y());
+    s * v.x() + t * v.y() + n * v.z()
+}
+
+/// Interior angles at `a`, `b` and `c` of a spherical triangle
+fn spherical_triangle_angles(a: Vector3D, b: Vector3D, c: Vector3D) -> (f32, f32, f32) {
+    let n_ab = unit_vector(a.cross(b));
+    let n_bc = unit_vector(b.cross(c));
+    let n_ca = unit_vector(c.cross(a));
+    (
+        angle_between(n_ab, -n_ca),
+        angle_between(n_bc, -n_ab),
+        angle_between(n_ca, -n_bc),
+    )
+}
+
+/// Angle between two unit vectors, accurate even when they are nearly parallel
+fn angle_between(v1: Vector3D, v2: Vector3D) -> f32 {
+    match v1.dot(v2) < 0. {
+        true => PI - 2. * ((v1 + v2).length() / 2.).min(1.).asin(),
+        false => 2. * ((v2 - v1).length() / 2.).min(1.).asin(),
+    }
+}
+
+/// The part of `v` at right angles to the unit vector `w`
+fn orthogonal_part(v: Vector3D, w: Vector3D) -> Vector3D {
+    v - w * v.dot(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const SAMPLES: usize = 200_000;
+
+    /// Pearson's chi-square test of `SAMPLES` warped points falling into a grid of `n` by `n`
+    /// cells over `[x0, x1) x [y0, y1)`. `warp` gives the grid coordinates of a warped sample,
+    /// and `pdf` the density the warp should have over those coordinates. Cells expected to get
+    /// few samples are pooled, as the test is unreliable for them.
+    fn chi_square(
+        n: usize,
+        (x0, x1): (f32, f32),
+        (y0, y1): (f32, f32),
+        warp: impl Fn(&mut StdRng) -> Option<(f32, f32)>,
+        pdf: impl Fn(f32, f32) -> f32,
+    ) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (dx, dy) = ((x1 - x0) / n as f32, (y1 - y0) / n as f32);
+        let mut observed = vec![0.; n * n];
+        for _ in 0..SAMPLES {
+            if let Some((x, y)) = warp(&mut rng) {
+                let i = (((x - x0) / dx) as usize).min(n - 1);
+                let j = (((y - y0) / dy) as usize).min(n - 1);
+                observed[j * n + i] += 1.;
+            }
+        }
+        // Integrate the density over every cell with the midpoint rule
+        let sub = 32;
+        let mut expected = vec![0.; n * n];
+        for j in 0..n {
+            for i in 0..n {
+                let mut sum = 0.;
+                for sj in 0..sub {
+                    for si in 0..sub {
+                        let x = x0 + dx * (i as f32 + (si as f32 + 0.5) / sub as f32);
+                        let y = y0 + dy * (j as f32 + (sj as f32 + 0.5) / sub as f32);
+                        sum += pdf(x, y) as f64;
+                    }
+                }
+                expected[j * n + i] = sum * (dx * dy) as f64 / (sub * sub) as f64 * SAMPLES as f64;
+            }
+        }
+        let total: f64 = expected.iter().sum();
+        assert!(
+            (total / SAMPLES as f64 - 1.).abs() < 1e-2,
+            "density sums to {}",
+            total
+        );
+
+        let mut cells: Vec<(f64, f64)> = expected.into_iter().zip(observed).collect();
+        cells.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let (mut chi2, mut dof) = (0., 0);
+        let (mut pooled_expected, mut pooled_observed) = (0., 0.);
+        let mut stray = 0.;
+        for (e, o) in cells {
+            if e == 0. {
+                // Only cells the midpoint rule misses a sliver of should be here
+                stray += o;
+            }
+            if e < 5. {
+                pooled_expected += e;
+                pooled_observed += o;
+                continue;
+            }
+            chi2 += (o - e) * (o - e) / e;
+            dof += 1;
+        }
+        assert!(
+            stray < 1e-4 * SAMPLES as f64,
+            "{} samples with zero density",
+            stray
+        );
+        if pooled_expected > 5. {
+            chi2 += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            dof += 1;
+        }
+        // Wilson-Hilferty approximation of the 99.9% quantile of the chi-square distribution
+        let k = (dof - 1) as f64;
+        let h = 2. / (9. * k);
+        let critical = k * (1. - h + 3.09 * h.sqrt()).powi(3);
+        assert!(
+            chi2 < critical,
+            "chi2 {} over {} with {} dof",
+            chi2,
+            critical,
+            k
+        );
+    }
+
+    fn square(rng: &mut StdRng) -> (f32, f32) {
+        (rng.gen::<f32>(), rng.gen::<f32>())
+    }
+
+    /// Direction in coordinates where solid angle is area: height and azimuth
+    fn spherical(d: Vector3D) -> (f32, f32) {
+        let phi = d.y().atan2(d.x());
+        (d.z(), if phi < 0. { phi + 2. * PI } else { phi })
+    }
+
+    fn from_spherical(z: f32, phi: f32) -> Vector3D {
+        let r = (1. - z * z).max(0.).sqrt();
+        Vector3D::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    #[test]
+    fn test_concentric_disk() {
+        chi_square(
+            20,
+            (-1., 1.),
+            (-1., 1.),
+            |rng| {
+                let p = concentric_disk(square(rng));
+                Some((p.x(), p.y()))
+            },
+            |x, y| match x * x + y * y < 1. {
+                true => concentric_disk_pdf(),
+                false => 0.,
+            },
+        );
+        assert_eq!(concentric_disk((0.5, 0.5)), Vector3D::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_uniform_sphere() {
+        chi_square(
+            20,
+            (-1., 1.),
+            (0., 2. * PI),
+            |rng| Some(spherical(uniform_sphere(square(rng)))),
+            |_, _| uniform_sphere_pdf(),
+        );
+    }
+
+    #[test]
+    fn test_uniform_ball() {
+        // Inside the ball, the cube of the radius and the height of the direction are uniform
+        chi_square(
+            20,
+            (0., 1.),
+            (-1., 1.),
+            |rng| {
+                let p = uniform_ball(square(rng), rng.gen::<f32>());
+                let r = p.length();
+                assert!(r <= 1.);
+                Some((r * r * r, p.z() / r))
+            },
+            |_, _| 0.5,
+        );
+        assert!((uniform_ball_pdf() * 4. / 3. * PI - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_uniform_hemisphere() {
+        chi_square(
+            20,
+            (-1., 1.),
+            (0., 2. * PI),
+            |rng| Some(spherical(uniform_hemisphere(square(rng)))),
+            |z, _| match z > 0. {
+                true => uniform_hemisphere_pdf(),
+                false => 0.,
+            },
+        );
+    }
+
+    #[test]
+    fn test_cosine_hemisphere() {
+        chi_square(
+            20,
+            (-1., 1.),
+            (0., 2. * PI),
+            |rng| Some(spherical(cosine_hemisphere(square(rng)))),
+            |z, _| cosine_hemisphere_pdf(z),
+        );
+    }
+
+    #[test]
+    fn test_uniform_cone() {
+        let cos_max = 0.8;
+        chi_square(
+            20,
+            (0.7, 1.),
+            (0., 2. * PI),
+            |rng| Some(spherical(uniform_cone(square(rng), cos_max))),
+            |z, _| match z > cos_max {
+                true => uniform_cone_pdf(cos_max),
+                false => 0.,
+            },
+        );
+    }
+
+    #[test]
+    fn test_uniform_triangle() {
+        chi_square(
+            20,
+            (0., 1.),
+            (0., 1.),
+            |rng| {
+                let b = uniform_triangle(square(rng));
+                assert!(b.iter().all(|x| *x >= 0.));
+                Some((b[0], b[1]))
+            },
+            // The barycentric triangle has area 1/2
+            |x, y| match x + y < 1. {
+                true => uniform_triangle_pdf(0.5),
+                false => 0.,
+            },
+        );
+    }
+
+    #[test]
+    fn test_spherical_triangle() {
+        let a = unit_vector(Vector3D::new(0.2, 0.1, 1.));
+        let b = unit_vector(Vector3D::new(1., -0.3, 0.4));
+        let c = unit_vector(Vector3D::new(-0.4, 0.9, 0.5));
+        let inside = |d: Vector3D| {
+            let side = a.cross(b).dot(c).signum();
+            a.cross(b).dot(d) * side >= 0.
+                && b.cross(c).dot(d) * side >= 0.
+                && c.cross(a).dot(d) * side >= 0.
+        };
+        chi_square(
+            24,
+            (-0.2, 1.),
+            (0., 2. * PI),
+            |rng| {
+                let d = spherical_triangle(a, b, c, square(rng))?;
+                assert!((d.length() - 1.).abs() < 1e-4);
+                Some(spherical(d))
+            },
+            |z, phi| match inside(from_spherical(z, phi)) {
+                true => spherical_triangle_pdf(a, b, c),
+                false => 0.,
+            },
+        );
+        // An octant of the sphere covers an eighth of its solid angle
+        let x = Vector3D::new(1., 0., 0.);
+        let y = Vector3D::new(0., 1., 0.);
+        let z = Vector3D::new(0., 0., 1.);
+        assert!((spherical_triangle_area(x, y, z) - PI / 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_distribution_2d() {
+        #[rustfmt::skip]
+        let image = [
+            0., 1., 2., 3.,
+            1., 0., 0., 1.,
+            5., 5., 0., 0.,
+            0., 0., 0., 8.,
+        ];
+        let distribution = Distribution2D::new(&image, 4, 4);
+        chi_square(
+            8,
+            (0., 1.),
+            (0., 1.),
+            |rng| {
+                let (p, pdf) = distribution.sample(square(rng));
+                assert!((pdf - distribution.pdf(p.0, p.1)).abs() < 1e-4);
+                Some(p)
+            },
+            |x, y| distribution.pdf(x, y),
+        );
+        assert_eq!(distribution.pdf(0.1, 0.9), 0.);
+        assert!((distribution.pdf(0.9, 0.9) - 8. / (26. / 16.)).abs() < 1e-4);
+        // All zero falls back to uniform
+        let flat = Distribution2D::new(&[0.; 6], 3, 2);
+        assert_eq!(flat.sample((0.5, 0.25)), ((0.5, 0.25), 1.));
+    }
+
+    #[test]
+    fn test_to_world() {
+        for n in [
+            Vector3D::new(0., 0., 1.),
+            Vector3D::new(0., 0., -1.),
+            unit_vector(Vector3D::new(1., 2., -3.)),
+        ]
+        .iter()
+        {
+            assert!((to_world(Vector3D::new(0., 0., 1.), *n) - *n).length() < 1e-6);
+            let s = to_world(Vector3D::new(1., 0., 0.), *n);
+            let t = to_world(Vector3D::new(0., 1., 0.), *n);
+            assert!(s.dot(*n).abs() < 1e-6 && t.dot(*n).abs() < 1e-6 && s.dot(t).abs() < 1e-6);
+            assert!((s.length() - 1.).abs() < 1e-6 && (t.length() - 1.).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::OnceLock;
+
+use super::vector3d::Vector3D;
+
+/// Shortest wavelength sampled in spectral mode, in nanometres
+pub const LAMBDA_MIN: f32 = 380.;
+/// Longest wavelength sampled in spectral mode, in nanometres
+pub const LAMBDA_MAX: f32 = 720.;
+/// Wavelength of the helium d-line, where glass IORs are usually quoted
+pub const NOMINAL_WAVELENGTH: f32 = 587.6;
+
+/// Index of refraction of a dielectric, optionally varying with wavelength.
+#[derive(Clone, Copy, Debug)]
+pub enum Ior {
+    Constant(f32),
+    /// `n = a + b / λ²` with λ in micrometres
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// `n² = 1 + Σ b λ² / (λ² - c)` with λ in micrometres
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Index of refraction at `wavelength` nanometres
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let l2 = (wavelength / 1000.) * (wavelength / 1000.);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+
+    /// Index of refraction used when rendering in RGB
+    pub fn nominal(&self) -> f32 {
+        self.at(NOMINAL_WAVELENGTH)
+    }
+
+    /// Whether light of different wavelengths bends differently, in which case only the hero
+    /// wavelength of a path can follow the refracted direction.
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// Piecewise gaussian used by the analytic colour matching function fit
+fn lobe(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let t = (x - mu)
+        / match x < mu {
+            true => sigma_low,
+            false => sigma_high,
+        };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions at `wavelength` nanometres, using the multi-lobe fit from
+/// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions" (2013).
+pub fn cie_xyz(wavelength: f32) -> Vector3D {
+    let l = wavelength;
+    Vector3D::new(
+        1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(l, 501.1, 20.4, 26.2),
+        0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+        1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Converts CIE XYZ to linear sRGB
+pub fn xyz_to_rgb(c: Vector3D) -> Vector3D {
+    Vector3D::new(
+        3.2406 * c.x() - 1.5372 * c.y() - 0.4986 * c.z(),
+        -0.9689 * c.x() + 1.8758 * c.y() + 0.0415 * c.z(),
+        0.0557 * c.x() - 0.2040 * c.y() + 1.0570 * c.z(),
+    )
+}
+
+/// Linear sRGB of a constant spectrum with value 1 over the sampled range. Dividing by this
+/// white balances the film so a flat spectrum comes out as (1, 1, 1).
+fn film_white() -> Vector3D {
+    static WHITE: OnceLock<Vector3D> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let xyz: Vector3D = (0..steps)
+            .map(|i| cie_xyz(LAMBDA_MIN + (i as f32 + 0.5) * dl) * dl)
+            .sum();
+        xyz_to_rgb(xyz)
+    })
+}
+
+/// Maps a uniform random number in `[0, 1)` to a hero wavelength uniformly distributed over the
+/// sampled range.
+pub fn sample_wavelength(u: f32) -> f32 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+/// The hero wavelength followed by two more spaced evenly across the sampled range, one for each
+/// channel of a `Vector3D`. Tracing them together along one path cuts down colour noise.
+pub fn hero_wavelengths(hero: f32) -> [f32; 3] {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let rotate = |i: f32| LAMBDA_MIN + (hero - LAMBDA_MIN + i * range / 3.) % range;
+    [hero, rotate(1.), rotate(2.)]
+}
+
+/// Turns the radiance carried by a path at the hero wavelengths of `hero` into an estimate of
+/// the pixel's linear sRGB colour. The hero must have been picked with `sample_wavelength`.
+pub fn spectral_to_rgb(hero: f32, radiance: Vector3D) -> Vector3D {
+    let xyz: Vector3D = hero_wavelengths(hero)
+        .iter()
+        .zip(radiance.e.iter())
+        .map(|(l, r)| cie_xyz(*l) * *r)
+        .sum();
+    xyz_to_rgb(xyz) * ((LAMBDA_MAX - LAMBDA_MIN) / 3.) / film_white()
+}
+
+/// Value at `wavelength` of a smooth spectrum with linear sRGB colour `c`. Each wavelength is
+/// split between the channels in proportion to how strongly it excites them, so white
+/// upsamples to a constant spectrum and reflectances in `[0, 1]` stay in `[0, 1]`.
+pub fn rgb_to_spectrum(c: Vector3D, wavelength: f32) -> f32 {
+    let rgb = xyz_to_rgb(cie_xyz(wavelength));
+    let basis = Vector3D::new(rgb.r().max(0.), rgb.g().max(0.), rgb.b().max(0.));
+    let total = basis.r() + basis.g() + basis.b();
+    match total > 1e-6 {
+        true => c.dot(basis) / total,
+        false => (c.r() + c.g() + c.b()) / 3.,
+    }
+}
+
+/// Colour `c` as seen by a ray carrying hero wavelength `wavelength`. Unchanged when rendering
+/// in RGB, otherwise each channel holds the upsampled spectrum at one of the hero wavelengths.
+pub fn at_wavelength(c: Vector3D, wavelength: Option<f32>) -> Vector3D {
+    match wavelength {
+        Some(hero) => {
+            let l = hero_wavelengths(hero);
+            Vector3D::new(
+                rgb_to_spectrum(c, l[0]),
+                rgb_to_spectrum(c, l[1]),
+                rgb_to_spectrum(c, l[2]),
+            )
+        }
+        None => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cie_y_peak() {
+        let y = cie_xyz(555.).y();
+        assert!(y > 0.95 && y < 1.05);
+        assert!(cie_xyz(400.).y() < 0.01);
+        assert!(cie_xyz(700.).y() < 0.01);
+    }
+
+    #[test]
+    fn test_white_round_trip() {
+        let steps = 2000;
+        let rgb: Vector3D = (0..steps)
+            .map(|i| {
+                let l = sample_wavelength((i as f32 + 0.5) / steps as f32);
+                spectral_to_rgb(l, at_wavelength(Vector3D::new(1., 1., 1.), Some(l)))
+            })
+            .sum::<Vector3D>()
+            / steps as f32;
+        for c in rgb.e.iter() {
+            assert!((c - 1.).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_hero_wavelengths() {
+        let l = hero_wavelengths(650.);
+        assert_eq!(l[0], 650.);
+        for w in l.iter() {
+            assert!(*w >= LAMBDA_MIN && *w < LAMBDA_MAX);
+        }
+        assert!((l[1] - (650. + 340. / 3. - 340.)).abs() < 1e-3);
+        assert!((l[2] - (650. + 680. / 3. - 340.)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_upsampled_red_is_reddest() {
+        let red = Vector3D::new(1., 0., 0.);
+        assert!(rgb_to_spectrum(red, 650.) > 0.9);
+        assert!(rgb_to_spectrum(red, 520.) < 0.1);
+        for l in [400., 500., 600., 700.].iter() {
+            let s = rgb_to_spectrum(Vector3D::new(0.3, 0.6, 0.9), *l);
+            assert!((0.3..=0.9).contains(&s));
+        }
+    }
+
+    #[test]
+    fn test_ior() {
+        assert_eq!(Ior::Constant(1.5).at(450.), 1.5);
+        assert!(!Ior::Constant(1.5).is_dispersive());
+        let cauchy = Ior::Cauchy {
+            a: 1.5046,
+            b: 0.0042,
+        };
+        assert!(cauchy.at(450.) > cauchy.at(650.));
+        let bk7 = Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_3, 1.010_469],
+            c: [0.006_000_7, 0.020_017_9, 103.560_6],
+        };
+        assert!((bk7.nominal() - 1.5168).abs() < 1e-3);
+        assert!(bk7.at(450.) > bk7.at(650.));
+    }
+}
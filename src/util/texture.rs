@@ -0,0 +1,427 @@
+use std::io;
+use std::sync::{Arc, OnceLock};
+
+use crate::film::read_png;
+use crate::shapes::hitable::HitRecord;
+use crate::util::vector3d::Vector3D;
+
+/// How an image texture is filtered over the footprint of a ray
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    /// Bilinear lookup in the full resolution image, ignoring the footprint
+    Point,
+    /// Isotropic lookup between the two MIP map levels closest to the footprint's size
+    Trilinear,
+    /// Elliptically weighted average over the footprint, which stays sharp when the surface is
+    /// seen at a grazing angle
+    Ewa,
+}
+
+impl TextureFilter {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "point" => Ok(TextureFilter::Point),
+            "trilinear" => Ok(TextureFilter::Trilinear),
+            "ewa" => Ok(TextureFilter::Ewa),
+            _ => Err(format!("Unknown texture filter {}", name)),
+        }
+    }
+}
+
+/// How a hit is mapped to coordinates `(s, t)` in an image texture, which repeats every unit
+#[derive(Clone, Copy, Debug)]
+pub enum TextureMapping {
+    /// The surface coordinates of the hit, scaled to repeat the image
+    Uv { scale: (f32, f32) },
+    /// Position projected onto `s` and `t`, so the image repeats every `1 / |s|` along `s`
+    Planar { s: Vector3D, t: Vector3D },
+}
+
+/// Source of the colour of a surface at a hit
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Vector3D),
+    Image {
+        mipmap: Arc<MipMap>,
+        mapping: TextureMapping,
+        filter: TextureFilter,
+    },
+}
+
+impl Texture {
+    /// Image texture from a PNG, decoded with the same gamma 2 the output is written with
+    pub fn from_png(
+        path: &str,
+        mapping: TextureMapping,
+        filter: TextureFilter,
+    ) -> io::Result<Self> {
+        let (nx, ny, pixels) = read_png(path)?;
+        let linear = pixels.iter().map(|c| *c * *c).collect();
+        Ok(Self::from_image(nx as usize, ny as usize, linear, mapping, filter))
+    }
+
+    /// Image texture from linear colours stored row by row from the top, as images usually are
+    pub fn from_image(
+        nx: usize,
+        ny: usize,
+        pixels: Vec<Vector3D>,
+        mapping: TextureMapping,
+        filter: TextureFilter,
+    ) -> Self {
+        // t points up
+        let texels: Vec<Vector3D> = pixels.chunks(nx).rev().flatten().copied().collect();
+        Texture::Image {
+            mipmap: Arc::new(MipMap::new(nx, ny, texels)),
+            mapping,
+            filter,
+        }
+    }
+
+    /// Colour at a hit, filtered over the ray's footprint if the hit has one
+    pub fn value(&self, rec: &HitRecord) -> Vector3D {
+        let (mipmap, mapping, filter) = match self {
+            Texture::Constant(c) => return *c,
+            Texture::Image {
+                mipmap,
+                mapping,
+                filter,
+            } => (mipmap, mapping, filter),
+        };
+        // Texture coordinates and how they change from one pixel to the next
+        let (st, dstdx, dstdy) = match *mapping {
+            TextureMapping::Uv { scale: (su, sv) } => {
+                let st = (rec.uv.0 * su, rec.uv.1 * sv);
+                match rec.differentials {
+                    Some(d) => (st, (d.dudx * su, d.dvdx * sv), (d.dudy * su, d.dvdy * sv)),
+                    None => (st, (0., 0.), (0., 0.)),
+                }
+            }
+            TextureMapping::Planar { s, t } => {
+                let st = (rec.p.dot(s), rec.p.dot(t));
+                match rec.differentials {
+                    Some(d) => (
+                        st,
+                        (d.dpdx.dot(s), d.dpdx.dot(t)),
+                        (d.dpdy.dot(s), d.dpdy.dot(t)),
+                    ),
+                    None => (st, (0., 0.), (0., 0.)),
+                }
+            }
+        };
+        match (filter, rec.differentials.is_some()) {
+            (TextureFilter::Point, _) | (_, false) => mipmap.bilerp(0, st),
+            (TextureFilter::Trilinear, true) => {
+                let width = 2.
+                    * [dstdx.0, dstdx.1, dstdy.0, dstdy.1]
+                        .iter()
+                        .fold(0f32, |m, d| m.max(d.abs()));
+                mipmap.trilinear(st, width)
+            }
+            (TextureFilter::Ewa, true) => mipmap.ewa(st, dstdx, dstdy),
+        }
+    }
+
+    /// Average colour over the whole texture
+    pub fn average(&self) -> Vector3D {
+        match self {
+            Texture::Constant(c) => *c,
+            Texture::Image { mipmap, .. } => mipmap.texel(mipmap.levels.len() - 1, 0, 0),
+        }
+    }
+}
+
+/// Longest the EWA ellipse may get relative to its width, bounding the cost of a lookup
+const MAX_ANISOTROPY: f32 = 8.;
+const WEIGHT_LUT_SIZE: usize = 128;
+
+/// An image with a pyramid of successively halved copies, so lookups covering many texels can
+/// read a few from a smaller copy instead
+pub struct MipMap {
+    levels: Vec<Level>,
+}
+
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Vector3D>,
+}
+
+impl MipMap {
+    /// Builds the pyramid from texels stored row by row from `t = 0`
+    pub fn new(width: usize, height: usize, texels: Vec<Vector3D>) -> Self {
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let (width, height) = (last.width.div_ceil(2), last.height.div_ceil(2));
+            let mut texels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    // Box filter over the 2x2 texels below, wrapping at odd sizes
+                    let mut sum = Vector3D::new(0., 0., 0.);
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                        sum += last.texel((2 * x + dx) as i64, (2 * y + dy) as i64);
+                    }
+                    texels.push(sum / 4.);
+                }
+            }
+            levels.push(Level {
+                width,
+                height,
+                texels,
+            });
+        }
+        Self { levels }
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vector3D {
+        self.levels[level].texel(x, y)
+    }
+
+    /// Bilinear interpolation between the texels of `level` around `st`
+    pub fn bilerp(&self, level: usize, st: (f32, f32)) -> Vector3D {
+        let l = &self.levels[level];
+        let x = st.0 * l.width as f32 - 0.5;
+        let y = st.1 * l.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        l.texel(x0, y0) * ((1. - fx) * (1. - fy))
+            + l.texel(x0 + 1, y0) * (fx * (1. - fy))
+            + l.texel(x0, y0 + 1) * ((1. - fx) * fy)
+            + l.texel(x0 + 1, y0 + 1) * (fx * fy)
+    }
+
+    /// Lookup over a square footprint `width` across in texture coordinates
+    pub fn trilinear(&self, st: (f32, f32), width: f32) -> Vector3D {
+        let top = self.levels.len() - 1;
+        let level = top as f32 + width.max(1e-8).log2();
+        if level <= 0. {
+            return self.bilerp(0, st);
+        }
+        if level >= top as f32 {
+            return self.texel(top, 0, 0);
+        }
+        let i = level.floor();
+        let f = level - i;
+        self.bilerp(i as usize, st) * (1. - f) + self.bilerp(i as usize + 1, st) * f
+    }
+
+    /// Gaussian weighted average over the ellipse with axes `dst0` and `dst1`, as in Heckbert,
+    /// "Fundamentals of Texture Mapping and Image Warping" (1989)
+    pub fn ewa(&self, st: (f32, f32), dst0: (f32, f32), dst1: (f32, f32)) -> Vector3D {
+        let length = |d: (f32, f32)| (d.0 * d.0 + d.1 * d.1).sqrt();
+        let (major, mut minor) = match length(dst0) < length(dst1) {
+            true => (dst1, dst0),
+            false => (dst0, dst1),
+        };
+        let major_length = length(major);
+        let mut minor_length = length(minor);
+        // Widen overly thin ellipses, trading some blur for a bounded number of texels
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0. {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            minor_length *= scale;
+        }
+        if minor_length == 0. {
+            return self.bilerp(0, st);
+        }
+        let top = self.levels.len() - 1;
+        let lod = (top as f32 + minor_length.log2()).max(0.);
+        let i = lod.floor();
+        let f = lod - i;
+        let i = i as usize;
+        self.ewa_level(i, st, major, minor) * (1. - f) + self.ewa_level(i + 1, st, major, minor) * f
+    }
+
+    fn ewa_level(
+        &self,
+        level: usize,
+        st: (f32, f32),
+        dst0: (f32, f32),
+        dst1: (f32, f32),
+    ) -> Vector3D {
+        if level >= self.levels.len() - 1 {
+            return self.texel(self.levels.len() - 1, 0, 0);
+        }
+        let l = &self.levels[level];
+        let (w, h) = (l.width as f32, l.height as f32);
+        let (s, t) = (st.0 * w - 0.5, st.1 * h - 0.5);
+        let dst0 = (dst0.0 * w, dst0.1 * h);
+        let dst1 = (dst1.0 * w, dst1.1 * h);
+        // Implicit ellipse A s^2 + B s t + C t^2 = 1, grown by a texel so it always covers some
+        let mut a = dst0.1 * dst0.1 + dst1.1 * dst1.1 + 1.;
+        let mut b = -2. * (dst0.0 * dst0.1 + dst1.0 * dst1.1);
+        let mut c = dst0.0 * dst0.0 + dst1.0 * dst1.0 + 1.;
+        let inv_f = 1. / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+        let det = -b * b + 4. * a * c;
+        let s_extent = 2. * (det * c).sqrt() / det;
+        let t_extent = 2. * (a * det).sqrt() / det;
+        let lut = weight_lut();
+        let mut sum = Vector3D::new(0., 0., 0.);
+        let mut weights = 0.;
+        for y in (t - t_extent).ceil() as i64..=(t + t_extent).floor() as i64 {
+            let dt = y as f32 - t;
+            for x in (s - s_extent).ceil() as i64..=(s + s_extent).floor() as i64 {
+                let ds = x as f32 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1. {
+                    let weight =
+                        lut[((r2 * WEIGHT_LUT_SIZE as f32) as usize).min(WEIGHT_LUT_SIZE - 1)];
+                    sum += l.texel(x, y) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        match weights > 0. {
+            true => sum / weights,
+            false => self.bilerp(level, st),
+        }
+    }
+}
+
+impl Level {
+    /// Texel at `(x, y)`, repeating the image in both directions
+    fn texel(&self, x: i64, y: i64) -> Vector3D {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.texels[y * self.width + x]
+    }
+}
+
+/// Gaussian falloff over the squared radius of the EWA ellipse, shifted to reach 0 at its edge
+fn weight_lut() -> &'static [f32] {
+    static LUT: OnceLock<Vec<f32>> = OnceLock::new();
+    LUT.get_or_init(|| {
+        let alpha = 2.;
+        (0..WEIGHT_LUT_SIZE)
+            .map(|i| {
+                let r2 = i as f32 / (WEIGHT_LUT_SIZE - 1) as f32;
+                (-alpha * r2).exp() - (-alpha).exp()
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::differential::SurfaceDifferentials;
+    use crate::util::material::Material;
+
+    /// Black and white checkerboard of `n` by `n` texels
+    fn checkerboard(n: usize) -> MipMap {
+        let texels = (0..n * n)
+            .map(|i| match (i % n + i / n) % 2 {
+                0 => Vector3D::new(0., 0., 0.),
+                _ => Vector3D::new(1., 1., 1.),
+            })
+            .collect();
+        MipMap::new(n, n, texels)
+    }
+
+    fn assert_near(a: Vector3D, b: Vector3D, tolerance: f32) {
+        assert!((a - b).length() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_pyramid() {
+        let mipmap = checkerboard(64);
+        assert_eq!(mipmap.levels(), 7);
+        let gray = Vector3D::new(0.5, 0.5, 0.5);
+        assert_near(mipmap.texel(6, 0, 0), gray, 1e-6);
+        assert_near(mipmap.texel(1, 3, 5), gray, 1e-6);
+        // Odd sizes still end in a single texel
+        let odd = MipMap::new(5, 3, vec![Vector3D::new(1., 1., 1.); 15]);
+        assert_eq!(odd.levels(), 4);
+        assert_near(odd.texel(3, 0, 0), Vector3D::new(1., 1., 1.), 1e-6);
+    }
+
+    #[test]
+    fn test_lookups() {
+        let mipmap = checkerboard(64);
+        let gray = Vector3D::new(0.5, 0.5, 0.5);
+        // The centre of a texel reads it exactly
+        let centre = (2.5 / 64., 1.5 / 64.);
+        assert_near(mipmap.bilerp(0, centre), Vector3D::new(1., 1., 1.), 1e-5);
+        assert_near(
+            mipmap.trilinear(centre, 1e-4),
+            Vector3D::new(1., 1., 1.),
+            1e-5,
+        );
+        // A footprint of many checks averages them out
+        assert_near(mipmap.trilinear(centre, 0.25), gray, 1e-5);
+        assert_near(mipmap.ewa(centre, (0.25, 0.), (0., 0.25)), gray, 0.01);
+        // A thin footprint along stripes stays sharp with EWA but not with trilinear
+        let lines = MipMap::new(
+            64,
+            64,
+            (0..64 * 64)
+                .map(|i| match (i % 64) % 2 {
+                    0 => Vector3D::new(0., 0., 0.),
+                    _ => Vector3D::new(1., 1., 1.),
+                })
+                .collect(),
+        );
+        let along = (0., 0.05);
+        let across = (0.5 / 64., 0.);
+        let sharp = lines.ewa(centre, along, across);
+        let blurred = lines.trilinear(centre, 0.1);
+        assert!(sharp.x() < 0.2, "{:?}", sharp);
+        assert_near(blurred, gray, 1e-5);
+    }
+
+    #[test]
+    fn test_texture_value() {
+        let mipmap = Arc::new(checkerboard(2));
+        let texture = |filter| Texture::Image {
+            mipmap: mipmap.clone(),
+            mapping: TextureMapping::Planar {
+                s: Vector3D::new(0.5, 0., 0.),
+                t: Vector3D::new(0., 0., 0.5),
+            },
+            filter,
+        };
+        let mut rec = HitRecord::new(Material::DummyMat {
+            albedo: Vector3D::new(0., 0., 0.),
+        });
+        // Four units along x is two repeats, landing in the middle of the black corner texel
+        rec.p = Vector3D::new(4.5, 0., 0.5);
+        let black = Vector3D::new(0., 0., 0.);
+        assert_near(texture(TextureFilter::Ewa).value(&rec), black, 1e-5);
+        // A footprint covering the whole texture reads its average
+        rec.differentials = Some(SurfaceDifferentials {
+            dpdx: Vector3D::new(4., 0., 0.),
+            dpdy: Vector3D::new(0., 0., 4.),
+            dudx: 0.,
+            dvdx: 0.,
+            dudy: 0.,
+            dvdy: 0.,
+        });
+        let gray = Vector3D::new(0.5, 0.5, 0.5);
+        assert_near(texture(TextureFilter::Trilinear).value(&rec), gray, 1e-5);
+        assert_near(texture(TextureFilter::Ewa).value(&rec), gray, 1e-5);
+        assert_near(texture(TextureFilter::Point).value(&rec), black, 1e-5);
+        assert_near(texture(TextureFilter::Point).average(), gray, 1e-5);
+    }
+
+    #[test]
+    fn test_filter_names() {
+        assert_eq!(TextureFilter::from_name("ewa"), Ok(TextureFilter::Ewa));
+        assert_eq!(
+            TextureFilter::from_name("trilinear"),
+            Ok(TextureFilter::Trilinear)
+        );
+        assert_eq!(TextureFilter::from_name("point"), Ok(TextureFilter::Point));
+        assert!(TextureFilter::from_name("nearest").is_err());
+    }
+}
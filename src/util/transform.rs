@@ -0,0 +1,194 @@
+use std::ops::Mul;
+
+use crate::util::vector3d::{unit_vector, Vector3D};
+
+/// Rows of an affine matrix, with the translation in the last column
+type Matrix = [[f32; 4]; 3];
+
+const IDENTITY: Matrix = [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.]];
+
+/// Affine transformation of space, kept together with its inverse
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    m: Matrix,
+    inv: Matrix,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: IDENTITY,
+            inv: IDENTITY,
+        }
+    }
+
+    /// Transformation with matrix `m`, or `None` if it can't be undone
+    pub fn from_matrix(m: Matrix) -> Option<Self> {
+        Some(Self {
+            m,
+            inv: invert(&m)?,
+        })
+    }
+
+    pub fn translate(d: Vector3D) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for i in 0..3 {
+            m[i][3] = d.e[i];
+            inv[i][3] = -d.e[i];
+        }
+        Self { m, inv }
+    }
+
+    /// Scaling by `s` along each axis, or `None` if any factor is zero as that can't be undone
+    pub fn scale(s: Vector3D) -> Option<Self> {
+        if s.e.contains(&0.) {
+            return None;
+        }
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for i in 0..3 {
+            m[i][i] = s.e[i];
+            inv[i][i] = 1. / s.e[i];
+        }
+        Some(Self { m, inv })
+    }
+
+    /// Rotation by `degrees` counterclockwise about `axis`, looking down the axis
+    pub fn rotate(axis: Vector3D, degrees: f32) -> Self {
+        let a = unit_vector(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        // Rodrigues' formula
+        let cross = [[0., -a.z(), a.y()], [a.z(), 0., -a.x()], [-a.y(), a.x(), 0.]];
+        let rotation = |i: usize, j: usize| {
+            let diagonal = if i == j { cos } else { 0. };
+            a.e[i] * a.e[j] * (1. - cos) + cross[i][j] * sin + diagonal
+        };
+        // A rotation's inverse is its transpose
+        Self {
+            m: linear(rotation),
+            inv: linear(|i, j| rotation(j, i)),
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn point(&self, p: Vector3D) -> Vector3D {
+        let r = |row: &[f32; 4]| row[0] * p.x() + row[1] * p.y() + row[2] * p.z() + row[3];
+        Vector3D::new(r(&self.m[0]), r(&self.m[1]), r(&self.m[2]))
+    }
+
+    pub fn vector(&self, v: Vector3D) -> Vector3D {
+        let r = |row: &[f32; 4]| row[0] * v.x() + row[1] * v.y() + row[2] * v.z();
+        Vector3D::new(r(&self.m[0]), r(&self.m[1]), r(&self.m[2]))
+    }
+
+    /// Transforms a surface normal, which takes the inverse transpose so it stays perpendicular
+    /// to the surface. The result isn't normalized.
+    pub fn normal(&self, n: Vector3D) -> Vector3D {
+        let c = |j: usize| self.inv[0][j] * n.x() + self.inv[1][j] * n.y() + self.inv[2][j] * n.z();
+        Vector3D::new(c(0), c(1), c(2))
+    }
+
+    /// How much the transformation scales lengths on average
+    pub fn mean_scale(&self) -> f32 {
+        let m = &self.m;
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        det.abs().cbrt()
+    }
+}
+
+/// `a * b` applies `b` first and then `a`, like matrices
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            m: multiply(&self.m, &rhs.m),
+            inv: multiply(&rhs.inv, &self.inv),
+        }
+    }
+}
+
+/// Matrix without translation whose element in row `i` and column `j` is `f(i, j)`
+fn linear(f: impl Fn(usize, usize) -> f32) -> Matrix {
+    std::array::from_fn(|i| [f(i, 0), f(i, 1), f(i, 2), 0.])
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.; 4]; 3];
+    for i in 0..3 {
+        for j in 0..4 {
+            m[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum::<f32>();
+        }
+        m[i][3] += a[i][3];
+    }
+    m
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f32>();
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let mut inv = linear(|i, j| cofactor(j, i) / det);
+    for row in inv.iter_mut() {
+        row[3] = -(0..3).map(|k| row[k] * m[k][3]).sum::<f32>();
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vector3D, b: Vector3D) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let quarter = Transform::rotate(Vector3D::new(0., 1., 0.), 90.);
+        assert_near(
+            quarter.vector(Vector3D::new(1., 0., 0.)),
+            Vector3D::new(0., 0., -1.),
+        );
+        let p = Vector3D::new(1., 2., 3.);
+        assert_near(quarter.inverse().point(quarter.point(p)), p);
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let t = Transform::translate(Vector3D::new(1., 2., 3.))
+            * Transform::rotate(Vector3D::new(1., 1., 0.), 30.)
+            * Transform::scale(Vector3D::new(2., 1., 0.5)).unwrap();
+        let p = Vector3D::new(-1., 0.5, 4.);
+        assert_near(t.inverse().point(t.point(p)), p);
+        let general = Transform::from_matrix(t.m).unwrap();
+        assert_near(general.inverse().point(t.point(p)), p);
+        assert!(Transform::from_matrix([[0.; 4]; 3]).is_none());
+        assert!(Transform::scale(Vector3D::new(1., 0., 1.)).is_none());
+        assert!((t.mean_scale() - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_normal() {
+        // Squashing a slope makes its normal steeper
+        let t = Transform::scale(Vector3D::new(1., 0.5, 1.)).unwrap();
+        let tangent = Vector3D::new(1., 1., 0.);
+        let n = t.normal(Vector3D::new(-1., 1., 0.));
+        assert!(n.dot(t.vector(tangent)).abs() < 1e-6);
+    }
+}
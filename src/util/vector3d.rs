@@ -44,6 +44,10 @@ impl Vector3D {
         self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
     }
 
+    pub fn max_component(&self) -> f32 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
     pub fn make_unit_vector(&mut self) {
         let k = 1.0 / self.length();
         self.e[0] *= k;
@@ -237,6 +241,12 @@ mod tests {
         assert_eq!(v.squared_length(), 14.0);
     }
 
+    #[test]
+    fn test_max_component() {
+        assert_eq!(Vector3D::new(1., 3., 2.).max_component(), 3.0);
+        assert_eq!(Vector3D::new(-1., -3., -2.).max_component(), -1.0);
+    }
+
     #[test]
     fn test_make_unit_vector() {
         let mut v = Vector3D::new(1., 2., 3.);
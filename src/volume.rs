@@ -0,0 +1,512 @@
+//! Participating media whose density varies through space, such as clouds, smoke and ground
+//! fog. Where light meets the medium is sampled with delta tracking, and shadow rays see
+//! through it with ratio tracking (Novák et al. 2014). Both test tentative collisions against
+//! the densest the medium gets, so the density is only ever looked up, never integrated.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::sampler::Sampler;
+use crate::shapes::bvh::Aabb;
+use crate::util::ray::Ray;
+use crate::util::sampling::to_world;
+use crate::util::vector3d::Vector3D;
+
+/// Samples at the centres of a grid of cells filling a medium's box, in rows along `x`, then
+/// layers along `y`, blended between the nearest eight
+#[derive(Clone)]
+pub struct DensityGrid {
+    dims: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl DensityGrid {
+    /// Fails unless the grid is at least one cell each way and every cell has a density that's
+    /// finite and not negative
+    pub fn new(dims: [usize; 3], values: Vec<f32>) -> Result<Self, String> {
+        if dims.contains(&0) {
+            return Err(format!(
+                "a density grid can't be {} by {} by {}",
+                dims[0], dims[1], dims[2]
+            ));
+        }
+        let count = dims[0] * dims[1] * dims[2];
+        if values.len() != count {
+            return Err(format!(
+                "{} densities given for {} cells",
+                values.len(),
+                count
+            ));
+        }
+        if let Some(v) = values.iter().find(|v| !v.is_finite() || **v < 0.) {
+            return Err(format!("density {} isn't finite and positive", v));
+        }
+        Ok(Self { dims, values })
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    fn evaluate(&self, p: Vector3D, bounds: &Aabb) -> f32 {
+        let dims = self.dims;
+        let size = bounds.max - bounds.min;
+        // Continuous position among the samples, clamped to the outermost ones
+        let position = [0, 1, 2].map(|a| {
+            let x = (p.e[a] - bounds.min.e[a]) / size.e[a] * dims[a] as f32 - 0.5;
+            x.clamp(0., (dims[a] - 1) as f32)
+        });
+        let base = [0, 1, 2].map(|a| (position[a] as usize).min(dims[a].saturating_sub(2)));
+        let mut total = 0.;
+        for corner in 0..8 {
+            let mut weight = 1.;
+            let mut cell = [0; 3];
+            for a in 0..3 {
+                let far = corner >> a & 1 == 1;
+                cell[a] = (base[a] + far as usize).min(dims[a] - 1);
+                let f = position[a] - base[a] as f32;
+                weight *= if far { f } else { 1. - f };
+            }
+            total += weight * self.values[(cell[2] * dims[1] + cell[1]) * dims[0] + cell[0]];
+        }
+        total
+    }
+}
+
+/// How dense a medium is through its box, from 0 up
+#[derive(Clone)]
+pub enum Density {
+    Grid(DensityGrid),
+    /// Fractal value noise from 0 to 1 with `octaves` layers of detail, the coarsest changing
+    /// `frequency` times per unit
+    Noise { frequency: f32, octaves: u32 },
+    /// Any field of position, given the most it reaches
+    Function {
+        f: Arc<dyn Fn(Vector3D) -> f32 + Send + Sync>,
+        max: f32,
+    },
+}
+
+impl Density {
+    /// A grid of `dims` cells with the given densities, see `DensityGrid::new`
+    pub fn grid(dims: [usize; 3], values: Vec<f32>) -> Result<Self, String> {
+        DensityGrid::new(dims, values).map(Density::Grid)
+    }
+
+    pub fn function(f: impl Fn(Vector3D) -> f32 + Send + Sync + 'static, max: f32) -> Self {
+        Density::Function {
+            f: Arc::new(f),
+            max,
+        }
+    }
+
+    /// Density at `p` inside `bounds`
+    pub fn evaluate(&self, p: Vector3D, bounds: &Aabb) -> f32 {
+        match self {
+            Density::Grid(grid) => grid.evaluate(p, bounds),
+            Density::Noise { frequency, octaves } => fbm(p * *frequency, *octaves),
+            Density::Function { f, .. } => f(p),
+        }
+    }
+
+    /// Most the density reaches anywhere
+    pub fn max(&self) -> f32 {
+        match self {
+            Density::Grid(grid) => grid.values.iter().fold(0f32, |a, v| a.max(*v)),
+            Density::Noise { .. } => 1.,
+            Density::Function { max, .. } => *max,
+        }
+    }
+}
+
+/// Pseudorandom value from 0 to 1 for a point of the integer lattice
+fn lattice(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(73_856_093)
+        ^ (y as u32).wrapping_mul(19_349_663)
+        ^ (z as u32).wrapping_mul(83_492_791);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xff_ffff) as f32 / 0x100_0000 as f32
+}
+
+/// Value noise from 0 to 1, smoothly blending random values at the integer lattice
+pub fn value_noise(p: Vector3D) -> f32 {
+    let cell = p.e.map(|x| x.floor());
+    // Smoothstep so the gradient is continuous across cells
+    let f = [0, 1, 2].map(|a| {
+        let t = p.e[a] - cell[a];
+        t * t * (3. - 2. * t)
+    });
+    let [x, y, z] = cell.map(|c| c as i32);
+    let mut total = 0.;
+    for corner in 0..8 {
+        let (dx, dy, dz) = (corner & 1, corner >> 1 & 1, corner >> 2 & 1);
+        let weight = [dx, dy, dz]
+            .iter()
+            .zip(f.iter())
+            .map(|(d, f)| if *d == 1 { *f } else { 1. - f })
+            .product::<f32>();
+        total += weight * lattice(x + dx, y + dy, z + dz);
+    }
+    total
+}
+
+/// Sum of `octaves` layers of value noise, each twice as fine and half as strong as the last,
+/// scaled back to 0 to 1
+pub fn fbm(p: Vector3D, octaves: u32) -> f32 {
+    let (mut total, mut amplitude, mut weights) = (0., 1., 0.);
+    let mut p = p;
+    for _ in 0..octaves.max(1) {
+        total += amplitude * value_noise(p);
+        weights += amplitude;
+        amplitude *= 0.5;
+        // Shifted so the layers' lattices don't line up
+        p = p * 2. + Vector3D::new(17.3, 5.1, 9.7);
+    }
+    total / weights
+}
+
+/// Henyey-Greenstein phase function: the density of light travelling along one direction
+/// turning by an angle of cosine `cos_theta`. `g` from -1 to 1 leans it backwards or forwards,
+/// 0 is the same every way.
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denominator = 1. + g * g - 2. * g * cos_theta;
+    (1. - g * g) / (4. * PI * denominator * denominator.max(1e-12).sqrt())
+}
+
+/// Direction light travelling along unit `direction` turns into, chosen by the
+/// Henyey-Greenstein phase function so its weight is 1
+pub fn sample_henyey_greenstein(direction: Vector3D, g: f32, (u, v): (f32, f32)) -> Vector3D {
+    let cos_theta = match g.abs() < 1e-3 {
+        true => 1. - 2. * u,
+        false => {
+            let s = (1. - g * g) / (1. - g + 2. * g * u);
+            ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+        }
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    to_world(
+        Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+        direction,
+    )
+}
+
+/// Medium filling a box, scattering and absorbing light in proportion to its density
+#[derive(Clone)]
+pub struct Medium {
+    pub density: Density,
+    pub bounds: Aabb,
+    /// Chance per unit distance of light meeting the medium where the density is 1
+    pub sigma_t: f32,
+    /// Share of the light met that's scattered rather than absorbed, for each colour
+    pub albedo: Vector3D,
+    /// Henyey-Greenstein asymmetry of the scattering
+    pub g: f32,
+    /// Most light is met per unit distance anywhere in the box
+    majorant: f32,
+}
+
+impl Medium {
+    /// Medium scattering all the light it meets equally every way
+    pub fn new(density: Density, bounds: Aabb, sigma_t: f32) -> Self {
+        let majorant = density.max().max(0.) * sigma_t;
+        Self {
+            density,
+            bounds,
+            sigma_t,
+            albedo: Vector3D::new(1., 1., 1.),
+            g: 0.,
+            majorant,
+        }
+    }
+
+    pub fn with_albedo(mut self, albedo: Vector3D) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    /// Leans scattering forwards for `g` above 0, as in clouds and haze, or backwards below 0
+    pub fn with_anisotropy(mut self, g: f32) -> Self {
+        self.g = g;
+        self
+    }
+
+    /// Light met per unit distance at `p`
+    pub fn sigma_t_at(&self, p: Vector3D) -> f32 {
+        self.density.evaluate(p, &self.bounds).max(0.) * self.sigma_t
+    }
+
+    /// Part of the ray between `t_min` and `t_max` inside the box, and the mean step in `t`
+    /// between tentative collisions
+    fn span(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        if self.majorant <= 0. {
+            return None;
+        }
+        let d = r.direction();
+        let inv_direction = Vector3D::new(1. / d.x(), 1. / d.y(), 1. / d.z());
+        let (t0, t1) = self.bounds.clip(r, inv_direction, t_min, t_max)?;
+        Some((t0, t1, 1. / (self.majorant * d.length())))
+    }
+
+    /// Where along the ray between `t_min` and `t_max` light first meets the medium, by delta
+    /// tracking: steps drawn as if the medium were at its densest everywhere are taken as real
+    /// in proportion to the density where they land
+    pub fn sample_collision(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f32> {
+        let (mut t, t_end, scale) = self.span(r, t_min, t_max)?;
+        loop {
+            t -= (1. - sampler.get_1d()).ln() * scale;
+            if t >= t_end {
+                return None;
+            }
+            let p = r.point_at_parameter(t);
+            if sampler.get_1d() * self.majorant < self.sigma_t_at(p) {
+                return Some(t);
+            }
+        }
+    }
+
+    /// Estimate of the fraction of light getting through the medium between `t_min` and
+    /// `t_max`, by ratio tracking: each tentative collision lets through the share of the
+    /// densest medium that isn't there
+    pub fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> f32 {
+        let (mut t, t_end, scale) = match self.span(r, t_min, t_max) {
+            Some(span) => span,
+            None => return 1.,
+        };
+        let mut transmittance = 1.;
+        loop {
+            t -= (1. - sampler.get_1d()).ln() * scale;
+            if t >= t_end {
+                return transmittance;
+            }
+            let p = r.point_at_parameter(t);
+            transmittance *= 1. - self.sigma_t_at(p) / self.majorant;
+            // Stop tracking light that's nearly all gone, keeping the estimate unbiased
+            if transmittance < 0.1 {
+                if sampler.get_1d() >= 0.5 {
+                    return 0.;
+                }
+                transmittance *= 2.;
+            }
+        }
+    }
+}
+
+/// Shapes of medium that can be added to a scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeKind {
+    /// Puffy ball of cloud
+    Cloud,
+    /// Patchy fog thinning with height
+    Fog,
+    /// Column of smoke widening as it rises, held in a grid as a simulation would leave it
+    Smoke,
+}
+
+impl VolumeKind {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "cloud" => Ok(VolumeKind::Cloud),
+            "fog" => Ok(VolumeKind::Fog),
+            "smoke" => Ok(VolumeKind::Smoke),
+            _ => Err(format!("Unknown volume {}", name)),
+        }
+    }
+
+    /// Medium of this shape around `center` reaching about `radius` from it, meeting up to
+    /// `sigma_t` of light per unit distance
+    pub fn medium(&self, center: Vector3D, radius: f32, sigma_t: f32) -> Medium {
+        match self {
+            VolumeKind::Cloud => {
+                let extent = Vector3D::new(radius, 0.6 * radius, radius);
+                let bounds = Aabb {
+                    min: center - extent,
+                    max: center + extent,
+                };
+                let frequency = 2. / radius;
+                let density = Density::function(
+                    move |p| {
+                        // Dense in the middle, broken up by noise towards the edges
+                        let q = (p - center) / extent;
+                        let falloff = 1. - q.squared_length();
+                        (2.5 * (0.6 * falloff + fbm(p * frequency, 5) - 0.7)).clamp(0., 1.)
+                    },
+                    1.,
+                );
+                Medium::new(density, bounds, sigma_t).with_albedo(Vector3D::new(0.95, 0.95, 0.95))
+            }
+            VolumeKind::Fog => {
+                let bounds = Aabb {
+                    min: center - Vector3D::new(2. * radius, 0.25 * radius, 2. * radius),
+                    max: center + Vector3D::new(2. * radius, radius, 2. * radius),
+                };
+                let (floor, height) = (bounds.min.y(), 1.25 * radius);
+                let frequency = 1.5 / radius;
+                let density = Density::function(
+                    move |p| {
+                        let patches = (1.5 * fbm(p * frequency, 4) - 0.25).clamp(0., 1.);
+                        patches * (-3. * (p.y() - floor).max(0.) / height).exp()
+                    },
+                    1.,
+                );
+                Medium::new(density, bounds, sigma_t).with_albedo(Vector3D::new(0.9, 0.9, 0.9))
+            }
+            VolumeKind::Smoke => {
+                let extent = Vector3D::new(0.6 * radius, radius, 0.6 * radius);
+                let bounds = Aabb {
+                    min: center - extent,
+                    max: center + extent,
+                };
+                let dims = [32, 48, 32];
+                let cells = (0..dims[2]).flat_map(|z| {
+                    (0..dims[1]).flat_map(move |y| (0..dims[0]).map(move |x| [x, y, z]))
+                });
+                let values = cells
+                    .map(|cell| {
+                        // Position of the cell's centre across the box, from 0 to 1
+                        let [u, h, w] = [0, 1, 2].map(|a| (cell[a] as f32 + 0.5) / dims[a] as f32);
+                        let sway = 0.12 * (h * 6.).sin() * h;
+                        let width = 0.08 + 0.32 * h;
+                        let off = ((u - 0.5 - sway).powi(2) + (w - 0.5).powi(2)).sqrt();
+                        let wisps = fbm(Vector3D::new(u, h, w) * 6., 4);
+                        let core = (1. - off / width).max(0.);
+                        (core * (0.3 + 1.4 * wisps) * (1. - h).sqrt()).clamp(0., 1.)
+                    })
+                    .collect();
+                let density = Density::grid(dims, values).expect("the smoke fills its grid");
+                Medium::new(density, bounds, sigma_t).with_albedo(Vector3D::new(0.6, 0.6, 0.6))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::util::sampling::uniform_sphere;
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Vector3D::new(0., 0., 0.),
+            max: Vector3D::new(2., 1., 1.),
+        }
+    }
+
+    #[test]
+    fn test_grid() {
+        // Two cells along x, one each other way
+        let density = Density::grid([2, 1, 1], vec![0.2, 1.]).unwrap();
+        let b = unit_box();
+        assert_eq!(density.max(), 1.);
+        // At the cell centres, halfway between and clamped beyond
+        assert!((density.evaluate(Vector3D::new(0.5, 0.5, 0.5), &b) - 0.2).abs() < 1e-6);
+        assert!((density.evaluate(Vector3D::new(1., 0.2, 0.9), &b) - 0.6).abs() < 1e-6);
+        assert!((density.evaluate(Vector3D::new(2., 0.5, 0.5), &b) - 1.).abs() < 1e-6);
+        // A single cell is the same everywhere
+        let single = Density::grid([1, 1, 1], vec![0.4]).unwrap();
+        assert!((single.evaluate(Vector3D::new(1.7, 0.1, 0.3), &b) - 0.4).abs() < 1e-6);
+        assert!(Density::grid([2, 1, 1], vec![0.2]).is_err());
+        assert!(Density::grid([2, 0, 1], vec![]).is_err());
+        assert!(Density::grid([2, 1, 1], vec![0.2, -1.]).is_err());
+        assert!(Density::grid([1, 1, 1], vec![f32::NAN]).is_err());
+    }
+
+    #[test]
+    fn test_noise() {
+        let values: Vec<f32> = (0..1000)
+            .map(|i| fbm(Vector3D::new(i as f32 * 0.37, i as f32 * 0.11, -0.3), 4))
+            .collect();
+        assert!(values.iter().all(|v| (0. ..=1.).contains(v)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 0.5).abs() < 0.05);
+        // Continuous
+        let p = Vector3D::new(3.3, 1.7, 0.2);
+        let q = p + Vector3D::new(1e-3, 0., 0.);
+        assert!((fbm(p, 4) - fbm(q, 4)).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_henyey_greenstein() {
+        let mut sampler = IndependentSampler;
+        let direction = Vector3D::new(0., 0., 1.);
+        for g in [-0.5, 0., 0.7].iter() {
+            // Normalised over the sphere, with the mean cosine equal to g
+            let n = 100_000;
+            let integral = (0..n)
+                .map(|_| {
+                    let w = uniform_sphere(sampler.get_2d());
+                    henyey_greenstein(w.z(), *g) * 4. * PI
+                })
+                .sum::<f32>()
+                / n as f32;
+            assert!(
+                (integral - 1.).abs() < 0.05,
+                "g {} integrates to {}",
+                g,
+                integral
+            );
+            let mean = (0..n)
+                .map(|_| sample_henyey_greenstein(direction, *g, sampler.get_2d()).z())
+                .sum::<f32>()
+                / n as f32;
+            assert!((mean - g).abs() < 0.01, "g {} has mean cosine {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn test_tracking() {
+        // Half density over a box two long, so light gets through e^-1 of the time
+        let medium = Medium::new(Density::function(|_| 0.5, 1.), unit_box(), 1.);
+        let r = Ray::new(Vector3D::new(-1., 0.5, 0.5), Vector3D::new(2., 0., 0.));
+        let mut sampler = IndependentSampler;
+        let n = 50_000;
+        let expected = (-1f32).exp();
+        let ratio = (0..n)
+            .map(|_| medium.transmittance(&r, 0., f32::MAX, &mut sampler))
+            .sum::<f32>()
+            / n as f32;
+        assert!(
+            (ratio - expected).abs() < 0.01,
+            "ratio tracking gives {}",
+            ratio
+        );
+        let collisions: Vec<f32> = (0..n)
+            .filter_map(|_| medium.sample_collision(&r, 0., f32::MAX, &mut sampler))
+            .collect();
+        let through = 1. - collisions.len() as f32 / n as f32;
+        assert!(
+            (through - expected).abs() < 0.01,
+            "delta tracking gives {}",
+            through
+        );
+        assert!(collisions.iter().all(|t| (0.5..1.5).contains(t)));
+        // Nothing before the box or past the end of the ray
+        assert_eq!(medium.transmittance(&r, 0., 0.5, &mut sampler), 1.);
+        assert!(medium.sample_collision(&r, 0., 0.5, &mut sampler).is_none());
+    }
+
+    #[test]
+    fn test_presets() {
+        let center = Vector3D::new(0., 1., 0.);
+        for kind in [VolumeKind::Cloud, VolumeKind::Fog, VolumeKind::Smoke].iter() {
+            let medium = kind.medium(center, 2., 1.);
+            assert!(medium.sigma_t_at(center) > 0.);
+            assert!(medium.bounds.min.y() < center.y() && medium.bounds.max.y() > center.y());
+        }
+        // The cloud fades out at the edges of its box
+        let cloud = VolumeKind::Cloud.medium(center, 2., 1.);
+        assert_eq!(cloud.sigma_t_at(cloud.bounds.max), 0.);
+        assert_eq!(VolumeKind::from_name("fog"), Ok(VolumeKind::Fog));
+        assert_eq!(VolumeKind::from_name("smoke"), Ok(VolumeKind::Smoke));
+        assert!(VolumeKind::from_name("steam").is_err());
+    }
+}